@@ -0,0 +1,20 @@
+extern crate tower_grpc_build;
+
+fn main() {
+    let iface_files = &[
+        "envoy/service/auth/v3/external_auth.proto",
+        "envoy/service/ratelimit/v3/rls.proto",
+    ];
+    let dirs = &["."];
+
+    tower_grpc_build::Config::new()
+        .enable_client(true)
+        .enable_server(true)
+        .build(iface_files, dirs)
+        .unwrap_or_else(|e| panic!("protobuf compilation failed: {}", e));
+
+    // recompile protobufs only if any of the proto files changes.
+    for file in iface_files {
+        println!("cargo:rerun-if-changed={}", file);
+    }
+}
@@ -2,6 +2,8 @@
 //!
 //! * `/metrics` -- reports prometheus-formatted metrics.
 //! * `/ready` -- returns 200 when the proxy is ready to participate in meshed traffic.
+//! * `/tap` -- streams tap events as newline-delimited JSON to loopback clients,
+//!   if tap is enabled.
 
 use crate::{svc, transport::tls::accept::Connection};
 use futures::{future, Future, Poll};
@@ -11,17 +13,21 @@ use hyper::{Body, Request, Response};
 use linkerd2_error::Error;
 use linkerd2_metrics::{self as metrics, FmtMetrics};
 use std::io;
+use tracing::{error, warn};
 
 mod readiness;
+mod tap;
 mod trace_level;
 
 pub use self::readiness::{Latch, Readiness};
+pub use self::tap::Tap;
 use self::trace_level::TraceLevel;
 
 #[derive(Debug, Clone)]
 pub struct Admin<M: FmtMetrics> {
     metrics: metrics::Serve<M>,
     trace_level: TraceLevel,
+    tap: Option<Tap>,
     ready: Readiness,
 }
 
@@ -35,10 +41,11 @@ pub type ResponseFuture =
     Box<dyn Future<Item = Response<Body>, Error = io::Error> + Send + 'static>;
 
 impl<M: FmtMetrics> Admin<M> {
-    pub fn new(m: M, ready: Readiness, trace_level: TraceLevel) -> Self {
+    pub fn new(m: M, ready: Readiness, trace_level: TraceLevel, tap: Option<Tap>) -> Self {
         Self {
             metrics: metrics::Serve::new(m),
             trace_level,
+            tap,
             ready,
         }
     }
@@ -79,6 +86,10 @@ impl<M: FmtMetrics> Service for Admin<M> {
         match req.uri().path() {
             "/metrics" => Box::new(self.metrics.call(req)),
             "/proxy-log-level" => self.trace_level.call(req),
+            "/tap" => match self.tap {
+                Some(ref mut tap) => tap.call(req),
+                None => Box::new(future::ok(rsp(StatusCode::NOT_FOUND, Body::empty()))),
+            },
             "/ready" => Box::new(future::ok(self.ready_rsp())),
             "/live" => Box::new(future::ok(self.live_rsp())),
             _ => Box::new(future::ok(rsp(StatusCode::NOT_FOUND, Body::empty()))),
@@ -96,9 +107,9 @@ impl<M: FmtMetrics + Clone + Send + 'static> svc::Service<Connection> for Accept
     }
 
     fn call(&mut self, (meta, io): Connection) -> Self::Future {
-        // Since the `/proxy-log-level` controls access based on the
-        // client's IP address, we wrap the service with a new service
-        // that adds the remote IP as a request extension.
+        // Since the `/proxy-log-level` and `/tap` endpoints control access
+        // based on the client's IP address, we wrap the service with a new
+        // service that adds the remote IP as a request extension.
         let peer = meta.addrs.peer();
         let mut svc = self.0.clone();
         let svc = service_fn(move |mut req| {
@@ -117,6 +128,29 @@ impl ClientAddr {
     }
 }
 
+/// Returns an error response unless the request was sent from a loopback IP.
+fn check_loopback(req: &Request<Body>) -> Result<(), Response<Body>> {
+    if let Some(addr) = req.extensions().get::<ClientAddr>() {
+        let addr = addr.addr();
+        if !addr.ip().is_loopback() {
+            warn!(message = "denying request from non-loopback IP", %addr);
+            return Err(rsp(
+                StatusCode::FORBIDDEN,
+                format!(
+                    "access to {} only allowed from loopback interface",
+                    req.uri().path()
+                ),
+            ));
+        }
+        Ok(())
+    } else {
+        // TODO: should we panic if this was unset? It's a bug, but should
+        // it crash the proxy?
+        error!(message = "ClientAddr extension should always be set");
+        Err(rsp(StatusCode::INTERNAL_SERVER_ERROR, Body::empty()))
+    }
+}
+
 fn rsp(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
     Response::builder()
         .status(status)
@@ -127,6 +161,7 @@ fn rsp(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::proxy::tap::BodyCapture;
     use http::method::Method;
     use linkerd2_test_util::BlockOnFor;
     use std::time::Duration;
@@ -134,13 +169,18 @@ mod tests {
 
     const TIMEOUT: Duration = Duration::from_secs(1);
 
+    fn tap(body_capture: BodyCapture) -> Option<Tap> {
+        let (_, server, _) = crate::proxy::tap::new(body_capture);
+        Some(Tap::new(crate::proxy::tap::Json::new(server)))
+    }
+
     #[test]
     fn ready_when_latches_dropped() {
         let (r, l0) = Readiness::new();
         let l1 = l0.clone();
 
         let mut rt = Runtime::new().unwrap();
-        let mut srv = Admin::new((), r, TraceLevel::dangling());
+        let mut srv = Admin::new((), r, TraceLevel::dangling(), tap(BodyCapture::Permitted));
         macro_rules! call {
             () => {{
                 let r = Request::builder()
@@ -161,4 +201,68 @@ mod tests {
         drop(l1);
         assert_eq!(call!().status(), StatusCode::OK);
     }
+
+    #[test]
+    fn tap_only_from_loopback() {
+        let (r, _l) = Readiness::new();
+
+        let mut rt = Runtime::new().unwrap();
+        let mut srv = Admin::new((), r, TraceLevel::dangling(), tap(BodyCapture::Permitted));
+        macro_rules! call {
+            ($addr:expr, $uri:expr) => {{
+                let mut r = Request::builder()
+                    .method(Method::GET)
+                    .uri($uri)
+                    .body(Body::empty())
+                    .unwrap();
+                r.extensions_mut()
+                    .insert(ClientAddr($addr.parse().unwrap()));
+                let f = srv.call(r);
+                rt.block_on_for(TIMEOUT, f).expect("call")
+            };};
+        }
+
+        let rsp = call!("10.1.1.1:5678", "http://4.3.2.1:5678/tap");
+        assert_eq!(rsp.status(), StatusCode::FORBIDDEN);
+
+        let rsp = call!("127.0.0.1:5678", "http://4.3.2.1:5678/tap?port=80");
+        assert_eq!(rsp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn tap_not_found_when_disabled() {
+        let (r, _l) = Readiness::new();
+
+        let mut rt = Runtime::new().unwrap();
+        let mut srv = Admin::new((), r, TraceLevel::dangling(), None);
+
+        let mut req = Request::builder()
+            .method(Method::GET)
+            .uri("http://4.3.2.1:5678/tap")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ClientAddr("127.0.0.1:5678".parse().unwrap()));
+        let rsp = rt.block_on_for(TIMEOUT, srv.call(req)).expect("call");
+        assert_eq!(rsp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn tap_body_capture_may_be_forbidden() {
+        let (r, _l) = Readiness::new();
+
+        let mut rt = Runtime::new().unwrap();
+        let tap = tap(BodyCapture::Forbidden);
+        let mut srv = Admin::new((), r, TraceLevel::dangling(), tap);
+
+        let mut req = Request::builder()
+            .method(Method::GET)
+            .uri("http://4.3.2.1:5678/tap?body=64")
+            .body(Body::empty())
+            .unwrap();
+        req.extensions_mut()
+            .insert(ClientAddr("127.0.0.1:5678".parse().unwrap()));
+        let rsp = rt.block_on_for(TIMEOUT, srv.call(req)).expect("call");
+        assert_eq!(rsp.status(), StatusCode::FORBIDDEN);
+    }
 }
@@ -0,0 +1,47 @@
+use super::{check_loopback, rsp};
+use crate::proxy::tap;
+use futures::future::{self, Future};
+use http::{Method, StatusCode};
+use hyper::{service::Service, Body, Request, Response};
+use std::io;
+
+/// Streams tap events for requests that match the query string as
+/// newline-delimited JSON.
+#[derive(Clone, Debug)]
+pub struct Tap(tap::Json);
+
+impl Tap {
+    pub fn new(json: tap::Json) -> Self {
+        Tap(json)
+    }
+}
+
+impl Service for Tap {
+    type ReqBody = Body;
+    type ResBody = Body;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = Response<Body>, Error = Self::Error> + Send + 'static>;
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // `/tap` endpoint can only be called from loopback IPs
+        if let Err(rsp) = check_loopback(&req) {
+            return Box::new(future::ok(rsp));
+        }
+
+        if *req.method() != Method::GET {
+            return Box::new(future::ok(
+                Response::builder()
+                    .status(StatusCode::METHOD_NOT_ALLOWED)
+                    .header("allow", "GET")
+                    .body(Body::empty())
+                    .expect("builder with known status code must not fail"),
+            ));
+        }
+
+        Box::new(
+            self.0
+                .observe(req.uri().query())
+                .map_err(|never| match never {}),
+        )
+    }
+}
@@ -1,4 +1,4 @@
-use super::{rsp, ClientAddr};
+use super::{check_loopback, rsp};
 pub use crate::trace::LevelHandle as TraceLevel;
 use futures::{
     future::{self, Future},
@@ -7,7 +7,7 @@ use futures::{
 use http::{Method, StatusCode};
 use hyper::{service::Service, Body, Request, Response};
 use std::{io, str};
-use tracing::{error, trace, warn};
+use tracing::{trace, warn};
 
 impl Service for TraceLevel {
     type ReqBody = Body;
@@ -17,23 +17,8 @@ impl Service for TraceLevel {
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
         // `/proxy-log-level` endpoint can only be called from loopback IPs
-        if let Some(addr) = req.extensions().get::<ClientAddr>() {
-            let addr = addr.addr();
-            if !addr.ip().is_loopback() {
-                warn!(message = "denying request from non-loopback IP", %addr);
-                return Box::new(future::ok(rsp(
-                    StatusCode::FORBIDDEN,
-                    "access to /proxy-log-level only allowed from loopback interface",
-                )));
-            }
-        } else {
-            // TODO: should we panic if this was unset? It's a bug, but should
-            // it crash the proxy?
-            error!(message = "ClientAddr extension should always be set");
-            return Box::new(future::ok(rsp(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Body::empty(),
-            )));
+        if let Err(rsp) = check_loopback(&req) {
+            return Box::new(future::ok(rsp));
         }
 
         match req.method() {
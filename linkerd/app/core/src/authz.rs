@@ -0,0 +1,384 @@
+//! Authorizes inbound connections and requests by their client's identity.
+//!
+//! A `Policy` configures, for each inbound port and optionally each of the
+//! port's service profile routes, which clients are authorized: clients whose
+//! TLS identity matches one of the rule's identities and clients (with or
+//! without an identity) connecting from one of the rule's networks. Ports
+//! without a rule are governed by the policy's default.
+//!
+//! Opaque TCP connections are checked as they are accepted, so unauthorized
+//! connections are closed. HTTP requests are checked individually, so that
+//! route rules may be applied, and unauthorized requests fail with an
+//! `Unauthorized` error.
+
+use super::dst::Route;
+use crate::proxy::identity;
+use crate::transport::tls;
+use indexmap::IndexMap;
+use linkerd2_admit::Admit;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+metrics! {
+    inbound_authz_denied_total: Counter {
+        "Total count of inbound connections and requests denied by the authorization policy."
+    }
+}
+
+/// The profile route label that names a route.
+const ROUTE_LABEL: &str = "route";
+
+/// Determines whether clients are authorized on ports without a rule.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DefaultPolicy {
+    Allow,
+    Deny,
+}
+
+/// Authorizes clients for a port or a route.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Clients {
+    pub identities: Vec<IdentityMatch>,
+    /// Clients connecting from these networks are authorized whether or not
+    /// they have an identity.
+    pub networks: Vec<ipnet::IpNet>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IdentityMatch {
+    /// Matches any identity.
+    Any,
+    Exact(identity::Name),
+    /// Matches identities ending with the suffix, e.g. all service accounts in
+    /// a namespace (`*.ns.serviceaccount.identity.linkerd.cluster.local`).
+    Suffix(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub port: u16,
+    /// If set, the rule only applies to requests on the named profile route.
+    pub route: Option<String>,
+    pub clients: Clients,
+}
+
+#[derive(Clone, Debug)]
+pub struct Policy {
+    default: DefaultPolicy,
+    ports: IndexMap<u16, PortPolicy>,
+}
+
+/// Enforces a `Policy` on inbound connections and requests.
+#[derive(Clone, Debug)]
+pub struct Authorize {
+    policy: Arc<Policy>,
+    metrics: Metrics,
+}
+
+/// Indicates that a client is not authorized by the policy.
+#[derive(Debug)]
+pub struct Unauthorized(());
+
+/// Counts denials, labeled by port and client identity.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<IndexMap<Labels, Arc<Counter>>>>);
+
+#[derive(Clone, Debug, Default)]
+struct PortPolicy {
+    clients: Option<Clients>,
+    routes: IndexMap<String, Clients>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Labels {
+    port: u16,
+    client_id: Option<identity::Name>,
+}
+
+// === impl Policy ===
+
+impl Policy {
+    pub fn new(default: DefaultPolicy, rules: impl IntoIterator<Item = Rule>) -> Self {
+        let mut ports = IndexMap::<u16, PortPolicy>::new();
+        for Rule {
+            port,
+            route,
+            clients,
+        } in rules
+        {
+            let port = ports.entry(port).or_insert_with(PortPolicy::default);
+            match route {
+                Some(route) => {
+                    port.routes.insert(route, clients);
+                }
+                None => port.clients = Some(clients),
+            }
+        }
+        Self { default, ports }
+    }
+
+    /// Authorizes all clients.
+    pub fn allow_all() -> Self {
+        Self::new(DefaultPolicy::Allow, None)
+    }
+
+    /// Returns true if the client is authorized on the port and, if
+    /// specified, the route.
+    ///
+    /// A route's rule is used instead of its port's rule.
+    fn is_authorized(
+        &self,
+        port: u16,
+        route: Option<&str>,
+        client_id: Option<&identity::Name>,
+        client_ip: IpAddr,
+    ) -> bool {
+        let port = match self.ports.get(&port) {
+            Some(port) => port,
+            None => return self.default == DefaultPolicy::Allow,
+        };
+        let clients = route
+            .and_then(|route| port.routes.get(route))
+            .or_else(|| port.clients.as_ref());
+        match clients {
+            Some(clients) => clients.is_authorized(client_id, client_ip),
+            None => self.default == DefaultPolicy::Allow,
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::allow_all()
+    }
+}
+
+// === impl Clients ===
+
+impl Clients {
+    fn is_authorized(&self, client_id: Option<&identity::Name>, client_ip: IpAddr) -> bool {
+        if self.networks.iter().any(|net| net.contains(&client_ip)) {
+            return true;
+        }
+
+        match client_id {
+            Some(id) => self.identities.iter().any(|m| m.matches(id)),
+            None => false,
+        }
+    }
+}
+
+// === impl IdentityMatch ===
+
+impl IdentityMatch {
+    fn matches(&self, id: &identity::Name) -> bool {
+        match self {
+            IdentityMatch::Any => true,
+            IdentityMatch::Exact(ref name) => name == id,
+            IdentityMatch::Suffix(ref suffix) => {
+                let id = id.as_ref();
+                id.len() > suffix.len() && id.ends_with(suffix.as_str())
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for IdentityMatch {
+    type Err = identity::InvalidName;
+
+    /// Parses `*`, an identity name, or a suffix pattern like `*.ns.svc`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(IdentityMatch::Any);
+        }
+
+        if s.starts_with("*.") {
+            // Validate the suffix as a name.
+            identity::Name::from_hostname(s[2..].as_bytes())?;
+            return Ok(IdentityMatch::Suffix(s[1..].to_ascii_lowercase()));
+        }
+
+        identity::Name::from_hostname(s.as_bytes()).map(IdentityMatch::Exact)
+    }
+}
+
+// === impl Authorize ===
+
+impl Authorize {
+    pub fn new(policy: Policy, metrics: Metrics) -> Self {
+        Self {
+            policy: Arc::new(policy),
+            metrics,
+        }
+    }
+
+    fn authorize(&self, meta: &tls::accept::Meta, route: Option<&str>) -> Result<(), Unauthorized> {
+        let port = meta.addrs.target_addr().port();
+        let client_id = meta.peer_identity.value();
+        let client_ip = meta.addrs.peer().ip();
+        if self.policy.is_authorized(port, route, client_id, client_ip) {
+            return Ok(());
+        }
+
+        debug!(%port, ?route, client.id = ?client_id, client.addr = %client_ip, "Unauthorized");
+        self.metrics.denied(Labels {
+            port,
+            client_id: client_id.cloned(),
+        });
+        Err(Unauthorized(()))
+    }
+}
+
+/// Authorizes opaque TCP connections.
+impl Admit<tls::accept::Meta> for Authorize {
+    type Error = Unauthorized;
+
+    fn admit(&mut self, meta: &tls::accept::Meta) -> Result<(), Self::Error> {
+        self.authorize(meta, None)
+    }
+}
+
+/// Authorizes HTTP requests by the connection on which they were received and
+/// their profile route, if any.
+impl<B> Admit<http::Request<B>> for Authorize {
+    type Error = Unauthorized;
+
+    fn admit(&mut self, req: &http::Request<B>) -> Result<(), Self::Error> {
+        let meta = req
+            .extensions()
+            .get::<tls::accept::Meta>()
+            .ok_or(Unauthorized(()))?;
+        let route = req
+            .extensions()
+            .get::<Route>()
+            .and_then(|r| r.route.labels().get(ROUTE_LABEL))
+            .map(String::as_str);
+        self.authorize(meta, route)
+    }
+}
+
+// === impl Unauthorized ===
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "client is not authorized")
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+// === impl Metrics ===
+
+impl Metrics {
+    fn denied(&self, labels: Labels) {
+        if let Ok(mut registry) = self.0.lock() {
+            registry
+                .entry(labels)
+                .or_insert_with(|| Arc::new(Counter::default()))
+                .incr();
+        }
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registry = match self.0.lock() {
+            Ok(registry) => registry,
+            Err(_) => return Ok(()),
+        };
+        if registry.is_empty() {
+            return Ok(());
+        }
+
+        inbound_authz_denied_total.fmt_help(f)?;
+        inbound_authz_denied_total.fmt_scopes(f, registry.iter(), |c| &**c)?;
+
+        Ok(())
+    }
+}
+
+impl FmtLabels for Labels {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "target_port=\"{}\",client_id=\"", self.port)?;
+        if let Some(ref id) = self.client_id {
+            write!(f, "{}", id.as_ref())?;
+        }
+        write!(f, "\"")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(name: &str) -> identity::Name {
+        identity::Name::from_hostname(name.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn matches_identity_patterns() {
+        let foo = id("foo.ns1.serviceaccount.identity.linkerd.cluster.local");
+        let any: IdentityMatch = "*".parse().unwrap();
+        let ns1: IdentityMatch = "*.ns1.serviceaccount.identity.linkerd.cluster.local"
+            .parse()
+            .unwrap();
+        let ns2: IdentityMatch = "*.ns2.serviceaccount.identity.linkerd.cluster.local"
+            .parse()
+            .unwrap();
+        let exact: IdentityMatch = "foo.ns1.serviceaccount.identity.linkerd.cluster.local"
+            .parse()
+            .unwrap();
+
+        assert!(any.matches(&foo));
+        assert!(ns1.matches(&foo));
+        assert!(!ns2.matches(&foo));
+        assert!(exact.matches(&foo));
+        assert!(!ns1.matches(&id("ns1.serviceaccount.identity.linkerd.cluster.local")));
+        assert!("*.".parse::<IdentityMatch>().is_err());
+    }
+
+    #[test]
+    fn applies_port_route_and_default_rules() {
+        let foo = id("foo.ns1.serviceaccount.identity.linkerd.cluster.local");
+        let bar = id("bar.ns2.serviceaccount.identity.linkerd.cluster.local");
+        let local = IpAddr::from([10, 1, 1, 1]);
+        let remote = IpAddr::from([192, 168, 1, 1]);
+
+        let policy = Policy::new(
+            DefaultPolicy::Deny,
+            vec![
+                Rule {
+                    port: 8080,
+                    route: None,
+                    clients: Clients {
+                        identities: vec![IdentityMatch::Any],
+                        networks: vec!["10.0.0.0/8".parse().unwrap()],
+                    },
+                },
+                Rule {
+                    port: 8080,
+                    route: Some("admin".into()),
+                    clients: Clients {
+                        identities: vec![IdentityMatch::Exact(foo.clone())],
+                        networks: vec![],
+                    },
+                },
+            ],
+        );
+
+        assert!(policy.is_authorized(8080, None, Some(&bar), remote));
+        assert!(policy.is_authorized(8080, None, None, local));
+        assert!(!policy.is_authorized(8080, None, None, remote));
+        assert!(policy.is_authorized(8080, Some("other"), Some(&bar), remote));
+
+        assert!(policy.is_authorized(8080, Some("admin"), Some(&foo), remote));
+        assert!(!policy.is_authorized(8080, Some("admin"), Some(&bar), remote));
+        assert!(!policy.is_authorized(8080, Some("admin"), None, local));
+
+        assert!(!policy.is_authorized(9090, None, Some(&foo), local));
+        assert!(Policy::allow_all().is_authorized(9090, None, None, remote));
+    }
+}
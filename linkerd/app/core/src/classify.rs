@@ -1,3 +1,5 @@
+use crate::errors::find_error;
+use crate::fault;
 use crate::profiles;
 use http;
 use linkerd2_error::Error;
@@ -27,9 +29,19 @@ pub enum Eos {
     Default(http::StatusCode),
     Grpc(GrpcEos),
     Profile(Class),
+    ProfileEos(ProfileEos),
     Error(&'static str),
 }
 
+/// Defers profile classification until the end of the response stream, when
+/// trailers are available.
+#[derive(Clone, Debug)]
+pub struct ProfileEos {
+    classes: profiles::ResponseClasses,
+    status: http::StatusCode,
+    headers: http::HeaderMap,
+}
+
 #[derive(Clone, Debug)]
 pub enum GrpcEos {
     NoBody(Class),
@@ -104,12 +116,12 @@ impl Default for Response {
 }
 
 impl Response {
-    fn match_class<B>(
-        rsp: &http::Response<B>,
-        classes: &[profiles::ResponseClass],
-    ) -> Option<Class> {
+    fn match_class<F>(classes: &[profiles::ResponseClass], is_match: F) -> Option<Class>
+    where
+        F: Fn(&profiles::ResponseClass) -> bool,
+    {
         for class in classes {
-            if class.is_match(rsp) {
+            if is_match(class) {
                 let result = if class.is_failure() {
                     SuccessOrFailure::Failure
                 } else {
@@ -135,19 +147,33 @@ impl classify::ClassifyResponse for Response {
             Response::Grpc => grpc_class(rsp.headers())
                 .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
                 .unwrap_or(Eos::Grpc(GrpcEos::Open)),
-            Response::Profile(ref classes) => Self::match_class(rsp, classes.as_ref())
-                .map(Eos::Profile)
-                .unwrap_or_else(|| {
-                    grpc_class(rsp.headers())
-                        .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
-                        .unwrap_or_else(|| Eos::Default(rsp.status()))
-                }),
+            // Classes that match on trailers (or gRPC status, which may be
+            // sent as a trailer) cannot be evaluated until the end of the
+            // stream.
+            Response::Profile(classes) if classes.iter().any(|c| c.requires_eos()) => {
+                Eos::ProfileEos(ProfileEos {
+                    classes,
+                    status: rsp.status(),
+                    headers: rsp.headers().clone(),
+                })
+            }
+            Response::Profile(ref classes) => {
+                Self::match_class(classes.as_ref(), |c| c.is_match(rsp))
+                    .map(Eos::Profile)
+                    .unwrap_or_else(|| {
+                        grpc_class(rsp.headers())
+                            .map(|c| Eos::Grpc(GrpcEos::NoBody(c)))
+                            .unwrap_or_else(|| Eos::Default(rsp.status()))
+                    })
+            }
         }
     }
 
     fn error(self, err: &Error) -> Self::Class {
         let msg = if err.is::<ResponseTimeout>() {
             "timeout".into()
+        } else if find_error::<fault::Aborted>(&**err).is_some() {
+            "fault_abort".into()
         } else {
             h2_error(err).into()
         };
@@ -174,6 +200,15 @@ impl classify::ClassifyEos for Eos {
                 .and_then(grpc_class)
                 .unwrap_or_else(|| Class::Grpc(SuccessOrFailure::Success, 0)),
             Eos::Profile(class) => class,
+            Eos::ProfileEos(ProfileEos {
+                classes,
+                status,
+                headers,
+            }) => Response::match_class(classes.as_ref(), |c| {
+                c.is_match_eos(status, &headers, trailers)
+            })
+            .or_else(|| grpc_class(&headers))
+            .unwrap_or_else(|| classify::ClassifyEos::eos(Eos::Default(status), trailers)),
             Eos::Error(msg) => Class::Stream(SuccessOrFailure::Failure, msg.into()),
         }
     }
@@ -225,12 +260,24 @@ impl Class {
     }
 }
 
+impl linkerd2_concurrency_limit::adaptive::IsFailure for Class {
+    fn is_failure(&self) -> bool {
+        Class::is_failure(self)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Class, SuccessOrFailure};
+    use crate::profiles;
     use http::{HeaderMap, Response, StatusCode};
     use linkerd2_http_classify::{ClassifyEos, ClassifyResponse};
 
+    fn profile(classes: Vec<profiles::ResponseClass>) -> super::Response {
+        let route = profiles::Route::new(std::iter::empty(), classes);
+        super::Response::Profile(route.response_classes().clone())
+    }
+
     #[test]
     fn http_response_status_ok() {
         let rsp = Response::builder().status(StatusCode::OK).body(()).unwrap();
@@ -319,4 +366,74 @@ mod tests {
             .eos(Some(&trailers));
         assert_eq!(class, Class::Grpc(SuccessOrFailure::Failure, 4));
     }
+
+    #[test]
+    fn profile_grpc_status_trailer_failure() {
+        let classes = vec![profiles::ResponseClass::new(
+            true,
+            profiles::ResponseMatch::GrpcStatus(14),
+        )];
+        let rsp = Response::builder().status(StatusCode::OK).body(()).unwrap();
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", 14.into());
+
+        let class = profile(classes).start(&rsp).eos(Some(&trailers));
+        assert_eq!(class, Class::Default(SuccessOrFailure::Failure));
+    }
+
+    #[test]
+    fn profile_grpc_status_header_failure() {
+        let classes = vec![profiles::ResponseClass::new(
+            true,
+            profiles::ResponseMatch::GrpcStatus(14),
+        )];
+        let rsp = Response::builder()
+            .header("grpc-status", "14")
+            .status(StatusCode::OK)
+            .body(())
+            .unwrap();
+
+        let class = profile(classes).start(&rsp).eos(None);
+        assert_eq!(class, Class::Default(SuccessOrFailure::Failure));
+    }
+
+    #[test]
+    fn profile_trailer_unmatched_falls_back() {
+        let classes = vec![profiles::ResponseClass::new(
+            true,
+            profiles::ResponseMatch::Trailer {
+                name: http::header::HeaderName::from_static("x-error"),
+                value: profiles::ValueMatch::Present,
+            },
+        )];
+        let rsp = Response::builder().status(StatusCode::OK).body(()).unwrap();
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", 0.into());
+
+        let class = profile(classes.clone()).start(&rsp).eos(Some(&trailers));
+        assert_eq!(class, Class::Grpc(SuccessOrFailure::Success, 0));
+
+        trailers.insert("x-error", "oops".parse().unwrap());
+        let class = profile(classes).start(&rsp).eos(Some(&trailers));
+        assert_eq!(class, Class::Default(SuccessOrFailure::Failure));
+    }
+
+    #[test]
+    fn profile_header_failure() {
+        let classes = vec![profiles::ResponseClass::new(
+            true,
+            profiles::ResponseMatch::Header {
+                name: http::header::HeaderName::from_static("x-error"),
+                value: profiles::ValueMatch::Exact("true".into()),
+            },
+        )];
+        let rsp = Response::builder()
+            .header("x-error", "true")
+            .status(StatusCode::OK)
+            .body(())
+            .unwrap();
+
+        let class = profile(classes).start(&rsp).eos(None);
+        assert_eq!(class, Class::Default(SuccessOrFailure::Failure));
+    }
 }
@@ -1,6 +1,15 @@
+use crate::authz::Unauthorized;
+use crate::ext_authz::Denied as ExtAuthzDenied;
+use crate::fault::Aborted as FaultAborted;
+use crate::global_rate_limit::OverLimit;
+use crate::grpc_check::Unavailable as CheckUnavailable;
+use crate::jwt::InvalidToken;
 use crate::proxy::identity;
+use crate::rate_limit::RateLimited;
+use bytes::Bytes;
 use futures::{Async, Poll};
 use http::{header::HeaderValue, StatusCode};
+use linkerd2_concurrency_limit::adaptive::Rejected as ConcurrencyLimitExceeded;
 use linkerd2_errno::Errno;
 use linkerd2_error::Error;
 use linkerd2_error_metrics as metrics;
@@ -31,8 +40,15 @@ pub enum Reason {
     DispatchTimeout,
     ResponseTimeout,
     IdentityRequired,
+    Unauthorized,
     Io(Option<Errno>),
     FailFast,
+    ConcurrencyLimit,
+    RateLimited,
+    CheckUnavailable,
+    ExtAuthzDenied,
+    Unauthenticated,
+    FaultInjected,
     Unexpected,
 }
 
@@ -126,7 +142,10 @@ impl<ReqB, RspB: Default + hyper::body::Payload>
     }
 }
 
-impl<RspB: Default + hyper::body::Payload> respond::Respond<http::Response<RspB>> for Respond {
+impl<RspB> respond::Respond<http::Response<RspB>> for Respond
+where
+    RspB: Default + From<Bytes> + hyper::body::Payload,
+{
     type Response = http::Response<ResponseBody<RspB>>;
 
     fn respond(
@@ -167,14 +186,38 @@ impl<RspB: Default + hyper::body::Payload> respond::Respond<http::Response<RspB>
                     Respond::Http2 { .. } => http::Version::HTTP_2,
                 };
 
+                // The authorization service describes the response returned
+                // for denied requests.
+                if let Some(denied) = find_error::<ExtAuthzDenied>(&*error) {
+                    debug!(status = %denied.status(), ?version, "Handling denied request");
+                    let body = Bytes::from(denied.body());
+                    let mut rsp = http::Response::builder()
+                        .version(version)
+                        .status(denied.status())
+                        .header(http::header::CONTENT_LENGTH, HeaderValue::from(body.len()))
+                        .body(ResponseBody::NonGrpc(RspB::from(body)))
+                        .expect("denied response must be valid");
+                    for (name, value) in denied.headers().iter() {
+                        rsp.headers_mut().insert(name, value.clone());
+                    }
+                    return Ok(rsp);
+                }
+
                 let status = http_status(&*error);
                 debug!(%status, ?version, "Handling error with HTTP response");
-                Ok(http::Response::builder()
+                let mut rsp = http::Response::builder()
                     .version(version)
                     .status(status)
                     .header(http::header::CONTENT_LENGTH, "0")
                     .body(ResponseBody::default())
-                    .expect("error response must be valid"))
+                    .expect("error response must be valid");
+                if find_error::<InvalidToken>(&*error).is_some() {
+                    rsp.headers_mut().insert(
+                        http::header::WWW_AUTHENTICATE,
+                        HeaderValue::from_static("Bearer error=\"invalid_token\""),
+                    );
+                }
+                Ok(rsp)
             }
         }
     }
@@ -185,10 +228,24 @@ fn http_status(error: &(dyn std::error::Error + 'static)) -> StatusCode {
         http::StatusCode::GATEWAY_TIMEOUT
     } else if error.is::<FailFastError>() {
         http::StatusCode::SERVICE_UNAVAILABLE
+    } else if error.is::<ConcurrencyLimitExceeded>() {
+        http::StatusCode::SERVICE_UNAVAILABLE
     } else if error.is::<tower::timeout::error::Elapsed>() {
         http::StatusCode::SERVICE_UNAVAILABLE
     } else if error.is::<IdentityRequired>() {
         http::StatusCode::FORBIDDEN
+    } else if error.is::<Unauthorized>() {
+        http::StatusCode::FORBIDDEN
+    } else if error.is::<InvalidToken>() {
+        http::StatusCode::UNAUTHORIZED
+    } else if error.is::<RateLimited>() || error.is::<OverLimit>() {
+        http::StatusCode::TOO_MANY_REQUESTS
+    } else if error.is::<CheckUnavailable>() {
+        http::StatusCode::SERVICE_UNAVAILABLE
+    } else if let Some(denied) = error.downcast_ref::<ExtAuthzDenied>() {
+        denied.status()
+    } else if let Some(aborted) = error.downcast_ref::<FaultAborted>() {
+        aborted.http_status()
     } else if let Some(source) = error.source() {
         http_status(source)
     } else {
@@ -216,6 +273,14 @@ fn set_grpc_status(
             HeaderValue::from_static("proxy max-concurrency exhausted"),
         );
         code
+    } else if error.is::<ConcurrencyLimitExceeded>() {
+        let code = Code::Unavailable;
+        headers.insert(GRPC_STATUS, code_header(code));
+        headers.insert(
+            GRPC_MESSAGE,
+            HeaderValue::from_static("proxy concurrency limit exceeded"),
+        );
+        code
     } else if error.is::<tower::timeout::error::Elapsed>() {
         let code = Code::Unavailable;
         headers.insert(GRPC_STATUS, code_header(code));
@@ -231,6 +296,55 @@ fn set_grpc_status(
             headers.insert(GRPC_MESSAGE, msg);
         }
         code
+    } else if error.is::<Unauthorized>() {
+        let code = Code::PermissionDenied;
+        headers.insert(GRPC_STATUS, code_header(code));
+        headers.insert(
+            GRPC_MESSAGE,
+            HeaderValue::from_static("client is not authorized"),
+        );
+        code
+    } else if error.is::<InvalidToken>() {
+        let code = Code::Unauthenticated;
+        headers.insert(GRPC_STATUS, code_header(code));
+        if let Ok(msg) = HeaderValue::from_str(&error.to_string()) {
+            headers.insert(GRPC_MESSAGE, msg);
+        }
+        code
+    } else if error.is::<RateLimited>() || error.is::<OverLimit>() {
+        let code = Code::ResourceExhausted;
+        headers.insert(GRPC_STATUS, code_header(code));
+        headers.insert(
+            GRPC_MESSAGE,
+            HeaderValue::from_static("rate limit exceeded"),
+        );
+        code
+    } else if let Some(unavailable) = error.downcast_ref::<CheckUnavailable>() {
+        let code = Code::Unavailable;
+        headers.insert(GRPC_STATUS, code_header(code));
+        let msg = format!("{} unavailable", unavailable.name());
+        if let Ok(msg) = HeaderValue::from_str(&msg) {
+            headers.insert(GRPC_MESSAGE, msg);
+        }
+        code
+    } else if let Some(denied) = error.downcast_ref::<ExtAuthzDenied>() {
+        let code = match denied.status() {
+            http::StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+            http::StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+            _ => Code::PermissionDenied,
+        };
+        headers.insert(GRPC_STATUS, code_header(code));
+        let msg = HeaderValue::from_str(denied.body())
+            .ok()
+            .filter(|msg| !msg.is_empty())
+            .unwrap_or_else(|| HeaderValue::from_static("request denied"));
+        headers.insert(GRPC_MESSAGE, msg);
+        code
+    } else if let Some(aborted) = error.downcast_ref::<FaultAborted>() {
+        let code = aborted.grpc_code();
+        headers.insert(GRPC_STATUS, code_header(code));
+        headers.insert(GRPC_MESSAGE, HeaderValue::from_static("fault injected"));
+        code
     } else if error.is::<std::io::Error>() {
         let code = Code::Unavailable;
         headers.insert(GRPC_STATUS, code_header(code));
@@ -272,6 +386,15 @@ fn code_header(code: grpc::Code) -> HeaderValue {
     }
 }
 
+/// Finds an error of type `E` in the error's chain of sources.
+pub(crate) fn find_error<E: std::error::Error + 'static>(
+    error: &(dyn std::error::Error + 'static),
+) -> Option<&E> {
+    error
+        .downcast_ref::<E>()
+        .or_else(|| error.source().and_then(find_error::<E>))
+}
+
 #[derive(Debug)]
 pub struct IdentityRequired {
     pub required: identity::Name,
@@ -303,10 +426,24 @@ impl LabelError {
             Reason::ResponseTimeout
         } else if err.is::<FailFastError>() {
             Reason::FailFast
+        } else if err.is::<ConcurrencyLimitExceeded>() {
+            Reason::ConcurrencyLimit
         } else if err.is::<tower::timeout::error::Elapsed>() {
             Reason::DispatchTimeout
         } else if err.is::<IdentityRequired>() {
             Reason::IdentityRequired
+        } else if err.is::<Unauthorized>() {
+            Reason::Unauthorized
+        } else if err.is::<InvalidToken>() {
+            Reason::Unauthenticated
+        } else if err.is::<RateLimited>() || err.is::<OverLimit>() {
+            Reason::RateLimited
+        } else if err.is::<CheckUnavailable>() {
+            Reason::CheckUnavailable
+        } else if err.is::<ExtAuthzDenied>() {
+            Reason::ExtAuthzDenied
+        } else if err.is::<FaultAborted>() {
+            Reason::FaultInjected
         } else if let Some(e) = err.downcast_ref::<std::io::Error>() {
             Reason::Io(e.raw_os_error().map(Errno::from))
         } else if let Some(e) = err.source() {
@@ -332,9 +469,16 @@ impl metrics::FmtLabels for Reason {
             "message=\"{}\"",
             match self {
                 Reason::FailFast => "failfast",
+                Reason::ConcurrencyLimit => "concurrency limit",
+                Reason::RateLimited => "rate limited",
+                Reason::CheckUnavailable => "check unavailable",
+                Reason::ExtAuthzDenied => "ext authz denied",
                 Reason::DispatchTimeout => "dispatch timeout",
                 Reason::ResponseTimeout => "response timeout",
                 Reason::IdentityRequired => "identity required",
+                Reason::Unauthorized => "unauthorized",
+                Reason::Unauthenticated => "unauthenticated",
+                Reason::FaultInjected => "fault injected",
                 Reason::Io(_) => "i/o",
                 Reason::Unexpected => "unexpected",
             }
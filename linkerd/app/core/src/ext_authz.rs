@@ -0,0 +1,6 @@
+//! Checks inbound requests with an external authorization service.
+
+pub use linkerd2_ext_authz::{Client, ClientIdentity, Denied, Filter, CHECK};
+
+/// Forwards requests without checking them when no filter is configured.
+pub type Layer = crate::request_filter::AsyncLayer<Option<Filter>>;
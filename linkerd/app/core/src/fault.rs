@@ -0,0 +1,369 @@
+//! Injects faults into requests on routes that configure them.
+//!
+//! Faults are selected per-request by the route's `MakeFault` proxy: a
+//! delayed request is held before it is dispatched, and an aborted request is
+//! marked with an `Abort` extension. Marked requests are failed with an
+//! `Aborted` error by the endpoint stack's `AbortLayer`, i.e. once an endpoint
+//! has been selected, so that injected aborts are observable by tap. Outlier
+//! detection ignores `Aborted` errors, so injected aborts do not eject
+//! endpoints.
+//!
+//! Requests into which faults are injected carry an `l5d-proxy-fault` header
+//! naming each fault, and are counted by `route_fault_injected_total`.
+
+use super::dst::Route;
+use super::http_metrics::retries::Handle;
+use super::{HttpRouteRetry, L5D_PROXY_FAULT};
+use crate::profiles::{self, AbortStatus, DelayDuration};
+use crate::Error;
+use futures::{future, try_ready, Future, Poll};
+use http::header::HeaderValue;
+use linkerd2_stack::{NewService, Proxy, ProxyService};
+use rand::Rng;
+use std::time::Duration;
+use tokio_timer::{clock, Delay};
+use tower::util::{Oneshot, ServiceExt};
+use tower_grpc as grpc;
+use tracing::debug;
+
+pub fn layer(metrics: HttpRouteRetry) -> Layer {
+    Layer { metrics }
+}
+
+pub fn abort_layer() -> AbortLayer {
+    AbortLayer(())
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer {
+    metrics: HttpRouteRetry,
+}
+
+#[derive(Clone, Debug)]
+pub struct MakeFault<M> {
+    inner: M,
+    metrics: HttpRouteRetry,
+}
+
+#[derive(Clone, Debug)]
+pub struct Fault<P> {
+    inner: P,
+    fault: Option<(profiles::Fault, Handle)>,
+}
+
+pub enum ResponseFuture<P, S, Req>
+where
+    P: Proxy<Req, S>,
+    S: tower::Service<P::Request>,
+{
+    Disabled(P::Future),
+    Delayed {
+        delay: Option<Delay>,
+        inner: Oneshot<ProxyService<P, S>, Req>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct AbortLayer(());
+
+#[derive(Clone, Debug)]
+pub struct AbortService<S> {
+    inner: S,
+}
+
+/// Marks a request that is to be aborted by the endpoint stack.
+#[derive(Copy, Clone, Debug)]
+pub struct Abort(AbortStatus);
+
+/// Indicates that a request was aborted by an injected fault.
+#[derive(Debug)]
+pub struct Aborted(AbortStatus);
+
+/// The faults selected for a single request.
+#[derive(Debug, Default, PartialEq)]
+struct Selected {
+    delay: Option<Duration>,
+    abort: Option<AbortStatus>,
+}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = MakeFault<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeFault {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+// === impl MakeFault ===
+
+impl<M> NewService<Route> for MakeFault<M>
+where
+    M: NewService<Route>,
+{
+    type Service = Fault<M::Service>;
+
+    fn new_service(&self, route: Route) -> Self::Service {
+        let fault = route
+            .route
+            .fault()
+            .map(|fault| (*fault, self.metrics.get_handle(route.clone())));
+        Fault {
+            inner: self.inner.new_service(route),
+            fault,
+        }
+    }
+}
+
+// === impl Fault ===
+
+impl<P, S, B> Proxy<http::Request<B>, S> for Fault<P>
+where
+    P: Proxy<http::Request<B>, S> + Clone,
+    S: tower::Service<P::Request> + Clone,
+    S::Error: Into<Error>,
+{
+    type Request = P::Request;
+    type Response = P::Response;
+    type Error = Error;
+    type Future = ResponseFuture<P, S, http::Request<B>>;
+
+    fn proxy(&self, svc: &mut S, mut req: http::Request<B>) -> Self::Future {
+        let (fault, metrics) = match self.fault {
+            Some((ref fault, ref metrics)) => (fault, metrics),
+            None => return ResponseFuture::Disabled(self.inner.proxy(svc, req)),
+        };
+
+        // Only the proxy may mark a request as faulted.
+        req.headers_mut().remove(L5D_PROXY_FAULT);
+
+        let selected = Selected::new(fault, &mut rand::thread_rng());
+        if let Some(status) = selected.abort {
+            debug!(?status, "Injecting abort");
+            metrics.incr_fault_abort();
+            req.headers_mut()
+                .append(L5D_PROXY_FAULT, HeaderValue::from_static("abort"));
+            req.extensions_mut().insert(Abort(status));
+        }
+
+        match selected.delay {
+            None => ResponseFuture::Disabled(self.inner.proxy(svc, req)),
+            Some(delay) => {
+                debug!(?delay, "Injecting delay");
+                metrics.incr_fault_delay();
+                req.headers_mut()
+                    .append(L5D_PROXY_FAULT, HeaderValue::from_static("delay"));
+                let inner = self.inner.clone().wrap_service(svc.clone());
+                ResponseFuture::Delayed {
+                    delay: Some(Delay::new(clock::now() + delay)),
+                    inner: inner.oneshot(req),
+                }
+            }
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<P, S, Req> Future for ResponseFuture<P, S, Req>
+where
+    P: Proxy<Req, S>,
+    S: tower::Service<P::Request>,
+    S::Error: Into<Error>,
+{
+    type Item = P::Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self {
+            ResponseFuture::Disabled(ref mut f) => f.poll().map_err(Into::into),
+            ResponseFuture::Delayed {
+                ref mut delay,
+                ref mut inner,
+            } => {
+                if let Some(d) = delay.as_mut() {
+                    try_ready!(d.poll());
+                    *delay = None;
+                }
+                inner.poll()
+            }
+        }
+    }
+}
+
+// === impl Selected ===
+
+impl Selected {
+    fn new<R: Rng>(fault: &profiles::Fault, rng: &mut R) -> Self {
+        let delay = fault
+            .delay
+            .filter(|d| rng.gen::<f64>() * 100.0 < d.percentage)
+            .map(|d| match d.duration {
+                DelayDuration::Fixed(duration) => duration,
+                DelayDuration::Random { min, max } if min < max => rng.gen_range(min, max),
+                DelayDuration::Random { min, .. } => min,
+            });
+        let abort = fault
+            .abort
+            .filter(|a| rng.gen::<f64>() * 100.0 < a.percentage)
+            .map(|a| a.status);
+        Self { delay, abort }
+    }
+}
+
+// === impl AbortLayer ===
+
+impl<S> tower::layer::Layer<S> for AbortLayer {
+    type Service = AbortService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AbortService { inner }
+    }
+}
+
+// === impl AbortService ===
+
+impl<S, B> tower::Service<http::Request<B>> for AbortService<S>
+where
+    S: tower::Service<http::Request<B>>,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = future::Either<
+        future::MapErr<S::Future, fn(S::Error) -> Error>,
+        future::FutureResult<S::Response, Error>,
+    >;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        if let Some(Abort(status)) = req.extensions().get::<Abort>() {
+            return future::Either::B(future::err(Aborted(*status).into()));
+        }
+
+        future::Either::A(self.inner.call(req).map_err(Into::into))
+    }
+}
+
+// === impl Aborted ===
+
+impl Aborted {
+    /// The status of responses to HTTP requests.
+    ///
+    /// Requests aborted with a gRPC code fail with a `500 Internal Server
+    /// Error`.
+    pub fn http_status(&self) -> http::StatusCode {
+        match self.0 {
+            AbortStatus::Http(status) => status,
+            AbortStatus::Grpc(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The status of responses to gRPC requests.
+    ///
+    /// Requests aborted with an HTTP status fail with the gRPC code
+    /// corresponding to the status, as described by gRPC's
+    /// `http-grpc-status-mapping.md`.
+    pub fn grpc_code(&self) -> grpc::Code {
+        match self.0 {
+            AbortStatus::Grpc(code) => grpc::Code::from_i32(code as i32),
+            AbortStatus::Http(status) => match status.as_u16() {
+                400 => grpc::Code::Internal,
+                401 => grpc::Code::Unauthenticated,
+                403 => grpc::Code::PermissionDenied,
+                404 => grpc::Code::Unimplemented,
+                429 | 502 | 503 | 504 => grpc::Code::Unavailable,
+                _ => grpc::Code::Unknown,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Aborted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "request aborted by injected fault")
+    }
+}
+
+impl std::error::Error for Aborted {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use profiles::{FaultAbort, FaultDelay};
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    fn fault(delay: f64, duration: DelayDuration, abort: f64) -> profiles::Fault {
+        profiles::Fault {
+            delay: Some(FaultDelay {
+                percentage: delay,
+                duration,
+            }),
+            abort: Some(FaultAbort {
+                percentage: abort,
+                status: AbortStatus::Http(http::StatusCode::SERVICE_UNAVAILABLE),
+            }),
+        }
+    }
+
+    #[test]
+    fn selects_by_percentage() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let fixed = DelayDuration::Fixed(Duration::from_millis(100));
+
+        let never = fault(0.0, fixed, 0.0);
+        let always = fault(100.0, fixed, 100.0);
+        for _ in 0..100 {
+            assert_eq!(Selected::new(&never, &mut rng), Selected::default());
+            assert_eq!(
+                Selected::new(&always, &mut rng),
+                Selected {
+                    delay: Some(Duration::from_millis(100)),
+                    abort: Some(AbortStatus::Http(http::StatusCode::SERVICE_UNAVAILABLE)),
+                }
+            );
+        }
+
+        let half = fault(50.0, fixed, 0.0);
+        let delayed = (0..1_000)
+            .filter(|_| Selected::new(&half, &mut rng).delay.is_some())
+            .count();
+        assert!(delayed > 400 && delayed < 600, "delayed={}", delayed);
+    }
+
+    #[test]
+    fn selects_random_delays() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let min = Duration::from_millis(10);
+        let max = Duration::from_millis(20);
+        let random = fault(100.0, DelayDuration::Random { min, max }, 0.0);
+        for _ in 0..100 {
+            let delay = Selected::new(&random, &mut rng).delay.expect("must delay");
+            assert!(min <= delay && delay < max, "delay={:?}", delay);
+        }
+    }
+
+    #[test]
+    fn maps_statuses() {
+        let unavailable = Aborted(AbortStatus::Http(http::StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(
+            unavailable.http_status(),
+            http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(unavailable.grpc_code(), grpc::Code::Unavailable);
+
+        let exhausted = Aborted(AbortStatus::Grpc(8));
+        assert_eq!(
+            exhausted.http_status(),
+            http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(exhausted.grpc_code(), grpc::Code::ResourceExhausted);
+    }
+}
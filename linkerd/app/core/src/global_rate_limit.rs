@@ -0,0 +1,131 @@
+//! Describes inbound requests to an external rate limit service.
+//!
+//! Each configured descriptor is a list of entries whose values are taken from
+//! the request. A descriptor is only sent to the rate limit service when all of
+//! its entries have a value for the request.
+
+use super::dst::Route;
+use crate::transport::tls;
+pub use linkerd2_ratelimit::{Client, OverLimit, SHOULD_RATE_LIMIT};
+use linkerd2_ratelimit::{Descriptor, DescriptorEntry, ExtractDescriptors};
+
+pub type Layer = linkerd2_ratelimit::Layer<Descriptors>;
+
+/// The descriptors sent to the rate limit service for each request.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Descriptors(Vec<Vec<Entry>>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    pub source: Source,
+}
+
+/// Where the value of a descriptor entry is taken from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The value of a label on the request's service profile route.
+    RouteLabel(String),
+    /// The value of a request header.
+    Header(http::header::HeaderName),
+    /// The client's TLS identity.
+    ClientId,
+    /// A fixed value.
+    Value(String),
+}
+
+// === impl Descriptors ===
+
+impl Descriptors {
+    pub fn new(descriptors: Vec<Vec<Entry>>) -> Self {
+        Descriptors(descriptors)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<B> ExtractDescriptors<http::Request<B>> for Descriptors {
+    fn descriptors(&self, req: &http::Request<B>) -> Vec<Descriptor> {
+        self.0
+            .iter()
+            .filter_map(|entries| {
+                let entries = entries
+                    .iter()
+                    .map(|e| {
+                        e.source.value(req).map(|value| DescriptorEntry {
+                            key: e.key.clone(),
+                            value,
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Some(Descriptor { entries })
+            })
+            .collect()
+    }
+}
+
+// === impl Source ===
+
+impl Source {
+    fn value<B>(&self, req: &http::Request<B>) -> Option<String> {
+        match self {
+            Source::RouteLabel(ref label) => req
+                .extensions()
+                .get::<Route>()
+                .and_then(|route| route.route.labels().get(label).cloned()),
+            Source::Header(ref name) => req
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from),
+            Source::ClientId => req
+                .extensions()
+                .get::<tls::accept::Meta>()
+                .and_then(|meta| meta.peer_identity.value().map(|id| id.as_ref().to_string())),
+            Source::Value(ref value) => Some(value.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_descriptors_with_missing_entries() {
+        let descriptors = Descriptors::new(vec![
+            vec![
+                Entry {
+                    key: "generic_key".into(),
+                    source: Source::Value("inbound".into()),
+                },
+                Entry {
+                    key: "tenant".into(),
+                    source: Source::Header(http::header::HeaderName::from_static("x-tenant")),
+                },
+            ],
+            vec![Entry {
+                key: "client".into(),
+                source: Source::ClientId,
+            }],
+        ]);
+
+        let req = http::Request::builder()
+            .header("x-tenant", "acme")
+            .body(())
+            .unwrap();
+        let sent = descriptors.descriptors(&req);
+        assert_eq!(sent.len(), 1);
+        let values = sent[0]
+            .entries
+            .iter()
+            .map(|e| (e.key.as_str(), e.value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![("generic_key", "inbound"), ("tenant", "acme")]);
+
+        let req = http::Request::builder().body(()).unwrap();
+        assert!(descriptors.descriptors(&req).is_empty());
+    }
+}
@@ -0,0 +1,255 @@
+//! Active health checking of load balancer endpoints.
+//!
+//! When a destination's profile configures a `HealthCheck`, a task is spawned
+//! for each of its endpoints that periodically probes the endpoint (over its
+//! own connection). Endpoints that fail `unhealthy_threshold` consecutive
+//! probes are removed from their balancer (i.e. they do not become ready)
+//! until they pass `healthy_threshold` consecutive probes.
+//!
+//! Probes are issued through a separate stack, so that they are not observed
+//! (e.g. by tap or endpoint metrics) as requests.
+
+use super::metric_labels::EndpointLabels;
+use crate::profiles::HealthCheck;
+use futures::task;
+use indexmap::IndexMap;
+use linkerd2_metrics::{metrics, FmtLabels, FmtMetrics, Gauge};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+mod probe;
+mod service;
+
+pub use self::service::{MakeFuture, MakeHealthCheck, Service};
+
+metrics! {
+    endpoint_healthy: Gauge {
+        "Whether an endpoint is passing its active health checks."
+    }
+}
+
+/// Describes how a target's endpoint should be health checked.
+pub trait HasHealthCheck {
+    fn health_check(&self) -> Option<&HealthCheck>;
+
+    /// The authority to set on probe requests.
+    fn probe_authority(&self) -> http::uri::Authority;
+}
+
+/// Wraps endpoint stacks to actively check the health of their endpoints,
+/// probing them with clients built by `P`.
+#[derive(Clone, Debug)]
+pub struct Layer<P> {
+    metrics: Metrics,
+    probe: P,
+}
+
+/// Reports endpoint health, labeled by endpoint.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<IndexMap<EndpointLabels, Arc<Gauge>>>>);
+
+/// An endpoint's health, shared by its service and its probe task.
+#[derive(Debug)]
+struct Endpoint {
+    labels: EndpointLabels,
+    healthy: Arc<Gauge>,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug)]
+struct Health {
+    healthy: bool,
+    consecutive_successes: u32,
+    consecutive_failures: u32,
+    /// Tasks polling the endpoint's service while it is unhealthy.
+    waiting: Vec<task::Task>,
+}
+
+// === impl Layer ===
+
+pub fn layer<P>(metrics: Metrics, probe: P) -> Layer<P> {
+    Layer { metrics, probe }
+}
+
+impl<M, P: Clone> tower::layer::Layer<M> for Layer<P> {
+    type Service = MakeHealthCheck<M, P>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeHealthCheck::new(self.metrics.clone(), self.probe.clone(), inner)
+    }
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    fn endpoint(&self, labels: EndpointLabels) -> Arc<Endpoint> {
+        let healthy = {
+            let mut registry = self.0.lock().expect("health metrics lock poisoned");
+            registry
+                .entry(labels.clone())
+                .or_insert_with(|| Arc::new(Gauge::default()))
+                .clone()
+        };
+        // Endpoints are considered healthy until they fail their probes.
+        healthy.incr();
+
+        Arc::new(Endpoint {
+            labels,
+            healthy,
+            health: Mutex::new(Health {
+                healthy: true,
+                consecutive_successes: 0,
+                consecutive_failures: 0,
+                waiting: Vec::new(),
+            }),
+        })
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut registry = match self.0.lock() {
+            Ok(registry) => registry,
+            Err(_) => return Ok(()),
+        };
+        if registry.is_empty() {
+            return Ok(());
+        }
+
+        endpoint_healthy.fmt_help(f)?;
+        endpoint_healthy.fmt_scopes(f, registry.iter(), |g| &**g)?;
+
+        // Endpoints are only reported while they are in a balancer.
+        registry.retain(|_, g| Arc::strong_count(g) > 1);
+
+        Ok(())
+    }
+}
+
+// === impl Endpoint ===
+
+impl Endpoint {
+    /// Returns true if the endpoint is healthy. Otherwise, the current task is
+    /// notified when the endpoint becomes healthy.
+    fn poll_healthy(&self) -> bool {
+        let mut health = self.health.lock().expect("health lock poisoned");
+        if health.healthy {
+            return true;
+        }
+
+        if !health.waiting.iter().any(|t| t.will_notify_current()) {
+            health.waiting.push(task::current());
+        }
+        false
+    }
+
+    /// Records the result of a probe, updating the endpoint's health once it
+    /// crosses the configured threshold.
+    fn record(&self, config: &HealthCheck, success: bool) {
+        let mut health = self.health.lock().expect("health lock poisoned");
+        if success {
+            health.consecutive_successes += 1;
+            health.consecutive_failures = 0;
+            if !health.healthy && health.consecutive_successes >= config.healthy_threshold {
+                health.healthy = true;
+                self.healthy.incr();
+                for task in health.waiting.drain(..) {
+                    task.notify();
+                }
+                info!(labels = %Labels(&self.labels), "Endpoint is healthy");
+            }
+        } else {
+            health.consecutive_failures += 1;
+            health.consecutive_successes = 0;
+            if health.healthy && health.consecutive_failures >= config.unhealthy_threshold {
+                health.healthy = false;
+                self.healthy.decr();
+                info!(labels = %Labels(&self.labels), "Endpoint is unhealthy");
+            }
+        }
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        let healthy = self.health.lock().map(|h| h.healthy).unwrap_or(false);
+        if healthy {
+            self.healthy.decr();
+        }
+    }
+}
+
+/// Formats endpoint labels for logging.
+struct Labels<'a>(&'a EndpointLabels);
+
+impl<'a> fmt::Display for Labels<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt_labels(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metric_labels::Direction;
+    use crate::profiles::HealthProbe;
+    use crate::transport::tls;
+    use futures::{future, Future};
+    use linkerd2_conditional::Conditional;
+    use std::time::Duration;
+
+    fn config() -> HealthCheck {
+        HealthCheck {
+            probe: HealthProbe::Http {
+                path: "/ready".into(),
+            },
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(1),
+            unhealthy_threshold: 2,
+            healthy_threshold: 2,
+        }
+    }
+
+    fn endpoint(metrics: &Metrics) -> Arc<Endpoint> {
+        metrics.endpoint(EndpointLabels {
+            direction: Direction::Out,
+            tls_id: Conditional::None(tls::ReasonForNoIdentity::Disabled),
+            authority: None,
+            labels: None,
+        })
+    }
+
+    fn poll_healthy(ep: &Endpoint) -> bool {
+        future::lazy(|| Ok::<_, ()>(ep.poll_healthy()))
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn health_changes_at_thresholds() {
+        let config = config();
+        let metrics = Metrics::default();
+        let ep = endpoint(&metrics);
+        assert!(poll_healthy(&ep));
+        assert_eq!(ep.healthy.value(), 1);
+
+        ep.record(&config, false);
+        assert!(poll_healthy(&ep));
+        ep.record(&config, false);
+        assert!(!poll_healthy(&ep));
+        assert_eq!(ep.healthy.value(), 0);
+
+        ep.record(&config, true);
+        ep.record(&config, false);
+        ep.record(&config, true);
+        assert!(!poll_healthy(&ep));
+        ep.record(&config, true);
+        assert!(poll_healthy(&ep));
+        assert_eq!(ep.healthy.value(), 1);
+
+        drop(ep);
+        let registry = metrics.0.lock().unwrap();
+        assert_eq!(registry.values().next().unwrap().value(), 0);
+    }
+}
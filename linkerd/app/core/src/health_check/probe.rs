@@ -0,0 +1,269 @@
+use super::{Endpoint, HasHealthCheck};
+use crate::profiles::{HealthCheck, HealthProbe};
+use crate::proxy::http::boxed;
+use crate::Error;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{future, try_ready, Async, Future, Poll};
+use hyper::body::Payload;
+use std::sync::Weak;
+use tokio_timer::{clock, Delay, Timeout};
+use tower::ServiceExt;
+use tracing::{debug, trace, warn};
+
+const GRPC_HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+/// A task that periodically probes an endpoint, recording the results on the
+/// endpoint's health, until the endpoint's service is dropped.
+///
+/// Each probe is issued through a new service, built by the probe stack, so
+/// that probes do not contend with (or reuse) the connection used to serve
+/// requests.
+pub struct Probe<M, T> {
+    config: HealthCheck,
+    endpoint: Weak<Endpoint>,
+    make: M,
+    target: T,
+    state: State,
+}
+
+enum State {
+    Idle(Delay),
+    Probing(Timeout<Box<dyn Future<Item = bool, Error = Error> + Send + 'static>>),
+}
+
+/// Determines whether a `grpc.health.v1.Health/Check` response indicates that
+/// the service is `SERVING`.
+struct GrpcServing<B> {
+    status: Option<http::HeaderValue>,
+    body: B,
+    message: BytesMut,
+    data_done: bool,
+}
+
+// === impl Probe ===
+
+impl<M, T> Probe<M, T> {
+    pub(super) fn new(config: HealthCheck, endpoint: Weak<Endpoint>, make: M, target: T) -> Self {
+        Self {
+            config,
+            endpoint,
+            make,
+            target,
+            // The first probe is issued immediately.
+            state: State::Idle(Delay::new(clock::now())),
+        }
+    }
+}
+
+impl<M, T, S, B> Probe<M, T>
+where
+    T: HasHealthCheck + Clone + Send + 'static,
+    M: tower::Service<T, Response = S> + Clone + Send + 'static,
+    M::Error: Into<Error>,
+    M::Future: Send,
+    S: tower::Service<http::Request<boxed::Payload>, Response = http::Response<B>> + Send + 'static,
+    S::Error: Into<Error>,
+    S::Future: Send,
+    B: Payload + Send + 'static,
+{
+    fn probe(&self) -> Box<dyn Future<Item = bool, Error = Error> + Send + 'static> {
+        let make = self
+            .make
+            .clone()
+            .oneshot(self.target.clone())
+            .map_err(Into::into);
+        let req = future::result(request(&self.config.probe, self.target.probe_authority()));
+        let is_grpc = match self.config.probe {
+            HealthProbe::Grpc { .. } => true,
+            HealthProbe::Http { .. } => false,
+        };
+
+        let probe = make
+            .join(req)
+            .and_then(|(svc, req)| svc.oneshot(req).map_err(Into::into))
+            .and_then(move |rsp| {
+                trace!(status = %rsp.status(), "Probe response");
+                if !is_grpc {
+                    return future::Either::A(future::ok(rsp.status().is_success()));
+                }
+
+                let (head, body) = rsp.into_parts();
+                future::Either::B(GrpcServing {
+                    status: head.headers.get("grpc-status").cloned(),
+                    body,
+                    message: BytesMut::new(),
+                    data_done: false,
+                })
+            });
+        Box::new(probe)
+    }
+}
+
+impl<M, T, S, B> Future for Probe<M, T>
+where
+    T: HasHealthCheck + Clone + Send + 'static,
+    M: tower::Service<T, Response = S> + Clone + Send + 'static,
+    M::Error: Into<Error>,
+    M::Future: Send,
+    S: tower::Service<http::Request<boxed::Payload>, Response = http::Response<B>> + Send + 'static,
+    S::Error: Into<Error>,
+    S::Future: Send,
+    B: Payload + Send + 'static,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            let endpoint = match self.endpoint.upgrade() {
+                Some(endpoint) => endpoint,
+                None => {
+                    debug!("Endpoint dropped; stopping health checks");
+                    return Ok(Async::Ready(()));
+                }
+            };
+
+            self.state = match self.state {
+                State::Idle(ref mut delay) => {
+                    try_ready!(delay
+                        .poll()
+                        .map_err(|error| warn!(%error, "Health check timer failed")));
+                    State::Probing(Timeout::new(self.probe(), self.config.timeout))
+                }
+                State::Probing(ref mut probe) => {
+                    let success = match probe.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(success)) => success,
+                        Err(error) => {
+                            debug!(?error, "Probe failed");
+                            false
+                        }
+                    };
+                    endpoint.record(&self.config, success);
+                    State::Idle(Delay::new(clock::now() + self.config.interval))
+                }
+            };
+        }
+    }
+}
+
+// === impl GrpcServing ===
+
+impl<B> Future for GrpcServing<B>
+where
+    B: Payload,
+    B::Error: Into<Error>,
+{
+    type Item = bool;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<bool, Error> {
+        while !self.data_done {
+            match try_ready!(self.body.poll_data().map_err(Into::into)) {
+                Some(mut data) => {
+                    while data.has_remaining() {
+                        let n = {
+                            let bytes = data.bytes();
+                            self.message.extend_from_slice(bytes);
+                            bytes.len()
+                        };
+                        data.advance(n);
+                    }
+                }
+                None => self.data_done = true,
+            }
+        }
+
+        let trailers = try_ready!(self.body.poll_trailers().map_err(Into::into));
+        // Trailers-only responses carry their status in the headers.
+        let status = trailers
+            .as_ref()
+            .and_then(|t| t.get("grpc-status"))
+            .or_else(|| self.status.as_ref());
+        let ok = status.map_or(false, |s| s == "0");
+
+        Ok(Async::Ready(ok && is_serving(&self.message)))
+    }
+}
+
+fn request(
+    probe: &HealthProbe,
+    authority: http::uri::Authority,
+) -> Result<http::Request<boxed::Payload>, Error> {
+    let req = match probe {
+        HealthProbe::Http { ref path } => {
+            let uri = format!("http://{}{}", authority, path);
+            http::Request::get(uri.as_str()).body(boxed::Payload::default())?
+        }
+        HealthProbe::Grpc { ref service } => {
+            let uri = format!("http://{}{}", authority, GRPC_HEALTH_CHECK_PATH);
+            http::Request::post(uri.as_str())
+                .version(http::Version::HTTP_2)
+                .header(http::header::CONTENT_TYPE, "application/grpc")
+                .header(http::header::TE, "trailers")
+                .body(boxed::Payload::new(hyper::Body::from(grpc_request(
+                    service,
+                ))))?
+        }
+    };
+    Ok(req)
+}
+
+/// Encodes a length-prefixed `HealthCheckRequest { service }` message.
+fn grpc_request(service: &str) -> Bytes {
+    let mut message = BytesMut::with_capacity(service.len() + 6);
+    if !service.is_empty() {
+        // Field 1, length-delimited.
+        message.put_u8(0x0a);
+        let mut len = service.len();
+        while len >= 0x80 {
+            message.put_u8((len as u8 & 0x7f) | 0x80);
+            len >>= 7;
+        }
+        message.put_u8(len as u8);
+        message.put_slice(service.as_bytes());
+    }
+
+    let mut frame = BytesMut::with_capacity(message.len() + 5);
+    // Uncompressed.
+    frame.put_u8(0);
+    frame.put_u32_be(message.len() as u32);
+    frame.put_slice(&message);
+    frame.freeze()
+}
+
+/// Returns true if a length-prefixed `HealthCheckResponse` has a `SERVING`
+/// status.
+fn is_serving(frame: &[u8]) -> bool {
+    // `HealthCheckResponse { status = 1 }`, where `SERVING` is 1, is the only
+    // encoding of a serving response.
+    frame.len() >= 5 && frame[0] == 0 && frame[5..] == [0x08, 0x01]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_grpc_request() {
+        assert_eq!(&grpc_request("")[..], &[0, 0, 0, 0, 0][..]);
+        assert_eq!(
+            &grpc_request("foo")[..],
+            &[0, 0, 0, 0, 5, 0x0a, 3, b'f', b'o', b'o'][..]
+        );
+
+        let long = "a".repeat(200);
+        let frame = grpc_request(&long);
+        assert_eq!(&frame[..8], &[0, 0, 0, 0, 203, 0x0a, 0xc8, 0x01][..]);
+    }
+
+    #[test]
+    fn detects_serving_response() {
+        assert!(is_serving(&[0, 0, 0, 0, 2, 0x08, 0x01]));
+        // NOT_SERVING
+        assert!(!is_serving(&[0, 0, 0, 0, 2, 0x08, 0x02]));
+        // UNKNOWN, i.e. an empty message.
+        assert!(!is_serving(&[0, 0, 0, 0, 0]));
+        assert!(!is_serving(&[]));
+    }
+}
@@ -0,0 +1,130 @@
+use super::probe::Probe;
+use super::{Endpoint, HasHealthCheck, Metrics};
+use crate::metric_labels::EndpointLabels;
+use crate::proxy::http::boxed;
+use crate::Error;
+use futures::{try_ready, Async, Future, Poll};
+use hyper::body::Payload;
+use std::sync::Arc;
+use tracing_futures::Instrument;
+
+/// Wraps endpoint services so that they are removed from their balancer while
+/// they fail their health checks, which are probed with clients built by `P`.
+#[derive(Clone, Debug)]
+pub struct MakeHealthCheck<M, P> {
+    metrics: Metrics,
+    probe: P,
+    inner: M,
+}
+
+pub struct MakeFuture<F, P> {
+    endpoint: Option<(Arc<Endpoint>, P)>,
+    inner: F,
+}
+
+/// An endpoint service that is not ready while its endpoint is unhealthy.
+#[derive(Debug)]
+pub struct Service<S> {
+    endpoint: Option<Arc<Endpoint>>,
+    inner: S,
+}
+
+// === impl MakeHealthCheck ===
+
+impl<M, P> MakeHealthCheck<M, P> {
+    pub(super) fn new(metrics: Metrics, probe: P, inner: M) -> Self {
+        Self {
+            metrics,
+            probe,
+            inner,
+        }
+    }
+}
+
+impl<T, M, P, S, B> tower::Service<T> for MakeHealthCheck<M, P>
+where
+    T: HasHealthCheck + Into<EndpointLabels> + Clone + Send + 'static,
+    M: tower::Service<T>,
+    P: tower::Service<T, Response = S> + Clone + Send + 'static,
+    P::Error: Into<Error>,
+    P::Future: Send,
+    S: tower::Service<http::Request<boxed::Payload>, Response = http::Response<B>> + Send + 'static,
+    S::Error: Into<Error>,
+    S::Future: Send,
+    B: Payload + Send + 'static,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future, Probe<P, T>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let endpoint = target.health_check().cloned().map(|config| {
+            let endpoint = self.metrics.endpoint(target.clone().into());
+            let probe = Probe::new(
+                config,
+                Arc::downgrade(&endpoint),
+                self.probe.clone(),
+                target.clone(),
+            );
+            (endpoint, probe)
+        });
+
+        MakeFuture {
+            endpoint,
+            inner: self.inner.call(target),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F, P> Future for MakeFuture<F, P>
+where
+    F: Future,
+    P: Future<Item = (), Error = ()> + Send + 'static,
+{
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+
+        // The probe task is spawned once the endpoint's service is built, and
+        // it completes when the service is dropped.
+        let endpoint = self.endpoint.take().map(|(endpoint, probe)| {
+            tokio::spawn(probe.in_current_span());
+            endpoint
+        });
+
+        Ok(Async::Ready(Service { endpoint, inner }))
+    }
+}
+
+// === impl Service ===
+
+impl<S, Req> tower::Service<Req> for Service<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        if let Some(ref endpoint) = self.endpoint {
+            if !endpoint.poll_healthy() {
+                return Ok(Async::NotReady);
+            }
+        }
+
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req)
+    }
+}
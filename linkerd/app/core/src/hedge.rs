@@ -0,0 +1,84 @@
+use super::dst::Route;
+use super::http_metrics::{requests::Latencies, retries::Handle};
+use super::proxy::http::balance::Attempts;
+use super::retry::clone_request;
+use super::{classify, HttpRouteMetrics, HttpRouteRetry};
+use hyper::body::Payload;
+use linkerd2_hedge::NewHedgeLayer;
+use std::sync::Arc;
+use std::time::Duration;
+use tower::retry::budget::Budget;
+
+/// Hedges requests on routes that have a hedging policy.
+///
+/// The hedging delay is derived from the route's latency histogram, as
+/// recorded by `latencies`. Hedged requests are dispatched to the route's
+/// balancer, which avoids the endpoint to which the original request was
+/// dispatched while another endpoint is ready. Requests that are balanced by
+/// consistent hashing or session affinity are hedged to the same endpoint.
+pub fn layer(latencies: HttpRouteMetrics, metrics: HttpRouteRetry) -> NewHedgeLayer<NewHedge> {
+    NewHedgeLayer::new(NewHedge { latencies, metrics })
+}
+
+#[derive(Clone, Debug)]
+pub struct NewHedge {
+    latencies: HttpRouteMetrics,
+    metrics: HttpRouteRetry,
+}
+
+#[derive(Clone, Debug)]
+pub struct Hedge {
+    quantile: f64,
+    latencies: Latencies<classify::Class>,
+    metrics: Handle,
+    budget: Arc<Budget>,
+}
+
+impl linkerd2_hedge::NewPolicy<Route> for NewHedge {
+    type Policy = Hedge;
+
+    fn new_policy(&self, route: &Route) -> Option<Self::Policy> {
+        let hedge = route.route.hedge()?;
+        // Hedges share the route's retry budget.
+        let retries = route.route.retries()?;
+
+        Some(Hedge {
+            quantile: hedge.percentile() / 100.0,
+            latencies: self.latencies.latencies(route.clone()),
+            metrics: self.metrics.get_handle(route.clone()),
+            budget: retries.budget().clone(),
+        })
+    }
+}
+
+impl<A> linkerd2_hedge::Policy<http::Request<A>> for Hedge
+where
+    A: Default + Payload,
+{
+    fn delay(&self, req: &http::Request<A>) -> Option<Duration> {
+        // Only idempotent requests without bodies are hedged, so that
+        // requests need not be buffered.
+        if !req.method().is_idempotent() || !req.body().is_end_stream() {
+            return None;
+        }
+
+        self.latencies.quantile(self.quantile)
+    }
+
+    fn clone_request(&self, req: &mut http::Request<A>) -> Option<http::Request<A>> {
+        // The original and hedged requests record the endpoints to which
+        // they are dispatched, so that the balancer dispatches them to
+        // distinct endpoints.
+        let attempts = Attempts::default();
+        req.extensions_mut().insert(attempts.clone());
+        let mut hedge = clone_request(req, A::default());
+        hedge.extensions_mut().insert(attempts);
+        Some(hedge)
+    }
+
+    fn can_hedge(&self) -> bool {
+        let withdrew = self.budget.withdraw().is_ok();
+        self.metrics.incr_hedgeable(withdrew);
+        withdrew
+    }
+}
@@ -0,0 +1,6 @@
+//! Validates bearer tokens on inbound requests.
+
+pub use linkerd2_jwt::{watch, Config, Daemon, Filter, InvalidToken, Reason};
+
+/// Forwards requests without validating them when no filter is configured.
+pub type Layer = crate::request_filter::Layer<Option<Filter>>;
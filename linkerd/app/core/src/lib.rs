@@ -12,10 +12,12 @@
 pub use linkerd2_addr::{self as addr, Addr, NameAddr};
 pub use linkerd2_admit as admit;
 pub use linkerd2_cache as cache;
+pub use linkerd2_concurrency_limit as concurrency_limit;
 pub use linkerd2_conditional::Conditional;
 pub use linkerd2_drain as drain;
 pub use linkerd2_error::{Error, Never, Recover};
 pub use linkerd2_exp_backoff as exp_backoff;
+pub use linkerd2_grpc_check as grpc_check;
 pub use linkerd2_http_metrics as http_metrics;
 pub use linkerd2_metrics as metrics;
 pub use linkerd2_opencensus as opencensus;
@@ -29,15 +31,26 @@ pub use linkerd2_trace_context::TraceContextLayer;
 
 pub mod accept_error;
 pub mod admin;
+pub mod authz;
 pub mod classify;
 pub mod config;
 pub mod control;
 pub mod dns;
 pub mod dst;
 pub mod errors;
+pub mod ext_authz;
+pub mod fault;
+pub mod global_rate_limit;
 pub mod handle_time;
+pub mod health_check;
+pub mod hedge;
+pub mod jwt;
+pub mod locality;
 pub mod metric_labels;
+pub mod mirror;
+pub mod outlier;
 pub mod proxy;
+pub mod rate_limit;
 pub mod retry;
 pub mod serve;
 pub mod spans;
@@ -52,6 +65,9 @@ pub const L5D_REMOTE_IP: &'static str = "l5d-remote-ip";
 pub const L5D_SERVER_ID: &'static str = "l5d-server-id";
 pub const L5D_CLIENT_ID: &'static str = "l5d-client-id";
 pub const L5D_REQUIRE_ID: &'static str = "l5d-require-id";
+pub const L5D_PROXY_FAULT: &'static str = "l5d-proxy-fault";
+pub const L5D_DST_CONCRETE: &'static str = "l5d-dst-concrete";
+pub const L5D_MIRROR: &'static str = "l5d-mirror";
 
 const DEFAULT_PORT: u16 = 80;
 
@@ -88,6 +104,9 @@ pub type HttpRouteMetrics = http_metrics::Requests<metric_labels::RouteLabels, c
 
 pub type HttpRouteRetry = http_metrics::Retries<metric_labels::RouteLabels>;
 
+pub type HttpConcreteMetrics =
+    http_metrics::Requests<metric_labels::ConcreteLabels, classify::Class>;
+
 pub type StackMetrics = stack_metrics::Registry<metric_labels::StackLabels>;
 
 #[derive(Clone)]
@@ -95,9 +114,16 @@ pub struct ProxyMetrics {
     pub http_handle_time: handle_time::Scope,
     pub http_route: HttpRouteMetrics,
     pub http_route_actual: HttpRouteMetrics,
+    pub http_route_mirror: HttpRouteMetrics,
+    pub http_concrete: HttpConcreteMetrics,
     pub http_route_retry: HttpRouteRetry,
     pub http_endpoint: HttpEndpointMetrics,
     pub http_errors: errors::MetricsLayer,
+    pub http_outlier: outlier::Metrics,
+    pub http_health_check: health_check::Metrics,
+    pub http_locality: locality::Metrics,
+    pub http_adaptive_concurrency: concurrency_limit::adaptive::Metrics,
+    pub authz: authz::Metrics,
     pub stack: StackMetrics,
     pub transport: transport::Metrics,
 }
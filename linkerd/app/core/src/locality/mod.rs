@@ -0,0 +1,403 @@
+//! Zone-aware endpoint selection for load balancers.
+//!
+//! Endpoints in a different zone from the proxy are withheld from their
+//! balancer (i.e. they do not become ready) while any endpoint in the proxy's
+//! zone is available. A local endpoint is available while it is ready and has
+//! fewer than `max_pending` requests in flight, so that traffic spills over to
+//! other zones only when the local zone's endpoints are unavailable or
+//! overloaded. Endpoints whose zone is unknown are treated as local.
+
+use super::outlier::HasPool;
+use crate::Addr;
+use futures::{task, Async};
+use indexmap::IndexMap;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, Weak};
+use tracing::debug;
+
+mod service;
+
+pub use self::service::{MakeFuture, MakeLocality, ResponseFuture, Service};
+
+metrics! {
+    locality_requests_total: Counter {
+        "Total count of balanced requests, by whether they were dispatched to an endpoint in the proxy's zone."
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The proxy's zone.
+    pub zone: String,
+
+    /// The number of in-flight requests at which a local endpoint is
+    /// considered overloaded. Zero disables zone preference.
+    pub max_pending: usize,
+}
+
+/// Describes the zone of an endpoint target.
+pub trait HasZone {
+    /// The destination that the endpoint serves, used to label metrics.
+    fn dst(&self) -> &Addr;
+
+    /// The zone in which the endpoint runs, if it is known.
+    fn zone(&self) -> Option<&str>;
+}
+
+/// Wraps endpoint stacks to prefer endpoints in the proxy's zone.
+///
+/// If no `Config` is provided, all endpoints are treated as local.
+#[derive(Clone, Debug)]
+pub struct Layer<P: Hash + Eq> {
+    config: Option<Arc<Config>>,
+    pools: Arc<Mutex<HashMap<P, Weak<Pool>>>>,
+    metrics: Metrics,
+}
+
+/// Reports balanced requests, labeled by destination.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<IndexMap<Addr, Arc<DstMetrics>>>>);
+
+#[derive(Debug, Default)]
+struct DstMetrics {
+    local: Counter,
+    cross_zone: Counter,
+}
+
+/// The endpoints of a single balancer.
+#[derive(Debug, Default)]
+struct Pool {
+    state: Mutex<PoolState>,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    /// The number of local endpoints that are available.
+    available: usize,
+    /// Tasks polling remote endpoints, notified when no local endpoints are
+    /// available.
+    waiting: Vec<task::Task>,
+}
+
+/// An endpoint's load, shared by its service and its response futures.
+#[derive(Debug)]
+struct Endpoint {
+    /// Whether the endpoint is in the proxy's zone.
+    local: bool,
+    max_pending: usize,
+    pool: Arc<Pool>,
+    metrics: Option<Arc<DstMetrics>>,
+    state: Mutex<EndpointState>,
+}
+
+#[derive(Debug, Default)]
+struct EndpointState {
+    ready: bool,
+    pending: usize,
+    available: bool,
+}
+
+// === impl Layer ===
+
+pub fn layer<P: Hash + Eq>(config: Option<Config>, metrics: Metrics) -> Layer<P> {
+    Layer {
+        config: config.map(Arc::new),
+        pools: Arc::new(Mutex::new(HashMap::new())),
+        metrics,
+    }
+}
+
+impl<P: Clone + Hash + Eq> Layer<P> {
+    fn endpoint<T: HasPool<Pool = P> + HasZone>(&self, target: &T) -> Arc<Endpoint> {
+        let config = match self.config.as_ref() {
+            Some(config) => config,
+            // Without a zone, all endpoints are local and are never
+            // considered overloaded.
+            None => {
+                return Arc::new(Endpoint {
+                    local: true,
+                    max_pending: usize::max_value(),
+                    pool: Arc::new(Pool::default()),
+                    metrics: None,
+                    state: Mutex::new(EndpointState::default()),
+                })
+            }
+        };
+
+        let pool = {
+            let mut pools = self.pools.lock().expect("locality pools lock poisoned");
+            let existing = pools.get(&target.pool()).and_then(Weak::upgrade);
+            existing.unwrap_or_else(|| {
+                // Drop pools whose balancers have been dropped.
+                pools.retain(|_, p| p.upgrade().is_some());
+                let p = Arc::new(Pool::default());
+                pools.insert(target.pool(), Arc::downgrade(&p));
+                p
+            })
+        };
+
+        let local = target.zone().map_or(true, |zone| zone == config.zone);
+        debug!(local, zone = ?target.zone(), "Adding endpoint");
+        Arc::new(Endpoint {
+            local,
+            max_pending: config.max_pending,
+            pool,
+            metrics: Some(self.metrics.dst(target.dst())),
+            state: Mutex::new(EndpointState::default()),
+        })
+    }
+}
+
+impl<M, P: Clone + Hash + Eq> tower::layer::Layer<M> for Layer<P> {
+    type Service = MakeLocality<M, P>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeLocality::new(self.clone(), inner)
+    }
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    fn dst(&self, dst: &Addr) -> Arc<DstMetrics> {
+        let mut registry = self.0.lock().expect("locality metrics lock poisoned");
+        registry
+            .entry(dst.clone())
+            .or_insert_with(|| Arc::new(DstMetrics::default()))
+            .clone()
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut registry = match self.0.lock() {
+            Ok(registry) => registry,
+            Err(_) => return Ok(()),
+        };
+        if registry.is_empty() {
+            return Ok(());
+        }
+
+        locality_requests_total.fmt_help(f)?;
+        locality_requests_total.fmt_scopes(
+            f,
+            registry.iter().map(|(dst, m)| ((Dst(dst), Zone::Local), m)),
+            |m| &m.local,
+        )?;
+        locality_requests_total.fmt_scopes(
+            f,
+            registry.iter().map(|(dst, m)| ((Dst(dst), Zone::Cross), m)),
+            |m| &m.cross_zone,
+        )?;
+
+        // Destinations are only reported while they have endpoints.
+        registry.retain(|_, m| Arc::strong_count(m) > 1);
+
+        Ok(())
+    }
+}
+
+// === impl Pool ===
+
+impl Pool {
+    fn add_available(&self) {
+        let mut state = self.state.lock().expect("locality pool lock poisoned");
+        state.available += 1;
+    }
+
+    fn remove_available(&self) {
+        let mut state = self.state.lock().expect("locality pool lock poisoned");
+        state.available = state.available.saturating_sub(1);
+        if state.available == 0 {
+            debug!("No local endpoints available");
+            for task in state.waiting.drain(..) {
+                task.notify();
+            }
+        }
+    }
+
+    /// Returns ready if no local endpoints are available. Otherwise, the
+    /// current task is notified when this changes.
+    fn poll_remote(&self) -> Async<()> {
+        let mut state = self.state.lock().expect("locality pool lock poisoned");
+        if state.available == 0 {
+            return Async::Ready(());
+        }
+
+        if !state.waiting.iter().any(|t| t.will_notify_current()) {
+            state.waiting.push(task::current());
+        }
+        Async::NotReady
+    }
+}
+
+// === impl Endpoint ===
+
+impl Endpoint {
+    fn set_ready(&self, ready: bool) {
+        let mut state = self.state.lock().expect("locality endpoint lock poisoned");
+        state.ready = ready;
+        self.update(&mut state);
+    }
+
+    /// Records a request dispatched to the endpoint.
+    fn start(&self) {
+        if let Some(ref metrics) = self.metrics {
+            if self.local {
+                metrics.local.incr();
+            } else {
+                metrics.cross_zone.incr();
+            }
+        }
+
+        let mut state = self.state.lock().expect("locality endpoint lock poisoned");
+        state.pending += 1;
+        self.update(&mut state);
+    }
+
+    fn end(&self) {
+        let mut state = self.state.lock().expect("locality endpoint lock poisoned");
+        state.pending = state.pending.saturating_sub(1);
+        self.update(&mut state);
+    }
+
+    /// Updates the pool if the local endpoint's availability has changed.
+    fn update(&self, state: &mut EndpointState) {
+        if !self.local {
+            return;
+        }
+
+        let available = state.ready && state.pending < self.max_pending;
+        if available == state.available {
+            return;
+        }
+        state.available = available;
+        if available {
+            self.pool.add_available();
+        } else {
+            self.pool.remove_available();
+        }
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        let available = self.state.lock().map(|s| s.available).unwrap_or(false);
+        if available {
+            self.pool.remove_available();
+        }
+    }
+}
+
+/// Formats a destination label.
+struct Dst<'a>(&'a Addr);
+
+impl<'a> FmtLabels for Dst<'a> {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dst=\"{}\"", self.0)
+    }
+}
+
+enum Zone {
+    Local,
+    Cross,
+}
+
+impl FmtLabels for Zone {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Zone::Local => write!(f, "zone=\"local\""),
+            Zone::Cross => write!(f, "zone=\"cross\""),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NameAddr;
+    use futures::{future, Future};
+
+    struct Ep(Option<&'static str>, Addr);
+
+    impl HasPool for Ep {
+        type Pool = ();
+
+        fn pool(&self) {}
+    }
+
+    impl HasZone for Ep {
+        fn dst(&self) -> &Addr {
+            &self.1
+        }
+
+        fn zone(&self) -> Option<&str> {
+            self.0
+        }
+    }
+
+    fn layer() -> Layer<()> {
+        super::layer(
+            Some(Config {
+                zone: "a".into(),
+                max_pending: 2,
+            }),
+            Metrics::default(),
+        )
+    }
+
+    fn endpoint(layer: &Layer<()>, zone: Option<&'static str>) -> Arc<Endpoint> {
+        let dst = NameAddr::from_str("foo.ns.svc.cluster.local:8080").unwrap();
+        layer.endpoint(&Ep(zone, dst.into()))
+    }
+
+    fn poll_remote(ep: &Endpoint) -> bool {
+        future::lazy(|| Ok::<_, ()>(ep.pool.poll_remote().is_ready()))
+            .wait()
+            .unwrap()
+    }
+
+    #[test]
+    fn remote_endpoints_withheld_while_local_available() {
+        let layer = layer();
+        let local = endpoint(&layer, Some("a"));
+        let unknown = endpoint(&layer, None);
+        let remote = endpoint(&layer, Some("b"));
+        assert!(local.local && unknown.local && !remote.local);
+
+        // Remote endpoints are used until a local endpoint becomes ready.
+        assert!(poll_remote(&remote));
+        local.set_ready(true);
+        assert!(!poll_remote(&remote));
+
+        local.set_ready(false);
+        assert!(poll_remote(&remote));
+    }
+
+    #[test]
+    fn spills_over_when_local_overloaded() {
+        let layer = layer();
+        let local = endpoint(&layer, Some("a"));
+        let remote = endpoint(&layer, Some("b"));
+        local.set_ready(true);
+
+        local.start();
+        assert!(!poll_remote(&remote));
+        local.start();
+        assert!(poll_remote(&remote));
+        local.end();
+        assert!(!poll_remote(&remote));
+
+        // Dropping the local endpoint releases the remote endpoint.
+        drop(local);
+        assert!(poll_remote(&remote));
+
+        remote.start();
+        let metrics = remote.metrics.as_ref().unwrap();
+        assert_eq!(metrics.local.value(), 2);
+        assert_eq!(metrics.cross_zone.value(), 1);
+    }
+}
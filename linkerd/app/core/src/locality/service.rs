@@ -0,0 +1,141 @@
+use super::{Endpoint, HasPool, HasZone, Layer};
+use futures::{try_ready, Async, Future, Poll};
+use std::hash::Hash;
+use std::sync::Arc;
+
+/// Wraps endpoint services so that remote endpoints are only used when local
+/// endpoints are unavailable.
+#[derive(Clone, Debug)]
+pub struct MakeLocality<M, P: Hash + Eq> {
+    layer: Layer<P>,
+    inner: M,
+}
+
+pub struct MakeFuture<F> {
+    endpoint: Option<Arc<Endpoint>>,
+    inner: F,
+}
+
+/// An endpoint service that tracks its load and, if it is remote, is not
+/// ready while local endpoints are available.
+#[derive(Debug)]
+pub struct Service<S> {
+    endpoint: Arc<Endpoint>,
+    inner: S,
+}
+
+/// Releases the endpoint's pending request when the response is received or
+/// the request is dropped.
+pub struct ResponseFuture<F> {
+    pending: Option<Pending>,
+    inner: F,
+}
+
+struct Pending(Arc<Endpoint>);
+
+// === impl MakeLocality ===
+
+impl<M, P: Hash + Eq> MakeLocality<M, P> {
+    pub(super) fn new(layer: Layer<P>, inner: M) -> Self {
+        Self { layer, inner }
+    }
+}
+
+impl<T, M, P> tower::Service<T> for MakeLocality<M, P>
+where
+    T: HasPool<Pool = P> + HasZone,
+    M: tower::Service<T>,
+    P: Clone + Hash + Eq,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let endpoint = self.layer.endpoint(&target);
+        MakeFuture {
+            endpoint: Some(endpoint),
+            inner: self.inner.call(target),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let endpoint = self.endpoint.take().expect("polled after ready");
+        Ok(Async::Ready(Service { endpoint, inner }))
+    }
+}
+
+// === impl Service ===
+
+impl<S, Req> tower::Service<Req> for Service<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // Remote endpoints wait until no local endpoints are available so
+        // that the balancer does not dispatch requests to them.
+        if !self.endpoint.local && self.endpoint.pool.poll_remote().is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+
+        match self.inner.poll_ready() {
+            Ok(Async::Ready(())) => {
+                self.endpoint.set_ready(true);
+                Ok(Async::Ready(()))
+            }
+            Ok(Async::NotReady) => {
+                self.endpoint.set_ready(false);
+                Ok(Async::NotReady)
+            }
+            Err(e) => {
+                self.endpoint.set_ready(false);
+                Err(e)
+            }
+        }
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.endpoint.start();
+        ResponseFuture {
+            pending: Some(Pending(self.endpoint.clone())),
+            inner: self.inner.call(req),
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F: Future> Future for ResponseFuture<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = self.inner.poll();
+        if !matches!(rsp, Ok(Async::NotReady)) {
+            self.pending = None;
+        }
+        rsp
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.end();
+    }
+}
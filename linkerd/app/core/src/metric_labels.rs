@@ -21,6 +21,15 @@ pub struct EndpointLabels {
     pub labels: Option<String>,
 }
 
+/// Labels requests to the concrete destination selected for a logical
+/// destination, e.g. by a profile's overrides.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ConcreteLabels {
+    pub direction: Direction,
+    pub target: Addr,
+    pub concrete: Addr,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct StackLabels {
     pub direction: Direction,
@@ -94,6 +103,19 @@ impl FmtLabels for RouteLabels {
     }
 }
 
+// === impl ConcreteLabels ===
+
+impl FmtLabels for ConcreteLabels {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.direction.fmt_labels(f)?;
+        write!(
+            f,
+            ",dst=\"{}\",dst_concrete=\"{}\"",
+            self.target, self.concrete
+        )
+    }
+}
+
 // === impl EndpointLabels ===
 
 impl FmtLabels for EndpointLabels {
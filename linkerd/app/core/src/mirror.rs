@@ -0,0 +1,390 @@
+//! Mirrors requests on routes that configure a mirror destination.
+//!
+//! A copy of each selected request is dispatched to the mirror's concrete
+//! target on a background task, marked with an `l5d-mirror` header. The
+//! copy's response is read to completion and discarded, so that it neither
+//! delays nor fails the original request.
+//!
+//! A copy's body replays the original request's body as it is read by the
+//! original request, buffering up to `max_body_bytes`. The copy never reads
+//! the body itself, so a mirror that falls more than `max_body_bytes` behind
+//! fails rather than holding up the original request.
+//!
+//! The number of mirrored requests in flight is bounded: when the bound is
+//! reached, requests are not mirrored until mirrored requests complete, so
+//! that a slow mirror destination cannot accumulate an unbounded number of
+//! background requests.
+
+use super::dst::Route;
+use super::transport::tls;
+use super::L5D_MIRROR;
+use crate::profiles;
+use crate::proxy::http::replay::ReplayBody;
+use crate::{Error, NameAddr};
+use futures::{try_ready, Async, Future, Poll};
+use http::header::HeaderValue;
+use hyper::body::Payload;
+use linkerd2_stack::{NewService, Proxy};
+use rand::Rng;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tower::util::{Oneshot, ServiceExt};
+use tracing::{debug, trace};
+use tracing_futures::Instrument;
+
+/// Mirrors requests on routes that configure a mirror.
+///
+/// `proxy` builds the per-route middleware through which mirrored requests
+/// are dispatched, e.g. to record metrics. `make` obtains the service for each
+/// mirrored request's target, as determined by `target`, and should be
+/// cached. At most `max_in_flight` mirrored requests are dispatched at once,
+/// and at most `max_body_bytes` of each request's body are buffered for its
+/// copy.
+pub fn layer<R, M, F>(
+    proxy: R,
+    make: M,
+    target: F,
+    max_in_flight: usize,
+    max_body_bytes: usize,
+) -> Layer<R, M, F> {
+    Layer {
+        proxy,
+        make,
+        target,
+        in_flight: InFlight {
+            max: max_in_flight,
+            count: Arc::new(AtomicUsize::new(0)),
+        },
+        max_body_bytes,
+    }
+}
+
+/// Determines the concrete target to which a request is mirrored.
+pub trait MirrorTarget {
+    type Target;
+
+    fn mirror_target<B>(&self, dst: &NameAddr, req: &http::Request<B>) -> Option<Self::Target>;
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer<R, M, F> {
+    proxy: R,
+    make: M,
+    target: F,
+    in_flight: InFlight,
+    max_body_bytes: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct MakeMirror<N, R, M, F> {
+    inner: N,
+    proxy: R,
+    make: M,
+    target: F,
+    in_flight: InFlight,
+    max_body_bytes: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct Mirror<P, R, M, F> {
+    inner: P,
+    mirror: Option<(profiles::Mirror, R)>,
+    make: M,
+    target: F,
+    in_flight: InFlight,
+    max_body_bytes: usize,
+}
+
+/// Counts the mirrored requests in flight across all routes.
+#[derive(Clone, Debug)]
+struct InFlight {
+    max: usize,
+    count: Arc<AtomicUsize>,
+}
+
+/// Holds a slot in an `InFlight` count until the mirrored request completes.
+struct InFlightGuard(Arc<AtomicUsize>);
+
+/// Obtains a service for the mirror target and dispatches a request to it.
+#[derive(Clone, Debug)]
+pub struct Dispatch<M, T> {
+    make: M,
+    target: T,
+}
+
+pub enum DispatchFuture<M, T, Req>
+where
+    M: tower::Service<T>,
+    M::Response: tower::Service<Req>,
+{
+    Make(Oneshot<M, T>, Option<Req>),
+    Call(Oneshot<M::Response, Req>),
+}
+
+/// Reads a response body to completion.
+struct Drain<B>(B);
+
+// === impl Layer ===
+
+impl<N, R: Clone, M: Clone, F: Clone> tower::layer::Layer<N> for Layer<R, M, F> {
+    type Service = MakeMirror<N, R, M, F>;
+
+    fn layer(&self, inner: N) -> Self::Service {
+        MakeMirror {
+            inner,
+            proxy: self.proxy.clone(),
+            make: self.make.clone(),
+            target: self.target.clone(),
+            in_flight: self.in_flight.clone(),
+            max_body_bytes: self.max_body_bytes,
+        }
+    }
+}
+
+// === impl MakeMirror ===
+
+impl<N, R, M, F> NewService<Route> for MakeMirror<N, R, M, F>
+where
+    N: NewService<Route>,
+    R: NewService<Route>,
+    M: Clone,
+    F: Clone,
+{
+    type Service = Mirror<N::Service, R::Service, M, F>;
+
+    fn new_service(&self, route: Route) -> Self::Service {
+        let mirror = route
+            .route
+            .mirror()
+            .cloned()
+            .map(|mirror| (mirror, self.proxy.new_service(route.clone())));
+        Mirror {
+            inner: self.inner.new_service(route),
+            mirror,
+            make: self.make.clone(),
+            target: self.target.clone(),
+            in_flight: self.in_flight.clone(),
+            max_body_bytes: self.max_body_bytes,
+        }
+    }
+}
+
+// === impl Mirror ===
+
+impl<P, R, M, F, S, B, RspB> Proxy<http::Request<B>, S> for Mirror<P, R, M, F>
+where
+    P: Proxy<http::Request<B>, S>,
+    S: tower::Service<P::Request>,
+    B: Default + Payload,
+    B::Error: Into<Error>,
+    ReplayBody<B>: Into<B>,
+    F: MirrorTarget,
+    F::Target: Clone,
+    M: tower::Service<F::Target> + Clone,
+    M::Error: Into<Error>,
+    M::Response: tower::Service<R::Request>,
+    <M::Response as tower::Service<R::Request>>::Error: Into<Error>,
+    R: Proxy<http::Request<B>, Dispatch<M, F::Target>, Response = http::Response<RspB>>,
+    R::Future: Send + 'static,
+    RspB: Payload,
+{
+    type Request = P::Request;
+    type Response = P::Response;
+    type Error = P::Error;
+    type Future = P::Future;
+
+    fn proxy(&self, svc: &mut S, req: http::Request<B>) -> Self::Future {
+        let (mirror, proxy) = match self.mirror {
+            Some((ref mirror, ref proxy))
+                if rand::thread_rng().gen::<f64>() * 100.0 < mirror.percentage =>
+            {
+                (mirror, proxy)
+            }
+            _ => return self.inner.proxy(svc, req),
+        };
+
+        let target = match self.target.mirror_target(&mirror.addr, &req) {
+            Some(target) => target,
+            None => {
+                trace!(dst = %mirror.addr, "No mirror target for request");
+                return self.inner.proxy(svc, req);
+            }
+        };
+        let guard = match self.in_flight.acquire() {
+            Some(guard) => guard,
+            None => {
+                debug!(
+                    dst = %mirror.addr,
+                    max = self.in_flight.max,
+                    "Too many mirrored requests in flight; not mirroring request"
+                );
+                return self.inner.proxy(svc, req);
+            }
+        };
+
+        // The copy's body follows the original request's body, so that the
+        // copy can never hold up the original request.
+        let (req, body) = if req.body().is_end_stream() {
+            (req, B::default())
+        } else {
+            let (head, body) = req.into_parts();
+            let body = ReplayBody::new(body, self.max_body_bytes);
+            let follower = body.follower();
+            (
+                http::Request::from_parts(head, body.into()),
+                follower.into(),
+            )
+        };
+
+        debug!(dst = %mirror.addr, "Mirroring request");
+        let mut dispatch = Dispatch {
+            make: self.make.clone(),
+            target,
+        };
+        let rsp = proxy.proxy(&mut dispatch, clone_request(&req, body));
+        tokio::spawn(
+            rsp.map_err(Into::into)
+                .and_then(|rsp| Drain(rsp.into_body()).map_err(Into::into))
+                .then(move |result: Result<(), Error>| {
+                    // Frees the in-flight slot once the response completes.
+                    drop(guard);
+                    match result {
+                        Ok(()) => trace!("Mirrored request completed"),
+                        Err(error) => debug!(%error, "Mirrored request failed"),
+                    }
+                    Ok(())
+                })
+                .in_current_span(),
+        );
+
+        self.inner.proxy(svc, req)
+    }
+}
+
+/// Copies a request's head with the given body, marking the copy as mirrored.
+///
+/// The request's connection metadata is retained so that the mirror target
+/// may be determined as it would be for the original request.
+fn clone_request<B>(req: &http::Request<B>, body: B) -> http::Request<B> {
+    let mut clone = http::Request::new(body);
+    *clone.method_mut() = req.method().clone();
+    *clone.uri_mut() = req.uri().clone();
+    *clone.headers_mut() = req.headers().clone();
+    *clone.version_mut() = req.version();
+    clone
+        .headers_mut()
+        .insert(L5D_MIRROR, HeaderValue::from_static("true"));
+
+    if let Some(ext) = req.extensions().get::<tls::accept::Meta>() {
+        clone.extensions_mut().insert(ext.clone());
+    }
+
+    clone
+}
+
+// === impl InFlight ===
+
+impl InFlight {
+    /// Returns a guard if fewer than `max` mirrored requests are in flight.
+    fn acquire(&self) -> Option<InFlightGuard> {
+        if self.count.fetch_add(1, Ordering::AcqRel) >= self.max {
+            self.count.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        Some(InFlightGuard(self.count.clone()))
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// === impl Dispatch ===
+
+impl<M, T, Req> tower::Service<Req> for Dispatch<M, T>
+where
+    M: tower::Service<T> + Clone,
+    M::Error: Into<Error>,
+    M::Response: tower::Service<Req>,
+    <M::Response as tower::Service<Req>>::Error: Into<Error>,
+    T: Clone,
+{
+    type Response = <M::Response as tower::Service<Req>>::Response;
+    type Error = Error;
+    type Future = DispatchFuture<M, T, Req>;
+
+    /// Readiness is determined when the request is dispatched, as the
+    /// target's service is obtained for each request.
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let make = self.make.clone().oneshot(self.target.clone());
+        DispatchFuture::Make(make, Some(req))
+    }
+}
+
+impl<M, T, Req> Future for DispatchFuture<M, T, Req>
+where
+    M: tower::Service<T>,
+    M::Error: Into<Error>,
+    M::Response: tower::Service<Req>,
+    <M::Response as tower::Service<Req>>::Error: Into<Error>,
+{
+    type Item = <M::Response as tower::Service<Req>>::Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            *self = match self {
+                DispatchFuture::Make(ref mut make, ref mut req) => {
+                    let svc = try_ready!(make.poll().map_err(Into::into));
+                    let req = req.take().expect("polled after ready");
+                    DispatchFuture::Call(svc.oneshot(req))
+                }
+                DispatchFuture::Call(ref mut call) => {
+                    return call.poll().map_err(Into::into);
+                }
+            };
+        }
+    }
+}
+
+// === impl Drain ===
+
+impl<B: Payload> Future for Drain<B> {
+    type Item = ();
+    type Error = B::Error;
+
+    fn poll(&mut self) -> Poll<(), B::Error> {
+        while try_ready!(self.0.poll_data()).is_some() {}
+        try_ready!(self.0.poll_trailers());
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_in_flight_mirrors() {
+        let in_flight = InFlight {
+            max: 2,
+            count: Arc::new(AtomicUsize::new(0)),
+        };
+        let first = in_flight.acquire().expect("first mirror must be admitted");
+        let _second = in_flight.acquire().expect("second mirror must be admitted");
+        assert!(
+            in_flight.acquire().is_none(),
+            "third mirror must be dropped"
+        );
+        assert_eq!(in_flight.count.load(Ordering::Acquire), 2);
+
+        drop(first);
+        assert!(in_flight.acquire().is_some(), "a freed slot must be reused");
+    }
+}
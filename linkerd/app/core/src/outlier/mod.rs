@@ -0,0 +1,470 @@
+//! Passive outlier detection for load balancer endpoints.
+//!
+//! Each response received from a balanced endpoint is classified (via the
+//! request's `classify::Response`). Endpoints that fail too many requests
+//! consecutively, or that fail too great a ratio of requests, are ejected from
+//! their balancer (i.e. they do not become ready) for a period that grows
+//! exponentially with each ejection. No more than `max_ejection_percent` of a
+//! balancer's endpoints may be ejected at once.
+//!
+//! Requests aborted by fault injection are not recorded, since they fail
+//! before reaching the endpoint.
+
+use super::metric_labels::EndpointLabels;
+use indexmap::IndexMap;
+use linkerd2_metrics::{metrics, Counter, FmtLabels, FmtMetrics, Gauge};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
+use tracing::{debug, info};
+
+mod service;
+
+pub use self::service::{MakeFuture, MakeOutlier, ResponseBody, ResponseFuture, Service};
+
+metrics! {
+    outlier_ejections_total: Counter {
+        "Total count of endpoints ejected from a load balancer by outlier detection."
+    },
+    outlier_ejected: Gauge {
+        "Whether an endpoint is currently ejected from a load balancer."
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Ejects an endpoint after this many consecutive failures. Zero disables
+    /// consecutive-failure detection.
+    pub consecutive_failures: u32,
+
+    /// Optionally ejects an endpoint when its failure rate exceeds a
+    /// threshold.
+    pub failure_rate: Option<FailureRate>,
+
+    /// The duration of an endpoint's first ejection. Each subsequent ejection
+    /// doubles this duration, up to `max_ejection`.
+    pub base_ejection: Duration,
+
+    pub max_ejection: Duration,
+
+    /// The maximum percentage of a balancer's endpoints that may be ejected
+    /// at once.
+    pub max_ejection_percent: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct FailureRate {
+    /// The ratio of failed requests, on `[0, 1]`, at which an endpoint is
+    /// ejected.
+    pub ratio: f64,
+
+    /// The minimum number of requests an endpoint must have received within
+    /// an `interval` for its failure rate to be considered.
+    pub min_requests: u32,
+
+    /// The interval over which failure rates are computed.
+    pub interval: Duration,
+}
+
+/// Identifies the balancer to which an endpoint target belongs.
+pub trait HasPool {
+    type Pool: Clone + Hash + Eq;
+
+    fn pool(&self) -> Self::Pool;
+}
+
+/// Wraps endpoint stacks to eject outliers from their balancers.
+#[derive(Clone, Debug)]
+pub struct Layer<P: Hash + Eq> {
+    config: Arc<Config>,
+    pools: Arc<Mutex<HashMap<P, Weak<Pool>>>>,
+    metrics: Metrics,
+}
+
+/// Reports outlier ejections, labeled by endpoint.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<IndexMap<EndpointLabels, Arc<EndpointMetrics>>>>);
+
+#[derive(Debug, Default)]
+struct EndpointMetrics {
+    ejections: Counter,
+    ejected: Gauge,
+}
+
+/// The endpoints of a single balancer.
+#[derive(Debug)]
+struct Pool {
+    config: Arc<Config>,
+    state: Mutex<PoolState>,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    endpoints: usize,
+    ejected: usize,
+}
+
+/// An endpoint's health, shared by its service and its response futures.
+///
+/// The endpoint is removed from its pool when this is dropped.
+#[derive(Debug)]
+struct Endpoint {
+    addr: SocketAddr,
+    labels: EndpointLabels,
+    pool: Arc<Pool>,
+    metrics: Arc<EndpointMetrics>,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug)]
+struct Health {
+    consecutive_failures: u32,
+    window_start: Instant,
+    window_requests: u32,
+    window_failures: u32,
+    /// The number of times the endpoint has been ejected since it was last
+    /// healthy for at least `max_ejection`.
+    ejections: u32,
+    ejected_until: Option<Instant>,
+    restored_at: Option<Instant>,
+}
+
+// === impl Config ===
+
+impl Config {
+    fn ejection(&self, ejections: u32) -> Duration {
+        self.base_ejection
+            .checked_mul(2_u32.saturating_pow(ejections))
+            .unwrap_or(self.max_ejection)
+            .min(self.max_ejection)
+    }
+}
+
+// === impl Layer ===
+
+pub fn layer<P: Hash + Eq>(config: Config, metrics: Metrics) -> Layer<P> {
+    Layer {
+        config: Arc::new(config),
+        pools: Arc::new(Mutex::new(HashMap::new())),
+        metrics,
+    }
+}
+
+impl<P: Clone + Hash + Eq> Layer<P> {
+    fn endpoint(&self, pool: P, addr: SocketAddr, labels: EndpointLabels) -> Arc<Endpoint> {
+        let pool = {
+            let mut pools = self.pools.lock().expect("outlier pools lock poisoned");
+            let existing = pools.get(&pool).and_then(Weak::upgrade);
+            existing.unwrap_or_else(|| {
+                // Drop pools whose balancers have been dropped.
+                pools.retain(|_, p| p.upgrade().is_some());
+                let p = Arc::new(Pool {
+                    config: self.config.clone(),
+                    state: Mutex::new(PoolState::default()),
+                });
+                pools.insert(pool, Arc::downgrade(&p));
+                p
+            })
+        };
+        pool.state
+            .lock()
+            .expect("outlier pool lock poisoned")
+            .endpoints += 1;
+
+        Arc::new(Endpoint {
+            addr,
+            metrics: self.metrics.endpoint(labels.clone()),
+            labels,
+            pool,
+            health: Mutex::new(Health {
+                consecutive_failures: 0,
+                window_start: clock::now(),
+                window_requests: 0,
+                window_failures: 0,
+                ejections: 0,
+                ejected_until: None,
+                restored_at: None,
+            }),
+        })
+    }
+}
+
+impl<M, P: Clone + Hash + Eq> tower::layer::Layer<M> for Layer<P> {
+    type Service = MakeOutlier<M, P>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeOutlier::new(self.clone(), inner)
+    }
+}
+
+// === impl Metrics ===
+
+impl Metrics {
+    fn endpoint(&self, labels: EndpointLabels) -> Arc<EndpointMetrics> {
+        let mut registry = self.0.lock().expect("outlier metrics lock poisoned");
+        registry
+            .entry(labels)
+            .or_insert_with(|| Arc::new(EndpointMetrics::default()))
+            .clone()
+    }
+}
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut registry = match self.0.lock() {
+            Ok(registry) => registry,
+            Err(_) => return Ok(()),
+        };
+        if registry.is_empty() {
+            return Ok(());
+        }
+
+        outlier_ejections_total.fmt_help(f)?;
+        outlier_ejections_total.fmt_scopes(f, registry.iter(), |m| &m.ejections)?;
+
+        outlier_ejected.fmt_help(f)?;
+        outlier_ejected.fmt_scopes(f, registry.iter(), |m| &m.ejected)?;
+
+        // Endpoints are only reported while they are in a balancer.
+        registry.retain(|_, m| Arc::strong_count(m) > 1);
+
+        Ok(())
+    }
+}
+
+// === impl Pool ===
+
+impl Pool {
+    /// Reserves an ejection, unless doing so would exceed the maximum
+    /// percentage of ejected endpoints.
+    fn try_eject(&self) -> bool {
+        let mut state = self.state.lock().expect("outlier pool lock poisoned");
+        let max = state.endpoints * (self.config.max_ejection_percent as usize) / 100;
+        if state.ejected >= max {
+            return false;
+        }
+        state.ejected += 1;
+        true
+    }
+
+    fn restore(&self) {
+        let mut state = self.state.lock().expect("outlier pool lock poisoned");
+        state.ejected = state.ejected.saturating_sub(1);
+    }
+}
+
+// === impl Endpoint ===
+
+impl Endpoint {
+    /// Returns the time until which the endpoint is ejected, restoring the
+    /// endpoint if its ejection has elapsed.
+    fn ejected_until(&self) -> Option<Instant> {
+        let mut health = self.health.lock().expect("outlier health lock poisoned");
+        let until = health.ejected_until?;
+
+        let now = clock::now();
+        if now < until {
+            return Some(until);
+        }
+
+        health.ejected_until = None;
+        health.restored_at = Some(now);
+        health.reset_window(now);
+        self.pool.restore();
+        self.metrics.ejected.decr();
+        info!(addr = %self.addr, labels = %Labels(&self.labels), "Restoring endpoint");
+        None
+    }
+
+    /// Records the classification of a response from this endpoint, ejecting
+    /// it if it has become an outlier.
+    fn record(&self, is_failure: bool) {
+        let config = &self.pool.config;
+        let mut health = self.health.lock().expect("outlier health lock poisoned");
+        if health.ejected_until.is_some() {
+            // Responses to requests dispatched before the ejection are not
+            // counted against the endpoint.
+            return;
+        }
+
+        let now = clock::now();
+        if let Some(ref rate) = config.failure_rate {
+            if now - health.window_start >= rate.interval {
+                health.reset_window(now);
+            }
+        }
+
+        health.window_requests += 1;
+        if is_failure {
+            health.consecutive_failures += 1;
+            health.window_failures += 1;
+        } else {
+            health.consecutive_failures = 0;
+        }
+
+        let consecutive = config.consecutive_failures > 0
+            && health.consecutive_failures >= config.consecutive_failures;
+        let rate = config.failure_rate.as_ref().map_or(false, |rate| {
+            health.window_requests >= rate.min_requests
+                && f64::from(health.window_failures) / f64::from(health.window_requests)
+                    >= rate.ratio
+        });
+        if !consecutive && !rate {
+            return;
+        }
+
+        if !self.pool.try_eject() {
+            debug!(
+                addr = %self.addr,
+                labels = %Labels(&self.labels),
+                "Not ejecting endpoint; too many endpoints are ejected"
+            );
+            return;
+        }
+
+        // Endpoints that have remained healthy for a while are ejected as if
+        // for the first time.
+        if health
+            .restored_at
+            .map_or(false, |at| now - at >= config.max_ejection)
+        {
+            health.ejections = 0;
+        }
+
+        let duration = config.ejection(health.ejections);
+        health.ejections = health.ejections.saturating_add(1);
+        health.ejected_until = Some(now + duration);
+        health.consecutive_failures = 0;
+        health.reset_window(now);
+
+        self.metrics.ejections.incr();
+        self.metrics.ejected.incr();
+        info!(
+            addr = %self.addr,
+            labels = %Labels(&self.labels),
+            ?duration,
+            consecutive,
+            "Ejecting endpoint",
+        );
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        let ejected = self
+            .health
+            .lock()
+            .map(|h| h.ejected_until.is_some())
+            .unwrap_or(false);
+        if ejected {
+            self.metrics.ejected.decr();
+        }
+
+        if let Ok(mut state) = self.pool.state.lock() {
+            state.endpoints = state.endpoints.saturating_sub(1);
+            if ejected {
+                state.ejected = state.ejected.saturating_sub(1);
+            }
+        }
+    }
+}
+
+// === impl Health ===
+
+impl Health {
+    fn reset_window(&mut self, now: Instant) {
+        self.window_start = now;
+        self.window_requests = 0;
+        self.window_failures = 0;
+    }
+}
+
+/// Formats endpoint labels for logging.
+struct Labels<'a>(&'a EndpointLabels);
+
+impl<'a> fmt::Display for Labels<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt_labels(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metric_labels::Direction;
+    use crate::transport::tls;
+    use linkerd2_conditional::Conditional;
+
+    fn layer(consecutive_failures: u32) -> Layer<()> {
+        super::layer(
+            Config {
+                consecutive_failures,
+                failure_rate: None,
+                base_ejection: Duration::from_secs(10),
+                max_ejection: Duration::from_secs(30),
+                max_ejection_percent: 50,
+            },
+            Metrics::default(),
+        )
+    }
+
+    fn endpoint(layer: &Layer<()>, port: u16) -> Arc<Endpoint> {
+        let labels = EndpointLabels {
+            direction: Direction::Out,
+            tls_id: Conditional::None(tls::ReasonForNoIdentity::Disabled),
+            authority: None,
+            labels: Some(format!("port=\"{}\"", port)),
+        };
+        layer.endpoint((), ([127, 0, 0, 1], port).into(), labels)
+    }
+
+    #[test]
+    fn ejects_after_consecutive_failures() {
+        let layer = layer(2);
+        let ep0 = endpoint(&layer, 1);
+        let _ep1 = endpoint(&layer, 2);
+
+        ep0.record(true);
+        ep0.record(false);
+        ep0.record(true);
+        assert!(ep0.ejected_until().is_none());
+
+        ep0.record(true);
+        assert!(ep0.ejected_until().is_some());
+        assert_eq!(ep0.metrics.ejections.value(), 1);
+        assert_eq!(ep0.metrics.ejected.value(), 1);
+    }
+
+    #[test]
+    fn caps_ejected_endpoints() {
+        let layer = layer(1);
+        let ep0 = endpoint(&layer, 1);
+        let ep1 = endpoint(&layer, 2);
+        let ep2 = endpoint(&layer, 3);
+
+        ep0.record(true);
+        assert!(ep0.ejected_until().is_some());
+
+        // Only one of three endpoints may be ejected at once.
+        ep1.record(true);
+        assert!(ep1.ejected_until().is_none());
+
+        // Once the ejected endpoint is removed, another may be ejected.
+        drop(ep0);
+        ep2.record(true);
+        assert!(ep2.ejected_until().is_some());
+    }
+
+    #[test]
+    fn ejections_grow_exponentially() {
+        let config = layer(1).config;
+        assert_eq!(config.ejection(0), Duration::from_secs(10));
+        assert_eq!(config.ejection(1), Duration::from_secs(20));
+        assert_eq!(config.ejection(2), Duration::from_secs(30));
+        assert_eq!(config.ejection(40), Duration::from_secs(30));
+    }
+}
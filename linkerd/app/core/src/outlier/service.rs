@@ -0,0 +1,236 @@
+use super::{Endpoint, HasPool, Layer};
+use crate::classify;
+use crate::fault::Aborted;
+use crate::metric_labels::EndpointLabels;
+use crate::transport::connect::ConnectAddr;
+use crate::Error;
+use futures::{try_ready, Async, Future, Poll};
+use hyper::body::Payload;
+use linkerd2_http_classify::{ClassifyEos, ClassifyResponse};
+use std::hash::Hash;
+use std::sync::Arc;
+use tokio_timer::Delay;
+
+/// Wraps endpoint services so that they are ejected when they become outliers.
+#[derive(Clone, Debug)]
+pub struct MakeOutlier<M, P: Hash + Eq> {
+    layer: Layer<P>,
+    inner: M,
+}
+
+pub struct MakeFuture<F> {
+    endpoint: Option<Arc<Endpoint>>,
+    inner: F,
+}
+
+/// An endpoint service that is not ready while it is ejected.
+#[derive(Debug)]
+pub struct Service<S> {
+    endpoint: Arc<Endpoint>,
+    delay: Option<Delay>,
+    inner: S,
+}
+
+pub struct ResponseFuture<F> {
+    classify: Option<classify::Response>,
+    endpoint: Arc<Endpoint>,
+    inner: F,
+}
+
+#[derive(Debug)]
+pub struct ResponseBody<B> {
+    classify: Option<classify::Eos>,
+    endpoint: Arc<Endpoint>,
+    inner: B,
+}
+
+// === impl MakeOutlier ===
+
+impl<M, P: Hash + Eq> MakeOutlier<M, P> {
+    pub(super) fn new(layer: Layer<P>, inner: M) -> Self {
+        Self { layer, inner }
+    }
+}
+
+impl<T, M, P> tower::Service<T> for MakeOutlier<M, P>
+where
+    T: HasPool<Pool = P> + ConnectAddr + Clone + Into<EndpointLabels>,
+    M: tower::Service<T>,
+    P: Clone + Hash + Eq,
+{
+    type Response = Service<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let endpoint =
+            self.layer
+                .endpoint(target.pool(), target.connect_addr(), target.clone().into());
+        MakeFuture {
+            endpoint: Some(endpoint),
+            inner: self.inner.call(target),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Service<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        let endpoint = self.endpoint.take().expect("polled after ready");
+        Ok(Async::Ready(Service {
+            endpoint,
+            delay: None,
+            inner,
+        }))
+    }
+}
+
+// === impl Service ===
+
+impl<S, A, B> tower::Service<http::Request<A>> for Service<S>
+where
+    S: tower::Service<http::Request<A>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+    B: Payload,
+{
+    type Response = http::Response<ResponseBody<B>>;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        // While the endpoint is ejected, wait for the ejection to elapse so
+        // that the balancer does not dispatch requests to it.
+        while let Some(until) = self.endpoint.ejected_until() {
+            let delay = self.delay.get_or_insert_with(|| Delay::new(until));
+            delay.reset(until);
+            try_ready!(delay.poll());
+        }
+        self.delay = None;
+
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let classify = req
+            .extensions()
+            .get::<classify::Response>()
+            .cloned()
+            .unwrap_or_default();
+
+        ResponseFuture {
+            classify: Some(classify),
+            endpoint: self.endpoint.clone(),
+            inner: self.inner.call(req),
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+    B: Payload,
+{
+    type Item = http::Response<ResponseBody<B>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = match self.inner.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(rsp)) => rsp,
+            Err(e) => {
+                let e = e.into();
+                if let Some(classify) = self.classify.take() {
+                    // Injected faults say nothing about the endpoint's health.
+                    if !e.is::<Aborted>() {
+                        self.endpoint.record(classify.error(&e).is_failure());
+                    }
+                }
+                return Err(e);
+            }
+        };
+
+        let classify = self
+            .classify
+            .take()
+            .expect("polled after ready")
+            .start(&rsp);
+        let classify = if rsp.body().is_end_stream() {
+            self.endpoint.record(classify.eos(None).is_failure());
+            None
+        } else {
+            Some(classify)
+        };
+
+        let endpoint = self.endpoint.clone();
+        Ok(Async::Ready(rsp.map(|inner| ResponseBody {
+            classify,
+            endpoint,
+            inner,
+        })))
+    }
+}
+
+// === impl ResponseBody ===
+
+impl<B> ResponseBody<B> {
+    fn record_eos(&mut self, trailers: Option<&http::HeaderMap>) {
+        if let Some(classify) = self.classify.take() {
+            self.endpoint.record(classify.eos(trailers).is_failure());
+        }
+    }
+
+    fn record_error(&mut self, error: Error) -> Error {
+        if let Some(classify) = self.classify.take() {
+            self.endpoint.record(classify.error(&error).is_failure());
+        }
+        error
+    }
+}
+
+impl<B> Payload for ResponseBody<B>
+where
+    B: Payload,
+{
+    type Data = B::Data;
+    type Error = Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        let data = try_ready!(self
+            .inner
+            .poll_data()
+            .map_err(|e| self.record_error(e.into())));
+
+        // If the stream ended without trailers, classify it now, as
+        // `poll_trailers` may not be called.
+        if data.is_none() && self.inner.is_end_stream() {
+            self.record_eos(None);
+        }
+
+        Ok(Async::Ready(data))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        let trailers = try_ready!(self
+            .inner
+            .poll_trailers()
+            .map_err(|e| self.record_error(e.into())));
+        self.record_eos(trailers.as_ref());
+        Ok(Async::Ready(trailers))
+    }
+}
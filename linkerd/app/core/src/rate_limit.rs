@@ -0,0 +1,395 @@
+//! Limits the rate of requests on routes that have a rate limit.
+//!
+//! Each route's limits are enforced with token buckets: one for all of the
+//! route's requests and/or one for each client identity, as determined by the
+//! `tls::accept::Meta` set on the request. Requests that exceed a limit fail
+//! with a `RateLimited` error.
+//!
+//! A route's `RateLimit` proxy only marks requests with the route's `Limit`;
+//! limits are enforced by the target stack's `EnforceLayer`, once the request
+//! has been authorized, so that unauthorized requests do not consume tokens.
+//!
+//! Routes are rebuilt whenever their profile is updated, so buckets are shared
+//! by routes with the same destination, labels, and limits. A route's buckets
+//! are only replaced when its limits change.
+
+use super::dst::Route;
+use crate::profiles::{self, TokenBucket};
+use crate::proxy::identity;
+use crate::transport::tls;
+use crate::Addr;
+use crate::Error;
+use futures::{future, Future, Poll};
+use linkerd2_stack::{NewService, Proxy};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+use tokio_timer::clock;
+use tracing::debug;
+
+/// The number of client buckets after which full buckets are discarded.
+const MAX_IDLE_CLIENTS: usize = 1_000;
+
+pub fn layer() -> Layer {
+    Layer(Limiters::default())
+}
+
+pub fn enforce_layer() -> EnforceLayer {
+    EnforceLayer(())
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer(Limiters);
+
+#[derive(Clone, Debug)]
+pub struct MakeRateLimit<M> {
+    limiters: Limiters,
+    inner: M,
+}
+
+/// The limiters of all routes, so that they are retained as routes are
+/// rebuilt.
+type Limiters = Arc<Mutex<HashMap<Key, Weak<Limiter>>>>;
+
+/// Identifies a route's limiter by its destination, labels, and limits.
+type Key = (Addr, Vec<(String, String)>, profiles::RateLimit);
+
+#[derive(Clone, Debug)]
+pub struct RateLimit<P> {
+    inner: P,
+    limiter: Option<Arc<Limiter>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EnforceLayer(());
+
+#[derive(Clone, Debug)]
+pub struct Enforce<S> {
+    inner: S,
+}
+
+/// Marks a request with the limits of its route.
+#[derive(Clone, Debug)]
+pub struct Limit(Arc<Limiter>);
+
+/// Indicates that a request exceeded its route's rate limit.
+#[derive(Debug)]
+pub struct RateLimited(());
+
+#[derive(Debug)]
+struct Limiter {
+    total: Option<(TokenBucket, Mutex<Bucket>)>,
+    per_client: Option<(TokenBucket, Mutex<HashMap<Option<identity::Name>, Bucket>>)>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: u32,
+    last_fill: Instant,
+}
+
+// === impl Layer ===
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = MakeRateLimit<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeRateLimit {
+            limiters: self.0.clone(),
+            inner,
+        }
+    }
+}
+
+// === impl MakeRateLimit ===
+
+impl<M> NewService<Route> for MakeRateLimit<M>
+where
+    M: NewService<Route>,
+{
+    type Service = RateLimit<M::Service>;
+
+    fn new_service(&self, route: Route) -> Self::Service {
+        let limiter = route.route.rate_limit().map(|limit| {
+            let labels = route
+                .route
+                .labels()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            let key = (route.target.clone(), labels, limit.clone());
+            get_or_insert_limiter(&self.limiters, key)
+        });
+        RateLimit {
+            inner: self.inner.new_service(route),
+            limiter,
+        }
+    }
+}
+
+fn get_or_insert_limiter(limiters: &Limiters, key: Key) -> Arc<Limiter> {
+    let mut limiters = limiters.lock().expect("rate limiters lock poisoned");
+    if let Some(limiter) = limiters.get(&key).and_then(Weak::upgrade) {
+        return limiter;
+    }
+
+    // Drop limiters whose routes have been dropped.
+    limiters.retain(|_, l| l.upgrade().is_some());
+    debug!(limit = ?key.2, "Building limiter");
+    let limiter = Arc::new(Limiter::new(&key.2));
+    limiters.insert(key, Arc::downgrade(&limiter));
+    limiter
+}
+
+// === impl RateLimit ===
+
+impl<P, S, B> Proxy<http::Request<B>, S> for RateLimit<P>
+where
+    P: Proxy<http::Request<B>, S>,
+    S: tower::Service<P::Request>,
+{
+    type Request = P::Request;
+    type Response = P::Response;
+    type Error = P::Error;
+    type Future = P::Future;
+
+    fn proxy(&self, svc: &mut S, mut req: http::Request<B>) -> Self::Future {
+        if let Some(ref limiter) = self.limiter {
+            req.extensions_mut().insert(Limit(limiter.clone()));
+        }
+
+        self.inner.proxy(svc, req)
+    }
+}
+
+// === impl EnforceLayer ===
+
+impl<S> tower::layer::Layer<S> for EnforceLayer {
+    type Service = Enforce<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Enforce { inner }
+    }
+}
+
+// === impl Enforce ===
+
+impl<S, B> tower::Service<http::Request<B>> for Enforce<S>
+where
+    S: tower::Service<http::Request<B>>,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = future::Either<
+        future::MapErr<S::Future, fn(S::Error) -> Error>,
+        future::FutureResult<S::Response, Error>,
+    >;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        if let Some(Limit(limiter)) = req.extensions().get::<Limit>() {
+            let client_id = req
+                .extensions()
+                .get::<tls::accept::Meta>()
+                .and_then(|meta| meta.peer_identity.value().cloned());
+            if !limiter.try_acquire(client_id, clock::now()) {
+                debug!("Rate limited");
+                return future::Either::B(future::err(RateLimited(()).into()));
+            }
+        }
+
+        future::Either::A(self.inner.call(req).map_err(Into::into))
+    }
+}
+
+// === impl RateLimited ===
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rate limit exceeded")
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+// === impl Limiter ===
+
+impl Limiter {
+    fn new(limit: &profiles::RateLimit) -> Self {
+        let now = clock::now();
+        Self {
+            total: limit
+                .total
+                .map(|config| (config, Mutex::new(Bucket::new(&config, now)))),
+            per_client: limit
+                .per_client
+                .map(|config| (config, Mutex::new(HashMap::new()))),
+        }
+    }
+
+    /// Returns true if the request is admitted by all of the route's limits.
+    ///
+    /// A request rejected by the per-client limit does not consume a token
+    /// from the route's bucket.
+    fn try_acquire(&self, client_id: Option<identity::Name>, now: Instant) -> bool {
+        if let Some((ref config, ref clients)) = self.per_client {
+            let mut clients = clients.lock().expect("rate limit lock poisoned");
+            if clients.len() >= MAX_IDLE_CLIENTS && !clients.contains_key(&client_id) {
+                clients.retain(|_, bucket| !bucket.is_full(config, now));
+            }
+            let bucket = clients
+                .entry(client_id)
+                .or_insert_with(|| Bucket::new(config, now));
+            if !bucket.try_acquire(config, now) {
+                return false;
+            }
+        }
+
+        if let Some((ref config, ref bucket)) = self.total {
+            let mut bucket = bucket.lock().expect("rate limit lock poisoned");
+            if !bucket.try_acquire(config, now) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+// === impl Bucket ===
+
+impl Bucket {
+    fn new(config: &TokenBucket, now: Instant) -> Self {
+        Self {
+            tokens: config.max_tokens,
+            last_fill: now,
+        }
+    }
+
+    fn try_acquire(&mut self, config: &TokenBucket, now: Instant) -> bool {
+        self.fill(config, now);
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+
+    fn is_full(&mut self, config: &TokenBucket, now: Instant) -> bool {
+        self.fill(config, now);
+        self.tokens >= config.max_tokens
+    }
+
+    fn fill(&mut self, config: &TokenBucket, now: Instant) {
+        let interval = config.fill_interval.as_nanos();
+        if interval == 0 {
+            self.tokens = config.max_tokens;
+            return;
+        }
+
+        let elapsed = now.saturating_duration_since(self.last_fill).as_nanos();
+        let fills = elapsed / interval;
+        if fills == 0 {
+            return;
+        }
+
+        let tokens = u128::from(self.tokens) + fills * u128::from(config.tokens_per_fill);
+        self.tokens = tokens.min(u128::from(config.max_tokens)) as u32;
+        self.last_fill += config.fill_interval * (fills.min(u128::from(u32::MAX)) as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const BUCKET: TokenBucket = TokenBucket {
+        max_tokens: 2,
+        tokens_per_fill: 1,
+        fill_interval: Duration::from_secs(1),
+    };
+
+    fn id(name: &str) -> Option<identity::Name> {
+        Some(identity::Name::from_hostname(name.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn bucket_refills() {
+        let t0 = Instant::now();
+        let mut bucket = Bucket::new(&BUCKET, t0);
+        assert!(bucket.try_acquire(&BUCKET, t0));
+        assert!(bucket.try_acquire(&BUCKET, t0));
+        assert!(!bucket.try_acquire(&BUCKET, t0));
+
+        let t1 = t0 + Duration::from_millis(1500);
+        assert!(bucket.try_acquire(&BUCKET, t1));
+        assert!(!bucket.try_acquire(&BUCKET, t1));
+
+        // The partial interval is carried over.
+        let t2 = t0 + Duration::from_secs(2);
+        assert!(bucket.try_acquire(&BUCKET, t2));
+
+        let t3 = t0 + Duration::from_secs(60);
+        assert!(bucket.is_full(&BUCKET, t3));
+    }
+
+    #[test]
+    fn retains_limiters_until_limits_change() {
+        let limiters = Limiters::default();
+        let key = |limit: &profiles::RateLimit| {
+            let dst = Addr::from_str("web.ns.svc.cluster.local:8080").unwrap();
+            (dst, vec![("route".into(), "users".into())], limit.clone())
+        };
+        let limit = profiles::RateLimit {
+            total: Some(BUCKET),
+            per_client: None,
+        };
+
+        let a = get_or_insert_limiter(&limiters, key(&limit));
+        let b = get_or_insert_limiter(&limiters, key(&limit));
+        assert!(Arc::ptr_eq(&a, &b));
+
+        let changed = profiles::RateLimit {
+            total: Some(TokenBucket {
+                max_tokens: 3,
+                ..BUCKET
+            }),
+            per_client: None,
+        };
+        let c = get_or_insert_limiter(&limiters, key(&changed));
+        assert!(!Arc::ptr_eq(&a, &c));
+
+        // Once a limiter's routes are dropped, it is replaced.
+        drop((a, b));
+        let _d = get_or_insert_limiter(&limiters, key(&limit));
+        assert_eq!(limiters.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn limits_each_client_and_route() {
+        let limiter = Limiter::new(&profiles::RateLimit {
+            total: Some(TokenBucket {
+                max_tokens: 3,
+                ..BUCKET
+            }),
+            per_client: Some(BUCKET),
+        });
+        let now = Instant::now();
+        let foo = id("foo.ns.serviceaccount.identity.linkerd.cluster.local");
+        let bar = id("bar.ns.serviceaccount.identity.linkerd.cluster.local");
+
+        assert!(limiter.try_acquire(foo.clone(), now));
+        assert!(limiter.try_acquire(foo.clone(), now));
+        assert!(!limiter.try_acquire(foo, now));
+
+        // The route's limit is shared by all clients.
+        assert!(limiter.try_acquire(bar.clone(), now));
+        assert!(!limiter.try_acquire(bar, now));
+        assert!(!limiter.try_acquire(None, now));
+    }
+}
@@ -1,84 +1,202 @@
 use super::classify;
 use super::dst::Route;
+use super::fault;
 use super::handle_time;
 use super::http_metrics::retries::Handle;
 use super::transport::tls;
 use super::HttpRouteRetry;
 use crate::profiles;
-use futures::future;
+use crate::proxy::http::{boxed, replay::ReplayBody, HasH2Reason};
+use crate::Error;
+use futures::{Async, Future, Poll};
 use hyper::body::Payload;
+use linkerd2_exp_backoff::ExponentialBackoff;
 use linkerd2_http_classify::{Classify, ClassifyEos, ClassifyResponse};
 use linkerd2_retry::NewRetryLayer;
+use linkerd2_timeout::error::ResponseTimeout;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio_timer::{clock, Delay};
 use tower::retry::budget::Budget;
+use tracing::{debug, warn};
 
-pub fn layer(metrics: HttpRouteRetry) -> NewRetryLayer<NewRetry> {
-    NewRetryLayer::new(NewRetry::new(metrics))
+pub fn layer(metrics: HttpRouteRetry, max_body_bytes: usize) -> NewRetryLayer<NewRetry> {
+    NewRetryLayer::new(NewRetry::new(metrics, max_body_bytes))
 }
 
-pub trait CloneRequest<Req> {
-    fn clone_request(req: &Req) -> Option<Req>;
-}
-
-#[derive(Clone, Debug)]
-pub struct NewRetry<C = ()> {
+#[derive(Debug)]
+pub struct NewRetry<A = boxed::Payload> {
     metrics: HttpRouteRetry,
-    _clone_request: PhantomData<C>,
+    max_body_bytes: usize,
+    _body: PhantomData<fn(A)>,
 }
 
-pub struct Retry<C = ()> {
+pub struct Retry<A = boxed::Payload> {
     metrics: Handle,
     budget: Arc<Budget>,
     response_classes: profiles::ResponseClasses,
-    _clone_request: PhantomData<C>,
+    errors: profiles::RetryErrors,
+    per_try_timeout: Option<Duration>,
+    backoff: Option<ExponentialBackoff>,
+    /// The number of times the request has been retried.
+    retries: u32,
+    max_body_bytes: usize,
+    /// Buffers the request's body so that it may be replayed. Bodiless
+    /// requests are not buffered.
+    body: Option<ReplayBody<A>>,
+}
+
+/// Waits for a backoff to elapse before yielding the policy used to retry a
+/// request.
+pub struct Backoff<A> {
+    delay: Option<Delay>,
+    policy: Option<Retry<A>>,
 }
 
 impl NewRetry {
-    pub fn new(metrics: super::HttpRouteRetry) -> Self {
+    pub fn new(metrics: super::HttpRouteRetry, max_body_bytes: usize) -> Self {
         Self {
             metrics,
-            _clone_request: PhantomData,
+            max_body_bytes,
+            _body: PhantomData,
         }
     }
+}
 
-    pub fn clone_requests_via<C>(self) -> NewRetry<C> {
-        NewRetry {
-            metrics: self.metrics,
-            _clone_request: PhantomData,
+impl<A> Clone for NewRetry<A> {
+    fn clone(&self) -> Self {
+        Self {
+            metrics: self.metrics.clone(),
+            max_body_bytes: self.max_body_bytes,
+            _body: PhantomData,
         }
     }
 }
 
-impl<C> linkerd2_retry::NewPolicy<Route> for NewRetry<C> {
-    type Policy = Retry<C>;
+impl<A> linkerd2_retry::NewPolicy<Route> for NewRetry<A> {
+    type Policy = Retry<A>;
 
     fn new_policy(&self, route: &Route) -> Option<Self::Policy> {
         let retries = route.route.retries().cloned()?;
 
+        // Responses are classified for retries as soon as their headers are
+        // received, so classes that match trailers cannot be evaluated.
+        let classes = route.route.response_classes();
+        if classes.iter().any(|c| c.requires_eos()) {
+            warn!("Retries disabled for a route that classifies responses by trailers");
+            return None;
+        }
+
         let metrics = self.metrics.get_handle(route.clone());
         Some(Retry {
             metrics,
             budget: retries.budget().clone(),
             response_classes: route.route.response_classes().clone(),
-            _clone_request: self._clone_request,
+            errors: retries.errors(),
+            per_try_timeout: retries.per_try_timeout(),
+            backoff: retries.backoff().cloned(),
+            retries: 0,
+            max_body_bytes: self.max_body_bytes,
+            body: None,
         })
     }
 }
 
-impl<C, A, B, E> linkerd2_retry::Policy<http::Request<A>, http::Response<B>, E> for Retry<C>
+impl<A> linkerd2_retry::PrepareRequest<http::Request<A>> for Retry<A>
 where
-    C: CloneRequest<http::Request<A>>,
+    A: Payload,
+    ReplayBody<A>: Into<A>,
 {
-    type Future = future::FutureResult<Self, ()>;
+    fn prepare_request(&self, req: http::Request<A>) -> (Self, http::Request<A>) {
+        if req.body().is_end_stream() {
+            return (self.clone(), req);
+        }
+
+        let (head, body) = req.into_parts();
+        let body = ReplayBody::new(body, self.max_body_bytes);
+        let policy = Retry {
+            body: Some(body.clone()),
+            ..self.clone()
+        };
+        (policy, http::Request::from_parts(head, body.into()))
+    }
+}
+
+impl<A> linkerd2_retry::PerTryTimeout for Retry<A> {
+    fn per_try_timeout(&self) -> Option<Duration> {
+        self.per_try_timeout
+    }
+}
+
+impl<A> Retry<A> {
+    /// Determines whether an error belongs to one of the policy's retryable
+    /// error categories.
+    ///
+    /// Errors are only observed before response headers are received, so any
+    /// reset is a reset before response headers.
+    fn is_retryable_error(&self, err: &Error) -> bool {
+        if self.errors.timeout && err.is::<ResponseTimeout>() {
+            return true;
+        }
+
+        if let Some(reason) = err.h2_reason() {
+            return (self.errors.reset && reason == h2::Reason::REFUSED_STREAM)
+                || self.errors.reset_codes.contains(reason.into());
+        }
+
+        let mut source = Some(&**err as &(dyn std::error::Error + 'static));
+        while let Some(e) = source {
+            // HTTP/1 connections are established as requests are dispatched,
+            // so connection failures surface as request errors.
+            if let Some(e) = e.downcast_ref::<hyper::Error>() {
+                if e.is_connect() {
+                    return self.errors.connect_refused && is_connection_refused(e);
+                }
+            }
+            if let Some(e) = e.downcast_ref::<std::io::Error>() {
+                use std::io::ErrorKind;
+                match e.kind() {
+                    ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe => return self.errors.reset,
+                    _ => {}
+                }
+            }
+            source = e.source();
+        }
+
+        false
+    }
+}
+
+fn is_connection_refused(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            if e.kind() == std::io::ErrorKind::ConnectionRefused {
+                return true;
+            }
+        }
+        source = e.source();
+    }
+    false
+}
+
+impl<A, B> linkerd2_retry::Policy<http::Request<A>, http::Response<B>, Error> for Retry<A>
+where
+    A: Default + Payload,
+    ReplayBody<A>: Into<A>,
+{
+    type Future = Backoff<A>;
 
     fn retry(
         &self,
         req: &http::Request<A>,
-        result: Result<&http::Response<B>, &E>,
+        result: Result<&http::Response<B>, &Error>,
     ) -> Option<Self::Future> {
         let retryable = match result {
-            Err(_) => false,
+            Err(e) => self.is_retryable_error(e),
             Ok(rsp) => classify::Request::from(self.response_classes.clone())
                 .classify(req)
                 .start(rsp)
@@ -91,52 +209,103 @@ where
             return None;
         }
 
+        // If the request body exceeded the buffer, it cannot be replayed.
+        if self.body.as_ref().map_or(false, ReplayBody::is_capped) {
+            self.metrics.incr_body_too_large();
+            return None;
+        }
+
         let withdrew = self.budget.withdraw().is_ok();
         self.metrics.incr_retryable(withdrew);
         if !withdrew {
             return None;
         }
 
-        Some(future::ok(self.clone()))
+        let delay = self.backoff.map(|backoff| {
+            let delay = backoff.delay(self.retries, &mut rand::thread_rng());
+            Delay::new(clock::now() + delay)
+        });
+        let policy = Retry {
+            retries: self.retries.saturating_add(1),
+            ..self.clone()
+        };
+        Some(Backoff {
+            delay,
+            policy: Some(policy),
+        })
     }
 
     fn clone_request(&self, req: &http::Request<A>) -> Option<http::Request<A>> {
-        C::clone_request(req)
+        let body = match self.body {
+            Some(ref body) if body.is_capped() => return None,
+            Some(ref body) => body.clone().into(),
+            None => A::default(),
+        };
+
+        Some(clone_request(req, body))
     }
 }
 
-impl<C> Clone for Retry<C> {
+/// Clones a request's head, along with the extensions that must be preserved
+/// when it is dispatched more than once, with the given body.
+pub(crate) fn clone_request<A>(req: &http::Request<A>, body: A) -> http::Request<A> {
+    let mut clone = http::Request::new(body);
+    *clone.method_mut() = req.method().clone();
+    *clone.uri_mut() = req.uri().clone();
+    *clone.headers_mut() = req.headers().clone();
+    *clone.version_mut() = req.version();
+
+    if let Some(ext) = req.extensions().get::<tls::accept::Meta>() {
+        clone.extensions_mut().insert(ext.clone());
+    }
+
+    // Hedged requests are aborted along with the original request.
+    if let Some(ext) = req.extensions().get::<fault::Abort>() {
+        clone.extensions_mut().insert(*ext);
+    }
+
+    // Count retries toward the request's total handle time.
+    if let Some(ext) = req.extensions().get::<handle_time::Tracker>() {
+        clone.extensions_mut().insert(ext.clone());
+    }
+
+    clone
+}
+
+impl<A> Clone for Retry<A> {
     fn clone(&self) -> Self {
         Self {
             metrics: self.metrics.clone(),
             budget: self.budget.clone(),
             response_classes: self.response_classes.clone(),
-            _clone_request: self._clone_request,
+            errors: self.errors,
+            per_try_timeout: self.per_try_timeout,
+            backoff: self.backoff,
+            retries: self.retries,
+            max_body_bytes: self.max_body_bytes,
+            body: self.body.clone(),
         }
     }
 }
 
-impl<B: Default + Payload> CloneRequest<http::Request<B>> for () {
-    fn clone_request(req: &http::Request<B>) -> Option<http::Request<B>> {
-        if !req.body().is_end_stream() {
-            return None;
-        }
-
-        let mut clone = http::Request::new(B::default());
-        *clone.method_mut() = req.method().clone();
-        *clone.uri_mut() = req.uri().clone();
-        *clone.headers_mut() = req.headers().clone();
-        *clone.version_mut() = req.version();
+// === impl Backoff ===
 
-        if let Some(ext) = req.extensions().get::<tls::accept::Meta>() {
-            clone.extensions_mut().insert(ext.clone());
-        }
+impl<A> Future for Backoff<A> {
+    type Item = Retry<A>;
+    type Error = ();
 
-        // Count retries toward the request's total handle time.
-        if let Some(ext) = req.extensions().get::<handle_time::Tracker>() {
-            clone.extensions_mut().insert(ext.clone());
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(delay) = self.delay.as_mut() {
+            match delay.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {}
+                // If the timer fails, retry without waiting.
+                Err(error) => debug!(%error, "backoff timer failed"),
+            }
+            self.delay = None;
         }
 
-        Some(clone)
+        let policy = self.policy.take().expect("polled after ready");
+        Ok(Async::Ready(policy))
     }
 }
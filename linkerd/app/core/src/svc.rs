@@ -118,6 +118,19 @@ impl<L> Layers<L> {
         self.push(concurrency_limit::Layer::new(max))
     }
 
+    /// Rejects requests when the adaptive concurrency limit, if configured,
+    /// is exhausted. Responses are classified by `classify`.
+    pub fn push_adaptive_concurrency_limit<C: Clone>(
+        self,
+        config: Option<concurrency_limit::adaptive::Config>,
+        metrics: concurrency_limit::adaptive::Metrics,
+        classify: C,
+    ) -> Layers<Pair<L, concurrency_limit::adaptive::Layer<C>>> {
+        self.push(concurrency_limit::adaptive::Layer::new(
+            config, metrics, classify,
+        ))
+    }
+
     pub fn push_make_ready<Req>(self) -> Layers<Pair<L, stack::MakeReadyLayer<Req>>> {
         self.push(stack::MakeReadyLayer::new())
     }
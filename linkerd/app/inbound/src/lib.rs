@@ -11,9 +11,9 @@ pub use self::endpoint::{
 use self::require_identity_for_ports::RequireIdentityForPorts;
 use futures::future;
 use linkerd2_app_core::{
-    admit, classify,
+    admit, authz, classify, concurrency_limit,
     config::{ProxyConfig, ServerConfig},
-    drain, dst, errors, metric_labels,
+    drain, dst, errors, ext_authz, global_rate_limit, jwt, metric_labels,
     opencensus::proto::trace::v1 as oc,
     profiles,
     proxy::{
@@ -24,12 +24,12 @@ use linkerd2_app_core::{
         server::{Protocol as ServerProtocol, ProtocolDetect, Server},
         tap, tcp,
     },
-    reconnect, router, serve,
+    rate_limit, reconnect, router, serve,
     spans::SpanConverter,
     svc::{self, NewService},
     transport::{self, io::BoxedIo, tls},
-    Error, ProxyMetrics, TraceContextLayer, DST_OVERRIDE_HEADER, L5D_CLIENT_ID, L5D_REMOTE_IP,
-    L5D_SERVER_ID,
+    Error, ProxyMetrics, TraceContextLayer, DST_OVERRIDE_HEADER, L5D_CLIENT_ID, L5D_DST_CONCRETE,
+    L5D_REMOTE_IP, L5D_SERVER_ID,
 };
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -50,6 +50,8 @@ use self::prevent_loop::PreventLoop;
 pub struct Config {
     pub proxy: ProxyConfig,
     pub require_identity_for_inbound_ports: RequireIdentityForPorts,
+    pub adaptive_concurrency: Option<concurrency_limit::adaptive::Config>,
+    pub authz: authz::Policy,
 }
 
 pub struct Inbound {
@@ -63,6 +65,9 @@ impl Config {
         local_identity: tls::Conditional<identity::Local>,
         profiles_client: P,
         tap_layer: tap::Layer,
+        rate_limit_layer: global_rate_limit::Layer,
+        ext_authz_filter: Option<ext_authz::Filter>,
+        jwt_layer: jwt::Layer,
         metrics: ProxyMetrics,
         span_sink: Option<mpsc::Sender<oc::Span>>,
         drain: drain::Watch,
@@ -85,6 +90,8 @@ impl Config {
                     detect_protocol_timeout,
                 },
             require_identity_for_inbound_ports,
+            adaptive_concurrency,
+            authz,
         } = self;
 
         let listen = bind.bind().map_err(Error::from)?;
@@ -95,6 +102,11 @@ impl Config {
         // The stack is served lazily since some layers (notably buffer) spawn
         // tasks from their constructor. This helps to ensure that tasks are
         // spawned on the same runtime as the proxy.
+        let authorize = authz::Authorize::new(authz, metrics.authz.clone());
+        let ext_authz_layer = ext_authz::Layer::new(
+            ext_authz_filter.map(|f| f.with_client_identity(set_client_id_on_req::client_id)),
+        );
+
         let serve = Box::new(future::lazy(move || {
             // Establishes connections to the local application (for both
             // TCP forwarding and HTTP proxying).
@@ -110,7 +122,11 @@ impl Config {
                 .push_map_target(|meta: tls::accept::Meta| {
                     TcpEndpoint::from(meta.addrs.target_addr())
                 })
-                .push(svc::layer::mk(tcp::Forward::new));
+                // Closes connections from unauthorized clients.
+                .push(admit::AdmitLayer::new(authorize.clone()))
+                .push(svc::layer::mk(tcp::Forward::new))
+                // Registers forwarded connections to be tapped.
+                .push(tap_layer.tcp(tap::Direction::Inbound));
 
             // Creates HTTP clients for each inbound port & HTTP settings.
             let http_endpoint = tcp_connect
@@ -133,6 +149,9 @@ impl Config {
                 ));
 
             let http_profile_route_proxy = svc::proxies()
+                // Marks requests with the route's rate limits, if any, which
+                // are enforced once the request has been authorized.
+                .push(rate_limit::layer())
                 // Sets the route as a request extension so that it can be used
                 // by tap.
                 .push_http_insert_target()
@@ -163,6 +182,21 @@ impl Config {
                                 buffer_capacity,
                                 cache_max_idle_age,
                             )
+                            // Checks requests against the external rate limit
+                            // service, if one is configured.
+                            .push(rate_limit_layer)
+                            // Rejects requests that exceed their route's rate
+                            // limits, if any.
+                            .push(rate_limit::enforce_layer())
+                            // Checks requests with the external authorization
+                            // service, if one is configured.
+                            .push(ext_authz_layer)
+                            // Validates bearer tokens, if configured, setting
+                            // their claims as request headers.
+                            .push(jwt_layer)
+                            // Fails requests from clients that are not
+                            // authorized for the port or route.
+                            .push(admit::AdmitLayer::new(authorize))
                             .push(metrics.stack.layer(stack_labels("target"))),
                     ),
                 )
@@ -220,6 +254,14 @@ impl Config {
                 .push(svc::layer::mk(orig_proto::Downgrade::new))
                 // Limits the number of in-flight requests.
                 .push_concurrency_limit(max_in_flight_requests)
+                // Rejects requests when the adaptive limit, which follows the
+                // latency and errors of requests to the application, is
+                // exhausted.
+                .push_adaptive_concurrency_limit(
+                    adaptive_concurrency,
+                    metrics.http_adaptive_concurrency.clone(),
+                    classify::Request::default(),
+                )
                 // Eagerly fail requests when the proxy is out of capacity for a
                 // dispatch_timeout.
                 .push_failfast(dispatch_timeout)
@@ -251,6 +293,9 @@ impl Config {
                 // Removes the override header after it has been used to
                 // determine a reuquest target.
                 .push_on_response(strip_header::request::layer(DST_OVERRIDE_HEADER))
+                // Removes the concrete-dst header, which is set by the outbound
+                // proxy for tap, so that it is not forwarded to the application.
+                .push_on_response(strip_header::request::layer(L5D_DST_CONCRETE))
                 // Routes each request to a target, obtains a service for that
                 // target, and dispatches the request.
                 .instrument_from_target()
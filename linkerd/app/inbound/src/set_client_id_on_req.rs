@@ -11,15 +11,29 @@ use tracing::{debug, warn};
 
 pub fn layer() -> Layer<&'static str, tls::accept::Meta, ReqHeader> {
     add_header::request::layer(L5D_CLIENT_ID, |source: &tls::accept::Meta| {
-        if let Conditional::Some(ref id) = source.peer_identity {
-            if let Ok(value) = HeaderValue::from_str(id.as_ref()) {
-                debug!("l5d-client-id enabled");
-                return Some(value);
-            }
-
-            warn!("l5d-client-id identity header is invalid");
+        let id = peer_identity(source)?;
+        if let Ok(value) = HeaderValue::from_str(id) {
+            debug!("l5d-client-id enabled");
+            return Some(value);
         }
 
+        warn!("l5d-client-id identity header is invalid");
         None
     })
 }
+
+/// Returns the client identity that `l5d-client-id` carries for a request,
+/// given the request's extensions.
+pub fn client_id(extensions: &http::Extensions) -> Option<String> {
+    extensions
+        .get::<tls::accept::Meta>()
+        .and_then(peer_identity)
+        .map(String::from)
+}
+
+fn peer_identity(source: &tls::accept::Meta) -> Option<&str> {
+    match source.peer_identity {
+        Conditional::Some(ref id) => Some(id.as_ref()),
+        Conditional::None(_) => None,
+    }
+}
@@ -16,10 +16,18 @@ pub fn new_unordered() -> Controller {
     Controller::new_unordered()
 }
 
+pub fn ext_authz() -> ext_authz::Controller {
+    ext_authz::Controller::new()
+}
+
 pub fn identity() -> identity::Controller {
     identity::Controller::new()
 }
 
+pub fn rate_limit() -> rate_limit::Controller {
+    rate_limit::Controller::new()
+}
+
 pub type Labels = HashMap<String, String>;
 
 #[derive(Debug)]
@@ -0,0 +1,104 @@
+use super::*;
+use envoy_proto::envoy::config::core::v3::{HeaderValue, HeaderValueOption};
+use envoy_proto::envoy::r#type::v3::HttpStatus;
+use envoy_proto::envoy::service::auth::v3 as pb;
+use envoy_proto::google::rpc::Status;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// A mock external authorization service that answers checks with queued
+/// responses and records the requests it receives.
+#[derive(Clone)]
+pub struct Controller {
+    responses: Arc<Mutex<VecDeque<pb::CheckResponse>>>,
+    requests: Arc<Mutex<Vec<pb::CheckRequest>>>,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self {
+            responses: Arc::new(Mutex::new(VecDeque::new())),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Allows a request, adding the given headers to it.
+    pub fn allow(self, headers: &[(&str, &str)]) -> Self {
+        self.respond_with(pb::CheckResponse {
+            status: Some(Status {
+                code: 0,
+                message: String::new(),
+            }),
+            http_response: Some(pb::check_response::HttpResponse::OkResponse(
+                pb::OkHttpResponse {
+                    headers: header_options(headers),
+                },
+            )),
+        })
+    }
+
+    /// Denies a request with the given response.
+    pub fn deny(self, status: u16, headers: &[(&str, &str)], body: &str) -> Self {
+        self.respond_with(pb::CheckResponse {
+            status: Some(Status {
+                code: grpc::Code::PermissionDenied as i32,
+                message: String::new(),
+            }),
+            http_response: Some(pb::check_response::HttpResponse::DeniedResponse(
+                pb::DeniedHttpResponse {
+                    status: Some(HttpStatus {
+                        code: status.into(),
+                    }),
+                    headers: header_options(headers),
+                    body: body.to_owned(),
+                },
+            )),
+        })
+    }
+
+    pub fn respond_with(self, rsp: pb::CheckResponse) -> Self {
+        self.responses.lock().unwrap().push_back(rsp);
+        self
+    }
+
+    /// Returns the requests received so far.
+    pub fn requests(&self) -> Vec<pb::CheckRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn run(self) -> controller::Listening {
+        println!("running support authorization service");
+        controller::run(
+            pb::server::AuthorizationServer::new(self),
+            "support authorization service",
+            None,
+        )
+    }
+}
+
+fn header_options(headers: &[(&str, &str)]) -> Vec<HeaderValueOption> {
+    headers
+        .iter()
+        .map(|(key, value)| HeaderValueOption {
+            header: Some(HeaderValue {
+                key: (*key).to_owned(),
+                value: (*value).to_owned(),
+            }),
+        })
+        .collect()
+}
+
+impl pb::server::Authorization for Controller {
+    type CheckFuture = future::FutureResult<grpc::Response<pb::CheckResponse>, grpc::Status>;
+
+    fn check(&mut self, req: grpc::Request<pb::CheckRequest>) -> Self::CheckFuture {
+        self.requests.lock().unwrap().push(req.into_inner());
+        match self.responses.lock().unwrap().pop_front() {
+            Some(rsp) => future::ok(grpc::Response::new(rsp)),
+            None => future::err(grpc::Status::new(
+                grpc::Code::Unavailable,
+                "unit test authorization service has no results",
+            )),
+        }
+    }
+}
@@ -139,8 +139,10 @@ macro_rules! assert_eventually_contains {
 
 pub mod client;
 pub mod controller;
+pub mod ext_authz;
 pub mod identity;
 pub mod proxy;
+pub mod rate_limit;
 pub mod server;
 pub mod tap;
 pub mod tcp;
@@ -0,0 +1,68 @@
+use super::*;
+use envoy_proto::envoy::service::ratelimit::v3 as pb;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+pub use pb::rate_limit_response::Code;
+
+/// A mock rate limit service that answers checks with queued responses and
+/// records the requests it receives.
+#[derive(Clone)]
+pub struct Controller {
+    responses: Arc<Mutex<VecDeque<pb::RateLimitResponse>>>,
+    requests: Arc<Mutex<Vec<pb::RateLimitRequest>>>,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self {
+            responses: Arc::new(Mutex::new(VecDeque::new())),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn respond(self, code: Code) -> Self {
+        self.respond_with(pb::RateLimitResponse {
+            overall_code: code as i32,
+            ..Default::default()
+        })
+    }
+
+    pub fn respond_with(self, rsp: pb::RateLimitResponse) -> Self {
+        self.responses.lock().unwrap().push_back(rsp);
+        self
+    }
+
+    /// Returns the requests received so far.
+    pub fn requests(&self) -> Vec<pb::RateLimitRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn run(self) -> controller::Listening {
+        println!("running support rate limit service");
+        controller::run(
+            pb::server::RateLimitServiceServer::new(self),
+            "support rate limit service",
+            None,
+        )
+    }
+}
+
+impl pb::server::RateLimitService for Controller {
+    type ShouldRateLimitFuture =
+        future::FutureResult<grpc::Response<pb::RateLimitResponse>, grpc::Status>;
+
+    fn should_rate_limit(
+        &mut self,
+        req: grpc::Request<pb::RateLimitRequest>,
+    ) -> Self::ShouldRateLimitFuture {
+        self.requests.lock().unwrap().push(req.into_inner());
+        match self.responses.lock().unwrap().pop_front() {
+            Some(rsp) => future::ok(grpc::Response::new(rsp)),
+            None => future::err(grpc::Status::new(
+                grpc::Code::Unavailable,
+                "unit test rate limit service has no results",
+            )),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+#![deny(warnings, rust_2018_idioms)]
+
+use linkerd2_app_integration::*;
+
+const HOST: &str = "authz.test.svc.cluster.local";
+
+fn run(env: impl FnOnce(&server::Listening) -> TestEnv) -> (client::Client, proxy::Listening) {
+    let srv = server::http1().route("/", "hello").run();
+    let ctrl = controller::new();
+    ctrl.profile_tx_default(HOST);
+
+    let env = env(&srv);
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .inbound(srv)
+        .run_with_test_env(env);
+    let client = client::http1(proxy.inbound, HOST);
+    (client, proxy)
+}
+
+#[test]
+fn denies_unauthorized_requests_by_default() {
+    let _ = trace_init();
+    let (client, proxy) = run(|_| {
+        let mut env = TestEnv::new();
+        env.put(app::env::ENV_INBOUND_AUTHZ_DEFAULT, "deny".to_owned());
+        env
+    });
+
+    let rsp = client.request(&mut client.request_builder("/"));
+    assert_eq!(rsp.status(), http::StatusCode::FORBIDDEN);
+
+    let metrics = client::http1(proxy.metrics, "localhost");
+    assert_eventually_contains!(
+        metrics.get("/metrics"),
+        "inbound_authz_denied_total{target_port="
+    );
+}
+
+#[test]
+fn authorizes_unauthenticated_networks() {
+    let _ = trace_init();
+    let (client, _proxy) = run(|srv| {
+        let mut env = TestEnv::new();
+        env.put(app::env::ENV_INBOUND_AUTHZ_DEFAULT, "deny".to_owned());
+        env.put(
+            app::env::ENV_INBOUND_AUTHZ_RULES,
+            format!("{}=*,127.0.0.0/8", srv.addr.port()),
+        );
+        env
+    });
+
+    assert_eq!(client.get("/"), "hello");
+}
+
+#[test]
+fn requires_an_authorized_identity() {
+    let _ = trace_init();
+    let (client, _proxy) = run(|srv| {
+        let mut env = TestEnv::new();
+        env.put(
+            app::env::ENV_INBOUND_AUTHZ_RULES,
+            format!(
+                "{}=*.ns1.serviceaccount.identity.linkerd.cluster.local",
+                srv.addr.port()
+            ),
+        );
+        env
+    });
+
+    let rsp = client.request(&mut client.request_builder("/"));
+    assert_eq!(rsp.status(), http::StatusCode::FORBIDDEN);
+}
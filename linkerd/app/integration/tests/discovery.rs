@@ -642,6 +642,83 @@ mod http2 {
         let res = fut.wait().expect("/bye response");
         assert_eq!(res.status(), http::StatusCode::OK);
     }
+
+    /// Balances requests over two slow endpoints and then discovers a fast
+    /// one, returning the number of `requests` that the new endpoint served
+    /// immediately, and the number it served after `wait`.
+    fn new_endpoint_requests(env: TestEnv, wait: Duration, requests: usize) -> (usize, usize) {
+        let latency = Duration::from_millis(50);
+        let srv1 = server::http2()
+            .route_with_latency("/", "old", latency)
+            .run();
+        let srv2 = server::http2()
+            .route_with_latency("/", "old", latency)
+            .run();
+        let srv3 = server::http2().route("/", "new").run();
+
+        let host = "disco.test.svc.cluster.local";
+        let ctrl = controller::new();
+        ctrl.profile_tx_default(host);
+        let dst = ctrl.destination_tx(host);
+        dst.send_addr(srv1.addr);
+        dst.send_addr(srv2.addr);
+
+        let proxy = proxy::new().controller(ctrl.run()).run_with_test_env(env);
+        let client = client::http2(proxy.outbound, host);
+
+        for _ in 0..10 {
+            assert_eq!(client.get("/"), "old");
+        }
+
+        dst.send_addr(srv3.addr);
+        std::thread::sleep(Duration::from_millis(100));
+        let new =
+            |client: &client::Client| (0..requests).filter(|_| client.get("/") == "new").count();
+        let immediately = new(&client);
+
+        std::thread::sleep(wait);
+        (immediately, new(&client))
+    }
+
+    #[test]
+    fn outbound_balancer_slow_starts_new_endpoints() {
+        let _ = trace_init();
+
+        // The new endpoint's weight stays at its minimum for most of the
+        // window, and then quickly ramps up to its full weight.
+        let mut env = TestEnv::new();
+        env.put(app::env::ENV_OUTBOUND_SLOW_START_WINDOW, "4s".to_owned());
+        env.put(
+            app::env::ENV_OUTBOUND_SLOW_START_AGGRESSION,
+            "0.25".to_owned(),
+        );
+        env.put(
+            app::env::ENV_OUTBOUND_SLOW_START_MIN_WEIGHT,
+            "0.01".to_owned(),
+        );
+
+        // While its weight is a hundredth of the others', the fast endpoint is
+        // rarely sampled, even though it is the least loaded.
+        let (during, after) = new_endpoint_requests(env, Duration::from_secs(4), 20);
+        assert!(during <= 2, "new endpoint served {} of 20 requests", during);
+
+        // Once the window has elapsed, it is sampled as often as the others.
+        assert!(after >= 7, "new endpoint served {} of 20 requests", after);
+    }
+
+    #[test]
+    fn outbound_balancer_uses_new_endpoints_without_slow_start() {
+        let _ = trace_init();
+
+        // Without slow start, the fast endpoint is sampled as often as the
+        // others as soon as it is discovered.
+        let (immediately, _) = new_endpoint_requests(TestEnv::new(), Duration::from_secs(0), 20);
+        assert!(
+            immediately >= 7,
+            "new endpoint served {} of 20 requests",
+            immediately
+        );
+    }
 }
 
 mod http1 {
@@ -751,6 +828,32 @@ mod proxy_to_proxy {
         assert_eq!(res.status(), 200, "not successful");
     }
 
+    #[test]
+    fn inbound_should_strip_l5d_dst_concrete() {
+        let _ = trace_init();
+
+        let srv = server::http1()
+            .route_fn("/stripped", |req| {
+                assert_eq!(req.headers().get("l5d-dst-concrete"), None, "header is set");
+                Response::default()
+            })
+            .run();
+
+        let ctrl = controller::new();
+        ctrl.profile_tx_default("disco.test.svc.cluster.local");
+
+        let proxy = proxy::new().controller(ctrl.run()).inbound(srv).run();
+
+        let client = client::http1(proxy.inbound, "disco.test.svc.cluster.local");
+
+        let res = client.request(
+            client
+                .request_builder("/stripped")
+                .header("l5d-dst-concrete", "disco-v2.test.svc.cluster.local:80"),
+        );
+        assert_eq!(res.status(), 200, "not successful");
+    }
+
     #[test]
     fn outbound_should_strip_l5d_client_id() {
         let _ = trace_init();
@@ -0,0 +1,162 @@
+#![deny(warnings, rust_2018_idioms)]
+
+use linkerd2_app_integration::*;
+
+const HOST: &str = "extauthz.test.svc.cluster.local";
+
+struct Fixture {
+    client: client::Client,
+    _proxy: proxy::Listening,
+}
+
+fn fixture(ext_authz: &controller::Listening, failure_mode: Option<&str>) -> Fixture {
+    let mut env = TestEnv::new();
+    if let Some(mode) = failure_mode {
+        env.put(
+            app::env::ENV_INBOUND_EXT_AUTHZ_FAILURE_MODE,
+            mode.to_owned(),
+        );
+    }
+    fixture_with_env(ext_authz, env)
+}
+
+fn fixture_with_env(ext_authz: &controller::Listening, mut env: TestEnv) -> Fixture {
+    let srv = server::http1()
+        .route_fn("/", |req| {
+            // Echoes the header added by the authorization service.
+            let user = req
+                .headers()
+                .get("x-user")
+                .map(|v| v.to_str().unwrap().to_owned())
+                .unwrap_or_default();
+            Response::new(user.into())
+        })
+        .run();
+    let ctrl = controller::new();
+    ctrl.profile_tx_default(HOST);
+
+    env.put(
+        "LINKERD2_PROXY_INBOUND_EXT_AUTHZ_SVC_ADDR",
+        ext_authz.addr.to_string(),
+    );
+
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .inbound(srv)
+        .run_with_test_env(env);
+    let client = client::http1(proxy.inbound, HOST);
+
+    Fixture {
+        client,
+        _proxy: proxy,
+    }
+}
+
+#[test]
+fn forwards_allowed_requests_with_added_headers() {
+    let _ = trace_init();
+    let ext_authz = controller::ext_authz().allow(&[("x-user", "alice")]);
+    let listening = ext_authz.clone().run();
+    let Fixture { client, _proxy } = fixture(&listening, None);
+
+    let rsp = client.request(
+        client
+            .request_builder("/?q=1")
+            .method("POST")
+            .header("authorization", "Bearer token"),
+    );
+    assert_eq!(rsp.status(), http::StatusCode::OK);
+    let body = rsp.into_body().concat2().wait().unwrap();
+    assert_eq!(body, "alice");
+
+    let requests = ext_authz.requests();
+    assert_eq!(requests.len(), 1);
+    let http = requests[0]
+        .attributes
+        .as_ref()
+        .and_then(|a| a.request.as_ref())
+        .and_then(|r| r.http.as_ref())
+        .expect("request must have http attributes");
+    assert_eq!(http.method, "POST");
+    assert_eq!(http.path, "/?q=1");
+    assert_eq!(http.headers["authorization"], "Bearer token");
+}
+
+#[test]
+fn responds_to_denied_requests() {
+    let _ = trace_init();
+    let listening = controller::ext_authz()
+        .deny(401, &[("www-authenticate", "Bearer")], "denied")
+        .run();
+    let Fixture { client, _proxy } = fixture(&listening, None);
+
+    let rsp = client.request(&mut client.request_builder("/"));
+    assert_eq!(rsp.status(), http::StatusCode::UNAUTHORIZED);
+    assert_eq!(rsp.headers()["www-authenticate"], "Bearer");
+    let body = rsp.into_body().concat2().wait().unwrap();
+    assert_eq!(body, "denied");
+}
+
+#[test]
+fn fails_closed_when_unavailable() {
+    let _ = trace_init();
+    let listening = controller::ext_authz().run();
+    let Fixture { client, _proxy } = fixture(&listening, None);
+
+    let rsp = client.request(&mut client.request_builder("/"));
+    assert_eq!(rsp.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[test]
+fn fails_open_when_unavailable() {
+    let _ = trace_init();
+    let listening = controller::ext_authz().run();
+    let Fixture { client, _proxy } = fixture(&listening, Some("open"));
+
+    let rsp = client.request(&mut client.request_builder("/"));
+    assert_eq!(rsp.status(), http::StatusCode::OK);
+}
+
+#[test]
+fn denied_requests_do_not_consume_rate_limits() {
+    let _ = trace_init();
+    let ext_authz = controller::ext_authz()
+        .deny(403, &[], "denied")
+        .deny(403, &[], "denied")
+        .allow(&[("x-user", "alice")])
+        .allow(&[("x-user", "alice")]);
+    let listening = ext_authz.clone().run();
+
+    // The route admits a single request.
+    let dir = tempfile::tempdir().unwrap();
+    let policies = dir.path().join("policies.json");
+    let policy = format!(
+        r#"{{"{}:80": {{
+            "routes": [{{
+                "condition": {{ "path": "/" }},
+                "rateLimit": {{
+                    "total": {{ "maxTokens": 1, "tokensPerFill": 1, "fillIntervalMs": 3600000 }}
+                }}
+            }}]
+        }}}}"#,
+        HOST
+    );
+    std::fs::write(&policies, policy).unwrap();
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_DESTINATION_PROFILE_POLICIES_PATH,
+        policies.to_str().unwrap().to_owned(),
+    );
+    let Fixture { client, _proxy } = fixture_with_env(&listening, env);
+
+    for _ in 0..2 {
+        let rsp = client.request(&mut client.request_builder("/"));
+        assert_eq!(rsp.status(), http::StatusCode::FORBIDDEN);
+    }
+
+    // Denied requests did not consume the route's token.
+    assert_eq!(client.get("/"), "alice");
+    let rsp = client.request(&mut client.request_builder("/"));
+    assert_eq!(rsp.status(), http::StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(ext_authz.requests().len(), 4);
+}
@@ -0,0 +1,147 @@
+#![deny(warnings, rust_2018_idioms)]
+
+use linkerd2_app_integration::*;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HOST: &str = "jwt.test.svc.cluster.local";
+
+struct Signer {
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl Signer {
+    fn new() -> Self {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+        Self { key, rng }
+    }
+
+    /// Writes the signer's public key as a JWKS file.
+    fn write_jwks(&self, name: &str) -> PathBuf {
+        let point = self.key.public_key().as_ref();
+        let jwks = format!(
+            r#"{{"keys":[{{"kty":"EC","crv":"P-256","kid":"test","x":"{}","y":"{}"}}]}}"#,
+            encode(&point[1..33]),
+            encode(&point[33..])
+        );
+        let path = std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()));
+        std::fs::write(&path, jwks).unwrap();
+        path
+    }
+
+    fn sign(&self, sub: &str, exp: u64) -> String {
+        let header = r#"{"alg":"ES256","kid":"test"}"#;
+        let claims = format!(
+            r#"{{"iss":"issuer","aud":"web","sub":"{}","exp":{}}}"#,
+            sub, exp
+        );
+        let message = format!(
+            "{}.{}",
+            encode(header.as_bytes()),
+            encode(claims.as_bytes())
+        );
+        let signature = self.key.sign(&self.rng, message.as_bytes()).unwrap();
+        format!("{}.{}", message, encode(signature.as_ref()))
+    }
+}
+
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn run(jwks: &PathBuf) -> (client::Client, proxy::Listening) {
+    let srv = server::http1()
+        .route_fn("/", |req| {
+            let sub = req
+                .headers()
+                .get("l5d-jwt-sub")
+                .map(|v| v.to_str().unwrap().to_owned())
+                .unwrap_or_default();
+            Response::new(sub.into())
+        })
+        .run();
+    let ctrl = controller::new();
+    ctrl.profile_tx_default(HOST);
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_INBOUND_JWT_JWKS_PATH,
+        jwks.to_str().unwrap().to_owned(),
+    );
+    env.put(app::env::ENV_INBOUND_JWT_ISSUER, "issuer".to_owned());
+    env.put(app::env::ENV_INBOUND_JWT_AUDIENCES, "api,web".to_owned());
+
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .inbound(srv)
+        .run_with_test_env(env);
+    let client = client::http1(proxy.inbound, HOST);
+    (client, proxy)
+}
+
+#[test]
+fn forwards_claims_of_valid_tokens() {
+    let _ = trace_init();
+    let signer = Signer::new();
+    let jwks = signer.write_jwks("forwards-claims");
+    let (client, _proxy) = run(&jwks);
+
+    let token = signer.sign("alice", now() + 600);
+    let rsp = client.request(
+        client
+            .request_builder("/")
+            .header("authorization", format!("Bearer {}", token))
+            // Clients may not set claim headers themselves.
+            .header("l5d-jwt-sub", "mallory"),
+    );
+    assert_eq!(rsp.status(), http::StatusCode::OK);
+    let body = rsp.into_body().concat2().wait().unwrap();
+    assert_eq!(body, "alice");
+}
+
+#[test]
+fn rejects_invalid_tokens() {
+    let _ = trace_init();
+    let signer = Signer::new();
+    let jwks = signer.write_jwks("rejects-invalid");
+    let (client, proxy) = run(&jwks);
+
+    let rsp = client.request(&mut client.request_builder("/"));
+    assert_eq!(rsp.status(), http::StatusCode::UNAUTHORIZED);
+    assert!(rsp.headers().contains_key("www-authenticate"));
+
+    let expired = signer.sign("alice", now() - 600);
+    let rsp = client.request(
+        client
+            .request_builder("/")
+            .header("authorization", format!("Bearer {}", expired)),
+    );
+    assert_eq!(rsp.status(), http::StatusCode::UNAUTHORIZED);
+
+    let forged = Signer::new().sign("alice", now() + 600);
+    let rsp = client.request(
+        client
+            .request_builder("/")
+            .header("authorization", format!("Bearer {}", forged)),
+    );
+    assert_eq!(rsp.status(), http::StatusCode::UNAUTHORIZED);
+
+    let metrics = client::http1(proxy.metrics, "localhost");
+    assert_eventually_contains!(
+        metrics.get("/metrics"),
+        "request_errors_total{direction=\"inbound\",message=\"unauthenticated\"}"
+    );
+}
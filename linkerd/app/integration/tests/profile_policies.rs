@@ -0,0 +1,711 @@
+#![deny(warnings, rust_2018_idioms)]
+
+use linkerd2_app_integration::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tempfile::TempDir;
+
+const HOST: &str = "policies.test.svc.cluster.local";
+
+/// A profile policy file, which is removed when dropped.
+struct PolicyFile {
+    path: PathBuf,
+    _dir: TempDir,
+}
+
+impl PolicyFile {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replaces the file with one that applies the JSON `policy` to `HOST`.
+    fn write(&self, policy: &str) {
+        let policies = format!(r#"{{"{}:80":{}}}"#, HOST, policy);
+        // Write and rename the file so that the proxy never reads a partially
+        // written file.
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, policies).unwrap();
+        std::fs::rename(&tmp, &self.path).unwrap();
+    }
+}
+
+/// Writes a profile policy file that applies the JSON `policy` to `HOST`.
+fn write_policies(policy: &str) -> PolicyFile {
+    let dir = tempfile::tempdir().unwrap();
+    let file = PolicyFile {
+        path: dir.path().join("policies.json"),
+        _dir: dir,
+    };
+    file.write(policy);
+    file
+}
+
+struct Test {
+    client: client::Client,
+    metrics: client::Client,
+    _proxy: proxy::Listening,
+    _dst: controller::DstSender,
+    _profile: controller::ProfileSender,
+    _endpoints: Vec<server::Listening>,
+}
+
+fn run(srv: server::Listening, policies: &PolicyFile) -> Test {
+    run_balanced(srv, Vec::new(), policies)
+}
+
+/// Runs a proxy that balances requests to `HOST` over `srv` and `endpoints`.
+fn run_balanced(
+    srv: server::Listening,
+    endpoints: Vec<server::Listening>,
+    policies: &PolicyFile,
+) -> Test {
+    run_with_client(srv, endpoints, policies, |addr| client::http1(addr, HOST))
+}
+
+/// Runs a proxy whose client sends HTTP/2 requests, which the proxy forwards
+/// to `srv` over HTTP/2.
+fn run_h2(srv: server::Listening, policies: &PolicyFile) -> Test {
+    run_with_client(srv, Vec::new(), policies, |addr| client::http2(addr, HOST))
+}
+
+fn run_with_client(
+    srv: server::Listening,
+    endpoints: Vec<server::Listening>,
+    policies: &PolicyFile,
+    client: impl FnOnce(SocketAddr) -> client::Client,
+) -> Test {
+    let ctrl = controller::new();
+    let dst = ctrl.destination_tx(HOST);
+    dst.send_addr(srv.addr);
+    for ep in endpoints.iter() {
+        dst.send_addr(ep.addr);
+    }
+    let profile = ctrl.profile_tx_default(HOST);
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_DESTINATION_PROFILE_POLICIES_PATH,
+        policies.path().to_str().unwrap().to_owned(),
+    );
+    env.put(
+        app::env::ENV_DESTINATION_PROFILE_POLICIES_RELOAD_INTERVAL,
+        "100ms".to_owned(),
+    );
+
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .outbound(srv)
+        .run_with_test_env(env);
+    Test {
+        client: client(proxy.outbound),
+        metrics: client::http1(proxy.metrics, "localhost"),
+        _proxy: proxy,
+        _dst: dst,
+        _profile: profile,
+        _endpoints: endpoints,
+    }
+}
+
+#[test]
+fn routes_on_headers_and_query_params() {
+    let _ = trace_init();
+
+    let srv = server::http1()
+        .route_fn("/sleep", |_| {
+            ::std::thread::sleep(Duration::from_millis(500));
+            Response::new("slept".into())
+        })
+        .run();
+    let policies = write_policies(
+        r#"{
+            "routes": [{
+                "condition": { "any": [
+                    { "header": { "name": "x-api-version", "exact": "v2" } },
+                    { "query": { "name": "version", "regex": "2(\\.[0-9]+)?" } }
+                ]},
+                "labels": { "version": "v2" },
+                "timeoutMs": 100
+            }]
+        }"#,
+    );
+    let test = run(srv, &policies);
+    let client = &test.client;
+
+    let rsp = client.request(
+        client
+            .request_builder("/sleep")
+            .header("x-api-version", "v2"),
+    );
+    assert_eq!(rsp.status(), 504);
+    let rsp = client.request(&mut client.request_builder("/sleep?version=2.1"));
+    assert_eq!(rsp.status(), 504);
+    assert_eq!(client.get("/sleep?version=3"), "slept");
+    assert_eq!(client.get("/sleep"), "slept");
+
+    assert_eventually_contains!(test.metrics.get("/metrics"), "rt_version=\"v2\"");
+}
+
+#[test]
+fn classifies_responses_by_grpc_status() {
+    let _ = trace_init();
+
+    let srv = server::http1()
+        .route_fn("/missing", |_| {
+            Response::builder()
+                .header("grpc-status", "5")
+                .body(Bytes::new())
+                .unwrap()
+        })
+        .run();
+    // A `NOT_FOUND` status is not ordinarily a failure.
+    let policies = write_policies(
+        r#"{
+            "routes": [{
+                "condition": { "path": "/missing" },
+                "labels": { "route": "missing" },
+                "responseClasses": [
+                    { "condition": { "grpcStatus": 5 }, "isFailure": true }
+                ]
+            }]
+        }"#,
+    );
+    let test = run(srv, &policies);
+
+    assert_eq!(test.client.get("/missing"), "");
+    assert_eventually_contains!(
+        test.metrics.get("/metrics"),
+        "route_response_total{direction=\"outbound\",dst=\"policies.test.svc.cluster.local:80\",rt_route=\"missing\",status_code=\"200\",classification=\"failure\"} 1"
+    );
+}
+
+#[test]
+fn retries_attempts_that_exceed_per_try_timeout() {
+    let _ = trace_init();
+
+    let attempts = AtomicUsize::new(0);
+    let srv = server::http1()
+        .route_fn("/slow-once", move |_| {
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                ::std::thread::sleep(Duration::from_secs(1));
+            }
+            Response::new("retried".into())
+        })
+        .run();
+    // Without the per-try timeout, the request would exceed the route's
+    // timeout.
+    let policies = write_policies(
+        r#"{
+            "retryBudget": { "minRetriesPerSecond": 10, "retryRatio": 0.2, "ttlMs": 10000 },
+            "routes": [{
+                "condition": { "path": "/slow-once" },
+                "isRetryable": true,
+                "retries": {
+                    "errors": ["timeout"],
+                    "perTryTimeoutMs": 100,
+                    "backoff": { "minMs": 10, "maxMs": 10 }
+                },
+                "timeoutMs": 500
+            }]
+        }"#,
+    );
+    let test = run(srv, &policies);
+
+    assert_eq!(test.client.get("/slow-once"), "retried");
+}
+
+/// Serves `/reset-once`, resetting the first attempt's stream with `reason`.
+fn reset_once(reason: h2::Reason, attempts: Arc<AtomicUsize>) -> server::Listening {
+    server::http2()
+        .route_async("/reset-once", move |_| {
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(h2::Error::from(reason));
+            }
+            Ok(Response::new("retried".into()))
+        })
+        .run()
+}
+
+const RETRY_RESETS: &str = r#"{
+    "retryBudget": { "minRetriesPerSecond": 10, "retryRatio": 0.2, "ttlMs": 10000 },
+    "routes": [{
+        "condition": { "path": "/reset-once" },
+        "isRetryable": true,
+        "retries": { "errors": ["reset"], "backoff": { "minMs": 10, "maxMs": 10 } }
+    }]
+}"#;
+
+#[test]
+fn retries_refused_streams() {
+    let _ = trace_init();
+
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let srv = reset_once(h2::Reason::REFUSED_STREAM, attempts.clone());
+    let policies = write_policies(RETRY_RESETS);
+    let test = run_h2(srv, &policies);
+
+    assert_eq!(test.client.get("/reset-once"), "retried");
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn does_not_retry_unlisted_resets() {
+    let _ = trace_init();
+
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let srv = reset_once(h2::Reason::INTERNAL_ERROR, attempts.clone());
+    let policies = write_policies(RETRY_RESETS);
+    let test = run_h2(srv, &policies);
+
+    let client = &test.client;
+    let rsp = client
+        .request_async(&mut client.request_builder("/reset-once"))
+        .wait();
+    assert!(rsp.map(|rsp| !rsp.status().is_success()).unwrap_or(true));
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn retries_listed_resets() {
+    let _ = trace_init();
+
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let srv = reset_once(h2::Reason::INTERNAL_ERROR, attempts.clone());
+    let policies = write_policies(
+        r#"{
+            "retryBudget": { "minRetriesPerSecond": 10, "retryRatio": 0.2, "ttlMs": 10000 },
+            "routes": [{
+                "condition": { "path": "/reset-once" },
+                "isRetryable": true,
+                "retries": {
+                    "resetCodes": ["INTERNAL_ERROR"],
+                    "backoff": { "minMs": 10, "maxMs": 10 }
+                }
+            }]
+        }"#,
+    );
+    let test = run_h2(srv, &policies);
+
+    assert_eq!(test.client.get("/reset-once"), "retried");
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn hedges_requests_to_other_endpoints() {
+    let _ = trace_init();
+
+    // The first request marked as slow blocks the endpoint that receives it,
+    // so its hedge is only served promptly by the other endpoint.
+    let slow = Arc::new(Mutex::new(None));
+    let endpoint = |name: &'static str| {
+        let slow = slow.clone();
+        server::http1()
+            .route_fn("/hedge", move |req| {
+                if req.headers().contains_key("x-slow") {
+                    let mut slow = slow.lock().unwrap();
+                    if slow.is_none() {
+                        *slow = Some(name);
+                        drop(slow);
+                        ::std::thread::sleep(Duration::from_secs(2));
+                    }
+                }
+                Response::new(name.into())
+            })
+            .run()
+    };
+    let srv = endpoint("a");
+    let other = endpoint("b");
+    let policies = write_policies(
+        r#"{
+            "retryBudget": { "minRetriesPerSecond": 10, "retryRatio": 0.2, "ttlMs": 10000 },
+            "routes": [{
+                "condition": { "path": "/hedge" },
+                "isRetryable": true,
+                "hedge": { "percentile": 50 }
+            }]
+        }"#,
+    );
+    let test = run_balanced(srv, vec![other], &policies);
+    let client = &test.client;
+
+    // Hedging delays are derived from the route's latencies, so they must be
+    // recorded before requests are hedged.
+    for _ in 0..10 {
+        client.get("/hedge");
+    }
+
+    let rsp = client.request(client.request_builder("/hedge").header("x-slow", "1"));
+    assert_eq!(rsp.status(), 200);
+    let body = rsp.into_body().concat2().wait().unwrap();
+    let slow = slow.lock().unwrap().expect("a request must be slow");
+    // The original request is still blocked, so the hedge responded.
+    assert_ne!(&body[..], slow.as_bytes());
+}
+
+#[test]
+fn hashes_requests_by_header() {
+    let _ = trace_init();
+
+    let srv = |name: &'static str| {
+        server::http1()
+            .route_fn("/", move |_| Response::new(name.into()))
+            .run()
+    };
+    let policies = write_policies(
+        r#"{
+            "loadBalancer": { "consistentHash": { "header": { "name": "x-user" } } }
+        }"#,
+    );
+    let test = run_balanced(srv("a"), vec![srv("b"), srv("c")], &policies);
+    let client = &test.client;
+
+    let get = |user: &str| {
+        let rsp = client.request(client.request_builder("/").header("x-user", user));
+        assert_eq!(rsp.status(), 200);
+        rsp.into_parts()
+            .1
+            .concat2()
+            .map(|body| ::std::str::from_utf8(&body).unwrap().to_string())
+            .wait()
+            .expect("response body")
+    };
+    for user in &["alice", "bob", "carol"] {
+        let first = get(user);
+        for _ in 0..10 {
+            assert_eq!(get(user), first);
+        }
+    }
+}
+
+#[test]
+fn removes_endpoints_that_fail_health_checks() {
+    let _ = trace_init();
+
+    let srv = |name: &'static str, healthy: bool| {
+        server::http1()
+            .route_fn("/healthz", move |_| {
+                let status = if healthy {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                Response::builder()
+                    .status(status)
+                    .body(Bytes::new())
+                    .unwrap()
+            })
+            .route_fn("/", move |_| Response::new(name.into()))
+            .run()
+    };
+    let policies = write_policies(
+        r#"{
+            "healthCheck": {
+                "probe": { "http": { "path": "/healthz" } },
+                "intervalMs": 100,
+                "timeoutMs": 100,
+                "unhealthyThreshold": 1
+            }
+        }"#,
+    );
+    let test = run_balanced(
+        srv("unhealthy", false),
+        vec![srv("healthy", true)],
+        &policies,
+    );
+
+    assert_eventually!(
+        (0..10).all(|_| test.client.get("/") == "healthy"),
+        "requests must not be sent to the unhealthy endpoint"
+    );
+}
+
+#[test]
+fn aborts_requests_with_faults() {
+    let _ = trace_init();
+
+    let srv = server::http1()
+        .route("/", "hello")
+        .route("/aborted", "not aborted")
+        .run();
+    let policies = write_policies(
+        r#"{
+            "routes": [{
+                "condition": { "path": "/aborted" },
+                "fault": { "abort": { "percentage": 100, "httpStatus": 503 } }
+            }]
+        }"#,
+    );
+    let test = run(srv, &policies);
+    let client = &test.client;
+
+    let rsp = client.request(&mut client.request_builder("/aborted"));
+    assert_eq!(rsp.status(), 503);
+    assert_eq!(client.get("/"), "hello");
+}
+
+#[test]
+fn reloads_policies_when_the_file_changes() {
+    let _ = trace_init();
+
+    let srv = server::http1().route("/", "hello").run();
+    let policies = write_policies(
+        r#"{
+            "routes": [{
+                "condition": { "path": "/" },
+                "fault": { "abort": { "percentage": 100, "httpStatus": 503 } }
+            }]
+        }"#,
+    );
+    let test = run(srv, &policies);
+    let client = &test.client;
+
+    let rsp = client.request(&mut client.request_builder("/"));
+    assert_eq!(rsp.status(), 503);
+
+    // An invalid file is ignored, so the previous policy is retained.
+    policies.write(r#"{ "routes": 1 }"#);
+    std::thread::sleep(Duration::from_millis(500));
+    let rsp = client.request(&mut client.request_builder("/"));
+    assert_eq!(rsp.status(), 503);
+
+    policies.write("{}");
+    assert_eventually!(
+        client.request(&mut client.request_builder("/")).status() == 200,
+        "policy was not reloaded"
+    );
+    assert_eq!(client.get("/"), "hello");
+}
+
+#[test]
+fn mirrors_requests() {
+    let _ = trace_init();
+
+    const MIRROR: &str = "mirror.test.svc.cluster.local";
+
+    let mirrored = Arc::new(AtomicUsize::new(0));
+    let srv = server::http1().route("/", "hello").run();
+    let mirror = {
+        let mirrored = mirrored.clone();
+        server::http1()
+            .route_fn("/", move |req| {
+                assert_eq!(req.headers()["l5d-mirror"], "true");
+                mirrored.fetch_add(1, Ordering::SeqCst);
+                Response::new("mirrored".into())
+            })
+            .run()
+    };
+    let policies = write_policies(&format!(
+        r#"{{
+                "routes": [{{
+                    "condition": {{ "method": "GET" }},
+                    "mirror": {{ "authority": "{}:80", "percentage": 100 }}
+                }}]
+            }}"#,
+        MIRROR
+    ));
+
+    let ctrl = controller::new();
+    let dst = ctrl.destination_tx(HOST);
+    dst.send_addr(srv.addr);
+    let mirror_dst = ctrl.destination_tx(MIRROR);
+    mirror_dst.send_addr(mirror.addr);
+    let _profile = ctrl.profile_tx_default(HOST);
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_DESTINATION_PROFILE_POLICIES_PATH,
+        policies.path().to_str().unwrap().to_owned(),
+    );
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .outbound(srv)
+        .run_with_test_env(env);
+    let client = client::http1(proxy.outbound, HOST);
+
+    // Responses are served by the original destination, not the mirror.
+    for _ in 0..3 {
+        assert_eq!(client.get("/"), "hello");
+    }
+    assert_eventually!(
+        mirrored.load(Ordering::SeqCst) == 3,
+        "each request must be mirrored"
+    );
+}
+
+#[test]
+fn mirrors_request_bodies() {
+    let _ = trace_init();
+
+    const MIRROR: &str = "mirror.test.svc.cluster.local";
+
+    let bodies = Arc::new(Mutex::new(Vec::new()));
+    let srv = server::http1()
+        .route_async("/", |req| {
+            req.into_body()
+                .concat2()
+                .map_err(|()| "req concat error")
+                .map(|body| {
+                    assert_eq!(body, "hello");
+                    Response::new("world".into())
+                })
+        })
+        .run();
+    let mirror = {
+        let bodies = bodies.clone();
+        server::http1()
+            .route_async("/", move |req| {
+                let bodies = bodies.clone();
+                req.into_body()
+                    .concat2()
+                    .map_err(|()| "req concat error")
+                    .map(move |body| {
+                        let body = std::str::from_utf8(&body).unwrap().to_string();
+                        bodies.lock().unwrap().push(body);
+                        Response::new("mirrored".into())
+                    })
+            })
+            .run()
+    };
+    let policies = write_policies(&format!(
+        r#"{{
+                "routes": [{{
+                    "condition": {{ "method": "POST" }},
+                    "mirror": {{ "authority": "{}:80", "percentage": 100 }}
+                }}]
+            }}"#,
+        MIRROR
+    ));
+
+    let ctrl = controller::new();
+    let dst = ctrl.destination_tx(HOST);
+    dst.send_addr(srv.addr);
+    let mirror_dst = ctrl.destination_tx(MIRROR);
+    mirror_dst.send_addr(mirror.addr);
+    let _profile = ctrl.profile_tx_default(HOST);
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_DESTINATION_PROFILE_POLICIES_PATH,
+        policies.path().to_str().unwrap().to_owned(),
+    );
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .outbound(srv)
+        .run_with_test_env(env);
+    let client = client::http1(proxy.outbound, HOST);
+
+    for _ in 0..3 {
+        let req = client
+            .request_builder("/")
+            .method("POST")
+            .body("hello".into())
+            .unwrap();
+        let rsp = client.request_body(req);
+        assert_eq!(rsp.status(), 200);
+        let body = rsp.into_body().concat2().wait().unwrap();
+        assert_eq!(body, "world");
+    }
+    assert_eventually!(
+        bodies.lock().unwrap().len() == 3,
+        "each request must be mirrored"
+    );
+    assert!(bodies.lock().unwrap().iter().all(|body| body == "hello"));
+}
+
+#[test]
+fn routes_requests_by_dst_match() {
+    let _ = trace_init();
+
+    const CANARY: &str = "canary.test.svc.cluster.local";
+
+    let srv = server::http1().route("/", "stable").run();
+    let canary = server::http1().route("/", "canary").run();
+    let policies = write_policies(&format!(
+        r#"{{
+                "dstMatches": [{{
+                    "condition": {{ "header": {{ "name": "x-canary", "exact": "true" }} }},
+                    "authority": "{}:80"
+                }}]
+            }}"#,
+        CANARY
+    ));
+
+    let ctrl = controller::new();
+    let dst = ctrl.destination_tx(HOST);
+    dst.send_addr(srv.addr);
+    let canary_dst = ctrl.destination_tx(CANARY);
+    canary_dst.send_addr(canary.addr);
+    let _profile = ctrl.profile_tx_default(HOST);
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_DESTINATION_PROFILE_POLICIES_PATH,
+        policies.path().to_str().unwrap().to_owned(),
+    );
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .outbound(srv)
+        .run_with_test_env(env);
+    let client = client::http1(proxy.outbound, HOST);
+
+    let get_canary = || {
+        let rsp = client.request(client.request_builder("/").header("x-canary", "true"));
+        assert_eq!(rsp.status(), 200);
+        rsp.into_parts()
+            .1
+            .concat2()
+            .map(|body| ::std::str::from_utf8(&body).unwrap().to_string())
+            .wait()
+            .expect("response body")
+    };
+    for _ in 0..3 {
+        assert_eq!(get_canary(), "canary");
+        assert_eq!(client.get("/"), "stable");
+    }
+}
+
+#[test]
+fn pins_clients_with_sticky_cookies() {
+    let _ = trace_init();
+
+    let srv = |name: &'static str| {
+        server::http1()
+            .route_fn("/", move |_| Response::new(name.into()))
+            .run()
+    };
+    let policies = write_policies(
+        r#"{
+            "loadBalancer": { "stickyCookie": { "name": "l5d-sticky" } }
+        }"#,
+    );
+    let test = run_balanced(srv("a"), vec![srv("b"), srv("c")], &policies);
+    let client = &test.client;
+
+    let get = |cookie: Option<&str>| {
+        let mut req = client.request_builder("/");
+        if let Some(cookie) = cookie {
+            req.header("cookie", cookie);
+        }
+        let rsp = client.request(&mut req);
+        assert_eq!(rsp.status(), 200);
+        let (parts, body) = rsp.into_parts();
+        let set_cookie = parts
+            .headers
+            .get("set-cookie")
+            .map(|v| v.to_str().unwrap().split(';').next().unwrap().to_owned());
+        let body = body
+            .concat2()
+            .map(|body| ::std::str::from_utf8(&body).unwrap().to_string())
+            .wait()
+            .expect("response body");
+        (body, set_cookie)
+    };
+
+    let (first, cookie) = get(None);
+    let cookie = cookie.expect("response must set the sticky cookie");
+    for _ in 0..10 {
+        let (body, set_cookie) = get(Some(&cookie));
+        assert_eq!(body, first);
+        assert_eq!(set_cookie, None);
+    }
+}
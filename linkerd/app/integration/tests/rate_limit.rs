@@ -0,0 +1,109 @@
+#![deny(warnings, rust_2018_idioms)]
+
+use linkerd2_app_integration::rate_limit::Code;
+use linkerd2_app_integration::*;
+
+const HOST: &str = "ratelimit.test.svc.cluster.local";
+
+struct Fixture {
+    client: client::Client,
+    _proxy: proxy::Listening,
+}
+
+fn fixture(rate_limit: &controller::Listening, failure_mode: Option<&str>) -> Fixture {
+    let srv = server::http1().route("/", "hello").run();
+    let ctrl = controller::new();
+    ctrl.profile_tx_default(HOST);
+
+    let mut env = TestEnv::new();
+    env.put(
+        "LINKERD2_PROXY_INBOUND_RATELIMIT_SVC_ADDR",
+        rate_limit.addr.to_string(),
+    );
+    env.put(
+        app::env::ENV_INBOUND_RATELIMIT_DESCRIPTORS,
+        "generic_key=value:test,tenant=header:x-tenant".to_owned(),
+    );
+    if let Some(mode) = failure_mode {
+        env.put(
+            app::env::ENV_INBOUND_RATELIMIT_FAILURE_MODE,
+            mode.to_owned(),
+        );
+    }
+
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .inbound(srv)
+        .run_with_test_env(env);
+    let client = client::http1(proxy.inbound, HOST);
+
+    Fixture {
+        client,
+        _proxy: proxy,
+    }
+}
+
+fn get(client: &client::Client) -> http::StatusCode {
+    client
+        .request(client.request_builder("/").header("x-tenant", "acme"))
+        .status()
+}
+
+#[test]
+fn forwards_requests_under_the_limit() {
+    let _ = trace_init();
+    let rate_limit = controller::rate_limit().respond(Code::Ok);
+    let listening = rate_limit.clone().run();
+    let Fixture { client, _proxy } = fixture(&listening, None);
+
+    assert_eq!(get(&client), http::StatusCode::OK);
+
+    let requests = rate_limit.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].domain, "linkerd");
+    let entries = requests[0].descriptors[0]
+        .entries
+        .iter()
+        .map(|e| (e.key.as_str(), e.value.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(entries, vec![("generic_key", "test"), ("tenant", "acme")]);
+}
+
+#[test]
+fn rejects_requests_over_the_limit() {
+    let _ = trace_init();
+    let listening = controller::rate_limit().respond(Code::OverLimit).run();
+    let Fixture { client, _proxy } = fixture(&listening, None);
+
+    assert_eq!(get(&client), http::StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[test]
+fn skips_requests_without_descriptors() {
+    let _ = trace_init();
+    let rate_limit = controller::rate_limit();
+    let listening = rate_limit.clone().run();
+    let Fixture { client, _proxy } = fixture(&listening, Some("closed"));
+
+    // The descriptor requires the `x-tenant` header.
+    assert_eq!(client.get("/"), "hello");
+    assert!(rate_limit.requests().is_empty());
+}
+
+#[test]
+fn fails_open_when_unavailable() {
+    let _ = trace_init();
+    let listening = controller::rate_limit().run();
+    let Fixture { client, _proxy } = fixture(&listening, None);
+
+    assert_eq!(get(&client), http::StatusCode::OK);
+}
+
+#[test]
+fn fails_closed_when_unavailable() {
+    let _ = trace_init();
+    let listening = controller::rate_limit().run();
+    let Fixture { client, _proxy } = fixture(&listening, Some("closed"));
+
+    assert_eq!(get(&client), http::StatusCode::SERVICE_UNAVAILABLE);
+}
@@ -58,6 +58,44 @@ fn outbound_tcp() {
     assert_eq!(tcp_client.read(), msg2.as_bytes());
 }
 
+#[test]
+fn outbound_tcp_discovery() {
+    let _ = trace_init();
+
+    let msg1 = "custom tcp hello";
+    let msg2 = "custom tcp bye";
+
+    let srv = server::tcp()
+        .accept(move |read| {
+            assert_eq!(read, msg1.as_bytes());
+            msg2
+        })
+        .run();
+
+    // The original destination is not routable, so the connection can only
+    // succeed if it is balanced over the discovered endpoint.
+    let orig_dst = "10.1.2.3:5550";
+    let ctrl = controller::new();
+    ctrl.destination_tx(orig_dst).send_addr(srv.addr);
+
+    let mut env = TestEnv::new();
+    env.put(
+        app::env::ENV_DESTINATION_GET_NETWORKS,
+        "10.0.0.0/8".to_owned(),
+    );
+    let proxy = proxy::new()
+        .controller(ctrl.run())
+        .outbound_ip(orig_dst.parse().unwrap())
+        .run_with_test_env(env);
+
+    let client = client::tcp(proxy.outbound);
+
+    let tcp_client = client.connect();
+
+    tcp_client.write(msg1);
+    assert_eq!(tcp_client.read(), msg2.as_bytes());
+}
+
 #[test]
 fn inbound_tcp() {
     let _ = trace_init();
@@ -1,9 +1,10 @@
 use crate::http::uri::Authority;
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 use linkerd2_app_core::{
-    dst, metric_labels,
-    metric_labels::{prefix_labels, EndpointLabels},
-    profiles,
+    dst, health_check, locality, metric_labels,
+    metric_labels::{prefix_labels, ConcreteLabels, EndpointLabels},
+    mirror, outlier,
+    profiles::{self, HashKey, HealthCheck, LoadBalancer},
     proxy::{
         api_resolve::{Metadata, ProtocolHint},
         http::override_authority::CanOverrideAuthority,
@@ -14,18 +15,35 @@ use linkerd2_app_core::{
     },
     router,
     transport::{connect, tls},
-    Addr, Conditional, L5D_REQUIRE_ID,
+    Addr, Conditional, NameAddr, L5D_REQUIRE_ID,
 };
-use std::net::SocketAddr;
+use std::hash::Hash;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
+/// The destination label that describes the zone in which an endpoint runs.
+const ZONE_LABEL: &str = "zone";
+
 #[derive(Copy, Clone, Debug)]
 pub struct FromMetadata;
 
+/// Builds TCP endpoints from discovery metadata.
+///
+/// Peers do not terminate TLS on ports for which protocol detection is
+/// disabled, so connections to those ports are not secured with mTLS.
+#[derive(Clone, Debug)]
+pub struct FromTcpMetadata {
+    skip_ports: Arc<IndexSet<u16>>,
+}
+
 pub type Logical<T> = Target<T>;
 
 pub type Concrete<T> = Target<Logical<T>>;
 
+/// The logical target of an opaque TCP connection, i.e. the original
+/// destination address of the connection.
+pub type TcpLogical = Target<SocketAddr>;
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Profile(Addr);
 
@@ -35,6 +53,10 @@ pub struct LogicalPerRequest(tls::accept::Meta);
 #[derive(Clone, Debug)]
 pub struct ProfilePerTarget;
 
+/// Determines the concrete target of a mirrored request.
+#[derive(Copy, Clone, Debug)]
+pub struct MirrorPerRequest;
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Target<T> {
     pub addr: Addr,
@@ -47,8 +69,22 @@ pub struct HttpEndpoint {
     pub settings: http::Settings,
     pub identity: tls::PeerIdentity,
     pub metadata: Metadata,
+    pub load_balancer: LoadBalancer,
+    pub health_check: Option<HealthCheck>,
+}
+
+/// Configures the balancer for a concrete destination.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BalanceSettings {
+    pub settings: http::Settings,
+    pub load_balancer: LoadBalancer,
+    pub health_check: Option<HealthCheck>,
 }
 
+/// Hashes requests for a consistent-hashing balancer.
+#[derive(Clone, Debug)]
+pub struct RequestHash(HashKey);
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct TcpEndpoint {
     pub addr: SocketAddr,
@@ -108,6 +144,18 @@ impl<T> profiles::OverrideDestination for Target<T> {
     }
 }
 
+impl<T: profiles::OverrideLoadBalancer> profiles::OverrideLoadBalancer for Target<T> {
+    fn load_balancer_mut(&mut self) -> &mut LoadBalancer {
+        self.inner.load_balancer_mut()
+    }
+}
+
+impl<T: profiles::OverrideHealthCheck> profiles::OverrideHealthCheck for Target<T> {
+    fn health_check_mut(&mut self) -> &mut Option<HealthCheck> {
+        self.inner.health_check_mut()
+    }
+}
+
 impl<T: http::settings::HasSettings> http::settings::HasSettings for Target<T> {
     fn http_settings(&self) -> http::Settings {
         self.inner.http_settings()
@@ -191,7 +239,19 @@ impl std::hash::Hash for HttpEndpoint {
         self.addr.hash(state);
         self.identity.hash(state);
         http::settings::HasSettings::http_settings(self).hash(state);
-        // Ignore metadata.
+        // Ignore metadata, the load balancer, and the health check.
+    }
+}
+
+impl profiles::OverrideLoadBalancer for HttpEndpoint {
+    fn load_balancer_mut(&mut self) -> &mut LoadBalancer {
+        &mut self.load_balancer
+    }
+}
+
+impl profiles::OverrideHealthCheck for HttpEndpoint {
+    fn health_check_mut(&mut self) -> &mut Option<HealthCheck> {
+        &mut self.health_check
     }
 }
 
@@ -266,12 +326,12 @@ impl tap::Inspect for HttpEndpoint {
     }
 }
 
-impl MapEndpoint<Concrete<http::Settings>, Metadata> for FromMetadata {
+impl MapEndpoint<Concrete<BalanceSettings>, Metadata> for FromMetadata {
     type Out = Target<HttpEndpoint>;
 
     fn map_endpoint(
         &self,
-        concrete: &Concrete<http::Settings>,
+        concrete: &Concrete<BalanceSettings>,
         addr: SocketAddr,
         metadata: Metadata,
     ) -> Self::Out {
@@ -291,7 +351,9 @@ impl MapEndpoint<Concrete<http::Settings>, Metadata> for FromMetadata {
                 addr,
                 identity,
                 metadata,
-                settings: concrete.inner.inner,
+                settings: concrete.inner.inner.settings,
+                load_balancer: concrete.inner.inner.load_balancer.clone(),
+                health_check: concrete.inner.inner.health_check.clone(),
             },
         }
     }
@@ -303,6 +365,47 @@ impl CanOverrideAuthority for Target<HttpEndpoint> {
     }
 }
 
+impl outlier::HasPool for Target<HttpEndpoint> {
+    type Pool = Logical<http::Settings>;
+
+    fn pool(&self) -> Self::Pool {
+        Target {
+            addr: self.addr.clone(),
+            inner: self.inner.settings,
+        }
+    }
+}
+
+impl health_check::HasHealthCheck for Target<HttpEndpoint> {
+    fn health_check(&self) -> Option<&HealthCheck> {
+        self.inner.health_check.as_ref()
+    }
+
+    fn probe_authority(&self) -> Authority {
+        self.addr.to_http_authority()
+    }
+}
+
+impl locality::HasZone for Target<HttpEndpoint> {
+    fn dst(&self) -> &Addr {
+        &self.addr
+    }
+
+    fn zone(&self) -> Option<&str> {
+        self.inner
+            .metadata
+            .labels()
+            .get(ZONE_LABEL)
+            .map(String::as_str)
+    }
+}
+
+impl http::balance::HasWeight for Target<HttpEndpoint> {
+    fn weight(&self) -> u32 {
+        self.inner.metadata.weight()
+    }
+}
+
 impl Into<EndpointLabels> for Target<HttpEndpoint> {
     fn into(self) -> EndpointLabels {
         use linkerd2_app_core::metric_labels::{Direction, TlsId};
@@ -319,6 +422,81 @@ impl Into<EndpointLabels> for Target<HttpEndpoint> {
     }
 }
 
+impl Into<ConcreteLabels> for Concrete<HttpEndpoint> {
+    fn into(self) -> ConcreteLabels {
+        ConcreteLabels {
+            direction: metric_labels::Direction::Out,
+            target: self.inner.addr,
+            concrete: self.addr,
+        }
+    }
+}
+
+// === impl BalanceSettings ===
+
+impl http::balance::HasHashRequest for Concrete<BalanceSettings> {
+    type HashRequest = RequestHash;
+
+    fn hash_request(&self) -> Option<RequestHash> {
+        match self.inner.inner.load_balancer {
+            LoadBalancer::ConsistentHash(ref key) => Some(RequestHash(key.clone())),
+            LoadBalancer::PeakEwma | LoadBalancer::StickyCookie(_) => None,
+        }
+    }
+}
+
+impl http::balance::HasStickyCookie for Concrete<BalanceSettings> {
+    fn sticky_cookie(&self) -> Option<http::balance::StickyCookie> {
+        match self.inner.inner.load_balancer {
+            LoadBalancer::StickyCookie(ref name) => {
+                Some(http::balance::StickyCookie::new(name.as_str()))
+            }
+            LoadBalancer::PeakEwma | LoadBalancer::ConsistentHash(_) => None,
+        }
+    }
+}
+
+// === impl RequestHash ===
+
+impl<B> http::balance::HashRequest<http::Request<B>> for RequestHash {
+    fn hash_request(&self, req: &http::Request<B>) -> Option<u64> {
+        use http::balance::ring::hash;
+
+        match self.0 {
+            HashKey::Header(ref name) => Some(hash(&req.headers().get(name)?.as_bytes())),
+            HashKey::Cookie(ref name) => req
+                .headers()
+                .get_all(http::header::COOKIE)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(';'))
+                .find_map(|cookie| {
+                    let mut kv = cookie.trim().splitn(2, '=');
+                    if kv.next()? == name.as_str() {
+                        kv.next()
+                    } else {
+                        None
+                    }
+                })
+                .map(|value| hash(&value)),
+            // The outbound proxy's peer is always the local application, so
+            // the original client's address is taken from the first
+            // `x-forwarded-for` entry.
+            HashKey::SourceIp => req
+                .headers()
+                .get("x-forwarded-for")?
+                .to_str()
+                .ok()?
+                .split(',')
+                .next()?
+                .trim()
+                .parse::<IpAddr>()
+                .ok()
+                .map(|ip| hash(&ip)),
+        }
+    }
+}
+
 // === impl TcpEndpoint ===
 
 impl From<SocketAddr> for TcpEndpoint {
@@ -354,6 +532,36 @@ impl Into<EndpointLabels> for TcpEndpoint {
     }
 }
 
+// === impl FromTcpMetadata ===
+
+impl FromTcpMetadata {
+    pub fn new(skip_ports: Arc<IndexSet<u16>>) -> Self {
+        Self { skip_ports }
+    }
+}
+
+impl MapEndpoint<TcpLogical, Metadata> for FromTcpMetadata {
+    type Out = TcpEndpoint;
+
+    fn map_endpoint(&self, _: &TcpLogical, addr: SocketAddr, metadata: Metadata) -> Self::Out {
+        tracing::trace!(%addr, ?metadata, "Resolved endpoint");
+        if self.skip_ports.contains(&addr.port()) {
+            tracing::debug!(%addr, "Skipping mTLS on a port without protocol detection");
+            return TcpEndpoint::from(addr);
+        }
+
+        let identity = metadata
+            .identity()
+            .cloned()
+            .map(Conditional::Some)
+            .unwrap_or_else(|| {
+                Conditional::None(tls::ReasonForNoPeerName::NotProvidedByServiceDiscovery.into())
+            });
+
+        TcpEndpoint { addr, identity }
+    }
+}
+
 // === impl LogicalPerRequest ===
 
 impl From<tls::accept::Meta> for LogicalPerRequest {
@@ -401,6 +609,8 @@ impl<B> router::Recognize<http::Request<B>> for LogicalPerRequest {
             settings,
             addr: self.0.addrs.target_addr(),
             metadata: Metadata::empty(),
+            load_balancer: LoadBalancer::default(),
+            health_check: None,
             identity: identity_from_header(req, L5D_REQUIRE_ID)
                 .map(Conditional::Some)
                 .unwrap_or_else(|| {
@@ -414,6 +624,23 @@ impl<B> router::Recognize<http::Request<B>> for LogicalPerRequest {
     }
 }
 
+// === impl MirrorPerRequest ===
+
+impl mirror::MirrorTarget for MirrorPerRequest {
+    type Target = Concrete<HttpEndpoint>;
+
+    /// The mirrored request's logical target is determined as it is for the
+    /// original request, and the mirror is used as its concrete destination.
+    fn mirror_target<B>(&self, dst: &NameAddr, req: &http::Request<B>) -> Option<Self::Target> {
+        let accept = req.extensions().get::<tls::accept::Meta>()?;
+        let inner = router::Recognize::recognize(&LogicalPerRequest(accept.clone()), req);
+        Some(Target {
+            addr: dst.clone().into(),
+            inner,
+        })
+    }
+}
+
 // === impl ProfilePerTarget ===
 
 impl<T> router::Recognize<Target<T>> for ProfilePerTarget {
@@ -6,17 +6,17 @@
 #![deny(warnings, rust_2018_idioms)]
 
 pub use self::endpoint::{
-    Concrete, HttpEndpoint, Logical, LogicalPerRequest, Profile, ProfilePerTarget, Target,
-    TcpEndpoint,
+    BalanceSettings, Concrete, HttpEndpoint, Logical, LogicalPerRequest, MirrorPerRequest, Profile,
+    ProfilePerTarget, Target, TcpEndpoint, TcpLogical,
 };
 use ::http::header::HOST;
 use futures::future;
 use linkerd2_app_core::{
     admit, classify,
     config::{ProxyConfig, ServerConfig},
-    dns, drain, dst, errors, metric_labels,
+    dns, drain, dst, errors, fault, health_check, hedge, locality, metric_labels, mirror,
     opencensus::proto::trace::v1 as oc,
-    profiles,
+    outlier, profiles,
     proxy::{
         self, core::resolve::Resolve, detect::DetectProtocolLayer, discover, http, identity,
         resolve::map_endpoint, server::ProtocolDetect, tap, tcp, Server,
@@ -24,9 +24,10 @@ use linkerd2_app_core::{
     reconnect, retry, router, serve,
     spans::SpanConverter,
     svc::{self, NewService},
-    transport::{self, tls},
+    transport::{self, io::EitherIo, tls},
     Conditional, DiscoveryRejected, Error, ProxyMetrics, TraceContextLayer, CANONICAL_DST_HEADER,
-    DST_OVERRIDE_HEADER, L5D_CLIENT_ID, L5D_REMOTE_IP, L5D_REQUIRE_ID, L5D_SERVER_ID,
+    DST_OVERRIDE_HEADER, L5D_CLIENT_ID, L5D_DST_CONCRETE, L5D_REMOTE_IP, L5D_REQUIRE_ID,
+    L5D_SERVER_ID,
 };
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -54,6 +55,12 @@ const EWMA_DECAY: Duration = Duration::from_secs(10);
 pub struct Config {
     pub proxy: ProxyConfig,
     pub canonicalize_timeout: Duration,
+    pub retry_max_body_bytes: usize,
+    pub mirror_max_in_flight: usize,
+    pub mirror_max_body_bytes: usize,
+    pub outlier: outlier::Config,
+    pub locality: Option<locality::Config>,
+    pub slow_start: Option<http::balance::SlowStart>,
 }
 
 pub struct Outbound {
@@ -74,19 +81,28 @@ impl Config {
         drain: drain::Watch,
     ) -> Result<Outbound, Error>
     where
-        R: Resolve<Concrete<http::Settings>, Endpoint = proxy::api_resolve::Metadata>
+        R: Resolve<Concrete<BalanceSettings>, Endpoint = proxy::api_resolve::Metadata>
+            + Resolve<TcpLogical, Endpoint = proxy::api_resolve::Metadata>
             + Clone
             + Send
             + Sync
             + 'static,
-        R::Future: Send,
-        R::Resolution: Send,
+        <R as Resolve<Concrete<BalanceSettings>>>::Future: Send,
+        <R as Resolve<Concrete<BalanceSettings>>>::Resolution: Send,
+        <R as Resolve<TcpLogical>>::Future: Send,
+        <R as Resolve<TcpLogical>>::Resolution: Send,
         P: profiles::GetRoutes<Profile> + Clone + Send + 'static,
         P::Future: Send,
     {
         use proxy::core::listen::{Bind, Listen};
         let Config {
             canonicalize_timeout,
+            retry_max_body_bytes,
+            mirror_max_in_flight,
+            mirror_max_body_bytes,
+            outlier,
+            locality,
+            slow_start,
             proxy:
                 ProxyConfig {
                     server: ServerConfig { bind, h2_settings },
@@ -116,14 +132,73 @@ impl Config {
                 .push_timeout(connect.timeout)
                 .push(metrics.transport.layer_connect(TransportLabels));
 
-            // Forwards TCP streams that cannot be decoded as HTTP.
-            let tcp_forward = tcp_connect
+            // Resolves each TCP target via the control plane on a background task, buffering
+            // results.
+            let tcp_discover = {
+                const BUFFER_CAPACITY: usize = 1_000;
+                let resolve = map_endpoint::Resolve::new(
+                    endpoint::FromTcpMetadata::new(disable_protocol_detection_for_ports.clone()),
+                    resolve.clone(),
+                );
+                discover::Layer::new(BUFFER_CAPACITY, cache_max_idle_age, resolve)
+            };
+
+            // Builds a balancer for each logical TCP target. Each connection is dispatched to the
+            // endpoint with the fewest open connections.
+            let tcp_balance = tcp_connect
                 .clone()
                 .push(admit::AdmitLayer::new(PreventLoop::new(listen_addr.port())))
+                .push(svc::layer::mk(tcp::balance::MakeConnect::new))
+                .check_make_service::<TcpEndpoint, ()>()
+                .push(tcp_discover)
+                .push_on_response(tcp::balance::layer())
+                .into_new_service()
+                .cache(
+                    svc::layers().push_on_response(
+                        svc::layers()
+                            // If the balancer has been empty/unavailable for 10s, eagerly fail
+                            // connections.
+                            .push_failfast(dispatch_timeout)
+                            // Shares the balancer, ensuring discovery errors are propagated.
+                            .push_spawn_buffer_with_idle_timeout(
+                                buffer_capacity,
+                                cache_max_idle_age,
+                            )
+                            .push(metrics.stack.layer(stack_labels("tcp.balance"))),
+                    ),
+                )
+                .instrument(|t: &TcpLogical| info_span!("tcp.balance", addr = %t.addr))
+                // Obtains the balancer and establishes a connection through it.
+                .push(svc::make_response::Layer)
+                // Ensure that buffers don't hold the cache's lock in poll_ready.
+                .push_oneshot()
+                .check_service::<TcpLogical>();
+
+            // Forwards TCP streams that cannot be decoded as HTTP.
+            //
+            // If the target cannot be resolved, the connection is forwarded directly to its
+            // original destination.
+            let tcp_forward = tcp_balance
+                .push_fallback_with_predicate(
+                    tcp_connect
+                        .clone()
+                        .push(admit::AdmitLayer::new(PreventLoop::new(listen_addr.port())))
+                        .push_map_target(|t: TcpLogical| TcpEndpoint::from(t.inner))
+                        .into_inner(),
+                    is_discovery_rejected,
+                )
+                .push_map_response(either_io)
+                .check_service::<TcpLogical>()
                 .push_map_target(|meta: tls::accept::Meta| {
-                    TcpEndpoint::from(meta.addrs.target_addr())
+                    let addr = meta.addrs.target_addr();
+                    Target {
+                        addr: addr.into(),
+                        inner: addr,
+                    }
                 })
-                .push(svc::layer::mk(tcp::Forward::new));
+                .push(svc::layer::mk(tcp::Forward::new))
+                // Registers forwarded connections to be tapped.
+                .push(tap_layer.tcp(tap::Direction::Outbound));
 
             // Registers the stack with Tap, Metrics, and OpenCensus tracing
             // export.
@@ -160,6 +235,9 @@ impl Config {
                         move |_| Ok(backoff.stream())
                     }))
                     .push(admit::AdmitLayer::new(PreventLoop::new(listen_addr.port())))
+                    // Fails requests that a route's fault policy has marked
+                    // to be aborted, so that they are observed by tap.
+                    .push(fault::abort_layer())
                     .push(observability.clone())
                     .push(identity_headers.clone())
                     .push(http::override_authority::Layer::new(vec![HOST.as_str(), CANONICAL_DST_HEADER]))
@@ -176,6 +254,21 @@ impl Config {
                     })
             };
 
+            // Builds clients that probe endpoints' health. Probes are not
+            // observed by tap or endpoint metrics, and each probe client
+            // fails if it cannot connect rather than reconnecting.
+            let http_probe = tcp_connect
+                .clone()
+                .push(http::MakeClientLayer::new(connect.h2_settings))
+                .push(admit::AdmitLayer::new(PreventLoop::new(listen_addr.port())))
+                .push(http::override_authority::Layer::new(vec![
+                    HOST.as_str(),
+                    CANONICAL_DST_HEADER,
+                ]))
+                .push(http::normalize_uri::layer())
+                .push(OrigProtoUpgradeLayer::new())
+                .check_service::<Target<HttpEndpoint>>();
+
             // Resolves each target via the control plane on a background task, buffering results.
             //
             // This buffer controls how many discovery updates may be pending/unconsumed by the
@@ -196,10 +289,30 @@ impl Config {
                         .push(metrics.stack.layer(stack_labels("balance.endpoint")))
                         .box_http_request(),
                 )
+                // Removes endpoints that fail their profile's health checks
+                // from the balancer.
+                .push(health_check::layer(
+                    metrics.http_health_check.clone(),
+                    http_probe,
+                ))
+                // Ejects endpoints that fail too many requests from the balancer.
+                .push(outlier::layer(outlier, metrics.http_outlier.clone()))
+                // Prefers endpoints in the proxy's zone, spilling over to other
+                // zones when local endpoints are unavailable or overloaded.
+                .push(locality::layer(locality, metrics.http_locality.clone()))
                 .push_spawn_ready()
+                // Annotates each endpoint with its weight so that the balancer
+                // distributes requests in proportion to endpoint weights.
+                // Zero-weighted endpoints are only used while no
+                // positively-weighted endpoint is ready.
+                .push(http::balance::weight::layer())
                 .check_service::<Target<HttpEndpoint>>()
                 .push(discover)
-                .push_on_response(http::balance::layer(EWMA_DEFAULT_RTT, EWMA_DECAY))
+                // Balances requests with P2C, or by consistent hashing if the
+                // destination's profile configures it. Newly discovered
+                // endpoints are eased into the P2C balancer if slow-start is
+                // configured.
+                .push(http::balance::layer(EWMA_DEFAULT_RTT, EWMA_DECAY, slow_start))
                 .into_new_service()
                 .cache(
                     svc::layers().push_on_response(
@@ -215,7 +328,7 @@ impl Config {
                             .push(metrics.stack.layer(stack_labels("balance"))),
                     ),
                 )
-                .instrument(|c: &Concrete<http::Settings>| info_span!("balance", addr = %c.addr))
+                .instrument(|c: &Concrete<BalanceSettings>| info_span!("balance", addr = %c.addr))
                 // Ensure that buffers don't hold the cache's lock in poll_ready.
                 .push_oneshot();
 
@@ -253,7 +366,15 @@ impl Config {
             // If the balancer fails to be created, i.e., because it is unresolvable, fall back to
             // using a router that dispatches request to the application-selected original destination.
             let http_concrete = http_balancer
-                .push_map_target(|c: Concrete<HttpEndpoint>| c.map(|l| l.map(|e| e.settings)))
+                .push_map_target(|c: Concrete<HttpEndpoint>| {
+                    c.map(|l| {
+                        l.map(|e| BalanceSettings {
+                            settings: e.settings,
+                            load_balancer: e.load_balancer,
+                            health_check: e.health_check,
+                        })
+                    })
+                })
                 .check_service::<Concrete<HttpEndpoint>>()
                 .push_on_response(svc::layers().box_http_response())
                 .push_make_ready()
@@ -263,17 +384,88 @@ impl Config {
                         .into_inner(),
                     is_discovery_rejected,
                 )
+                .check_service::<Concrete<HttpEndpoint>>()
+                // Sets the concrete-dst header so that the destination selected
+                // for each request is visible to tap. The inbound proxy strips
+                // it before requests are forwarded to the application.
+                .push(http::header_from_target::layer(L5D_DST_CONCRETE))
+                // Records metrics for each concrete destination.
+                .push(metrics.http_concrete.into_layer::<classify::Response>())
+                .push_on_response(svc::layers().box_http_response())
                 .check_service::<Concrete<HttpEndpoint>>();
 
+            // Caches concrete services for mirrored requests, so that a
+            // mirror's stack is shared by all of the requests mirrored to it.
+            let http_mirror_cache = http_concrete
+                .clone()
+                .push_on_response(svc::layers().box_http_request())
+                .check_service::<Concrete<HttpEndpoint>>()
+                .into_new_service()
+                .cache(
+                    svc::layers().push_on_response(
+                        svc::layers()
+                            // If the mirror has been unavailable for an extended time, eagerly
+                            // fail mirrored requests.
+                            .push_failfast(dispatch_timeout)
+                            .push_spawn_buffer_with_idle_timeout(
+                                buffer_capacity,
+                                cache_max_idle_age,
+                            )
+                            .push(metrics.stack.layer(stack_labels("mirror"))),
+                    ),
+                )
+                .instrument(|c: &Concrete<HttpEndpoint>| info_span!("mirror", addr = %c.addr))
+                // Ensures that the cache isn't locked when polling readiness.
+                .push_oneshot();
+
+            // Records metrics and sets the response classifier for requests
+            // that are mirrored by a route.
+            let http_profile_route_mirror = svc::proxies()
+                .push(metrics.http_route_mirror.into_layer::<classify::Response>())
+                .push(classify::Layer::new())
+                .check_new_clone_service::<dst::Route>();
+
             let http_profile_route_proxy = svc::proxies()
                 .check_new_clone_service::<dst::Route>()
-                .push(metrics.http_route_actual.into_layer::<classify::Response>())
-                // Sets an optional retry policy.
-                .push(retry::layer(metrics.http_route_retry))
+                .push(
+                    metrics
+                        .http_route_actual
+                        .clone()
+                        .into_layer::<classify::Response>(),
+                )
+                // Sets an optional hedging policy. Hedging delays are derived from
+                // the latencies recorded by `http_route_actual`.
+                .push(hedge::layer(
+                    metrics.http_route_actual,
+                    metrics.http_route_retry.clone(),
+                ))
+                .check_new_clone_service::<dst::Route>()
+                // Sets an optional retry policy. Request bodies are buffered (up to
+                // `retry_max_body_bytes`) so that they may be replayed.
+                .push(retry::layer(metrics.http_route_retry.clone(), retry_max_body_bytes))
                 .check_new_clone_service::<dst::Route>()
                 // Sets an optional request timeout.
                 .push(http::MakeTimeoutLayer::default())
                 .check_new_clone_service::<dst::Route>()
+                // Sets an optional fault injection policy. Injected delays
+                // are not subject to the route's timeout.
+                .push(fault::layer(metrics.http_route_retry))
+                .check_new_clone_service::<dst::Route>()
+                // Sets an optional mirroring policy. Mirrored requests are
+                // dispatched to the mirror's concrete target in the
+                // background, recording `route_mirror` metrics. Requests are
+                // not mirrored while `mirror_max_in_flight` mirrored requests
+                // are pending, and a mirrored request fails if it falls more
+                // than `mirror_max_body_bytes` behind the original request's
+                // body.
+                .push(mirror::layer(
+                    http_profile_route_mirror.into_inner(),
+                    http_mirror_cache.into_inner(),
+                    MirrorPerRequest,
+                    mirror_max_in_flight,
+                    mirror_max_body_bytes,
+                ))
+                .check_new_clone_service::<dst::Route>()
                 // Records per-route metrics.
                 .push(metrics.http_route.into_layer::<classify::Response>())
                 .check_new_clone_service::<dst::Route>()
@@ -520,6 +712,13 @@ impl From<Error> for DiscoveryError {
     }
 }
 
+fn either_io<A, B>(io: svc::Either<A, B>) -> EitherIo<A, B> {
+    match io {
+        svc::Either::A(a) => EitherIo::A(a),
+        svc::Either::B(b) => EitherIo::B(b),
+    }
+}
+
 fn is_discovery_rejected(err: &Error) -> bool {
     tracing::trace!(?err, "is_discovery_rejected");
 
@@ -1,6 +1,6 @@
 use crate::identity::LocalIdentity;
 use linkerd2_app_core::{
-    admin, config::ServerConfig, drain, metrics::FmtMetrics, serve, trace::LevelHandle,
+    admin, config::ServerConfig, drain, metrics::FmtMetrics, proxy::tap, serve, trace::LevelHandle,
     transport::tls, Error,
 };
 use std::net::SocketAddr;
@@ -24,6 +24,7 @@ impl Config {
         identity: LocalIdentity,
         report: R,
         log_level: LevelHandle,
+        tap: Option<tap::Json>,
         drain: drain::Watch,
     ) -> Result<Admin, Error>
     where
@@ -35,7 +36,7 @@ impl Config {
         let listen_addr = listen.listen_addr();
 
         let (ready, latch) = admin::Readiness::new();
-        let admin = admin::Admin::new(report, ready, log_level);
+        let admin = admin::Admin::new(report, ready, log_level, tap.map(admin::Tap::new));
         let accept = tls::AcceptTls::new(identity, admin.into_accept());
         let serve = serve::serve(listen, accept, drain);
         Ok(Admin {
@@ -5,6 +5,7 @@ use linkerd2_app_core::{
     config::{ControlAddr, ControlConfig},
     dns, profiles, Error,
 };
+use std::path::PathBuf;
 use std::time::Duration;
 use tower_grpc::{generic::client::GrpcService, Body, BoxBody};
 
@@ -16,6 +17,9 @@ pub struct Config {
     pub get_networks: IndexSet<ipnet::IpNet>,
     pub profile_suffixes: IndexSet<dns::Suffix>,
     pub initial_profile_timeout: Duration,
+    pub profile_policies_path: Option<PathBuf>,
+    /// How often the profile policy file is checked for changes.
+    pub profile_policies_reload_interval: Duration,
 }
 
 /// Handles to destination service clients.
@@ -25,6 +29,8 @@ pub struct Dst<S> {
     pub addr: ControlAddr,
     pub profiles: profiles::Client<S, resolve::BackoffUnlessInvalidArgument>,
     pub resolve: resolve::Resolve<S>,
+    /// Reloads the profile policy file, if one is configured.
+    pub profile_policies: Option<profiles::PolicyDaemon>,
 }
 
 impl Config {
@@ -44,18 +50,29 @@ impl Config {
             self.control.connect.backoff,
         );
 
+        let (policies, profile_policies) = match self.profile_policies_path {
+            None => (profiles::Policies::default().into_receiver(), None),
+            Some(path) => {
+                let interval = self.profile_policies_reload_interval;
+                let (policies, daemon) = profiles::Policies::watch(path, interval)?;
+                (policies, Some(daemon))
+            }
+        };
+
         let profiles = profiles::Client::new(
             svc,
             resolve::BackoffUnlessInvalidArgument::from(self.control.connect.backoff),
             self.initial_profile_timeout,
             self.context,
             self.profile_suffixes,
+            policies,
         );
 
         Ok(Dst {
             addr: self.control.addr,
             resolve,
             profiles,
+            profile_policies,
         })
     }
 }
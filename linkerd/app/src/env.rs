@@ -1,11 +1,17 @@
 use crate::core::{
-    addr,
+    addr, authz, concurrency_limit,
     config::*,
-    proxy::http::h2,
+    locality, outlier,
+    proxy::{
+        http::{balance::SlowStart, h2},
+        tap::BodyCapture,
+    },
     transport::{listen, tls},
     Addr,
 };
-use crate::{dns, identity, inbound, oc_collector, outbound};
+use crate::{
+    dns, ext_authz, global_rate_limit, grpc_check, identity, inbound, jwt, oc_collector, outbound,
+};
 use indexmap::IndexSet;
 use std::collections::HashMap;
 use std::convert::TryFrom;
@@ -40,12 +46,18 @@ pub enum ParseError {
     NotADuration,
     NotADomainSuffix,
     NotANumber,
+    NotARatio,
     NotANetwork,
     HostIsNotAnIpAddress,
     AddrError(addr::Error),
     NameError,
     InvalidTokenSource,
     InvalidTrustAnchors,
+    NotARateLimitDescriptor,
+    NotAFailureMode,
+    NotAnAuthzRule,
+    NotAnAuthzDefault,
+    NotAClaimHeader,
 }
 
 // Environment variables to look at when loading the configuration
@@ -85,8 +97,141 @@ pub const ENV_OUTBOUND_ROUTER_MAX_IDLE_AGE: &str = "LINKERD2_PROXY_OUTBOUND_ROUT
 pub const ENV_INBOUND_MAX_IN_FLIGHT: &str = "LINKERD2_PROXY_INBOUND_MAX_IN_FLIGHT";
 pub const ENV_OUTBOUND_MAX_IN_FLIGHT: &str = "LINKERD2_PROXY_OUTBOUND_MAX_IN_FLIGHT";
 
+/// Configures an adaptive limit on the number of in-flight inbound requests.
+///
+/// If `MIN` is set, the limit starts at `MIN` and is adjusted, between `MIN`
+/// and `MAX`, according to the latency and errors of inbound requests.
+/// Requests received while the limit is exhausted fail immediately. `MAX`
+/// defaults to `LINKERD2_PROXY_INBOUND_MAX_IN_FLIGHT`.
+pub const ENV_INBOUND_ADAPTIVE_CONCURRENCY_MIN: &str =
+    "LINKERD2_PROXY_INBOUND_ADAPTIVE_CONCURRENCY_MIN";
+pub const ENV_INBOUND_ADAPTIVE_CONCURRENCY_MAX: &str =
+    "LINKERD2_PROXY_INBOUND_ADAPTIVE_CONCURRENCY_MAX";
+
+/// Configures an external rate limit service, implementing Envoy's
+/// `RateLimitService` API, that is checked before inbound requests are
+/// forwarded to the application.
+pub const ENV_INBOUND_RATELIMIT_SVC_BASE: &str = "LINKERD2_PROXY_INBOUND_RATELIMIT_SVC";
+
+/// The rate limit domain included in each check. Defaults to `linkerd`.
+pub const ENV_INBOUND_RATELIMIT_DOMAIN: &str = "LINKERD2_PROXY_INBOUND_RATELIMIT_DOMAIN";
+
+/// The descriptors sent to the rate limit service for each inbound request.
+///
+/// Descriptors are separated by `;` and each descriptor's entries are separated
+/// by `,`. Each entry is of the form `key=source`, where `source` is one of
+/// `route:<label>`, `header:<name>`, `client_id`, or `value:<literal>`. A
+/// descriptor is only sent if all of its entries have a value. Required when
+/// the rate limit service is configured.
+pub const ENV_INBOUND_RATELIMIT_DESCRIPTORS: &str = "LINKERD2_PROXY_INBOUND_RATELIMIT_DESCRIPTORS";
+
+/// The amount of time to wait for the rate limit service to respond before
+/// applying the failure mode.
+pub const ENV_INBOUND_RATELIMIT_TIMEOUT: &str = "LINKERD2_PROXY_INBOUND_RATELIMIT_TIMEOUT";
+
+/// Either `open`, to forward requests when the rate limit service cannot be
+/// reached, or `closed`, to fail them. Defaults to `open`.
+pub const ENV_INBOUND_RATELIMIT_FAILURE_MODE: &str =
+    "LINKERD2_PROXY_INBOUND_RATELIMIT_FAILURE_MODE";
+
+/// Configures an external authorization service, implementing Envoy's
+/// `Authorization` API, that checks inbound requests before they are forwarded
+/// to the application.
+pub const ENV_INBOUND_EXT_AUTHZ_SVC_BASE: &str = "LINKERD2_PROXY_INBOUND_EXT_AUTHZ_SVC";
+
+/// The amount of time to wait for the authorization service to respond before
+/// applying the failure mode.
+pub const ENV_INBOUND_EXT_AUTHZ_TIMEOUT: &str = "LINKERD2_PROXY_INBOUND_EXT_AUTHZ_TIMEOUT";
+
+/// Either `open`, to forward requests when the authorization service cannot be
+/// reached, or `closed`, to fail them. Defaults to `closed`.
+pub const ENV_INBOUND_EXT_AUTHZ_FAILURE_MODE: &str =
+    "LINKERD2_PROXY_INBOUND_EXT_AUTHZ_FAILURE_MODE";
+
+/// The path to a JSON Web Key Set file. If set, inbound requests must have a
+/// bearer token signed by one of the set's keys. The file is reloaded when it
+/// changes.
+pub const ENV_INBOUND_JWT_JWKS_PATH: &str = "LINKERD2_PROXY_INBOUND_JWT_JWKS_PATH";
+
+/// If set, tokens must have been issued by this issuer.
+pub const ENV_INBOUND_JWT_ISSUER: &str = "LINKERD2_PROXY_INBOUND_JWT_ISSUER";
+
+/// A comma-separated list of audiences. If set, tokens must be intended for
+/// one of them.
+pub const ENV_INBOUND_JWT_AUDIENCES: &str = "LINKERD2_PROXY_INBOUND_JWT_AUDIENCES";
+
+/// The clock skew tolerated when checking a token's `exp` and `nbf` claims.
+pub const ENV_INBOUND_JWT_LEEWAY: &str = "LINKERD2_PROXY_INBOUND_JWT_LEEWAY";
+
+/// A comma-separated list of `claim=header` pairs. Each claim that a validated
+/// token has is set as the named request header. Defaults to
+/// `sub=l5d-jwt-sub`.
+pub const ENV_INBOUND_JWT_CLAIM_HEADERS: &str = "LINKERD2_PROXY_INBOUND_JWT_CLAIM_HEADERS";
+
 pub const ENV_TRACE_ATTRIBUTES_PATH: &str = "LINKERD2_PROXY_TRACE_ATTRIBUTES_PATH";
 
+/// The maximum number of bytes of an outbound request body that may be
+/// buffered so that the request can be retried.
+///
+/// Requests with larger bodies are not retried.
+pub const ENV_OUTBOUND_RETRY_MAX_BODY_BYTES: &str = "LINKERD2_PROXY_OUTBOUND_RETRY_MAX_BODY_BYTES";
+
+/// The maximum number of mirrored outbound requests that may be in flight.
+///
+/// While this many mirrored requests are pending, requests are not mirrored.
+pub const ENV_OUTBOUND_MIRROR_MAX_IN_FLIGHT: &str = "LINKERD2_PROXY_OUTBOUND_MIRROR_MAX_IN_FLIGHT";
+
+/// The maximum number of bytes of an outbound request body that may be
+/// buffered for a mirrored copy of the request.
+///
+/// Mirrored requests that fall further behind the original request fail.
+pub const ENV_OUTBOUND_MIRROR_MAX_BODY_BYTES: &str =
+    "LINKERD2_PROXY_OUTBOUND_MIRROR_MAX_BODY_BYTES";
+
+/// Configures passive outlier detection for outbound load balancers, which is
+/// disabled unless `CONSECUTIVE_FAILURES` or `FAILURE_RATE` is set.
+///
+/// An endpoint is ejected from its balancer after it fails
+/// `CONSECUTIVE_FAILURES` requests in a row, or, if
+/// `FAILURE_RATE` is set, when at least `FAILURE_RATE` of its requests fail
+/// within `FAILURE_RATE_INTERVAL` (and it received at least
+/// `FAILURE_RATE_MIN_REQUESTS` requests). Endpoints are ejected for
+/// `BASE_EJECTION`, doubling with each subsequent ejection up to
+/// `MAX_EJECTION`. No more than `MAX_EJECTION_PERCENT` of a balancer's
+/// endpoints are ejected at once.
+pub const ENV_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES: &str =
+    "LINKERD2_PROXY_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES";
+pub const ENV_OUTBOUND_OUTLIER_FAILURE_RATE: &str = "LINKERD2_PROXY_OUTBOUND_OUTLIER_FAILURE_RATE";
+pub const ENV_OUTBOUND_OUTLIER_FAILURE_RATE_MIN_REQUESTS: &str =
+    "LINKERD2_PROXY_OUTBOUND_OUTLIER_FAILURE_RATE_MIN_REQUESTS";
+pub const ENV_OUTBOUND_OUTLIER_FAILURE_RATE_INTERVAL: &str =
+    "LINKERD2_PROXY_OUTBOUND_OUTLIER_FAILURE_RATE_INTERVAL";
+pub const ENV_OUTBOUND_OUTLIER_BASE_EJECTION: &str =
+    "LINKERD2_PROXY_OUTBOUND_OUTLIER_BASE_EJECTION";
+pub const ENV_OUTBOUND_OUTLIER_MAX_EJECTION: &str = "LINKERD2_PROXY_OUTBOUND_OUTLIER_MAX_EJECTION";
+pub const ENV_OUTBOUND_OUTLIER_MAX_EJECTION_PERCENT: &str =
+    "LINKERD2_PROXY_OUTBOUND_OUTLIER_MAX_EJECTION_PERCENT";
+
+/// Configures zone-aware load balancing for outbound load balancers.
+///
+/// If `ZONE` is set, endpoints whose `zone` label differs from it are only
+/// used while none of the balancer's endpoints in `ZONE` are available, i.e.
+/// ready with fewer than `MAX_PENDING` requests in flight.
+pub const ENV_OUTBOUND_LOCALITY_ZONE: &str = "LINKERD2_PROXY_OUTBOUND_LOCALITY_ZONE";
+pub const ENV_OUTBOUND_LOCALITY_MAX_PENDING: &str = "LINKERD2_PROXY_OUTBOUND_LOCALITY_MAX_PENDING";
+
+/// Configures slow-start for outbound load balancers.
+///
+/// If `WINDOW` is set, the weight of each newly discovered endpoint ramps up
+/// from `MIN_WEIGHT` (a ratio of its full weight) to its full weight over
+/// `WINDOW`. `AGGRESSION` shapes the ramp: 1.0 is linear, and greater values
+/// ramp up more quickly at the start of the window.
+pub const ENV_OUTBOUND_SLOW_START_WINDOW: &str = "LINKERD2_PROXY_OUTBOUND_SLOW_START_WINDOW";
+pub const ENV_OUTBOUND_SLOW_START_AGGRESSION: &str =
+    "LINKERD2_PROXY_OUTBOUND_SLOW_START_AGGRESSION";
+pub const ENV_OUTBOUND_SLOW_START_MIN_WEIGHT: &str =
+    "LINKERD2_PROXY_OUTBOUND_SLOW_START_MIN_WEIGHT";
+
 /// Constrains which destination names are resolved through the destination
 /// service.
 ///
@@ -121,6 +266,18 @@ pub const ENV_DESTINATION_GET_NETWORKS: &str = "LINKERD2_PROXY_DESTINATION_GET_N
 /// If unspecified, a default value is used.
 pub const ENV_DESTINATION_PROFILE_SUFFIXES: &str = "LINKERD2_PROXY_DESTINATION_PROFILE_SUFFIXES";
 
+/// The path to a JSON file of profile policies, which configure routes that
+/// supplement the profiles served by the destination service.
+///
+/// The file must be valid at startup. It is reloaded when it changes; if a
+/// changed file is invalid, the previously loaded policies are retained.
+pub const ENV_DESTINATION_PROFILE_POLICIES_PATH: &str =
+    "LINKERD2_PROXY_DESTINATION_PROFILE_POLICIES_PATH";
+
+/// How often the profile policy file is checked for changes.
+pub const ENV_DESTINATION_PROFILE_POLICIES_RELOAD_INTERVAL: &str =
+    "LINKERD2_PROXY_DESTINATION_PROFILE_POLICIES_RELOAD_INTERVAL";
+
 // These *disable* our protocol detection for connections whose SO_ORIGINAL_DST
 // has a port in the provided list.
 pub const ENV_INBOUND_PORTS_DISABLE_PROTOCOL_DETECTION: &str =
@@ -131,6 +288,20 @@ pub const ENV_OUTBOUND_PORTS_DISABLE_PROTOCOL_DETECTION: &str =
 pub const ENV_INBOUND_PORTS_REQUIRE_IDENTITY: &str =
     "LINKERD2_PROXY_INBOUND_PORTS_REQUIRE_IDENTITY";
 
+/// Configures which clients are authorized to connect to each inbound port.
+///
+/// Rules are separated by `;`. Each rule is of the form
+/// `<port>[/<route>]=<client>[,<client>...]`, where `route` names a service
+/// profile route and each `client` is either `*` (any identity), an identity
+/// name, an identity suffix pattern (e.g.
+/// `*.ns.serviceaccount.identity.linkerd.cluster.local`), or a network (e.g.
+/// `10.0.0.0/8`) from which clients are authorized without an identity.
+pub const ENV_INBOUND_AUTHZ_RULES: &str = "LINKERD2_PROXY_INBOUND_AUTHZ_RULES";
+
+/// Either `allow` or `deny`, determining whether clients are authorized on
+/// ports without a rule. Defaults to `allow`.
+pub const ENV_INBOUND_AUTHZ_DEFAULT: &str = "LINKERD2_PROXY_INBOUND_AUTHZ_DEFAULT";
+
 pub const ENV_IDENTITY_DISABLED: &str = "LINKERD2_PROXY_IDENTITY_DISABLED";
 pub const ENV_IDENTITY_DIR: &str = "LINKERD2_PROXY_IDENTITY_DIR";
 pub const ENV_IDENTITY_TRUST_ANCHORS: &str = "LINKERD2_PROXY_IDENTITY_TRUST_ANCHORS";
@@ -153,6 +324,10 @@ pub const ENV_DESTINATION_PROFILE_INITIAL_TIMEOUT: &str =
 
 pub const ENV_TAP_DISABLED: &str = "LINKERD2_PROXY_TAP_DISABLED";
 pub const ENV_TAP_SVC_NAME: &str = "LINKERD2_PROXY_TAP_SVC_NAME";
+
+/// Forbids taps from capturing request and response bodies, e.g. for
+/// workloads that handle sensitive data.
+pub const ENV_TAP_BODY_CAPTURE_DISABLED: &str = "LINKERD2_PROXY_TAP_BODY_CAPTURE_DISABLED";
 const ENV_RESOLV_CONF: &str = "LINKERD2_PROXY_RESOLV_CONF";
 
 /// Configures a minimum value for the TTL of DNS lookups.
@@ -183,6 +358,12 @@ const DEFAULT_ADMIN_LISTEN_ADDR: &str = "127.0.0.1:4191";
 const DEFAULT_METRICS_RETAIN_IDLE: Duration = Duration::from_secs(10 * 60);
 const DEFAULT_INBOUND_DISPATCH_TIMEOUT: Duration = Duration::from_secs(1);
 const DEFAULT_INBOUND_CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
+const DEFAULT_INBOUND_RATELIMIT_DOMAIN: &str = "linkerd";
+const DEFAULT_INBOUND_RATELIMIT_TIMEOUT: Duration = Duration::from_millis(100);
+const DEFAULT_INBOUND_EXT_AUTHZ_TIMEOUT: Duration = Duration::from_millis(500);
+const DEFAULT_INBOUND_JWT_LEEWAY: Duration = Duration::from_secs(60);
+const DEFAULT_INBOUND_JWT_CLAIM_HEADERS: &str = "sub=l5d-jwt-sub";
+const DEFAULT_INBOUND_JWT_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
 const DEFAULT_INBOUND_CONNECT_BACKOFF: ExponentialBackoff = ExponentialBackoff {
     min: Duration::from_millis(100),
     max: Duration::from_millis(500),
@@ -213,9 +394,25 @@ const DEFAULT_OUTBOUND_ROUTER_MAX_IDLE_AGE: Duration = Duration::from_secs(60);
 const DEFAULT_INBOUND_MAX_IN_FLIGHT: usize = 10_000;
 const DEFAULT_OUTBOUND_MAX_IN_FLIGHT: usize = 10_000;
 
+const DEFAULT_OUTBOUND_RETRY_MAX_BODY_BYTES: usize = 64 * 1024;
+const DEFAULT_OUTBOUND_MIRROR_MAX_IN_FLIGHT: usize = 100;
+const DEFAULT_OUTBOUND_MIRROR_MAX_BODY_BYTES: usize = 64 * 1024;
+
+const DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE_MIN_REQUESTS: u32 = 20;
+const DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_OUTBOUND_OUTLIER_BASE_EJECTION: Duration = Duration::from_secs(30);
+const DEFAULT_OUTBOUND_OUTLIER_MAX_EJECTION: Duration = Duration::from_secs(300);
+const DEFAULT_OUTBOUND_OUTLIER_MAX_EJECTION_PERCENT: u32 = 50;
+
+const DEFAULT_OUTBOUND_LOCALITY_MAX_PENDING: usize = 100;
+
+const DEFAULT_OUTBOUND_SLOW_START_AGGRESSION: f64 = 1.0;
+const DEFAULT_OUTBOUND_SLOW_START_MIN_WEIGHT: f64 = 0.1;
+
 const DEFAULT_DESTINATION_GET_SUFFIXES: &str = "svc.cluster.local.";
 const DEFAULT_DESTINATION_PROFILE_SUFFIXES: &str = "svc.cluster.local.";
 const DEFAULT_DESTINATION_PROFILE_INITIAL_TIMEOUT: Duration = Duration::from_millis(500);
+const DEFAULT_DESTINATION_PROFILE_POLICIES_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
 
 const DEFAULT_IDENTITY_MIN_REFRESH: Duration = Duration::from_secs(10);
 const DEFAULT_IDENTITY_MAX_REFRESH: Duration = Duration::from_secs(60 * 60 * 24);
@@ -279,6 +476,55 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
     let inbound_max_in_flight = parse(strings, ENV_INBOUND_MAX_IN_FLIGHT, parse_number);
     let outbound_max_in_flight = parse(strings, ENV_OUTBOUND_MAX_IN_FLIGHT, parse_number);
 
+    let inbound_adaptive_concurrency_min =
+        parse(strings, ENV_INBOUND_ADAPTIVE_CONCURRENCY_MIN, parse_number);
+    let inbound_adaptive_concurrency_max =
+        parse(strings, ENV_INBOUND_ADAPTIVE_CONCURRENCY_MAX, parse_number);
+
+    let outbound_retry_max_body_bytes =
+        parse(strings, ENV_OUTBOUND_RETRY_MAX_BODY_BYTES, parse_number);
+    let outbound_mirror_max_in_flight =
+        parse(strings, ENV_OUTBOUND_MIRROR_MAX_IN_FLIGHT, parse_number);
+    let outbound_mirror_max_body_bytes =
+        parse(strings, ENV_OUTBOUND_MIRROR_MAX_BODY_BYTES, parse_number);
+
+    let outbound_outlier_consecutive_failures = parse(
+        strings,
+        ENV_OUTBOUND_OUTLIER_CONSECUTIVE_FAILURES,
+        parse_number,
+    );
+    let outbound_outlier_failure_rate =
+        parse(strings, ENV_OUTBOUND_OUTLIER_FAILURE_RATE, parse_ratio);
+    let outbound_outlier_failure_rate_min_requests = parse(
+        strings,
+        ENV_OUTBOUND_OUTLIER_FAILURE_RATE_MIN_REQUESTS,
+        parse_number,
+    );
+    let outbound_outlier_failure_rate_interval = parse(
+        strings,
+        ENV_OUTBOUND_OUTLIER_FAILURE_RATE_INTERVAL,
+        parse_duration,
+    );
+    let outbound_outlier_base_ejection =
+        parse(strings, ENV_OUTBOUND_OUTLIER_BASE_EJECTION, parse_duration);
+    let outbound_outlier_max_ejection =
+        parse(strings, ENV_OUTBOUND_OUTLIER_MAX_EJECTION, parse_duration);
+    let outbound_outlier_max_ejection_percent = parse(
+        strings,
+        ENV_OUTBOUND_OUTLIER_MAX_EJECTION_PERCENT,
+        parse_number,
+    );
+
+    let outbound_locality_zone = strings.get(ENV_OUTBOUND_LOCALITY_ZONE);
+    let outbound_locality_max_pending =
+        parse(strings, ENV_OUTBOUND_LOCALITY_MAX_PENDING, parse_number);
+
+    let outbound_slow_start_window = parse(strings, ENV_OUTBOUND_SLOW_START_WINDOW, parse_duration);
+    let outbound_slow_start_aggression =
+        parse(strings, ENV_OUTBOUND_SLOW_START_AGGRESSION, parse_number);
+    let outbound_slow_start_min_weight =
+        parse(strings, ENV_OUTBOUND_SLOW_START_MIN_WEIGHT, parse_ratio);
+
     let metrics_retain_idle = parse(strings, ENV_METRICS_RETAIN_IDLE, parse_duration);
 
     // DNS
@@ -313,6 +559,43 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         parse_control_addr(strings, ENV_TRACE_COLLECTOR_SVC_BASE)
     };
 
+    let inbound_ratelimit_addr = if id_disabled {
+        parse_control_addr_disable_identity(strings, ENV_INBOUND_RATELIMIT_SVC_BASE)
+    } else {
+        parse_control_addr(strings, ENV_INBOUND_RATELIMIT_SVC_BASE)
+    };
+    let inbound_ratelimit_domain = strings.get(ENV_INBOUND_RATELIMIT_DOMAIN);
+    let inbound_ratelimit_descriptors = parse(
+        strings,
+        ENV_INBOUND_RATELIMIT_DESCRIPTORS,
+        parse_rate_limit_descriptors,
+    );
+    let inbound_ratelimit_timeout = parse(strings, ENV_INBOUND_RATELIMIT_TIMEOUT, parse_duration);
+    let inbound_ratelimit_failure_mode = parse(
+        strings,
+        ENV_INBOUND_RATELIMIT_FAILURE_MODE,
+        parse_failure_mode,
+    );
+
+    let inbound_ext_authz_addr = if id_disabled {
+        parse_control_addr_disable_identity(strings, ENV_INBOUND_EXT_AUTHZ_SVC_BASE)
+    } else {
+        parse_control_addr(strings, ENV_INBOUND_EXT_AUTHZ_SVC_BASE)
+    };
+    let inbound_ext_authz_timeout = parse(strings, ENV_INBOUND_EXT_AUTHZ_TIMEOUT, parse_duration);
+    let inbound_ext_authz_failure_mode = parse(
+        strings,
+        ENV_INBOUND_EXT_AUTHZ_FAILURE_MODE,
+        parse_failure_mode,
+    );
+
+    let inbound_jwt_jwks_path = strings.get(ENV_INBOUND_JWT_JWKS_PATH);
+    let inbound_jwt_issuer = strings.get(ENV_INBOUND_JWT_ISSUER);
+    let inbound_jwt_audiences = strings.get(ENV_INBOUND_JWT_AUDIENCES);
+    let inbound_jwt_leeway = parse(strings, ENV_INBOUND_JWT_LEEWAY, parse_duration);
+    let inbound_jwt_claim_headers =
+        parse(strings, ENV_INBOUND_JWT_CLAIM_HEADERS, parse_claim_headers);
+
     let dst_token = strings.get(ENV_DESTINATION_CONTEXT);
 
     let dst_get_suffixes = parse(strings, ENV_DESTINATION_GET_SUFFIXES, parse_dns_suffixes);
@@ -327,12 +610,22 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         ENV_DESTINATION_PROFILE_SUFFIXES,
         parse_dns_suffixes,
     );
+    let dst_profile_policies_path = strings.get(ENV_DESTINATION_PROFILE_POLICIES_PATH);
+    let dst_profile_policies_reload_interval = parse(
+        strings,
+        ENV_DESTINATION_PROFILE_POLICIES_RELOAD_INTERVAL,
+        parse_duration,
+    );
 
     let initial_stream_window_size = parse(strings, ENV_INITIAL_STREAM_WINDOW_SIZE, parse_number);
     let initial_connection_window_size =
         parse(strings, ENV_INITIAL_CONNECTION_WINDOW_SIZE, parse_number);
 
     let tap = parse_tap_config(strings, id_disabled);
+    let tap_body_capture = strings.get(ENV_TAP_BODY_CAPTURE_DISABLED).map(|d| match d {
+        Some(ref d) if !d.is_empty() => BodyCapture::Forbidden,
+        _ => BodyCapture::Permitted,
+    });
 
     let h2_settings = h2::Settings {
         initial_stream_window_size: Some(
@@ -389,9 +682,59 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         let dispatch_timeout =
             outbound_dispatch_timeout?.unwrap_or(DEFAULT_OUTBOUND_DISPATCH_TIMEOUT);
 
+        let outlier = {
+            let min_requests = outbound_outlier_failure_rate_min_requests?
+                .unwrap_or(DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE_MIN_REQUESTS);
+            let interval = outbound_outlier_failure_rate_interval?
+                .unwrap_or(DEFAULT_OUTBOUND_OUTLIER_FAILURE_RATE_INTERVAL);
+            outlier::Config {
+                consecutive_failures: outbound_outlier_consecutive_failures?.unwrap_or(0),
+                failure_rate: outbound_outlier_failure_rate?.map(|ratio| outlier::FailureRate {
+                    ratio,
+                    min_requests,
+                    interval,
+                }),
+                base_ejection: outbound_outlier_base_ejection?
+                    .unwrap_or(DEFAULT_OUTBOUND_OUTLIER_BASE_EJECTION),
+                max_ejection: outbound_outlier_max_ejection?
+                    .unwrap_or(DEFAULT_OUTBOUND_OUTLIER_MAX_EJECTION),
+                max_ejection_percent: outbound_outlier_max_ejection_percent?
+                    .unwrap_or(DEFAULT_OUTBOUND_OUTLIER_MAX_EJECTION_PERCENT),
+            }
+        };
+
+        let locality = match outbound_locality_zone? {
+            Some(zone) => Some(locality::Config {
+                zone,
+                max_pending: outbound_locality_max_pending?
+                    .unwrap_or(DEFAULT_OUTBOUND_LOCALITY_MAX_PENDING),
+            }),
+            None => None,
+        };
+
+        let slow_start = match outbound_slow_start_window? {
+            Some(window) if window > Duration::from_secs(0) => Some(SlowStart {
+                window,
+                aggression: outbound_slow_start_aggression?
+                    .unwrap_or(DEFAULT_OUTBOUND_SLOW_START_AGGRESSION),
+                min_weight_ratio: outbound_slow_start_min_weight?
+                    .unwrap_or(DEFAULT_OUTBOUND_SLOW_START_MIN_WEIGHT),
+            }),
+            _ => None,
+        };
+
         outbound::Config {
             canonicalize_timeout: dns_canonicalize_timeout?
                 .unwrap_or(DEFAULT_DNS_CANONICALIZE_TIMEOUT),
+            retry_max_body_bytes: outbound_retry_max_body_bytes?
+                .unwrap_or(DEFAULT_OUTBOUND_RETRY_MAX_BODY_BYTES),
+            mirror_max_in_flight: outbound_mirror_max_in_flight?
+                .unwrap_or(DEFAULT_OUTBOUND_MIRROR_MAX_IN_FLIGHT),
+            mirror_max_body_bytes: outbound_mirror_max_body_bytes?
+                .unwrap_or(DEFAULT_OUTBOUND_MIRROR_MAX_BODY_BYTES),
+            outlier,
+            locality,
+            slow_start,
             proxy: ProxyConfig {
                 server,
                 connect,
@@ -445,6 +788,30 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
             return Err(EnvError::InvalidEnvVar);
         }
 
+        let authz = authz::Policy::new(
+            parse(strings, ENV_INBOUND_AUTHZ_DEFAULT, parse_authz_default)?
+                .unwrap_or(authz::DefaultPolicy::Allow),
+            parse(strings, ENV_INBOUND_AUTHZ_RULES, parse_authz_rules)?.unwrap_or_default(),
+        );
+
+        let max_in_flight_requests =
+            inbound_max_in_flight?.unwrap_or(DEFAULT_INBOUND_MAX_IN_FLIGHT);
+
+        let adaptive_concurrency = match inbound_adaptive_concurrency_min? {
+            Some(min) if min > 0 => {
+                let max = inbound_adaptive_concurrency_max?.unwrap_or(max_in_flight_requests);
+                if max < min {
+                    error!(
+                        "{} must not be less than {}",
+                        ENV_INBOUND_ADAPTIVE_CONCURRENCY_MAX, ENV_INBOUND_ADAPTIVE_CONCURRENCY_MIN
+                    );
+                    return Err(EnvError::InvalidEnvVar);
+                }
+                Some(concurrency_limit::adaptive::Config { min, max })
+            }
+            _ => None,
+        };
+
         inbound::Config {
             proxy: ProxyConfig {
                 server,
@@ -456,11 +823,12 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
                     .unwrap_or(DEFAULT_INBOUND_ROUTER_MAX_IDLE_AGE),
                 buffer_capacity,
                 dispatch_timeout,
-                max_in_flight_requests: inbound_max_in_flight?
-                    .unwrap_or(DEFAULT_INBOUND_MAX_IN_FLIGHT),
+                max_in_flight_requests,
                 detect_protocol_timeout: dispatch_timeout,
             },
             require_identity_for_inbound_ports: require_identity_for_inbound_ports.into(),
+            adaptive_concurrency,
+            authz,
         }
     };
 
@@ -480,6 +848,9 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
                 .unwrap_or(parse_dns_suffixes(DEFAULT_DESTINATION_PROFILE_SUFFIXES).unwrap()),
             initial_profile_timeout: dst_profile_initial_timeout?
                 .unwrap_or(DEFAULT_DESTINATION_PROFILE_INITIAL_TIMEOUT),
+            profile_policies_path: dst_profile_policies_path?.map(Into::into),
+            profile_policies_reload_interval: dst_profile_policies_reload_interval?
+                .unwrap_or(DEFAULT_DESTINATION_PROFILE_POLICIES_RELOAD_INTERVAL),
             control: ControlConfig {
                 addr,
                 connect,
@@ -536,6 +907,68 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         }
     };
 
+    let inbound_rate_limit = match inbound_ratelimit_addr? {
+        None => global_rate_limit::Config::Disabled,
+        Some(addr) => {
+            let connect = if addr.addr.is_loopback() {
+                inbound.proxy.connect.clone()
+            } else {
+                outbound.proxy.connect.clone()
+            };
+
+            let descriptors = match inbound_ratelimit_descriptors? {
+                Some(descriptors) if !descriptors.is_empty() => descriptors,
+                _ => {
+                    error!(
+                        "{} must be set when {}_ADDR is set",
+                        ENV_INBOUND_RATELIMIT_DESCRIPTORS, ENV_INBOUND_RATELIMIT_SVC_BASE
+                    );
+                    return Err(EnvError::InvalidEnvVar);
+                }
+            };
+
+            global_rate_limit::Config::Enabled {
+                domain: inbound_ratelimit_domain?
+                    .unwrap_or_else(|| DEFAULT_INBOUND_RATELIMIT_DOMAIN.to_string()),
+                descriptors,
+                check: grpc_check::Config {
+                    failure_mode: inbound_ratelimit_failure_mode?
+                        .unwrap_or(grpc_check::FailureMode::Open),
+                    timeout: inbound_ratelimit_timeout?
+                        .unwrap_or(DEFAULT_INBOUND_RATELIMIT_TIMEOUT),
+                    control: ControlConfig {
+                        addr,
+                        connect,
+                        buffer_capacity,
+                    },
+                },
+            }
+        }
+    };
+
+    let inbound_ext_authz = match inbound_ext_authz_addr? {
+        None => ext_authz::Config::Disabled,
+        Some(addr) => {
+            let connect = if addr.addr.is_loopback() {
+                inbound.proxy.connect.clone()
+            } else {
+                outbound.proxy.connect.clone()
+            };
+
+            ext_authz::Config::Enabled(grpc_check::Config {
+                failure_mode: inbound_ext_authz_failure_mode?
+                    .unwrap_or(grpc_check::FailureMode::Closed),
+                timeout: inbound_ext_authz_timeout?.unwrap_or(DEFAULT_INBOUND_EXT_AUTHZ_TIMEOUT),
+                control: ControlConfig {
+                    addr,
+                    connect,
+                    buffer_capacity,
+                },
+            })
+        }
+    };
+
+    let body_capture = tap_body_capture?;
     let tap = tap?
         .map(|(addr, ids)| super::tap::Config::Enabled {
             permitted_peer_identities: ids,
@@ -543,6 +976,7 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
                 bind: listen::Bind::new(addr, inbound.proxy.server.bind.keepalive()),
                 h2_settings,
             },
+            body_capture,
         })
         .unwrap_or(super::tap::Config::Disabled);
 
@@ -565,6 +999,35 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         })
         .unwrap_or(identity::Config::Disabled);
 
+    let inbound_jwt = match inbound_jwt_jwks_path? {
+        None => jwt::Config::Disabled,
+        Some(path) => {
+            let claim_headers = match inbound_jwt_claim_headers? {
+                Some(headers) => headers,
+                None => parse_claim_headers(DEFAULT_INBOUND_JWT_CLAIM_HEADERS)
+                    .expect("default claim headers must be valid"),
+            };
+            jwt::Config::Enabled {
+                jwks_path: path.into(),
+                reload_interval: DEFAULT_INBOUND_JWT_RELOAD_INTERVAL,
+                validation: jwt::Validation {
+                    issuer: inbound_jwt_issuer?,
+                    audiences: inbound_jwt_audiences?
+                        .map(|auds| {
+                            auds.split(',')
+                                .map(str::trim)
+                                .filter(|aud| !aud.is_empty())
+                                .map(String::from)
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    leeway: inbound_jwt_leeway?.unwrap_or(DEFAULT_INBOUND_JWT_LEEWAY),
+                    claim_headers,
+                },
+            }
+        }
+    };
+
     Ok(super::Config {
         admin,
         dns,
@@ -574,6 +1037,9 @@ pub fn parse_config<S: Strings>(strings: &S) -> Result<super::Config, EnvError>
         identity,
         outbound,
         inbound,
+        inbound_rate_limit,
+        inbound_ext_authz,
+        inbound_jwt,
     })
 }
 
@@ -688,6 +1154,14 @@ where
     s.parse().map_err(|_| ParseError::NotANumber)
 }
 
+fn parse_ratio(s: &str) -> Result<f64, ParseError> {
+    let ratio = parse_number::<f64>(s)?;
+    if ratio < 0.0 || ratio > 1.0 {
+        return Err(ParseError::NotARatio);
+    }
+    Ok(ratio)
+}
+
 fn parse_duration(s: &str) -> Result<Duration, ParseError> {
     use regex::Regex;
 
@@ -707,6 +1181,134 @@ fn parse_duration(s: &str) -> Result<Duration, ParseError> {
     }
 }
 
+fn parse_rate_limit_descriptors(s: &str) -> Result<global_rate_limit::Descriptors, ParseError> {
+    use global_rate_limit::{Entry, Source};
+
+    let mut descriptors = Vec::new();
+    for descriptor in s.split(';') {
+        let descriptor = descriptor.trim();
+        if descriptor.is_empty() {
+            continue;
+        }
+
+        let mut entries = Vec::new();
+        for entry in descriptor.split(',') {
+            let mut parts = entry.trim().splitn(2, '=');
+            let key = parts.next().unwrap_or_default().trim();
+            let source = parts.next().ok_or(ParseError::NotARateLimitDescriptor)?;
+            if key.is_empty() {
+                return Err(ParseError::NotARateLimitDescriptor);
+            }
+
+            let mut parts = source.trim().splitn(2, ':');
+            let source = match (parts.next(), parts.next()) {
+                (Some("route"), Some(label)) if !label.is_empty() => {
+                    Source::RouteLabel(label.to_string())
+                }
+                (Some("header"), Some(name)) => {
+                    http::header::HeaderName::from_bytes(name.as_bytes())
+                        .map(Source::Header)
+                        .map_err(|_| ParseError::NotARateLimitDescriptor)?
+                }
+                (Some("client_id"), None) => Source::ClientId,
+                (Some("value"), Some(value)) => Source::Value(value.to_string()),
+                _ => return Err(ParseError::NotARateLimitDescriptor),
+            };
+            entries.push(Entry {
+                key: key.to_string(),
+                source,
+            });
+        }
+        descriptors.push(entries);
+    }
+
+    Ok(global_rate_limit::Descriptors::new(descriptors))
+}
+
+fn parse_failure_mode(s: &str) -> Result<grpc_check::FailureMode, ParseError> {
+    match s {
+        "open" => Ok(grpc_check::FailureMode::Open),
+        "closed" => Ok(grpc_check::FailureMode::Closed),
+        _ => Err(ParseError::NotAFailureMode),
+    }
+}
+
+fn parse_claim_headers(s: &str) -> Result<Vec<(String, http::header::HeaderName)>, ParseError> {
+    let mut headers = Vec::new();
+    for pair in s.split(',') {
+        let pair = pair.trim();
+        if pair.is_empty() {
+            continue;
+        }
+
+        let mut parts = pair.splitn(2, '=');
+        let claim = parts.next().unwrap_or_default().trim();
+        let header = parts.next().ok_or(ParseError::NotAClaimHeader)?.trim();
+        if claim.is_empty() {
+            return Err(ParseError::NotAClaimHeader);
+        }
+        let header = http::header::HeaderName::from_bytes(header.as_bytes())
+            .map_err(|_| ParseError::NotAClaimHeader)?;
+        headers.push((claim.to_string(), header));
+    }
+    Ok(headers)
+}
+
+fn parse_authz_rules(s: &str) -> Result<Vec<authz::Rule>, ParseError> {
+    let mut rules = Vec::new();
+    for rule in s.split(';') {
+        let rule = rule.trim();
+        if rule.is_empty() {
+            continue;
+        }
+
+        let mut parts = rule.splitn(2, '=');
+        let target = parts.next().unwrap_or_default().trim();
+        let clients = parts.next().ok_or(ParseError::NotAnAuthzRule)?;
+
+        let mut target = target.splitn(2, '/');
+        let port = parse_number::<u16>(target.next().unwrap_or_default())
+            .map_err(|_| ParseError::NotAnAuthzRule)?;
+        let route = match target.next() {
+            Some(route) if !route.is_empty() => Some(route.to_string()),
+            Some(_) => return Err(ParseError::NotAnAuthzRule),
+            None => None,
+        };
+
+        let mut authorized = authz::Clients::default();
+        for client in clients.split(',') {
+            let client = client.trim();
+            if client.is_empty() {
+                continue;
+            }
+            if let Ok(net) = ipnet::IpNet::from_str(client) {
+                authorized.networks.push(net);
+            } else {
+                let id = client.parse().map_err(|_| {
+                    error!(%client, "Invalid authorized client");
+                    ParseError::NotAnAuthzRule
+                })?;
+                authorized.identities.push(id);
+            }
+        }
+
+        rules.push(authz::Rule {
+            port,
+            route,
+            clients: authorized,
+        });
+    }
+    Ok(rules)
+}
+
+fn parse_authz_default(s: &str) -> Result<authz::DefaultPolicy, ParseError> {
+    match s {
+        "allow" => Ok(authz::DefaultPolicy::Allow),
+        "deny" => Ok(authz::DefaultPolicy::Deny),
+        _ => Err(ParseError::NotAnAuthzDefault),
+    }
+}
+
 fn parse_socket_addr(s: &str) -> Result<SocketAddr, ParseError> {
     match parse_addr(s)? {
         Addr::Socket(a) => Ok(a),
@@ -1102,6 +1704,16 @@ mod tests {
         assert_eq!(parse_duration("1"), Err(ParseError::NotADuration));
     }
 
+    #[test]
+    fn parse_ratio_range() {
+        assert_eq!(parse_ratio("0"), Ok(0.0));
+        assert_eq!(parse_ratio("0.25"), Ok(0.25));
+        assert_eq!(parse_ratio("1"), Ok(1.0));
+        assert_eq!(parse_ratio("1.5"), Err(ParseError::NotARatio));
+        assert_eq!(parse_ratio("-0.1"), Err(ParseError::NotARatio));
+        assert_eq!(parse_ratio("half"), Err(ParseError::NotANumber));
+    }
+
     #[test]
     fn convert_attributes_string_to_map_different_values() {
         let attributes_string = "\
@@ -1187,4 +1799,117 @@ mod tests {
             "names are coerced to lowercase"
         );
     }
+
+    #[test]
+    fn rate_limit_descriptors() {
+        use global_rate_limit::{Descriptors, Entry, Source};
+
+        let entry = |key: &str, source| Entry {
+            key: key.to_string(),
+            source,
+        };
+        assert_eq!(
+            parse_rate_limit_descriptors(
+                "generic_key=value:inbound, path=route:path; client=client_id,tenant=header:x-tenant"
+            ),
+            Ok(Descriptors::new(vec![
+                vec![
+                    entry("generic_key", Source::Value("inbound".into())),
+                    entry("path", Source::RouteLabel("path".into())),
+                ],
+                vec![
+                    entry("client", Source::ClientId),
+                    entry(
+                        "tenant",
+                        Source::Header(http::header::HeaderName::from_static("x-tenant"))
+                    ),
+                ],
+            ]))
+        );
+        for invalid in &[
+            "key",
+            "=client_id",
+            "key=route:",
+            "key=host:foo",
+            "key=header:a b",
+        ] {
+            assert_eq!(
+                parse_rate_limit_descriptors(invalid),
+                Err(ParseError::NotARateLimitDescriptor),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn authz_rules() {
+        use authz::{Clients, IdentityMatch, Rule};
+
+        let ns1 = "*.ns1.serviceaccount.identity.linkerd.cluster.local";
+        let foo = "foo.ns1.serviceaccount.identity.linkerd.cluster.local";
+        assert_eq!(
+            parse_authz_rules(&format!("8080={},10.0.0.0/8; 8080/admin={}", ns1, foo)),
+            Ok(vec![
+                Rule {
+                    port: 8080,
+                    route: None,
+                    clients: Clients {
+                        identities: vec![ns1.parse().unwrap()],
+                        networks: vec!["10.0.0.0/8".parse().unwrap()],
+                    },
+                },
+                Rule {
+                    port: 8080,
+                    route: Some("admin".into()),
+                    clients: Clients {
+                        identities: vec![IdentityMatch::Exact(parse_identity(foo).unwrap())],
+                        networks: vec![],
+                    },
+                },
+            ])
+        );
+        assert_eq!(
+            parse_authz_rules("9090="),
+            Ok(vec![Rule {
+                port: 9090,
+                route: None,
+                clients: Clients::default(),
+            }]),
+            "a rule may deny all clients"
+        );
+        for invalid in &["8080", "http=*", "8080/=*", "8080=not a name"] {
+            assert_eq!(
+                parse_authz_rules(invalid),
+                Err(ParseError::NotAnAuthzRule),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn claim_headers() {
+        use http::header::HeaderName;
+
+        assert_eq!(
+            parse_claim_headers("sub=x-user, email = x-email,"),
+            Ok(vec![
+                ("sub".to_string(), HeaderName::from_static("x-user")),
+                ("email".to_string(), HeaderName::from_static("x-email")),
+            ])
+        );
+        assert_eq!(
+            parse_claim_headers(DEFAULT_INBOUND_JWT_CLAIM_HEADERS).map(|h| h.len()),
+            Ok(1)
+        );
+        for invalid in &["sub", "=x-user", "sub=not a header"] {
+            assert_eq!(
+                parse_claim_headers(invalid),
+                Err(ParseError::NotAClaimHeader),
+                "{}",
+                invalid
+            );
+        }
+    }
 }
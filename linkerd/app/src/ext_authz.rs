@@ -0,0 +1,18 @@
+use crate::{dns, grpc_check, identity::LocalIdentity};
+pub use linkerd2_app_core::ext_authz::Filter;
+use linkerd2_app_core::ext_authz::CHECK;
+
+#[derive(Clone, Debug)]
+pub enum Config {
+    Disabled,
+    Enabled(grpc_check::Config),
+}
+
+impl Config {
+    pub fn build(self, identity: LocalIdentity, dns: dns::Resolver) -> Option<Filter> {
+        match self {
+            Config::Disabled => None,
+            Config::Enabled(check) => Some(Filter::new(check.build(CHECK, identity, dns))),
+        }
+    }
+}
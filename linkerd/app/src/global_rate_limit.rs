@@ -0,0 +1,29 @@
+use crate::{dns, grpc_check, identity::LocalIdentity};
+use linkerd2_app_core::global_rate_limit::SHOULD_RATE_LIMIT;
+pub use linkerd2_app_core::global_rate_limit::{Descriptors, Entry, Layer, Source};
+
+#[derive(Clone, Debug)]
+pub enum Config {
+    Disabled,
+    Enabled {
+        check: grpc_check::Config,
+        domain: String,
+        descriptors: Descriptors,
+    },
+}
+
+impl Config {
+    pub fn build(self, identity: LocalIdentity, dns: dns::Resolver) -> Layer {
+        match self {
+            Config::Disabled => Layer::disabled(),
+            Config::Enabled {
+                check,
+                domain,
+                descriptors,
+            } => {
+                let client = check.build(SHOULD_RATE_LIMIT, identity, dns);
+                Layer::new(client, domain, descriptors)
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{dns, identity::LocalIdentity};
+pub use linkerd2_app_core::grpc_check::FailureMode;
+use linkerd2_app_core::{
+    config::ControlConfig,
+    control,
+    grpc_check::{self, Client, Message, Method},
+    proxy, reconnect,
+    svc::{self, NewService},
+    transport::tls,
+};
+use std::time::Duration;
+
+/// Configures a client for an external check service, e.g. the inbound rate
+/// limit or authorization service.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub control: ControlConfig,
+    pub failure_mode: FailureMode,
+    pub timeout: Duration,
+}
+
+impl Config {
+    pub fn build<Req, Rsp>(
+        self,
+        method: Method<Req, Rsp>,
+        identity: LocalIdentity,
+        dns: dns::Resolver,
+    ) -> Client<Req, Rsp>
+    where
+        Req: Message,
+        Rsp: Message,
+    {
+        let Config {
+            control,
+            failure_mode,
+            timeout,
+        } = self;
+
+        let svc = svc::connect(control.connect.keepalive)
+            .push(tls::ConnectLayer::new(identity))
+            .push_timeout(control.connect.timeout)
+            .push(control::client::layer())
+            .push(control::resolve::layer(dns))
+            .push(reconnect::layer({
+                let backoff = control.connect.backoff;
+                move |_| Ok(backoff.stream())
+            }))
+            .push(control::add_origin::Layer::new())
+            .into_new_service()
+            .push_on_response(
+                svc::layers()
+                    .push(proxy::grpc::req_body_as_payload::layer())
+                    .push_spawn_buffer(control.buffer_capacity),
+            )
+            .new_service(control.addr);
+
+        let config = grpc_check::Config {
+            failure_mode,
+            timeout,
+        };
+        Client::new(method, config, svc)
+    }
+}
@@ -0,0 +1,36 @@
+use linkerd2_app_core::jwt::{self, Filter};
+pub use linkerd2_app_core::jwt::{Config as Validation, Daemon, Layer};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+pub enum Config {
+    Disabled,
+    Enabled {
+        jwks_path: PathBuf,
+        /// How often the JWKS file is checked for changes.
+        reload_interval: Duration,
+        validation: Validation,
+    },
+}
+
+impl Config {
+    /// Builds the layer that validates inbound requests and, if enabled, the
+    /// daemon that reloads the JWKS file.
+    pub fn build(self) -> (Layer, Option<Daemon>) {
+        match self {
+            Config::Disabled => (Layer::new(None), None),
+            Config::Enabled {
+                jwks_path,
+                reload_interval,
+                validation,
+            } => {
+                let (jwks, daemon) = jwt::watch(jwks_path, reload_interval);
+                (
+                    Layer::new(Some(Filter::new(validation, jwks))),
+                    Some(daemon),
+                )
+            }
+        }
+    }
+}
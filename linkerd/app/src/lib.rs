@@ -5,7 +5,11 @@
 pub mod admin;
 pub mod dst;
 pub mod env;
+pub mod ext_authz;
+pub mod global_rate_limit;
+pub mod grpc_check;
 pub mod identity;
+pub mod jwt;
 pub mod metrics;
 pub mod oc_collector;
 pub mod tap;
@@ -15,7 +19,7 @@ use futures::{future, Async, Future};
 pub use linkerd2_app_core::{self as core, trace};
 use linkerd2_app_core::{
     config::ControlAddr,
-    dns, drain,
+    dns, drain, profiles,
     svc::{self, NewService},
     Error,
 };
@@ -48,6 +52,9 @@ pub struct Config {
     pub admin: admin::Config,
     pub tap: tap::Config,
     pub oc_collector: oc_collector::Config,
+    pub inbound_rate_limit: global_rate_limit::Config,
+    pub inbound_ext_authz: ext_authz::Config,
+    pub inbound_jwt: jwt::Config,
 }
 
 pub struct App {
@@ -57,8 +64,10 @@ pub struct App {
     dst: ControlAddr,
     identity: identity::Identity,
     inbound: inbound::Inbound,
+    jwks: Option<jwt::Daemon>,
     oc_collector: oc_collector::OcCollector,
     outbound: outbound::Outbound,
+    profile_policies: Option<profiles::PolicyDaemon>,
     tap: tap::Tap,
 }
 
@@ -78,6 +87,9 @@ impl Config {
             dst,
             identity,
             inbound,
+            inbound_ext_authz,
+            inbound_jwt,
+            inbound_rate_limit,
             oc_collector,
             outbound,
             tap,
@@ -137,10 +149,27 @@ impl Config {
         let admin = {
             let identity = identity.local();
             let drain = drain_rx.clone();
-            info_span!("admin").in_scope(move || admin.build(identity, report, log_level, drain))?
+            let tap = tap.json();
+            info_span!("admin")
+                .in_scope(move || admin.build(identity, report, log_level, tap, drain))?
         };
 
+        let rate_limit = {
+            let identity = identity.local();
+            let dns = dns.resolver.clone();
+            info_span!("ratelimit").in_scope(|| inbound_rate_limit.build(identity, dns))
+        };
+
+        let ext_authz = {
+            let identity = identity.local();
+            let dns = dns.resolver.clone();
+            info_span!("ext_authz").in_scope(|| inbound_ext_authz.build(identity, dns))
+        };
+
+        let (jwt, jwks) = info_span!("jwt").in_scope(|| inbound_jwt.build());
+
         let dst_addr = dst.addr.clone();
+        let profile_policies = dst.profile_policies;
         let inbound = {
             let inbound = inbound;
             let identity = identity.local();
@@ -149,8 +178,11 @@ impl Config {
             let metrics = metrics.inbound;
             let oc = oc_collector.span_sink();
             let drain = drain_rx.clone();
-            info_span!("inbound")
-                .in_scope(move || inbound.build(identity, profiles, tap, metrics, oc, drain))?
+            info_span!("inbound").in_scope(move || {
+                inbound.build(
+                    identity, profiles, tap, rate_limit, ext_authz, jwt, metrics, oc, drain,
+                )
+            })?
         };
         let outbound = {
             let identity = identity.local();
@@ -179,8 +211,10 @@ impl Config {
             drain: drain_tx,
             identity,
             inbound,
+            jwks,
             oc_collector,
             outbound,
+            profile_policies,
             tap,
         })
     }
@@ -238,8 +272,10 @@ impl App {
             drain,
             identity,
             inbound,
+            jwks,
             oc_collector,
             outbound,
+            profile_policies,
             tap,
             ..
         } = self;
@@ -318,6 +354,14 @@ impl App {
                                 );
                             }
 
+                            if let Some(jwks) = jwks {
+                                tokio::spawn(jwks.instrument(info_span!("jwks")));
+                            }
+
+                            if let Some(policies) = profile_policies {
+                                tokio::spawn(policies.instrument(info_span!("profile_policies")));
+                            }
+
                             admin_shutdown_rx.map_err(|_| ())
                         })
                         .instrument(info_span!("daemon")),
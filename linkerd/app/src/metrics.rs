@@ -1,9 +1,11 @@
 pub use linkerd2_app_core::{
+    authz,
     classify::Class,
-    errors, handle_time, http_metrics as metrics,
-    metric_labels::{ControlLabels, EndpointLabels, RouteLabels},
+    concurrency_limit, errors, handle_time, health_check, http_metrics as metrics, locality,
+    metric_labels::{ConcreteLabels, ControlLabels, EndpointLabels, RouteLabels},
     metrics::FmtMetrics,
-    opencensus, proxy, stack_metrics, telemetry, transport, ControlHttpMetrics, ProxyMetrics,
+    opencensus, outlier, proxy, stack_metrics, telemetry, transport, ControlHttpMetrics,
+    ProxyMetrics,
 };
 use std::time::{Duration, SystemTime};
 
@@ -51,8 +53,36 @@ impl Metrics {
             (m, r.without_latencies())
         };
 
+        let (http_route_mirror, mirror_report) = {
+            let m = metrics::Requests::<RouteLabels, Class>::default();
+            let r = m
+                .clone()
+                .into_report(retain_idle)
+                .with_prefix("route_mirror");
+            (m, r)
+        };
+
+        let (http_concrete, concrete_report) = {
+            let m = metrics::Requests::<ConcreteLabels, Class>::default();
+            let r = m
+                .clone()
+                .into_report(retain_idle)
+                .with_prefix("route_concrete");
+            (m, r)
+        };
+
         let http_errors = errors::Metrics::default();
 
+        let http_outlier = outlier::Metrics::default();
+
+        let http_locality = locality::Metrics::default();
+
+        let http_health_check = health_check::Metrics::default();
+
+        let http_adaptive_concurrency = concurrency_limit::adaptive::Metrics::default();
+
+        let authz = authz::Metrics::default();
+
         let handle_time_report = handle_time::Metrics::new();
         let inbound_handle_time = handle_time_report.inbound();
         let outbound_handle_time = handle_time_report.outbound();
@@ -69,8 +99,15 @@ impl Metrics {
                 http_endpoint: http_endpoint.clone(),
                 http_route: http_route.clone(),
                 http_route_actual: http_route_actual.clone(),
+                http_route_mirror: http_route_mirror.clone(),
+                http_concrete: http_concrete.clone(),
                 http_route_retry: http_route_retry.clone(),
                 http_errors: http_errors.inbound(),
+                http_outlier: http_outlier.clone(),
+                http_locality: http_locality.clone(),
+                http_health_check: http_health_check.clone(),
+                http_adaptive_concurrency: http_adaptive_concurrency.clone(),
+                authz: authz.clone(),
                 stack: stack.clone(),
                 transport: transport.clone(),
             },
@@ -80,7 +117,14 @@ impl Metrics {
                 http_route,
                 http_route_retry,
                 http_route_actual,
+                http_route_mirror,
+                http_concrete,
                 http_errors: http_errors.outbound(),
+                http_outlier: http_outlier.clone(),
+                http_locality: http_locality.clone(),
+                http_health_check: http_health_check.clone(),
+                http_adaptive_concurrency: http_adaptive_concurrency.clone(),
+                authz: authz.clone(),
                 stack: stack.clone(),
                 transport,
             },
@@ -93,6 +137,13 @@ impl Metrics {
             .and_then(route_report)
             .and_then(retry_report)
             .and_then(actual_report)
+            .and_then(mirror_report)
+            .and_then(concrete_report)
+            .and_then(http_outlier)
+            .and_then(http_locality)
+            .and_then(http_health_check)
+            .and_then(http_adaptive_concurrency)
+            .and_then(authz)
             .and_then(control_report)
             .and_then(handle_time_report)
             .and_then(transport_report)
@@ -18,6 +18,7 @@ pub enum Config {
     Enabled {
         server: ServerConfig,
         permitted_peer_identities: IndexSet<identity::Name>,
+        body_capture: tap::BodyCapture,
     },
 }
 
@@ -29,6 +30,7 @@ pub enum Tap {
         listen_addr: SocketAddr,
         layer: tap::Layer,
         daemon: tap::Daemon,
+        json: tap::Json,
         serve: serve::Task,
     },
 }
@@ -39,17 +41,22 @@ impl Config {
         identity: tls::Conditional<identity::Local>,
         drain: drain::Watch,
     ) -> Result<Tap, Error> {
-        let (layer, grpc, daemon) = tap::new();
         match self {
             Config::Disabled => {
-                drop((grpc, daemon));
+                // Without a daemon, the layer's registrations are dropped, so
+                // nothing is tapped.
+                let (layer, _, _) = tap::new(tap::BodyCapture::Forbidden);
                 Ok(Tap::Disabled { layer })
             }
 
             Config::Enabled {
                 server,
                 permitted_peer_identities,
+                body_capture,
             } => {
+                let (layer, grpc, daemon) = tap::new(body_capture);
+                let json = tap::Json::new(grpc.clone());
+
                 let listen = server.bind.bind().map_err(Error::from)?;
                 let listen_addr = listen.listen_addr();
 
@@ -63,6 +70,7 @@ impl Config {
                 Ok(Tap::Enabled {
                     layer,
                     daemon,
+                    json,
                     serve,
                     listen_addr,
                 })
@@ -74,8 +82,16 @@ impl Config {
 impl Tap {
     pub fn layer(&self) -> tap::Layer {
         match self {
-            Tap::Disabled { ref layer } => layer.clone(),
+            Tap::Disabled { ref layer, .. } => layer.clone(),
             Tap::Enabled { ref layer, .. } => layer.clone(),
         }
     }
+
+    /// Returns a JSON tap server for the admin server, if tap is enabled.
+    pub fn json(&self) -> Option<tap::Json> {
+        match self {
+            Tap::Disabled { .. } => None,
+            Tap::Enabled { ref json, .. } => Some(json.clone()),
+        }
+    }
 }
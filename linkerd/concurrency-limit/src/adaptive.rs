@@ -0,0 +1,600 @@
+//! A concurrency limit that adapts to the inner service's latency and errors.
+//!
+//! The limit is adjusted as each request completes, following the gradient
+//! algorithm from Netflix's concurrency-limits library: while request latency
+//! stays near its long-term average, the limit grows by roughly the square
+//! root of the limit; as latency increases (i.e. as requests begin to queue in
+//! the inner service), the limit shrinks proportionally. Failed requests
+//! reduce the limit multiplicatively.
+//!
+//! Responses are classified, so that requests fail when the inner service
+//! fails or when their responses are classified as failures (e.g. with a 5XX
+//! status). Latency is measured until the response headers are received, but
+//! a request holds its place in the limit until its response body completes.
+//!
+//! Unlike the fixed limit, requests are not queued while the limit is
+//! exhausted. They fail immediately with a `Rejected` error so that callers
+//! may retry them elsewhere.
+
+use bytes::Bytes;
+use futures::{try_ready, Async, Future, Poll};
+use hyper::body::Payload;
+use linkerd2_error::Error;
+use linkerd2_http_classify::{Classify, ClassifyEos, ClassifyResponse};
+use linkerd2_metrics::{metrics, Counter, FmtMetrics, Gauge};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
+use tower::Service;
+use tracing::trace;
+
+metrics! {
+    adaptive_concurrency_limit: Gauge {
+        "The current adaptive limit on the number of in-flight requests."
+    },
+    adaptive_concurrency_rejected_total: Counter {
+        "Total count of requests rejected because the adaptive concurrency limit was exhausted."
+    }
+}
+
+/// The number of samples over which the long-term latency is averaged.
+const LONG_WINDOW: f64 = 600.0;
+
+/// The factor by which sampled latency may exceed the long-term latency before
+/// the limit is reduced.
+const TOLERANCE: f64 = 1.5;
+
+/// The weight given to each new estimate of the limit.
+const SMOOTHING: f64 = 0.2;
+
+/// The factor by which the limit is reduced when a request fails.
+const BACKOFF: f64 = 0.9;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Config {
+    /// The limit never drops below `min`. This is also the initial limit.
+    pub min: usize,
+
+    /// The limit never grows beyond `max`.
+    pub max: usize,
+}
+
+/// Determines whether a response classification indicates that its request
+/// failed.
+pub trait IsFailure {
+    fn is_failure(&self) -> bool;
+}
+
+#[derive(Clone, Debug)]
+pub struct Layer<C> {
+    limiter: Option<Arc<Limiter>>,
+    classify: C,
+}
+
+/// Rejects requests when the adaptive limit is exhausted.
+///
+/// If no limit is configured, requests are passed through to the inner
+/// service.
+#[derive(Clone, Debug)]
+pub struct AdaptiveConcurrencyLimit<S, C> {
+    inner: S,
+    limiter: Option<Arc<Limiter>>,
+    classify: C,
+}
+
+pub struct ResponseFuture<F, C> {
+    inner: Option<F>,
+    permit: Option<(Permit, C)>,
+}
+
+/// Holds a request's place in the limit until the response body completes.
+pub struct ResponseBody<B, C> {
+    inner: B,
+    permit: Option<(Permit, C)>,
+}
+
+/// Indicates that a request was rejected because the adaptive concurrency
+/// limit was exhausted.
+#[derive(Debug)]
+pub struct Rejected(());
+
+/// Reports the current limit and the number of rejected requests.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics(Arc<Mutex<Option<Arc<Limiter>>>>);
+
+#[derive(Debug)]
+struct Limiter {
+    config: Config,
+    rejected: Counter,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: f64,
+    in_flight: usize,
+    /// The long-term average latency, in seconds.
+    long_rtt: Option<f64>,
+}
+
+/// Holds a request's place in the limit until it completes or is dropped.
+struct Permit {
+    limiter: Arc<Limiter>,
+    start: Instant,
+    /// Set when the response headers are received.
+    rtt: Option<Duration>,
+}
+
+// === impl Layer ===
+
+impl<C> Layer<C> {
+    /// Limits requests, counting those whose responses `classify` deems
+    /// failures as failed.
+    pub fn new(config: Option<Config>, metrics: Metrics, classify: C) -> Self {
+        let limiter = config.map(|config| {
+            let limiter = Arc::new(Limiter::new(config));
+            *metrics.0.lock().expect("metrics lock poisoned") = Some(limiter.clone());
+            limiter
+        });
+        Self { limiter, classify }
+    }
+}
+
+impl<S, C: Clone> tower::layer::Layer<S> for Layer<C> {
+    type Service = AdaptiveConcurrencyLimit<S, C>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AdaptiveConcurrencyLimit {
+            inner,
+            limiter: self.limiter.clone(),
+            classify: self.classify.clone(),
+        }
+    }
+}
+
+// === impl AdaptiveConcurrencyLimit ===
+
+impl<S, C, A, B> Service<http::Request<A>> for AdaptiveConcurrencyLimit<S, C>
+where
+    S: Service<http::Request<A>, Response = http::Response<B>>,
+    S::Error: Into<Error>,
+    C: Classify,
+    C::Class: IsFailure,
+    B: Payload,
+{
+    type Response = http::Response<ResponseBody<B, C::ClassifyEos>>;
+    type Error = Error;
+    type Future = ResponseFuture<S::Future, C::ClassifyResponse>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, request: http::Request<A>) -> Self::Future {
+        let permit = match self.limiter {
+            None => None,
+            Some(ref limiter) => match limiter.acquire() {
+                Some(permit) => Some((permit, self.classify.classify(&request))),
+                None => {
+                    return ResponseFuture {
+                        inner: None,
+                        permit: None,
+                    }
+                }
+            },
+        };
+
+        ResponseFuture {
+            inner: Some(self.inner.call(request)),
+            permit,
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<F, C, B> Future for ResponseFuture<F, C>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+    C: ClassifyResponse,
+    C::Class: IsFailure,
+    B: Payload,
+{
+    type Item = http::Response<ResponseBody<B, C::ClassifyEos>>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = match self.inner.as_mut() {
+            Some(inner) => inner,
+            None => return Err(Rejected(()).into()),
+        };
+
+        let rsp = match inner.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(rsp)) => rsp,
+            Err(e) => {
+                let e = e.into();
+                if let Some((permit, classify)) = self.permit.take() {
+                    permit.record(classify.error(&e).is_failure());
+                }
+                return Err(e);
+            }
+        };
+
+        let permit = self.permit.take().and_then(|(mut permit, classify)| {
+            permit.responded();
+            let classify = classify.start(&rsp);
+            if rsp.body().is_end_stream() {
+                permit.record(classify.eos(None).is_failure());
+                return None;
+            }
+            Some((permit, classify))
+        });
+
+        Ok(Async::Ready(
+            rsp.map(|inner| ResponseBody { inner, permit }),
+        ))
+    }
+}
+
+// === impl ResponseBody ===
+
+impl<B, C: ClassifyEos> ResponseBody<B, C>
+where
+    C::Class: IsFailure,
+{
+    fn record_eos(&mut self, trailers: Option<&http::HeaderMap>) {
+        if let Some((permit, classify)) = self.permit.take() {
+            permit.record(classify.eos(trailers).is_failure());
+        }
+    }
+
+    fn record_error(&mut self, error: Error) -> Error {
+        if let Some((permit, classify)) = self.permit.take() {
+            permit.record(classify.error(&error).is_failure());
+        }
+        error
+    }
+}
+
+impl<B, C> Payload for ResponseBody<B, C>
+where
+    B: Payload,
+    C: ClassifyEos + Send + 'static,
+    C::Class: IsFailure,
+{
+    type Data = B::Data;
+    type Error = Error;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        let data = try_ready!(self
+            .inner
+            .poll_data()
+            .map_err(|e| self.record_error(e.into())));
+
+        // If the stream ended without trailers, classify it now, as
+        // `poll_trailers` may not be called.
+        if data.is_none() && self.inner.is_end_stream() {
+            self.record_eos(None);
+        }
+
+        Ok(Async::Ready(data))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        let trailers = try_ready!(self
+            .inner
+            .poll_trailers()
+            .map_err(|e| self.record_error(e.into())));
+        self.record_eos(trailers.as_ref());
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B: Default, C> Default for ResponseBody<B, C> {
+    fn default() -> Self {
+        Self {
+            inner: B::default(),
+            permit: None,
+        }
+    }
+}
+
+impl<B: From<Bytes>, C> From<Bytes> for ResponseBody<B, C> {
+    fn from(bytes: Bytes) -> Self {
+        Self {
+            inner: B::from(bytes),
+            permit: None,
+        }
+    }
+}
+
+// === impl Rejected ===
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "concurrency limit exceeded")
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+// === impl Metrics ===
+
+impl FmtMetrics for Metrics {
+    fn fmt_metrics(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let limiter = match self.0.lock() {
+            Ok(limiter) => match *limiter {
+                Some(ref limiter) => limiter.clone(),
+                None => return Ok(()),
+            },
+            Err(_) => return Ok(()),
+        };
+
+        let limit = limiter.limit();
+        adaptive_concurrency_limit.fmt_help(f)?;
+        adaptive_concurrency_limit.fmt_metric(f, &Gauge::from(limit as u64))?;
+
+        adaptive_concurrency_rejected_total.fmt_help(f)?;
+        adaptive_concurrency_rejected_total.fmt_metric(f, &limiter.rejected)?;
+
+        Ok(())
+    }
+}
+
+// === impl Limiter ===
+
+impl Limiter {
+    fn new(config: Config) -> Self {
+        Self {
+            config,
+            rejected: Counter::default(),
+            state: Mutex::new(State {
+                limit: config.min as f64,
+                in_flight: 0,
+                long_rtt: None,
+            }),
+        }
+    }
+
+    fn limit(&self) -> usize {
+        self.state.lock().expect("limit lock poisoned").limit as usize
+    }
+
+    fn acquire(self: &Arc<Self>) -> Option<Permit> {
+        {
+            let mut state = self.state.lock().expect("limit lock poisoned");
+            if state.in_flight >= state.limit as usize {
+                trace!(limit = %state.limit, "Rejecting request");
+                drop(state);
+                self.rejected.incr();
+                return None;
+            }
+            state.in_flight += 1;
+        }
+
+        Some(Permit {
+            limiter: self.clone(),
+            start: clock::now(),
+            rtt: None,
+        })
+    }
+}
+
+// === impl State ===
+
+impl State {
+    /// Updates the limit with the result of a completed request. The request
+    /// is still counted as in-flight.
+    fn update(&mut self, config: &Config, rtt: Duration, failed: bool) {
+        let min = config.min as f64;
+        let max = config.max as f64;
+
+        if failed {
+            self.limit = (self.limit * BACKOFF).max(min);
+            trace!(limit = %self.limit, "Request failed");
+            return;
+        }
+
+        let rtt = rtt.as_secs_f64();
+        let long_rtt = match self.long_rtt {
+            None => rtt,
+            Some(long) => long + (rtt - long) * 2.0 / (LONG_WINDOW + 1.0),
+        };
+        self.long_rtt = Some(long_rtt);
+
+        // When less than half of the limit is used, latency says little about
+        // whether the limit could be higher.
+        if (self.in_flight as f64) < self.limit / 2.0 || rtt <= 0.0 {
+            return;
+        }
+
+        let gradient = (TOLERANCE * long_rtt / rtt).max(0.5).min(1.0);
+        let estimate = self.limit * gradient + self.limit.sqrt();
+        self.limit = (self.limit * (1.0 - SMOOTHING) + estimate * SMOOTHING)
+            .max(min)
+            .min(max);
+        trace!(limit = %self.limit, %gradient, "Updated limit");
+    }
+}
+
+// === impl Permit ===
+
+impl Permit {
+    /// Records the request's latency once its response headers are received.
+    fn responded(&mut self) {
+        self.rtt = Some(clock::now().saturating_duration_since(self.start));
+    }
+
+    fn record(self, failed: bool) {
+        let rtt = self
+            .rtt
+            .unwrap_or_else(|| clock::now().saturating_duration_since(self.start));
+        let mut state = self.limiter.state.lock().expect("limit lock poisoned");
+        state.update(&self.limiter.config, rtt, failed);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.limiter.state.lock() {
+            state.in_flight -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: Config = Config { min: 10, max: 100 };
+
+    fn saturated(limit: f64) -> State {
+        State {
+            limit,
+            in_flight: limit as usize,
+            long_rtt: Some(0.1),
+        }
+    }
+
+    #[test]
+    fn grows_while_latency_is_steady() {
+        let mut state = saturated(10.0);
+        for _ in 0..200 {
+            state.in_flight = state.limit as usize;
+            state.update(&CONFIG, Duration::from_millis(100), false);
+        }
+        assert_eq!(state.limit, 100.0);
+    }
+
+    #[test]
+    fn shrinks_when_latency_increases() {
+        let mut state = saturated(50.0);
+        state.update(&CONFIG, Duration::from_secs(1), false);
+        assert!(state.limit < 50.0);
+
+        let mut state = saturated(50.0);
+        for _ in 0..100 {
+            state.update(&CONFIG, Duration::from_secs(1), false);
+        }
+        assert_eq!(state.limit, 10.0);
+    }
+
+    #[test]
+    fn backs_off_on_failure() {
+        let mut state = saturated(50.0);
+        state.update(&CONFIG, Duration::from_millis(100), true);
+        assert_eq!(state.limit, 45.0);
+    }
+
+    #[test]
+    fn does_not_grow_when_underused() {
+        let mut state = saturated(50.0);
+        state.in_flight = 10;
+        state.update(&CONFIG, Duration::from_millis(100), false);
+        assert_eq!(state.limit, 50.0);
+    }
+
+    #[test]
+    fn backs_off_on_failure_responses() {
+        use futures::future;
+
+        /// Classifies server errors as failures.
+        #[derive(Clone)]
+        struct ServerErrors;
+
+        struct Failed(bool);
+
+        impl IsFailure for Failed {
+            fn is_failure(&self) -> bool {
+                self.0
+            }
+        }
+
+        impl Classify for ServerErrors {
+            type Class = Failed;
+            type ClassifyResponse = Self;
+            type ClassifyEos = Failed;
+
+            fn classify<B>(&self, _: &http::Request<B>) -> Self {
+                ServerErrors
+            }
+        }
+
+        impl ClassifyResponse for ServerErrors {
+            type Class = Failed;
+            type ClassifyEos = Failed;
+
+            fn start<B>(self, rsp: &http::Response<B>) -> Failed {
+                Failed(rsp.status().is_server_error())
+            }
+
+            fn error(self, _: &Error) -> Failed {
+                Failed(true)
+            }
+        }
+
+        impl ClassifyEos for Failed {
+            type Class = Failed;
+
+            fn eos(self, _: Option<&http::HeaderMap>) -> Failed {
+                self
+            }
+
+            fn error(self, _: &Error) -> Failed {
+                Failed(true)
+            }
+        }
+
+        struct Respond(http::StatusCode);
+
+        impl Service<http::Request<()>> for Respond {
+            type Response = http::Response<hyper::Body>;
+            type Error = Error;
+            type Future = future::FutureResult<Self::Response, Error>;
+
+            fn poll_ready(&mut self) -> Poll<(), Error> {
+                Ok(Async::Ready(()))
+            }
+
+            fn call(&mut self, _: http::Request<()>) -> Self::Future {
+                let mut rsp = http::Response::new(hyper::Body::empty());
+                *rsp.status_mut() = self.0;
+                future::ok(rsp)
+            }
+        }
+
+        let metrics = Metrics::default();
+        let layer = Layer::new(Some(Config { min: 10, max: 100 }), metrics, ServerErrors);
+        let limiter = layer.limiter.clone().unwrap();
+        limiter.state.lock().unwrap().limit = 50.0;
+
+        let mut svc = tower::layer::Layer::layer(&layer, Respond(http::StatusCode::OK));
+        svc.call(http::Request::new(())).wait().unwrap();
+        assert_eq!(limiter.limit(), 50);
+
+        let mut svc =
+            tower::layer::Layer::layer(&layer, Respond(http::StatusCode::SERVICE_UNAVAILABLE));
+        svc.call(http::Request::new(())).wait().unwrap();
+        assert_eq!(limiter.limit(), 45);
+        assert_eq!(limiter.state.lock().unwrap().in_flight, 0);
+    }
+
+    #[test]
+    fn rejects_when_exhausted() {
+        let limiter = Arc::new(Limiter::new(Config { min: 2, max: 2 }));
+        let a = limiter.acquire().expect("permit");
+        let _b = limiter.acquire().expect("permit");
+        assert!(limiter.acquire().is_none());
+        assert_eq!(limiter.rejected.value(), 1);
+
+        drop(a);
+        assert!(limiter.acquire().is_some());
+    }
+}
@@ -6,6 +6,8 @@
 
 #![deny(warnings, rust_2018_idioms)]
 
+pub mod adaptive;
+
 use futures::{try_ready, Future, Poll};
 use linkerd2_error::Error;
 use std::sync::Arc;
@@ -60,6 +60,13 @@ impl ExponentialBackoff {
         Ok(ExponentialBackoff { min, max, jitter })
     }
 
+    /// Returns a jittered delay for an operation that has already been
+    /// attempted `iterations` times.
+    pub fn delay<R: rand::Rng>(&self, iterations: u32, rng: &mut R) -> Duration {
+        let base = self.base(iterations);
+        base + self.jitter(base, rng)
+    }
+
     fn base(&self, iterations: u32) -> Duration {
         debug_assert!(
             self.min <= self.max,
@@ -111,10 +118,7 @@ impl Stream for ExponentialBackoffStream {
                 return Ok(None.into());
             }
 
-            let backoff = {
-                let base = self.backoff.base(self.iterations);
-                base + self.backoff.jitter(base, &mut self.rng)
-            };
+            let backoff = self.backoff.delay(self.iterations, &mut self.rng);
             self.delay = Some(timer::Delay::new(timer::clock::now() + backoff));
         }
     }
@@ -171,5 +175,17 @@ mod tests {
                 TestResult::from_bool(j > Duration::default())
             }
         }
+
+        fn backoff_delay(min_ms: u64, max_ms: u64, jitter: f64, iterations: u32) -> TestResult {
+            let min = Duration::from_millis(min_ms);
+            let max = Duration::from_millis(max_ms);
+            let backoff = match ExponentialBackoff::new(min, max, jitter) {
+                Err(_) => return TestResult::discard(),
+                Ok(backoff) => backoff,
+            };
+
+            let delay = backoff.delay(iterations, &mut rand::thread_rng());
+            TestResult::from_bool(min <= delay && delay <= max)
+        }
     }
 }
@@ -0,0 +1,253 @@
+//! A request filter that checks each request with an external authorization
+//! service, implementing Envoy's `envoy.service.auth.v3.Authorization` API,
+//! before the request is forwarded.
+//!
+//! The service is sent the request's method, path, headers and, if known, the
+//! client's identity. If the service allows the request, headers it returns
+//! are added to the request. If it denies the request, the request fails with
+//! a `Denied` error that describes the response returned to the client. If
+//! the service cannot be reached in time, requests are handled according to
+//! the client's `FailureMode`.
+
+#![deny(warnings, rust_2018_idioms)]
+
+pub use envoy_proto as proto;
+use futures::{try_ready, Async, Future, Poll};
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use linkerd2_error::Error;
+pub use linkerd2_grpc_check::{Config, FailureMode, Unavailable};
+use linkerd2_grpc_check::{Method, ResponseFuture as CheckFuture};
+use linkerd2_request_filter::AsyncRequestFilter;
+use std::collections::HashMap;
+use std::fmt;
+use tracing::debug;
+
+use proto::envoy::config::core::v3::HeaderValueOption;
+use proto::envoy::service::auth::v3::{
+    attribute_context, check_response::HttpResponse, AttributeContext, CheckRequest, CheckResponse,
+};
+
+/// An authorization service client.
+pub type Client = linkerd2_grpc_check::Client<CheckRequest, CheckResponse>;
+
+/// The authorization service's `Check` method.
+pub const CHECK: Method<CheckRequest, CheckResponse> = Method::new(
+    "authorization service",
+    "/envoy.service.auth.v3.Authorization/Check",
+);
+
+/// Returns the identity of the client that sent a request, given the
+/// request's extensions.
+pub type ClientIdentity = fn(&http::Extensions) -> Option<String>;
+
+/// Checks requests with the authorization service.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    client: Client,
+    client_identity: ClientIdentity,
+}
+
+pub struct ResponseFuture<B> {
+    check: CheckFuture<CheckResponse>,
+    request: Option<http::Request<B>>,
+}
+
+/// Indicates that the authorization service denied a request.
+///
+/// Describes the response that should be returned to the client.
+#[derive(Debug)]
+pub struct Denied {
+    status: http::StatusCode,
+    headers: HeaderMap,
+    body: String,
+}
+
+// gRPC's `OK` status code.
+const OK: i32 = 0;
+
+/// Headers that describe the authorization service's connection or message
+/// framing rather than the denied response, so they are not returned to the
+/// client.
+const DENIED_SKIP_HEADERS: &[HeaderName] = &[
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+// === impl Filter ===
+
+impl Filter {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            client_identity: |_| None,
+        }
+    }
+
+    /// Sets how the client's identity is determined.
+    pub fn with_client_identity(self, client_identity: ClientIdentity) -> Self {
+        Self {
+            client_identity,
+            ..self
+        }
+    }
+
+    fn check_request<B>(&self, req: &http::Request<B>) -> CheckRequest {
+        let mut headers = HashMap::<String, String>::new();
+        for (name, value) in req.headers().iter() {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            headers
+                .entry(name.as_str().to_owned())
+                .and_modify(|v| {
+                    v.push(',');
+                    v.push_str(value);
+                })
+                .or_insert_with(|| value.to_owned());
+        }
+
+        let host = req
+            .uri()
+            .authority_part()
+            .map(|a| a.as_str().to_owned())
+            .or_else(|| headers.get(http::header::HOST.as_str()).cloned())
+            .unwrap_or_default();
+
+        let http = attribute_context::HttpRequest {
+            method: req.method().as_str().to_owned(),
+            headers,
+            path: req
+                .uri()
+                .path_and_query()
+                .map(|p| p.as_str().to_owned())
+                .unwrap_or_else(|| "/".to_owned()),
+            host,
+            scheme: req.uri().scheme_str().unwrap_or("http").to_owned(),
+            protocol: format!("{:?}", req.version()),
+        };
+
+        let source =
+            (self.client_identity)(req.extensions()).map(|principal| attribute_context::Peer {
+                principal,
+                ..Default::default()
+            });
+
+        CheckRequest {
+            attributes: Some(AttributeContext {
+                source,
+                destination: None,
+                request: Some(attribute_context::Request { http: Some(http) }),
+            }),
+        }
+    }
+}
+
+impl<B> AsyncRequestFilter<http::Request<B>> for Filter {
+    type Error = Error;
+    type Future = ResponseFuture<B>;
+
+    fn filter(&self, req: http::Request<B>) -> Self::Future {
+        let check = self.client.check(self.check_request(&req));
+        ResponseFuture {
+            check,
+            request: Some(req),
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<B> Future for ResponseFuture<B> {
+    type Item = http::Request<B>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let headers = match try_ready!(self.check.poll()) {
+            Some(rsp) => allowed_headers(rsp)?,
+            None => Vec::new(),
+        };
+
+        let mut req = self.request.take().expect("polled after complete");
+        for (name, value) in header_values(headers) {
+            req.headers_mut().insert(name, value);
+        }
+        Ok(Async::Ready(req))
+    }
+}
+
+/// Returns the headers to add to an allowed request, or a `Denied` error.
+fn allowed_headers(rsp: CheckResponse) -> Result<Vec<HeaderValueOption>, Denied> {
+    let allowed = rsp.status.map(|s| s.code == OK).unwrap_or(true);
+    match rsp.http_response {
+        Some(HttpResponse::OkResponse(ok)) if allowed => Ok(ok.headers),
+        Some(HttpResponse::DeniedResponse(denied)) if !allowed => {
+            let status = denied
+                .status
+                .and_then(|s| http::StatusCode::from_u16(s.code as u16).ok())
+                .filter(|s| !s.is_success())
+                .unwrap_or(http::StatusCode::FORBIDDEN);
+            debug!(%status, "Denied");
+            Err(Denied {
+                status,
+                headers: header_values(denied.headers)
+                    .filter(|(name, _)| !is_denied_skip_header(name))
+                    .collect(),
+                body: denied.body,
+            })
+        }
+        _ if allowed => Ok(Vec::new()),
+        _ => {
+            debug!("Denied");
+            Err(Denied {
+                status: http::StatusCode::FORBIDDEN,
+                headers: HeaderMap::new(),
+                body: String::new(),
+            })
+        }
+    }
+}
+
+/// Ignores invalid headers.
+fn header_values(
+    headers: Vec<HeaderValueOption>,
+) -> impl Iterator<Item = (HeaderName, HeaderValue)> {
+    headers.into_iter().filter_map(|h| {
+        let h = h.header?;
+        let name = HeaderName::from_bytes(h.key.as_bytes()).ok()?;
+        let value = HeaderValue::from_str(&h.value).ok()?;
+        Some((name, value))
+    })
+}
+
+fn is_denied_skip_header(name: &HeaderName) -> bool {
+    DENIED_SKIP_HEADERS.contains(name) || name == "keep-alive" || name == "proxy-connection"
+}
+
+// === impl Denied ===
+
+impl Denied {
+    pub fn status(&self) -> http::StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request denied by authorization service")
+    }
+}
+
+impl std::error::Error for Denied {}
@@ -0,0 +1,246 @@
+//! A client for external gRPC services that check requests before they are
+//! forwarded, e.g. Envoy's authorization and rate limit services.
+//!
+//! Each check is a unary call. If the service cannot be reached, or does not
+//! respond within the configured timeout, the check fails open (the request
+//! is forwarded unchecked) or closed (the request fails with an `Unavailable`
+//! error), depending on the configured `FailureMode`.
+
+#![deny(warnings, rust_2018_idioms)]
+
+use futures::{future, try_ready, Async, Future, Poll};
+use http::uri::PathAndQuery;
+use linkerd2_error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_timer::Timeout;
+use tower_grpc::{self as grpc, client::Grpc, generic::client::GrpcService, Body, BoxBody};
+use tracing::debug;
+
+/// Determines how requests are handled when a check fails.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FailureMode {
+    /// Requests are forwarded.
+    Open,
+    /// Requests fail with an `Unavailable` error.
+    Closed,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub failure_mode: FailureMode,
+    /// The time after which a check is considered to have failed.
+    pub timeout: Duration,
+}
+
+/// A message sent to, or received from, a check service.
+pub trait Message: prost::Message + Default + Send + 'static {}
+
+/// Describes a service's unary check method, which is called with `Req`
+/// messages and responds with `Rsp` messages.
+pub struct Method<Req, Rsp> {
+    /// Describes the service in errors and logs.
+    name: &'static str,
+    /// The method's gRPC path, e.g. `/envoy.service.auth.v3.Authorization/Check`.
+    path: &'static str,
+    _marker: PhantomData<fn(Req) -> Rsp>,
+}
+
+/// A check service client that hides the type of the underlying gRPC service
+/// so that it need not be threaded through the proxy's stacks.
+pub struct Client<Req, Rsp> {
+    name: &'static str,
+    config: Arc<Config>,
+    inner: Box<dyn Call<Req, Rsp> + Send>,
+}
+
+pub struct ResponseFuture<Rsp> {
+    name: &'static str,
+    failure_mode: FailureMode,
+    inner: Timeout<CallFuture<Rsp>>,
+}
+
+/// Indicates that a request could not be checked and the failure mode is
+/// `Closed`.
+#[derive(Debug)]
+pub struct Unavailable {
+    name: &'static str,
+    error: Error,
+}
+
+type CallFuture<Rsp> = Box<dyn Future<Item = Rsp, Error = Error> + Send + 'static>;
+
+trait Call<Req, Rsp> {
+    fn call(&self, req: Req) -> CallFuture<Rsp>;
+
+    fn clone_box(&self) -> Box<dyn Call<Req, Rsp> + Send>;
+}
+
+struct Inner<C> {
+    client: C,
+    path: PathAndQuery,
+}
+
+// === impl Message ===
+
+impl<M: prost::Message + Default + Send + 'static> Message for M {}
+
+// === impl Method ===
+
+impl<Req, Rsp> Method<Req, Rsp> {
+    pub const fn new(name: &'static str, path: &'static str) -> Self {
+        Self {
+            name,
+            path,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Req, Rsp> Copy for Method<Req, Rsp> {}
+
+impl<Req, Rsp> Clone for Method<Req, Rsp> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Req, Rsp> fmt::Debug for Method<Req, Rsp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Method")
+            .field("name", &self.name)
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+// === impl Client ===
+
+impl<Req: Message, Rsp: Message> Client<Req, Rsp> {
+    pub fn new<C>(method: Method<Req, Rsp>, config: Config, client: C) -> Self
+    where
+        C: GrpcService<BoxBody> + Clone + Send + 'static,
+        C::ResponseBody: Send + 'static,
+        <C::ResponseBody as Body>::Data: Send,
+        C::Future: Send + 'static,
+    {
+        Self {
+            name: method.name,
+            config: Arc::new(config),
+            inner: Box::new(Inner {
+                client,
+                path: PathAndQuery::from_static(method.path),
+            }),
+        }
+    }
+}
+
+impl<Req, Rsp> Client<Req, Rsp> {
+    pub fn check(&self, req: Req) -> ResponseFuture<Rsp> {
+        ResponseFuture {
+            name: self.name,
+            failure_mode: self.config.failure_mode,
+            inner: Timeout::new(self.inner.call(req), self.config.timeout),
+        }
+    }
+}
+
+impl<Req, Rsp> Clone for Client<Req, Rsp> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name,
+            config: self.config.clone(),
+            inner: self.inner.clone_box(),
+        }
+    }
+}
+
+impl<Req, Rsp> fmt::Debug for Client<Req, Rsp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("name", &self.name)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+// === impl Inner ===
+
+impl<C, Req, Rsp> Call<Req, Rsp> for Inner<C>
+where
+    C: GrpcService<BoxBody> + Clone + Send + 'static,
+    C::ResponseBody: Send + 'static,
+    <C::ResponseBody as Body>::Data: Send,
+    C::Future: Send + 'static,
+    Req: Message,
+    Rsp: Message,
+{
+    fn call(&self, req: Req) -> CallFuture<Rsp> {
+        let mut grpc = Some(Grpc::new(self.client.clone()));
+        let path = self.path.clone();
+        let call = future::poll_fn(move || {
+            try_ready!(grpc.as_mut().expect("polled after ready").poll_ready());
+            Ok(Async::Ready(grpc.take().expect("polled after ready")))
+        })
+        .and_then(move |mut grpc| grpc.unary(grpc::Request::new(req), path))
+        .map(grpc::Response::into_inner)
+        .map_err(Error::from);
+        Box::new(call)
+    }
+
+    fn clone_box(&self) -> Box<dyn Call<Req, Rsp> + Send> {
+        Box::new(Inner {
+            client: self.client.clone(),
+            path: self.path.clone(),
+        })
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<Rsp> Future for ResponseFuture<Rsp> {
+    /// The service's response, or `None` if the check failed open.
+    type Item = Option<Rsp>;
+    type Error = Unavailable;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.inner.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(rsp)) => Ok(Async::Ready(Some(rsp))),
+            Err(e) => {
+                let error = e.into_inner().unwrap_or_else(|| "check timed out".into());
+                if self.failure_mode == FailureMode::Closed {
+                    return Err(Unavailable {
+                        name: self.name,
+                        error,
+                    });
+                }
+                debug!(service = self.name, %error, "Check failed; forwarding request");
+                Ok(Async::Ready(None))
+            }
+        }
+    }
+}
+
+// === impl Unavailable ===
+
+impl Unavailable {
+    /// Describes the service that could not check the request.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl fmt::Display for Unavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} unavailable: {}", self.name, self.error)
+    }
+}
+
+impl std::error::Error for Unavailable {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
@@ -0,0 +1,289 @@
+#![deny(warnings, rust_2018_idioms)]
+
+use futures::{Async, Future, Poll};
+use linkerd2_error::Error;
+use linkerd2_stack::{NewService, Proxy, ProxyService};
+use std::time::Duration;
+use tokio_timer::{clock, Delay};
+use tower::util::{Oneshot, ServiceExt};
+use tracing::{debug, trace};
+
+/// A strategy for obtaining per-target hedging polices.
+pub trait NewPolicy<T> {
+    type Policy;
+
+    fn new_policy(&self, target: &T) -> Option<Self::Policy>;
+}
+
+/// Determines whether and when a request is hedged.
+pub trait Policy<Req> {
+    /// Returns the amount of time to wait for the original request before
+    /// issuing a hedged request, or `None` if the request may not be hedged.
+    fn delay(&self, req: &Req) -> Option<Duration>;
+
+    /// Clones a request so that it may be hedged.
+    ///
+    /// The original request may be annotated, e.g. so that the hedged request
+    /// is dispatched to a different endpoint.
+    fn clone_request(&self, req: &mut Req) -> Option<Req>;
+
+    /// Determines whether a hedged request may be issued once the delay has
+    /// elapsed, i.e. whether there is budget for it.
+    fn can_hedge(&self) -> bool;
+}
+
+/// A layer that applies per-target hedging polcies.
+///
+/// Composes `NewService`s that produce a `Proxy`.
+#[derive(Clone, Debug)]
+pub struct NewHedgeLayer<P> {
+    new_policy: P,
+}
+
+#[derive(Clone, Debug)]
+pub struct NewHedge<P, N> {
+    new_policy: P,
+    inner: N,
+}
+
+#[derive(Clone, Debug)]
+pub struct Hedge<P, S> {
+    policy: Option<P>,
+    inner: S,
+}
+
+pub enum ResponseFuture<R, P, S, Req>
+where
+    P: Proxy<Req, S> + Clone,
+    S: tower::Service<P::Request> + Clone,
+    S::Error: Into<Error>,
+{
+    Disabled(P::Future),
+    Hedged(Hedged<R, P, S, Req>),
+}
+
+/// Races an original request against a hedged copy of it.
+///
+/// The hedged request is only issued once the delay elapses. The first
+/// response to be received is returned, and the other request is dropped
+/// (i.e. canceled). If both requests fail, the last error is returned.
+pub struct Hedged<R, P, S, Req>
+where
+    P: Proxy<Req, S> + Clone,
+    S: tower::Service<P::Request> + Clone,
+    S::Error: Into<Error>,
+{
+    policy: R,
+    delay: Option<Delay>,
+    original: Option<P::Future>,
+    /// The hedged request, which is not polled until the delay elapses.
+    hedge: Option<Oneshot<ProxyService<P, S>, Req>>,
+    hedging: bool,
+}
+
+// === impl NewHedgeLayer ===
+
+impl<P> NewHedgeLayer<P> {
+    pub fn new(new_policy: P) -> Self {
+        Self { new_policy }
+    }
+}
+
+impl<P: Clone, N> tower::layer::Layer<N> for NewHedgeLayer<P> {
+    type Service = NewHedge<P, N>;
+
+    fn layer(&self, inner: N) -> Self::Service {
+        Self::Service {
+            inner,
+            new_policy: self.new_policy.clone(),
+        }
+    }
+}
+
+// === impl NewHedge ===
+
+impl<T, N, P> NewService<T> for NewHedge<P, N>
+where
+    N: NewService<T>,
+    P: NewPolicy<T>,
+{
+    type Service = Hedge<P::Policy, N::Service>;
+
+    fn new_service(&self, target: T) -> Self::Service {
+        // Determine if there is a hedging policy for the given target.
+        let policy = self.new_policy.new_policy(&target);
+
+        let inner = self.inner.new_service(target);
+        Hedge { policy, inner }
+    }
+}
+
+// === impl Hedge ===
+
+impl<R, P, Req, S> Proxy<Req, S> for Hedge<R, P>
+where
+    R: Policy<Req> + Clone,
+    P: Proxy<Req, S> + Clone,
+    S: tower::Service<P::Request> + Clone,
+    S::Error: Into<Error>,
+{
+    type Request = P::Request;
+    type Response = P::Response;
+    type Error = Error;
+    type Future = ResponseFuture<R, P, S, Req>;
+
+    fn proxy(&self, svc: &mut S, mut req: Req) -> Self::Future {
+        if let Some(policy) = self.policy.as_ref() {
+            if let Some(delay) = policy.delay(&req) {
+                if let Some(clone) = policy.clone_request(&mut req) {
+                    trace!(?delay, "hedging");
+                    let hedge = self.inner.clone().wrap_service(svc.clone());
+                    return ResponseFuture::Hedged(Hedged {
+                        policy: policy.clone(),
+                        delay: Some(Delay::new(clock::now() + delay)),
+                        original: Some(self.inner.proxy(svc, req)),
+                        hedge: Some(hedge.oneshot(clone)),
+                        hedging: false,
+                    });
+                }
+            }
+        }
+
+        ResponseFuture::Disabled(self.inner.proxy(svc, req))
+    }
+}
+
+impl<R, P, S, Req> Future for ResponseFuture<R, P, S, Req>
+where
+    R: Policy<Req>,
+    P: Proxy<Req, S> + Clone,
+    S: tower::Service<P::Request> + Clone,
+    S::Error: Into<Error>,
+{
+    type Item = P::Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self {
+            ResponseFuture::Disabled(ref mut f) => f.poll().map_err(Into::into),
+            ResponseFuture::Hedged(ref mut f) => f.poll(),
+        }
+    }
+}
+
+// === impl Hedged ===
+
+impl<R, P, S, Req> Future for Hedged<R, P, S, Req>
+where
+    R: Policy<Req>,
+    P: Proxy<Req, S> + Clone,
+    S: tower::Service<P::Request> + Clone,
+    S::Error: Into<Error>,
+{
+    type Item = P::Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(f) = self.original.as_mut() {
+            match f.poll() {
+                Ok(Async::NotReady) => {}
+                Ok(Async::Ready(rsp)) => return Ok(Async::Ready(rsp)),
+                Err(e) => {
+                    // If the hedged request is in flight, wait for it.
+                    // Otherwise, the error is returned, as failed requests
+                    // are not hedged.
+                    if !self.hedging || self.hedge.is_none() {
+                        return Err(e.into());
+                    }
+                    self.original = None;
+                }
+            }
+        }
+
+        if let Some(delay) = self.delay.as_mut() {
+            match delay.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(())) => {
+                    self.hedging = self.policy.can_hedge();
+                    trace!(hedging = self.hedging, "delay elapsed");
+                }
+                Err(error) => debug!(%error, "hedge timer failed"),
+            }
+            self.delay = None;
+            if !self.hedging {
+                self.hedge = None;
+            }
+        }
+
+        if let Some(f) = self.hedge.as_mut() {
+            match f.poll() {
+                Ok(Async::NotReady) => {}
+                Ok(Async::Ready(rsp)) => return Ok(Async::Ready(rsp)),
+                Err(e) => {
+                    if self.original.is_none() {
+                        return Err(e);
+                    }
+                    self.hedge = None;
+                }
+            }
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use tower::Service;
+    use tower_test::mock;
+
+    #[derive(Clone)]
+    struct AlwaysHedge;
+
+    impl Policy<()> for AlwaysHedge {
+        fn delay(&self, _: &()) -> Option<Duration> {
+            Some(Duration::from_millis(1))
+        }
+
+        fn clone_request(&self, _: &mut ()) -> Option<()> {
+            Some(())
+        }
+
+        fn can_hedge(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn fails_when_hedge_then_original_fail() {
+        let (mut svc, mut handle) = mock::pair::<(), ()>();
+        let hedge = Hedge {
+            policy: Some(AlwaysHedge),
+            inner: (),
+        };
+
+        tokio::runtime::current_thread::run(futures::future::lazy(move || {
+            assert!(svc.poll_ready().unwrap().is_ready());
+            let mut rsp = hedge.proxy(&mut svc, ());
+            let (_, original) = handle.next_request().expect("original must be issued");
+            assert!(rsp.poll().unwrap().is_not_ready());
+
+            Delay::new(Instant::now() + Duration::from_millis(10))
+                .map_err(|_| ())
+                .map(move |()| {
+                    // The delay has elapsed, so the hedge is issued.
+                    assert!(rsp.poll().unwrap().is_not_ready());
+                    let (_, hedged) = handle.next_request().expect("hedge must be issued");
+
+                    hedged.send_error("hedge failed");
+                    assert!(rsp.poll().unwrap().is_not_ready());
+
+                    original.send_error("original failed");
+                    let err = rsp.poll().err().expect("both requests failed");
+                    assert_eq!(err.to_string(), "original failed");
+                })
+        }));
+    }
+}
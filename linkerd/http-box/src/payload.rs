@@ -33,6 +33,12 @@ impl Payload {
     }
 }
 
+impl From<bytes::Bytes> for Payload {
+    fn from(bytes: bytes::Bytes) -> Self {
+        Self::new(hyper::Body::from(bytes))
+    }
+}
+
 impl hyper::body::Payload for Payload {
     type Data = Data;
     type Error = Error;
@@ -2,7 +2,7 @@ use super::{LastUpdate, Registry, Report};
 use http;
 use indexmap::IndexMap;
 use linkerd2_http_classify::ClassifyResponse;
-use linkerd2_metrics::{latency, Counter, FmtMetrics, Histogram};
+use linkerd2_metrics::{latency, Bucket, Counter, FmtMetrics, Histogram};
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::sync::{Arc, Mutex};
@@ -44,6 +44,12 @@ pub struct ClassMetrics {
     total: Counter,
 }
 
+/// A handle to a target's response latency histograms.
+#[derive(Debug)]
+pub struct Latencies<C>(Arc<Mutex<Metrics<C>>>)
+where
+    C: Hash + Eq;
+
 // === impl Requests ===
 
 impl<T: Hash + Eq, C: Hash + Eq> Default for Requests<T, C> {
@@ -66,6 +72,16 @@ impl<T: Hash + Eq, C: Hash + Eq> Requests<T, C> {
     {
         layer::Layer::new(self.0)
     }
+
+    pub fn latencies(&self, target: impl Into<T>) -> Latencies<C> {
+        let mut reg = self.0.lock().expect("request metrics registry poisoned");
+        Latencies(
+            reg.by_target
+                .entry(target.into())
+                .or_insert_with(|| Arc::new(Mutex::new(Metrics::default())))
+                .clone(),
+        )
+    }
 }
 
 impl<T: Hash + Eq, C: Hash + Eq> Clone for Requests<T, C> {
@@ -92,6 +108,55 @@ impl<C: Hash + Eq> LastUpdate for Metrics<C> {
     }
 }
 
+// === impl Latencies ===
+
+impl<C: Hash + Eq> Latencies<C> {
+    /// Estimates the latency below which the given ratio (on `[0, 1]`) of
+    /// responses were received, across all response statuses.
+    ///
+    /// The estimate is the upper bound of the histogram bucket containing the
+    /// quantile. `None` is returned if no latencies have been recorded or if
+    /// the quantile falls in the unbounded bucket.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let metrics = self.0.lock().ok()?;
+
+        let mut counts = Vec::<(Bucket, u64)>::new();
+        for status in metrics.by_status.values() {
+            for (i, (&bucket, count)) in status.latency.into_iter().enumerate() {
+                match counts.get_mut(i) {
+                    Some((_, total)) => *total += count.value(),
+                    None => counts.push((bucket, count.value())),
+                }
+            }
+        }
+
+        let total = counts.iter().map(|&(_, c)| c).sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+
+        let rank = ((total as f64) * q.max(0.0).min(1.0)).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in counts {
+            seen += count;
+            if seen >= rank {
+                return match bucket {
+                    Bucket::Le(ms) => Some(Duration::from_millis(ms)),
+                    Bucket::Inf => None,
+                };
+            }
+        }
+
+        None
+    }
+}
+
+impl<C: Hash + Eq> Clone for Latencies<C> {
+    fn clone(&self) -> Self {
+        Latencies(self.0.clone())
+    }
+}
+
 impl<C> Default for StatusMetrics<C>
 where
     C: Hash + Eq,
@@ -175,4 +240,36 @@ mod tests {
 
         drop((registry, report));
     }
+
+    #[test]
+    fn latency_quantile() {
+        use std::time::Duration;
+
+        let r = super::Requests::<usize, ()>::default();
+        let latencies = r.latencies(1);
+        assert_eq!(latencies.quantile(0.5), None, "no latencies recorded");
+
+        {
+            let registry = r.0.lock().unwrap();
+            let mut metrics = registry.by_target.get(&1).unwrap().lock().unwrap();
+            let ok = metrics
+                .by_status
+                .entry(Some(http::StatusCode::OK))
+                .or_insert_with(Default::default);
+            for _ in 0..8 {
+                ok.latency.add(Duration::from_millis(3));
+            }
+            let err = metrics
+                .by_status
+                .entry(None)
+                .or_insert_with(Default::default);
+            for _ in 0..2 {
+                err.latency.add(Duration::from_millis(150));
+            }
+        }
+
+        assert_eq!(latencies.quantile(0.5), Some(Duration::from_millis(3)));
+        assert_eq!(latencies.quantile(0.8), Some(Duration::from_millis(3)));
+        assert_eq!(latencies.quantile(0.9), Some(Duration::from_millis(200)));
+    }
 }
@@ -20,10 +20,19 @@ pub struct Metrics {
     last_update: Instant,
     retryable: Counter,
     no_budget: Counter,
+    body_too_large: Counter,
+    hedgeable: Counter,
+    hedge_no_budget: Counter,
+    fault_delay: Counter,
+    fault_abort: Counter,
 }
 
 struct NoBudgetLabel;
 
+struct FaultLabel(&'static str);
+
+struct BodyTooLargeLabel;
+
 // === impl Retries ===
 
 impl<T: Hash + Eq> Default for Retries<T> {
@@ -66,6 +75,44 @@ impl Handle {
             }
         }
     }
+
+    /// Records a retryable response that could not be retried because its
+    /// request body was too large to be buffered.
+    pub fn incr_body_too_large(&self) {
+        if let Ok(mut m) = self.0.lock() {
+            m.last_update = clock::now();
+            m.retryable.incr();
+            m.body_too_large.incr();
+        }
+    }
+
+    /// Records a request that was eligible to be hedged, i.e. whose response
+    /// was not received before the hedging delay elapsed.
+    pub fn incr_hedgeable(&self, has_budget: bool) {
+        if let Ok(mut m) = self.0.lock() {
+            m.last_update = clock::now();
+            m.hedgeable.incr();
+            if !has_budget {
+                m.hedge_no_budget.incr();
+            }
+        }
+    }
+
+    /// Records a request that was delayed by an injected fault.
+    pub fn incr_fault_delay(&self) {
+        if let Ok(mut m) = self.0.lock() {
+            m.last_update = clock::now();
+            m.fault_delay.incr();
+        }
+    }
+
+    /// Records a request that was aborted by an injected fault.
+    pub fn incr_fault_abort(&self) {
+        if let Ok(mut m) = self.0.lock() {
+            m.last_update = clock::now();
+            m.fault_abort.incr();
+        }
+    }
 }
 
 // === impl Metrics ===
@@ -76,6 +123,11 @@ impl Default for Metrics {
             last_update: clock::now(),
             retryable: Counter::default(),
             no_budget: Counter::default(),
+            body_too_large: Counter::default(),
+            hedgeable: Counter::default(),
+            hedge_no_budget: Counter::default(),
+            fault_delay: Counter::default(),
+            fault_abort: Counter::default(),
         }
     }
 }
@@ -98,6 +150,20 @@ where
             "Total count of retryable HTTP responses.",
         )
     }
+
+    fn hedgeable_total(&self) -> Metric<'_, Prefixed<'_, &'static str>, Counter> {
+        Metric::new(
+            self.prefix_key("hedgeable_total"),
+            "Total count of HTTP requests eligible to be hedged.",
+        )
+    }
+
+    fn fault_injected_total(&self) -> Metric<'_, Prefixed<'_, &'static str>, Counter> {
+        Metric::new(
+            self.prefix_key("fault_injected_total"),
+            "Total count of HTTP requests into which a fault was injected.",
+        )
+    }
 }
 
 impl<T> FmtMetrics for Report<T, Metrics>
@@ -126,6 +192,29 @@ where
                 m.retryable.fmt_metric_labeled(f, &metric.name, tgt)?;
                 m.no_budget
                     .fmt_metric_labeled(f, &metric.name, (tgt, NoBudgetLabel))?;
+                m.body_too_large
+                    .fmt_metric_labeled(f, &metric.name, (tgt, BodyTooLargeLabel))?;
+            }
+        }
+
+        let metric = self.hedgeable_total();
+        metric.fmt_help(f)?;
+        for (tgt, tm) in &registry.by_target {
+            if let Ok(m) = tm.lock() {
+                m.hedgeable.fmt_metric_labeled(f, &metric.name, tgt)?;
+                m.hedge_no_budget
+                    .fmt_metric_labeled(f, &metric.name, (tgt, NoBudgetLabel))?;
+            }
+        }
+
+        let metric = self.fault_injected_total();
+        metric.fmt_help(f)?;
+        for (tgt, tm) in &registry.by_target {
+            if let Ok(m) = tm.lock() {
+                m.fault_delay
+                    .fmt_metric_labeled(f, &metric.name, (tgt, FaultLabel("delay")))?;
+                m.fault_abort
+                    .fmt_metric_labeled(f, &metric.name, (tgt, FaultLabel("abort")))?;
             }
         }
 
@@ -140,3 +229,15 @@ impl FmtLabels for NoBudgetLabel {
         write!(f, "skipped=\"no_budget\"")
     }
 }
+
+impl FmtLabels for BodyTooLargeLabel {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "skipped=\"body_too_large\"")
+    }
+}
+
+impl FmtLabels for FaultLabel {
+    fn fmt_labels(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fault=\"{}\"", self.0)
+    }
+}
@@ -0,0 +1,62 @@
+use super::{AsyncRead, AsyncWrite, Poll, Read, Result, Write};
+use bytes::Buf;
+
+/// Wraps one of two transport types, delegating to whichever is present.
+///
+/// This is useful when a stack may produce connections from distinct
+/// connectors (e.g. when a fallback is used).
+#[derive(Debug)]
+pub enum EitherIo<A, B> {
+    A(A),
+    B(B),
+}
+
+impl<A: Read, B: Read> Read for EitherIo<A, B> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            EitherIo::A(a) => a.read(buf),
+            EitherIo::B(b) => b.read(buf),
+        }
+    }
+}
+
+impl<A: Write, B: Write> Write for EitherIo<A, B> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            EitherIo::A(a) => a.write(buf),
+            EitherIo::B(b) => b.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            EitherIo::A(a) => a.flush(),
+            EitherIo::B(b) => b.flush(),
+        }
+    }
+}
+
+impl<A: AsyncRead, B: AsyncRead> AsyncRead for EitherIo<A, B> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        match self {
+            EitherIo::A(a) => a.prepare_uninitialized_buffer(buf),
+            EitherIo::B(b) => b.prepare_uninitialized_buffer(buf),
+        }
+    }
+}
+
+impl<A: AsyncWrite, B: AsyncWrite> AsyncWrite for EitherIo<A, B> {
+    fn shutdown(&mut self) -> Poll<()> {
+        match self {
+            EitherIo::A(a) => a.shutdown(),
+            EitherIo::B(b) => b.shutdown(),
+        }
+    }
+
+    fn write_buf<T: Buf>(&mut self, buf: &mut T) -> Poll<usize> {
+        match self {
+            EitherIo::A(a) => a.write_buf(buf),
+            EitherIo::B(b) => b.write_buf(buf),
+        }
+    }
+}
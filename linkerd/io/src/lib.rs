@@ -1,8 +1,9 @@
 mod boxed;
+mod either;
 mod peek;
 mod prefixed;
 
-pub use self::{boxed::BoxedIo, peek::Peek, prefixed::PrefixedIo};
+pub use self::{boxed::BoxedIo, either::EitherIo, peek::Peek, prefixed::PrefixedIo};
 pub use std::io::{Error, Read, Result, Write};
 pub use tokio::io::{AsyncRead, AsyncWrite};
 
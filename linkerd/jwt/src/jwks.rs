@@ -0,0 +1,176 @@
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde_json::Value;
+use std::fmt;
+
+/// A set of public keys, parsed from a JSON Web Key Set document.
+#[derive(Clone, Debug, Default)]
+pub struct Jwks {
+    keys: Vec<Key>,
+}
+
+#[derive(Debug)]
+pub struct InvalidJwks(String);
+
+#[derive(Clone, Debug)]
+struct Key {
+    kid: Option<String>,
+    /// If set, the key may only be used with this algorithm.
+    alg: Option<String>,
+    public: PublicKey,
+}
+
+#[derive(Clone)]
+enum PublicKey {
+    Rsa {
+        n: Vec<u8>,
+        e: Vec<u8>,
+    },
+    /// An uncompressed EC point.
+    Ec {
+        crv: Curve,
+        point: Vec<u8>,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Curve {
+    P256,
+    P384,
+}
+
+// === impl Jwks ===
+
+impl Jwks {
+    /// Parses a JWKS document.
+    ///
+    /// Keys with unsupported types or uses are ignored.
+    pub fn from_json(json: &[u8]) -> Result<Self, InvalidJwks> {
+        let doc: Value = serde_json::from_slice(json).map_err(|e| InvalidJwks(e.to_string()))?;
+        let keys = doc
+            .get("keys")
+            .and_then(Value::as_array)
+            .ok_or_else(|| InvalidJwks("missing `keys`".into()))?;
+
+        let mut jwks = Jwks::default();
+        for key in keys {
+            if key.get("use").and_then(Value::as_str).unwrap_or("sig") != "sig" {
+                continue;
+            }
+            if let Some(public) = PublicKey::from_jwk(key)? {
+                jwks.keys.push(Key {
+                    kid: string(key, "kid"),
+                    alg: string(key, "alg"),
+                    public,
+                });
+            }
+        }
+        Ok(jwks)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns true if the signature was created by one of the set's keys
+    /// with the given algorithm.
+    ///
+    /// If `kid` is set, only the key with that ID is used.
+    pub(crate) fn verify(&self, alg: &str, kid: Option<&str>, msg: &[u8], sig: &[u8]) -> bool {
+        self.keys
+            .iter()
+            .filter(|k| kid.is_none() || k.kid.as_ref().map(String::as_str) == kid)
+            .filter(|k| k.alg.as_ref().map(|a| a == alg).unwrap_or(true))
+            .any(|k| k.public.verify(alg, msg, sig))
+    }
+}
+
+fn string(key: &Value, name: &str) -> Option<String> {
+    key.get(name).and_then(Value::as_str).map(String::from)
+}
+
+fn bytes(key: &Value, name: &str) -> Result<Vec<u8>, InvalidJwks> {
+    let value = key
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| InvalidJwks(format!("missing `{}`", name)))?;
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+        .map_err(|e| InvalidJwks(format!("invalid `{}`: {}", name, e)))
+}
+
+// === impl PublicKey ===
+
+impl PublicKey {
+    fn from_jwk(key: &Value) -> Result<Option<Self>, InvalidJwks> {
+        match key.get("kty").and_then(Value::as_str) {
+            Some("RSA") => Ok(Some(PublicKey::Rsa {
+                n: bytes(key, "n")?,
+                e: bytes(key, "e")?,
+            })),
+            Some("EC") => {
+                let crv = match key.get("crv").and_then(Value::as_str) {
+                    Some("P-256") => Curve::P256,
+                    Some("P-384") => Curve::P384,
+                    _ => return Ok(None),
+                };
+                let mut point = vec![0x04];
+                point.extend(bytes(key, "x")?);
+                point.extend(bytes(key, "y")?);
+                Ok(Some(PublicKey::Ec { crv, point }))
+            }
+            Some(_) => Ok(None),
+            None => Err(InvalidJwks("missing `kty`".into())),
+        }
+    }
+
+    fn verify(&self, alg: &str, msg: &[u8], sig: &[u8]) -> bool {
+        match self {
+            PublicKey::Rsa { n, e } => {
+                let params = match alg {
+                    "RS256" => &signature::RSA_PKCS1_2048_8192_SHA256,
+                    "RS384" => &signature::RSA_PKCS1_2048_8192_SHA384,
+                    "RS512" => &signature::RSA_PKCS1_2048_8192_SHA512,
+                    "PS256" => &signature::RSA_PSS_2048_8192_SHA256,
+                    "PS384" => &signature::RSA_PSS_2048_8192_SHA384,
+                    "PS512" => &signature::RSA_PSS_2048_8192_SHA512,
+                    _ => return false,
+                };
+                RsaPublicKeyComponents { n, e }
+                    .verify(params, msg, sig)
+                    .is_ok()
+            }
+            PublicKey::Ec { crv, point } => {
+                let params = match (alg, crv) {
+                    ("ES256", Curve::P256) => &signature::ECDSA_P256_SHA256_FIXED,
+                    ("ES384", Curve::P384) => &signature::ECDSA_P384_SHA384_FIXED,
+                    _ => return false,
+                };
+                UnparsedPublicKey::new(params, point)
+                    .verify(msg, sig)
+                    .is_ok()
+            }
+        }
+    }
+}
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublicKey::Rsa { .. } => f.debug_struct("Rsa").finish(),
+            PublicKey::Ec { crv, .. } => f.debug_struct("Ec").field("crv", crv).finish(),
+        }
+    }
+}
+
+// === impl InvalidJwks ===
+
+impl fmt::Display for InvalidJwks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid JWKS: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidJwks {}
@@ -0,0 +1,340 @@
+//! Validates JSON Web Tokens presented as bearer tokens in the `Authorization`
+//! header of HTTP requests.
+//!
+//! Tokens must be signed by a key in a JSON Web Key Set (JWKS) that is read
+//! from disk and reloaded when the file changes. A token's `exp` and `nbf`
+//! claims are checked, as are its issuer and audience when configured.
+//! Requests without a valid token fail with an `InvalidToken` error; otherwise,
+//! the configured claims are set as request headers so that the application
+//! need not validate the token itself.
+
+#![deny(warnings, rust_2018_idioms)]
+
+use http::header::{self, HeaderName, HeaderValue};
+use linkerd2_request_filter::RequestFilter;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+mod jwks;
+mod watch;
+
+pub use self::jwks::{InvalidJwks, Jwks};
+pub use self::watch::{watch, Daemon, Receiver};
+
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// If set, tokens must have been issued by this issuer.
+    pub issuer: Option<String>,
+    /// If not empty, tokens must be intended for one of these audiences.
+    pub audiences: Vec<String>,
+    /// Tolerates clock skew when checking the `exp` and `nbf` claims.
+    pub leeway: Duration,
+    /// Claims that are set, when present, as request headers.
+    pub claim_headers: Vec<(String, HeaderName)>,
+}
+
+/// Validates each request's token against the current key set.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    config: Arc<Config>,
+    jwks: Receiver,
+}
+
+/// Indicates that a request did not have a valid token.
+#[derive(Debug)]
+pub struct InvalidToken(Reason);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reason {
+    Missing,
+    Malformed,
+    Signature,
+    Expired,
+    NotYetValid,
+    Issuer,
+    Audience,
+}
+
+// === impl Filter ===
+
+impl Filter {
+    pub fn new(config: Config, jwks: Receiver) -> Self {
+        Self {
+            config: Arc::new(config),
+            jwks,
+        }
+    }
+}
+
+impl<B> RequestFilter<http::Request<B>> for Filter {
+    type Error = InvalidToken;
+
+    fn filter(&self, mut req: http::Request<B>) -> Result<http::Request<B>, InvalidToken> {
+        // Claim headers set by the client are never forwarded.
+        for (_, header) in self.config.claim_headers.iter() {
+            req.headers_mut().remove(header);
+        }
+
+        let claims = {
+            let token = bearer_token(req.headers()).ok_or(InvalidToken(Reason::Missing))?;
+            let jwks = self.jwks.get_ref();
+            validate(&self.config, &jwks, token, SystemTime::now()).map_err(|e| {
+                debug!(reason = %e, "Invalid token");
+                e
+            })?
+        };
+
+        for (claim, header) in self.config.claim_headers.iter() {
+            let value = match claims.get(claim) {
+                None | Some(Value::Null) => continue,
+                Some(Value::String(s)) => HeaderValue::from_str(s),
+                Some(v) => HeaderValue::from_str(&v.to_string()),
+            };
+            if let Ok(value) = value {
+                req.headers_mut().insert(header.clone(), value);
+            }
+        }
+
+        Ok(req)
+    }
+}
+
+fn bearer_token(headers: &http::HeaderMap) -> Option<&str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = value.splitn(2, ' ');
+    let scheme = parts.next()?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    parts.next().map(str::trim).filter(|t| !t.is_empty())
+}
+
+/// Verifies the token's signature and claims, returning its claims.
+fn validate(
+    config: &Config,
+    jwks: &Jwks,
+    token: &str,
+    now: SystemTime,
+) -> Result<Map<String, Value>, InvalidToken> {
+    let mut parts = token.split('.');
+    let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(h), Some(p), Some(s)) if parts.next().is_none() => (h, p, s),
+        _ => return Err(InvalidToken(Reason::Malformed)),
+    };
+
+    let header_len = header.len();
+    let header = match decode_json(header)? {
+        Value::Object(header) => header,
+        _ => return Err(InvalidToken(Reason::Malformed)),
+    };
+    let alg = header
+        .get("alg")
+        .and_then(Value::as_str)
+        .ok_or(InvalidToken(Reason::Malformed))?;
+    let kid = header.get("kid").and_then(Value::as_str);
+    let signature = decode(signature)?;
+    let message = &token[..header_len + 1 + payload.len()];
+    if !jwks.verify(alg, kid, message.as_bytes(), &signature) {
+        return Err(InvalidToken(Reason::Signature));
+    }
+
+    let claims = match decode_json(payload)? {
+        Value::Object(claims) => claims,
+        _ => return Err(InvalidToken(Reason::Malformed)),
+    };
+
+    let now = now
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let leeway = config.leeway.as_secs_f64();
+    if let Some(exp) = claims.get("exp") {
+        let exp = exp.as_f64().ok_or(InvalidToken(Reason::Malformed))?;
+        if now >= exp + leeway {
+            return Err(InvalidToken(Reason::Expired));
+        }
+    }
+    if let Some(nbf) = claims.get("nbf") {
+        let nbf = nbf.as_f64().ok_or(InvalidToken(Reason::Malformed))?;
+        if now + leeway < nbf {
+            return Err(InvalidToken(Reason::NotYetValid));
+        }
+    }
+
+    if let Some(ref issuer) = config.issuer {
+        if claims.get("iss").and_then(Value::as_str) != Some(issuer.as_str()) {
+            return Err(InvalidToken(Reason::Issuer));
+        }
+    }
+
+    if !config.audiences.is_empty() {
+        let is_audience = |aud: &Value| {
+            aud.as_str()
+                .map(|aud| config.audiences.iter().any(|a| a == aud))
+                .unwrap_or(false)
+        };
+        let valid = match claims.get("aud") {
+            Some(Value::Array(auds)) => auds.iter().any(is_audience),
+            Some(aud) => is_audience(aud),
+            None => false,
+        };
+        if !valid {
+            return Err(InvalidToken(Reason::Audience));
+        }
+    }
+
+    Ok(claims)
+}
+
+fn decode(segment: &str) -> Result<Vec<u8>, InvalidToken> {
+    base64::decode_config(segment, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| InvalidToken(Reason::Malformed))
+}
+
+fn decode_json(segment: &str) -> Result<Value, InvalidToken> {
+    serde_json::from_slice(&decode(segment)?).map_err(|_| InvalidToken(Reason::Malformed))
+}
+
+// === impl InvalidToken ===
+
+impl InvalidToken {
+    pub fn reason(&self) -> Reason {
+        self.0
+    }
+}
+
+impl fmt::Display for InvalidToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid token: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidToken {}
+
+// === impl Reason ===
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Reason::Missing => "missing bearer token",
+            Reason::Malformed => "malformed",
+            Reason::Signature => "signature not verified",
+            Reason::Expired => "expired",
+            Reason::NotYetValid => "not yet valid",
+            Reason::Issuer => "unexpected issuer",
+            Reason::Audience => "unexpected audience",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+
+    fn encode(bytes: &[u8]) -> String {
+        base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+    }
+
+    struct Signer {
+        key: EcdsaKeyPair,
+        rng: SystemRandom,
+    }
+
+    impl Signer {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                .expect("key must be generated");
+            let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref())
+                .expect("key must be valid");
+            Self { key, rng }
+        }
+
+        fn jwks(&self) -> Jwks {
+            let point = self.key.public_key().as_ref();
+            let json = serde_json::json!({
+                "keys": [{
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "kid": "test",
+                    "x": encode(&point[1..33]),
+                    "y": encode(&point[33..]),
+                }]
+            });
+            Jwks::from_json(json.to_string().as_bytes()).expect("JWKS must be valid")
+        }
+
+        fn sign(&self, claims: Value) -> String {
+            let header = serde_json::json!({ "alg": "ES256", "kid": "test" });
+            let message = format!(
+                "{}.{}",
+                encode(header.to_string().as_bytes()),
+                encode(claims.to_string().as_bytes())
+            );
+            let signature = self
+                .key
+                .sign(&self.rng, message.as_bytes())
+                .expect("token must be signed");
+            format!("{}.{}", message, encode(signature.as_ref()))
+        }
+    }
+
+    #[test]
+    fn validates_signature_and_claims() {
+        let signer = Signer::new();
+        let jwks = signer.jwks();
+        let config = Config {
+            issuer: Some("issuer".into()),
+            audiences: vec!["web".into()],
+            leeway: Duration::from_secs(10),
+            claim_headers: vec![],
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let check = |claims: Value| {
+            validate(&config, &jwks, &signer.sign(claims), now).map_err(|e| e.reason())
+        };
+
+        let claims = check(serde_json::json!({
+            "iss": "issuer",
+            "aud": ["api", "web"],
+            "sub": "alice",
+            "exp": 1005,
+            "nbf": 1005,
+        }))
+        .expect("token must be valid");
+        assert_eq!(claims["sub"], "alice");
+
+        assert_eq!(
+            check(serde_json::json!({ "iss": "issuer", "aud": "web", "exp": 990 })),
+            Err(Reason::Expired)
+        );
+        assert_eq!(
+            check(serde_json::json!({ "iss": "issuer", "aud": "web", "nbf": 1020 })),
+            Err(Reason::NotYetValid)
+        );
+        assert_eq!(
+            check(serde_json::json!({ "iss": "other", "aud": "web" })),
+            Err(Reason::Issuer)
+        );
+        assert_eq!(
+            check(serde_json::json!({ "iss": "issuer", "aud": "api" })),
+            Err(Reason::Audience)
+        );
+
+        let token = signer.sign(serde_json::json!({ "iss": "issuer", "aud": "web" }));
+        let other = Signer::new().jwks();
+        assert_eq!(
+            validate(&config, &other, &token, now).map_err(|e| e.reason()),
+            Err(Reason::Signature)
+        );
+        assert_eq!(
+            validate(&config, &jwks, "not.a-token", now).map_err(|e| e.reason()),
+            Err(Reason::Malformed)
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use crate::Jwks;
+use futures::{try_ready, Async, Future, Poll, Stream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::{fs, io};
+use tokio::sync::watch;
+use tokio::timer::Interval;
+use tracing::{debug, info, warn};
+
+pub type Receiver = watch::Receiver<Arc<Jwks>>;
+
+/// Reloads a JWKS file when it changes.
+///
+/// The file's modification time is checked at a fixed interval. If the file
+/// cannot be read or parsed, the previously loaded keys are retained.
+pub struct Daemon {
+    path: PathBuf,
+    interval: Interval,
+    modified: Option<SystemTime>,
+    tx: watch::Sender<Arc<Jwks>>,
+}
+
+/// Loads the JWKS file and returns a daemon that reloads it when it changes.
+///
+/// If the file cannot be loaded, no keys are published until it can be.
+pub fn watch(path: PathBuf, interval: Duration) -> (Receiver, Daemon) {
+    let (modified, jwks) = match load(&path) {
+        Ok((modified, jwks)) => {
+            info!(path = %path.display(), keys = jwks.len(), "Loaded JWKS");
+            (Some(modified), jwks)
+        }
+        Err(error) => {
+            warn!(path = %path.display(), %error, "Failed to load JWKS");
+            (None, Jwks::default())
+        }
+    };
+
+    let (tx, rx) = watch::channel(Arc::new(jwks));
+    let daemon = Daemon {
+        path,
+        interval: Interval::new_interval(interval),
+        modified,
+        tx,
+    };
+    (rx, daemon)
+}
+
+fn load(path: &Path) -> io::Result<(SystemTime, Jwks)> {
+    let modified = fs::metadata(path)?.modified()?;
+    let json = fs::read(path)?;
+    let jwks = Jwks::from_json(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok((modified, jwks))
+}
+
+impl Future for Daemon {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            match try_ready!(self.interval.poll().map_err(|e| warn!(%e, "Timer failed"))) {
+                Some(_) => {}
+                None => return Ok(Async::Ready(())),
+            }
+
+            let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+            if modified.is_none() || modified == self.modified {
+                continue;
+            }
+            // Failures are not retried until the file changes again.
+            self.modified = modified;
+
+            match load(&self.path) {
+                Ok((_, jwks)) => {
+                    info!(path = %self.path.display(), keys = jwks.len(), "Reloaded JWKS");
+                    if self.tx.broadcast(Arc::new(jwks)).is_err() {
+                        debug!("JWKS no longer used");
+                        return Ok(Async::Ready(()));
+                    }
+                }
+                Err(error) => {
+                    warn!(path = %self.path.display(), %error, "Failed to reload JWKS");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use crate::Error;
+use futures::{future, try_ready, Async, Future, Poll};
+use http;
+use hyper::body::Payload;
+pub use hyper_balance::{PendingUntilFirstData, PendingUntilFirstDataBody};
+use rand::{rngs::SmallRng, SeedableRng};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::{marker::PhantomData, time::Duration};
+use tower_discover::Discover;
+pub use tower_load::Load;
+
+pub mod p2c;
+pub mod peak_ewma;
+pub mod queue;
+pub mod ring;
+pub mod slow_start;
+pub mod sticky;
+pub mod weight;
+
+pub use self::p2c::{Attempts, P2c};
+pub use self::peak_ewma::PeakEwmaDiscover;
+pub use self::ring::{HasHashRequest, HashRequest, Ring};
+pub use self::slow_start::SlowStart;
+pub use self::sticky::{HasStickyCookie, Sticky, StickyCookie};
+pub use self::weight::{HasWeight, Weighted};
+
+/// Configures a stack to resolve `T` typed targets to balance requests over
+/// `M`-typed endpoint stacks.
+///
+/// Targets that describe a `HashRequest` are balanced by a consistent-hashing
+/// `Ring`, and targets that describe a `StickyCookie` are balanced by a
+/// session-affinity `Sticky` balancer; otherwise, requests are balanced with
+/// P2C over Peak-EWMA loads.
+///
+/// Endpoint services must be annotated with their weights (i.e. by
+/// `weight::layer`) so that requests are distributed in proportion to them.
+/// If a `SlowStart` is configured, the weights of newly discovered endpoints
+/// ramp up gradually.
+#[derive(Debug)]
+pub struct Layer<A, B> {
+    decay: Duration,
+    default_rtt: Duration,
+    slow_start: Option<SlowStart>,
+    rng: SmallRng,
+    _marker: PhantomData<fn(A) -> B>,
+}
+
+#[derive(Debug)]
+pub struct MakeBalance<M, A, B> {
+    inner: M,
+    layer: Layer<A, B>,
+}
+
+pub struct MakeFuture<F, H, A, B> {
+    inner: F,
+    hash: Option<H>,
+    sticky: Option<StickyCookie>,
+    layer: Layer<A, B>,
+}
+
+type Loaded<D> = PeakEwmaDiscover<D, PendingUntilFirstData>;
+
+/// Balances requests over a discovered set of endpoints, either with P2C, by
+/// consistent hashing, or by session affinity.
+///
+/// Requests that carry `Attempts` are dispatched to endpoints that other
+/// attempts of the request were not dispatched to, unless their endpoint is
+/// determined by their hash or cookie.
+pub enum Balancer<D: Discover, H> {
+    P2c(P2c<Loaded<D>>),
+    Ring(Ring<Loaded<D>, H>),
+    Sticky(Sticky<Loaded<D>>),
+}
+
+// === impl Layer ===
+
+pub fn layer<A, B>(
+    default_rtt: Duration,
+    decay: Duration,
+    slow_start: Option<SlowStart>,
+) -> Layer<A, B> {
+    Layer {
+        decay,
+        default_rtt,
+        slow_start,
+        rng: SmallRng::from_entropy(),
+        _marker: PhantomData,
+    }
+}
+
+impl<A, B> Clone for Layer<A, B> {
+    fn clone(&self) -> Self {
+        Self {
+            decay: self.decay,
+            default_rtt: self.default_rtt,
+            slow_start: self.slow_start,
+            rng: self.rng.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<M, A, B> tower::layer::Layer<M> for Layer<A, B> {
+    type Service = MakeBalance<M, A, B>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeBalance {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+// === impl MakeBalance ===
+
+impl<M: Clone, A, B> Clone for MakeBalance<M, A, B> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<T, M, D, A, B> tower::Service<T> for MakeBalance<M, A, B>
+where
+    A: Payload,
+    B: Payload,
+    T: HasHashRequest + HasStickyCookie,
+    M: tower::Service<T, Response = D>,
+    D: Discover,
+    D::Key: Hash,
+    D::Service: tower::Service<http::Request<A>, Response = http::Response<B>>,
+{
+    type Response = Balancer<D, T::HashRequest>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future, T::HashRequest, A, B>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            hash: target.hash_request(),
+            sticky: target.sticky_cookie(),
+            inner: self.inner.call(target),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F, D, H, A, B> Future for MakeFuture<F, H, A, B>
+where
+    A: Payload,
+    B: Payload,
+    F: Future<Item = D>,
+    D: Discover,
+    D::Key: Hash,
+    D::Service: tower::Service<http::Request<A>, Response = http::Response<B>>,
+{
+    type Item = Balancer<D, H>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let discover = try_ready!(self.inner.poll());
+
+        let instrument = PendingUntilFirstData::default();
+        let Layer {
+            default_rtt,
+            decay,
+            slow_start,
+            ref rng,
+            ..
+        } = self.layer;
+        let loaded = PeakEwmaDiscover::new(discover, default_rtt, decay, slow_start, instrument);
+
+        let balancer = match (self.hash.take(), self.sticky.take()) {
+            (Some(hash), _) => Balancer::Ring(Ring::new(loaded, hash, rng.clone())),
+            (None, Some(cookie)) => Balancer::Sticky(Sticky::new(loaded, cookie, rng.clone())),
+            (None, None) => Balancer::P2c(P2c::new(loaded, rng.clone())),
+        };
+        Ok(Async::Ready(balancer))
+    }
+}
+
+// === impl Balancer ===
+
+impl<D, H, A, B> tower::Service<http::Request<A>> for Balancer<D, H>
+where
+    A: Payload,
+    B: Payload,
+    D: Discover,
+    D::Key: Clone + Hash,
+    D::Error: Into<Error>,
+    D::Service: tower::Service<http::Request<A>, Response = http::Response<B>> + HasWeight,
+    <D::Service as tower::Service<http::Request<A>>>::Error: Into<Error>,
+    H: HashRequest<http::Request<A>>,
+    P2c<Loaded<D>>: tower::Service<
+        http::Request<A>,
+        Response = http::Response<PendingUntilFirstDataBody<peak_ewma::Handle, B>>,
+        Error = Error,
+    >,
+    Ring<Loaded<D>, H>: tower::Service<
+        http::Request<A>,
+        Response = http::Response<PendingUntilFirstDataBody<peak_ewma::Handle, B>>,
+        Error = Error,
+    >,
+    Sticky<Loaded<D>>: tower::Service<
+        http::Request<A>,
+        Response = http::Response<PendingUntilFirstDataBody<peak_ewma::Handle, B>>,
+        Error = Error,
+    >,
+{
+    type Response = http::Response<PendingUntilFirstDataBody<peak_ewma::Handle, B>>;
+    type Error = Error;
+    type Future = future::Either<
+        <P2c<Loaded<D>> as tower::Service<http::Request<A>>>::Future,
+        future::Either<
+            <Ring<Loaded<D>, H> as tower::Service<http::Request<A>>>::Future,
+            <Sticky<Loaded<D>> as tower::Service<http::Request<A>>>::Future,
+        >,
+    >;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        match self {
+            Balancer::P2c(ref mut b) => b.poll_ready(),
+            Balancer::Ring(ref mut r) => r.poll_ready(),
+            Balancer::Sticky(ref mut s) => s.poll_ready(),
+        }
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        match self {
+            Balancer::P2c(ref mut b) => future::Either::A(b.call(req)),
+            Balancer::Ring(ref mut r) => future::Either::B(future::Either::A(r.call(req))),
+            Balancer::Sticky(ref mut s) => future::Either::B(future::Either::B(s.call(req))),
+        }
+    }
+}
+
+/// Identifies an endpoint by its discovery key.
+///
+/// Identifiers are only compared within a proxy, e.g. to record the endpoints
+/// to which a request's `Attempts` were dispatched.
+fn endpoint_id<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
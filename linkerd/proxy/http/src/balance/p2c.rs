@@ -0,0 +1,333 @@
+//! A power-of-two-choices balancer.
+//!
+//! Requests are dispatched to the less-loaded of two randomly-selected ready
+//! endpoints. Unlike `tower_balance`'s P2C balancer, requests may carry
+//! `Attempts`, so that concurrent attempts of a request (i.e. hedged
+//! requests) are dispatched to distinct endpoints.
+
+use super::endpoint_id;
+use super::weight::HasEffectiveWeight;
+use crate::Error;
+use futures::{future, Async, Future, Poll};
+use indexmap::IndexMap;
+use rand::{rngs::SmallRng, Rng};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tower_discover::{Change, Discover};
+use tower_load::Load;
+use tracing::{debug, trace};
+
+/// Records the endpoints to which the attempts of a request are dispatched.
+///
+/// Each attempt of a request carries the same `Attempts` as a request
+/// extension. When an attempt is balanced, the endpoints to which other
+/// attempts were dispatched are avoided, unless no other endpoint is ready.
+#[derive(Clone, Debug, Default)]
+pub struct Attempts(Arc<Mutex<Vec<u64>>>);
+
+pub struct P2c<D: Discover> {
+    discover: D,
+    /// Endpoints, by their identifiers.
+    endpoints: IndexMap<u64, Endpoint<D::Service>>,
+    rng: SmallRng,
+}
+
+struct Endpoint<S> {
+    service: S,
+    ready: bool,
+}
+
+// === impl Attempts ===
+
+impl Attempts {
+    /// Returns the request's attempts, if it has any.
+    pub(super) fn get<B>(req: &http::Request<B>) -> Option<Self> {
+        req.extensions().get::<Self>().cloned()
+    }
+
+    /// Records that an attempt was dispatched to the endpoint `id`.
+    pub(super) fn dispatched(&self, id: u64) {
+        if let Ok(mut ids) = self.0.lock() {
+            ids.push(id);
+        }
+    }
+
+    fn contains(&self, id: u64) -> bool {
+        self.0.lock().map(|ids| ids.contains(&id)).unwrap_or(false)
+    }
+}
+
+/// Selects the less-loaded of two random `ready` endpoints, returning its
+/// index in `ready`.
+///
+/// Two distinct endpoints are sampled in proportion to their effective
+/// weights, and the first is selected unless the second is less loaded. Requests
+/// are therefore split between equally-loaded endpoints in proportion to their
+/// weights, while load differences skew the split towards less-loaded
+/// endpoints. Zero-weighted endpoints are only selected if no
+/// positively-weighted endpoint is ready.
+///
+/// Endpoints to which other `attempts` of the request were dispatched are
+/// only selected if no other endpoint is ready.
+pub(super) fn select<S>(
+    rng: &mut SmallRng,
+    ready: &[(u64, &S)],
+    attempts: Option<&Attempts>,
+) -> Option<usize>
+where
+    S: Load + HasEffectiveWeight,
+    S::Metric: PartialOrd,
+{
+    let candidates = ready
+        .iter()
+        .enumerate()
+        .filter(|(_, (id, _))| !attempts.map(|a| a.contains(*id)).unwrap_or(false))
+        .map(|(i, (_, svc))| (i, *svc, svc.effective_weight()))
+        .collect::<Vec<_>>();
+    if candidates.is_empty() && !ready.is_empty() {
+        trace!("all ready endpoints were attempted");
+        return select(rng, ready, None);
+    }
+
+    let weighted = candidates
+        .iter()
+        .filter(|(_, _, weight)| *weight > 0.0)
+        .cloned()
+        .collect::<Vec<_>>();
+    let candidates = if weighted.is_empty() {
+        // Zero-weighted endpoints are balanced amongst each other.
+        candidates
+            .into_iter()
+            .map(|(i, svc, _)| (i, svc, 1.0))
+            .collect()
+    } else {
+        weighted
+    };
+
+    match candidates.len() {
+        0 => None,
+        1 => Some(candidates[0].0),
+        _ => {
+            let a = sample(rng, &candidates, None);
+            let b = sample(rng, &candidates, Some(a));
+            let (ia, sa, _) = candidates[a];
+            let (ib, sb, _) = candidates[b];
+            if sb.load() < sa.load() {
+                Some(ib)
+            } else {
+                Some(ia)
+            }
+        }
+    }
+}
+
+/// Samples a candidate, other than `except`, in proportion to its weight.
+fn sample<S>(rng: &mut SmallRng, candidates: &[(usize, S, f64)], except: Option<usize>) -> usize {
+    let weights = candidates
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| Some(*i) != except)
+        .map(|(i, (_, _, weight))| (i, *weight))
+        .collect::<Vec<_>>();
+    let total = weights.iter().map(|(_, w)| w).sum::<f64>();
+    let mut point = rng.gen::<f64>() * total;
+    for (i, weight) in weights.iter() {
+        if point < *weight {
+            return *i;
+        }
+        point -= weight;
+    }
+    weights.last().expect("candidates must be sampled").0
+}
+
+// === impl P2c ===
+
+impl<D> P2c<D>
+where
+    D: Discover,
+    D::Key: Hash,
+{
+    pub fn new(discover: D, rng: SmallRng) -> Self {
+        Self {
+            discover,
+            endpoints: IndexMap::default(),
+            rng,
+        }
+    }
+
+    fn poll_discover(&mut self) -> Result<(), Error>
+    where
+        D::Error: Into<Error>,
+    {
+        while let Async::Ready(change) = self.discover.poll().map_err(Into::into)? {
+            match change {
+                Change::Insert(key, service) => {
+                    trace!(endpoints = self.endpoints.len() + 1, "inserting");
+                    let endpoint = Endpoint {
+                        service,
+                        ready: false,
+                    };
+                    self.endpoints.insert(endpoint_id(&key), endpoint);
+                }
+                Change::Remove(key) => {
+                    self.endpoints.swap_remove(&endpoint_id(&key));
+                    trace!(endpoints = self.endpoints.len(), "removing");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn select(&mut self, attempts: Option<&Attempts>) -> Option<u64>
+    where
+        D::Service: Load + HasEffectiveWeight,
+        <D::Service as Load>::Metric: PartialOrd,
+    {
+        let ready = self
+            .endpoints
+            .iter()
+            .filter(|(_, ep)| ep.ready)
+            .map(|(id, ep)| (*id, &ep.service))
+            .collect::<Vec<_>>();
+        select(&mut self.rng, &ready, attempts).map(|i| ready[i].0)
+    }
+}
+
+impl<D, A> tower::Service<http::Request<A>> for P2c<D>
+where
+    D: Discover,
+    D::Key: Hash,
+    D::Error: Into<Error>,
+    D::Service: tower::Service<http::Request<A>> + Load + HasEffectiveWeight,
+    <D::Service as tower::Service<http::Request<A>>>::Error: Into<Error>,
+    <D::Service as Load>::Metric: PartialOrd,
+{
+    type Response = <D::Service as tower::Service<http::Request<A>>>::Response;
+    type Error = Error;
+    type Future = future::MapErr<
+        <D::Service as tower::Service<http::Request<A>>>::Future,
+        fn(<D::Service as tower::Service<http::Request<A>>>::Error) -> Error,
+    >;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.poll_discover()?;
+
+        let mut failed = Vec::new();
+        for (id, ep) in self.endpoints.iter_mut() {
+            if ep.ready {
+                continue;
+            }
+            match ep.service.poll_ready() {
+                Ok(Async::Ready(())) => ep.ready = true,
+                Ok(Async::NotReady) => {}
+                Err(e) => {
+                    let error: Error = e.into();
+                    debug!(%error, "dropping failed endpoint");
+                    failed.push(*id);
+                }
+            }
+        }
+        for id in failed.iter() {
+            self.endpoints.swap_remove(id);
+        }
+
+        if self.endpoints.values().any(|ep| ep.ready) {
+            return Ok(Async::Ready(()));
+        }
+
+        trace!(endpoints = self.endpoints.len(), "no ready endpoints");
+        Ok(Async::NotReady)
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let attempts = Attempts::get(&req);
+        let id = self.select(attempts.as_ref()).expect("called before ready");
+        if let Some(attempts) = attempts {
+            attempts.dispatched(id);
+        }
+
+        let ep = self.endpoints.get_mut(&id).expect("selected endpoint");
+        ep.ready = false;
+        ep.service.call(req).map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    /// A service with a fixed load and weight.
+    struct Svc(usize, f64);
+
+    impl Load for Svc {
+        type Metric = usize;
+
+        fn load(&self) -> usize {
+            self.0
+        }
+    }
+
+    impl HasEffectiveWeight for Svc {
+        fn effective_weight(&self) -> f64 {
+            self.1
+        }
+    }
+
+    #[test]
+    fn selects_less_loaded() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let (idle, busy) = (Svc(0, 1.0), Svc(10, 1.0));
+        let ready = [(1, &busy), (2, &idle)];
+        for _ in 0..100 {
+            assert_eq!(select(&mut rng, &ready, None), Some(1));
+        }
+        assert_eq!(select::<Svc>(&mut rng, &[], None), None);
+    }
+
+    #[test]
+    fn splits_in_proportion_to_weights() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let (light, heavy) = (Svc(0, 1.0), Svc(0, 3.0));
+        let ready = [(1, &light), (2, &heavy)];
+        let selected = (0..10_000)
+            .filter(|_| select(&mut rng, &ready, None) == Some(1))
+            .count();
+        assert!(
+            7_250 < selected && selected < 7_750,
+            "heavy endpoint selected {} times",
+            selected
+        );
+    }
+
+    #[test]
+    fn selects_zero_weighted_only_if_no_other_is_ready() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let (standby, weighted) = (Svc(0, 0.0), Svc(10, 1.0));
+        let ready = [(1, &standby), (2, &weighted)];
+        for _ in 0..100 {
+            assert_eq!(select(&mut rng, &ready, None), Some(1));
+        }
+        assert_eq!(select(&mut rng, &ready[..1], None), Some(0));
+    }
+
+    #[test]
+    fn avoids_attempted_endpoints() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let (idle, busy) = (Svc(0, 1.0), Svc(10, 1.0));
+        let ready = [(1, &busy), (2, &idle)];
+
+        // The less-loaded endpoint is avoided once an attempt was dispatched
+        // to it.
+        let attempts = Attempts::default();
+        attempts.dispatched(2);
+        for _ in 0..100 {
+            assert_eq!(select(&mut rng, &ready, Some(&attempts)), Some(0));
+        }
+
+        // If every ready endpoint was attempted, the attempts are ignored.
+        attempts.dispatched(1);
+        assert_eq!(select(&mut rng, &ready, Some(&attempts)), Some(1));
+        assert_eq!(select(&mut rng, &ready[..1], Some(&attempts)), Some(0));
+    }
+}
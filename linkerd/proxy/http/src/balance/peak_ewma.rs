@@ -0,0 +1,306 @@
+//! A weighted variant of `tower_load::PeakEwma`.
+//!
+//! `tower_load`'s `PeakEwma` does not expose the service it wraps, so it
+//! cannot describe an endpoint's weight; this module reimplements the
+//! Peak-EWMA load metric so that balancers may sample endpoints by their
+//! effective weights.
+
+use super::slow_start::SlowStart;
+use super::weight::{HasEffectiveWeight, HasWeight, DEFAULT_WEIGHT};
+use futures::{try_ready, Async, Poll};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_timer::clock;
+use tower_discover::{Change, Discover};
+use tower_load::{Instrument, InstrumentFuture, Load};
+use tracing::trace;
+
+/// Wraps an `S`-typed service with a Peak-EWMA load metric.
+///
+/// The cost of an endpoint is the Peak-EWMA of its response latencies,
+/// multiplied by its number of pending requests. Endpoints' weights do not
+/// scale their costs; instead, balancers sample endpoints in proportion to
+/// their effective weights.
+///
+/// If a `SlowStart` is configured, the effective weight of a newly discovered
+/// endpoint ramps up over the slow-start window.
+#[derive(Debug)]
+pub struct PeakEwma<S, I> {
+    service: S,
+    decay_ns: f64,
+    slow_start: Option<(SlowStart, Instant)>,
+    rtt_estimate: Arc<Mutex<RttEstimate>>,
+    instrument: I,
+}
+
+/// Wraps a `D`-typed stream of discovery updates with `PeakEwma`.
+#[derive(Debug)]
+pub struct PeakEwmaDiscover<D, I> {
+    discover: D,
+    decay_ns: f64,
+    default_rtt: Duration,
+    slow_start: Option<SlowStart>,
+    instrument: I,
+}
+
+/// The cost of communicating with a service.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct Cost(f64);
+
+/// Tracks an in-flight request and updates the RTT estimate on drop.
+#[derive(Debug)]
+pub struct Handle {
+    sent_at: Instant,
+    decay_ns: f64,
+    rtt_estimate: Arc<Mutex<RttEstimate>>,
+}
+
+#[derive(Debug)]
+struct RttEstimate {
+    update_at: Instant,
+    rtt_ns: f64,
+}
+
+const NANOS_PER_MILLI: f64 = 1_000_000.0;
+
+// === impl PeakEwmaDiscover ===
+
+impl<D, I> PeakEwmaDiscover<D, I> {
+    pub fn new(
+        discover: D,
+        default_rtt: Duration,
+        decay: Duration,
+        slow_start: Option<SlowStart>,
+        instrument: I,
+    ) -> Self {
+        Self {
+            discover,
+            decay_ns: nanos(decay),
+            default_rtt,
+            slow_start,
+            instrument,
+        }
+    }
+}
+
+impl<D, I> Discover for PeakEwmaDiscover<D, I>
+where
+    D: Discover,
+    I: Clone,
+{
+    type Key = D::Key;
+    type Service = PeakEwma<D::Service, I>;
+    type Error = D::Error;
+
+    fn poll(&mut self) -> Poll<Change<D::Key, Self::Service>, D::Error> {
+        let change = match try_ready!(self.discover.poll()) {
+            Change::Remove(k) => Change::Remove(k),
+            Change::Insert(k, svc) => {
+                let svc = PeakEwma::new(
+                    svc,
+                    self.default_rtt,
+                    self.decay_ns,
+                    self.instrument.clone(),
+                )
+                .with_slow_start(self.slow_start);
+                Change::Insert(k, svc)
+            }
+        };
+
+        Ok(Async::Ready(change))
+    }
+}
+
+// === impl PeakEwma ===
+
+impl<S, I> PeakEwma<S, I> {
+    fn new(service: S, default_rtt: Duration, decay_ns: f64, instrument: I) -> Self {
+        Self {
+            service,
+            decay_ns,
+            slow_start: None,
+            rtt_estimate: Arc::new(Mutex::new(RttEstimate::new(nanos(default_rtt)))),
+            instrument,
+        }
+    }
+
+    fn with_slow_start(self, slow_start: Option<SlowStart>) -> Self {
+        Self {
+            slow_start: slow_start.map(|ss| (ss, clock::now())),
+            ..self
+        }
+    }
+
+    fn handle(&self) -> Handle {
+        Handle {
+            decay_ns: self.decay_ns,
+            sent_at: clock::now(),
+            rtt_estimate: self.rtt_estimate.clone(),
+        }
+    }
+}
+
+impl<S, I, Req> tower::Service<Req> for PeakEwma<S, I>
+where
+    S: tower::Service<Req>,
+    I: Instrument<Handle, S::Response>,
+{
+    type Response = I::Output;
+    type Error = S::Error;
+    type Future = InstrumentFuture<S::Future, I, Handle>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready()
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        InstrumentFuture::new(
+            self.instrument.clone(),
+            self.handle(),
+            self.service.call(req),
+        )
+    }
+}
+
+impl<S: HasWeight, I> HasWeight for PeakEwma<S, I> {
+    fn weight(&self) -> u32 {
+        self.service.weight()
+    }
+}
+
+impl<S: HasWeight, I> HasEffectiveWeight for PeakEwma<S, I> {
+    /// Returns the endpoint's weight, as a ratio of `DEFAULT_WEIGHT`, reduced
+    /// while the endpoint is within its slow-start window.
+    fn effective_weight(&self) -> f64 {
+        let weight = f64::from(self.service.weight()) / f64::from(DEFAULT_WEIGHT);
+        match self.slow_start {
+            Some((ref ss, added_at)) => {
+                weight * ss.ratio(clock::now().saturating_duration_since(added_at))
+            }
+            None => weight,
+        }
+    }
+}
+
+impl<S, I> Load for PeakEwma<S, I> {
+    type Metric = Cost;
+
+    fn load(&self) -> Self::Metric {
+        let pending = Arc::strong_count(&self.rtt_estimate) as u32 - 1;
+        let estimate = self
+            .rtt_estimate
+            .lock()
+            .expect("peak ewma estimate")
+            .decay(self.decay_ns);
+
+        let cost = Cost(estimate * f64::from(pending + 1));
+        trace!(
+            estimate.ms = estimate / NANOS_PER_MILLI,
+            pending,
+            ?cost,
+            "load"
+        );
+        cost
+    }
+}
+
+// === impl RttEstimate ===
+
+impl RttEstimate {
+    fn new(rtt_ns: f64) -> Self {
+        debug_assert!(0.0 < rtt_ns, "rtt must be positive");
+        Self {
+            rtt_ns,
+            update_at: clock::now(),
+        }
+    }
+
+    /// Decays the RTT estimate with a decay period of `decay_ns`.
+    fn decay(&mut self, decay_ns: f64) -> f64 {
+        // Updates with a 0 duration so that the estimate decays towards 0.
+        let now = clock::now();
+        self.update(now, now, decay_ns)
+    }
+
+    /// Updates the Peak-EWMA RTT estimate with the elapsed time from `sent_at`
+    /// to `recv_at`.
+    fn update(&mut self, sent_at: Instant, recv_at: Instant, decay_ns: f64) -> f64 {
+        let rtt = nanos(recv_at.saturating_duration_since(sent_at));
+        let now = clock::now();
+
+        self.rtt_ns = if self.rtt_ns < rtt {
+            // Always use the worst-case (peak) value as the estimate for
+            // subsequent requests.
+            rtt
+        } else {
+            // When an RTT is observed that is less than the estimate, the prior
+            // estimate is decayed according to how much time has elapsed since
+            // the last update.
+            let elapsed = nanos(now.saturating_duration_since(self.update_at));
+            let decay = (-elapsed / decay_ns).exp();
+            let recency = 1.0 - decay;
+            (self.rtt_ns * decay) + (rtt * recency)
+        };
+        self.update_at = now;
+
+        self.rtt_ns
+    }
+}
+
+// === impl Handle ===
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        let recv_at = clock::now();
+        if let Ok(mut rtt) = self.rtt_estimate.lock() {
+            rtt.update(self.sent_at, recv_at, self.decay_ns);
+        }
+    }
+}
+
+fn nanos(d: Duration) -> f64 {
+    const NANOS_PER_SEC: u64 = 1_000_000_000;
+    let n = f64::from(d.subsec_nanos());
+    let s = d.as_secs().saturating_mul(NANOS_PER_SEC) as f64;
+    n + s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::weight::Weighted;
+
+    struct Svc;
+
+    #[test]
+    fn cost_ignores_weight() {
+        let rtt = Duration::from_millis(20);
+        let decay = nanos(Duration::from_secs(10));
+        let default = PeakEwma::new(Weighted::new(Svc, DEFAULT_WEIGHT), rtt, decay, ());
+        let heavy = PeakEwma::new(Weighted::new(Svc, DEFAULT_WEIGHT * 2), rtt, decay, ());
+
+        assert_eq!(default.load(), heavy.load());
+        assert_eq!(default.effective_weight(), 1.0);
+        assert_eq!(heavy.effective_weight(), 2.0);
+    }
+
+    #[test]
+    fn weight_ramps_during_slow_start() {
+        let rtt = Duration::from_millis(20);
+        let decay = nanos(Duration::from_secs(10));
+        let slow_start = SlowStart {
+            window: Duration::from_secs(60),
+            aggression: 1.0,
+            min_weight_ratio: 0.1,
+        };
+        let warm = PeakEwma::new(Weighted::new(Svc, DEFAULT_WEIGHT), rtt, decay, ());
+        let cold = PeakEwma::new(Weighted::new(Svc, DEFAULT_WEIGHT), rtt, decay, ())
+            .with_slow_start(Some(slow_start));
+        let standby =
+            PeakEwma::new(Weighted::new(Svc, 0), rtt, decay, ()).with_slow_start(Some(slow_start));
+
+        assert_eq!(warm.effective_weight(), 1.0);
+        assert!(cold.effective_weight() <= 0.11);
+        assert_eq!(standby.effective_weight(), 0.0);
+    }
+}
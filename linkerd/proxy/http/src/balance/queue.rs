@@ -0,0 +1,123 @@
+//! Queues requests that wait for a particular endpoint to become ready.
+//!
+//! Balancers that dispatch requests to a particular endpoint (i.e. by hash or
+//! by cookie) queue requests while it is not ready. Requests wait for at most
+//! `MAX_WAIT`, and at most `MAX_WAITING` requests wait for an endpoint, so that
+//! an unready endpoint cannot accumulate requests indefinitely; other requests
+//! are dispatched to another endpoint.
+
+use futures::{sync::oneshot, Async, Future};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio_timer::{clock, Delay};
+use tracing::debug;
+
+/// The longest time a request waits for its endpoint to become ready.
+pub const MAX_WAIT: Duration = Duration::from_secs(1);
+
+/// The most requests that may wait for an endpoint.
+pub const MAX_WAITING: usize = 100;
+
+/// Dispatches a waiting request to an `S`-typed service, along with a `T`.
+pub(super) type Dispatch<S, T> = Box<dyn FnOnce(&mut S, T) + Send>;
+
+/// Requests that wait for an endpoint, in the order they were queued.
+pub(super) struct Waiting<R> {
+    requests: VecDeque<(R, Delay)>,
+}
+
+/// Indicates that the balancer was dropped before a queued request was
+/// dispatched.
+#[derive(Debug)]
+pub struct Dropped(());
+
+/// Creates a request that dispatches `req` to a service, and a receiver for
+/// its response future, along with the `T` it was dispatched with.
+pub(super) fn dispatch<S, Req, T>(req: Req) -> (Dispatch<S, T>, oneshot::Receiver<(S::Future, T)>)
+where
+    S: tower::Service<Req>,
+    Req: Send + 'static,
+    S::Future: Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    let dispatch = Box::new(move |svc: &mut S, t: T| {
+        let _ = tx.send((svc.call(req), t));
+    });
+    (dispatch, rx)
+}
+
+// === impl Waiting ===
+
+impl<R> Default for Waiting<R> {
+    fn default() -> Self {
+        Self {
+            requests: VecDeque::new(),
+        }
+    }
+}
+
+impl<R> Waiting<R> {
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.requests.len() >= MAX_WAITING
+    }
+
+    /// Queues a request, which waits for at most `MAX_WAIT`.
+    pub fn push(&mut self, request: R) {
+        let expiry = Delay::new(clock::now() + MAX_WAIT);
+        self.requests.push_back((request, expiry));
+    }
+
+    /// Returns the request that has waited the longest.
+    pub fn pop(&mut self) -> Option<R> {
+        self.requests.pop_front().map(|(request, _)| request)
+    }
+
+    /// Returns the request that has waited the longest, if it has waited for
+    /// `MAX_WAIT`.
+    ///
+    /// Requests expire in the order they were queued, so only the oldest
+    /// request's timer is polled.
+    pub fn pop_expired(&mut self) -> Option<R> {
+        let expired = match self.requests.front_mut()?.1.poll() {
+            Ok(Async::NotReady) => false,
+            Ok(Async::Ready(())) => true,
+            Err(error) => {
+                // If the timer fails, the request stops waiting rather than
+                // waiting indefinitely.
+                debug!(%error, "wait timer failed");
+                true
+            }
+        };
+        if expired {
+            self.pop()
+        } else {
+            None
+        }
+    }
+
+    /// Removes all waiting requests.
+    pub fn drain(&mut self) -> impl Iterator<Item = R> + '_ {
+        self.requests.drain(..).map(|(request, _)| request)
+    }
+}
+
+// === impl Dropped ===
+
+impl Dropped {
+    pub(super) fn new() -> Self {
+        Dropped(())
+    }
+}
+
+impl std::fmt::Display for Dropped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "balancer dropped before dispatching a queued request")
+    }
+}
+
+impl std::error::Error for Dropped {}
@@ -0,0 +1,593 @@
+//! A consistent-hashing balancer.
+//!
+//! Each endpoint occupies a number of points, proportional to its weight, on a
+//! hash ring. Requests are hashed onto the ring and dispatched to the endpoint
+//! that owns the first point at or after the request's point, so that requests
+//! with the same hash are dispatched to the same endpoint while it is
+//! available. As endpoints are added or removed, only the requests that hash to
+//! their points are moved to other endpoints.
+//!
+//! If a request's endpoint is not ready, the request waits for it (see
+//! `queue`). Requests that wait too long, or that cannot wait because too many
+//! requests are waiting, are dispatched to the next ready endpoint on the ring.
+//!
+//! Zero-weighted endpoints occupy no points, so requests are only dispatched
+//! to them, as if they had no hash key, when no endpoint on the ring is ready.
+
+use super::endpoint_id;
+use super::p2c::{self, Attempts};
+use super::queue::{self, Dispatch, Dropped, Waiting};
+use super::weight::{HasEffectiveWeight, HasWeight, DEFAULT_WEIGHT};
+use crate::Error;
+use fnv::FnvHasher;
+use futures::{sync::oneshot, try_ready, Async, Future, Poll};
+use indexmap::IndexMap;
+use rand::rngs::SmallRng;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use tower_discover::{Change, Discover};
+use tower_load::Load;
+use tracing::{debug, trace};
+
+/// The number of points occupied by an endpoint with the default weight.
+const POINTS_PER_ENDPOINT: u64 = 100;
+
+/// Bounds the number of points occupied by an endpoint, regardless of its
+/// weight, so that the size of the ring is bounded.
+const MAX_POINTS_PER_ENDPOINT: u64 = 10 * POINTS_PER_ENDPOINT;
+
+/// Hashes requests onto the ring.
+pub trait HashRequest<Req> {
+    /// Returns the request's hash, or `None` if the request has no hash key.
+    ///
+    /// Requests without a hash key are dispatched to the less-loaded of two
+    /// randomly-selected endpoints. Hedged requests with a hash key are
+    /// dispatched to the same endpoint as the original request.
+    fn hash_request(&self, req: &Req) -> Option<u64>;
+}
+
+/// Describes how requests to a target are hashed, if they are.
+pub trait HasHashRequest {
+    type HashRequest;
+
+    fn hash_request(&self) -> Option<Self::HashRequest>;
+}
+
+pub struct Ring<D: Discover, H> {
+    discover: D,
+    hash: H,
+    endpoints: IndexMap<D::Key, Endpoint<D::Service>>,
+    /// The endpoints' points, sorted by hash unless `unsorted` is set.
+    ring: Vec<(u64, D::Key)>,
+    /// Set when endpoints have been inserted, so that the ring is sorted once
+    /// all pending discovery changes have been applied.
+    unsorted: bool,
+    /// Requests, with their hashes, that stopped waiting for their endpoints
+    /// and are dispatched to the next ready endpoint on the ring.
+    rerouted: VecDeque<(u64, Dispatch<D::Service, ()>)>,
+    rng: SmallRng,
+}
+
+struct Endpoint<S> {
+    service: S,
+    ready: bool,
+    /// Requests, with their hashes, that wait for this endpoint to become
+    /// ready.
+    waiting: Waiting<(u64, Dispatch<S, ()>)>,
+}
+
+pub enum ResponseFuture<F> {
+    Called(F),
+    Waiting(oneshot::Receiver<(F, ())>),
+}
+
+// === impl Ring ===
+
+impl<D, H> Ring<D, H>
+where
+    D: Discover,
+    D::Key: Clone + Hash,
+    D::Service: HasWeight,
+{
+    pub fn new(discover: D, hash: H, rng: SmallRng) -> Self {
+        Self {
+            discover,
+            hash,
+            endpoints: IndexMap::default(),
+            ring: Vec::new(),
+            unsorted: false,
+            rerouted: VecDeque::new(),
+            rng,
+        }
+    }
+
+    fn poll_discover(&mut self) -> Result<(), Error>
+    where
+        D::Error: Into<Error>,
+    {
+        while let Async::Ready(change) = self.discover.poll().map_err(Into::into)? {
+            match change {
+                Change::Insert(key, service) => self.insert(key, service),
+                Change::Remove(key) => self.remove(&key),
+            }
+        }
+        self.sort();
+        Ok(())
+    }
+
+    fn insert(&mut self, key: D::Key, service: D::Service) {
+        // The endpoint's weight may have changed, so its points are replaced.
+        self.remove(&key);
+
+        let points = POINTS_PER_ENDPOINT * u64::from(service.weight()) / u64::from(DEFAULT_WEIGHT);
+        let points = match service.weight() {
+            0 => 0,
+            _ => points.max(1).min(MAX_POINTS_PER_ENDPOINT),
+        };
+        trace!(points, endpoints = self.endpoints.len() + 1, "inserting");
+        self.ring
+            .extend((0..points).map(|i| (hash(&(&key, i)), key.clone())));
+        self.unsorted = true;
+
+        self.endpoints.insert(
+            key,
+            Endpoint {
+                service,
+                ready: false,
+                waiting: Waiting::default(),
+            },
+        );
+    }
+
+    /// Removes an endpoint, so that the requests waiting for it are dispatched
+    /// to other endpoints.
+    fn remove(&mut self, key: &D::Key) {
+        if let Some(mut ep) = self.endpoints.swap_remove(key) {
+            trace!(
+                endpoints = self.endpoints.len(),
+                waiting = ep.waiting.len(),
+                "removing"
+            );
+            self.ring.retain(|(_, k)| k != key);
+            self.rerouted.extend(ep.waiting.drain());
+        }
+    }
+
+    fn sort(&mut self) {
+        if self.unsorted {
+            self.ring.sort_by_key(|(p, _)| *p);
+            self.unsorted = false;
+        }
+    }
+
+    /// Returns the endpoint that owns `hash`, i.e. the endpoint at or after
+    /// `hash` on the ring, whether or not it is ready.
+    fn owner(&self, hash: u64) -> Option<&D::Key> {
+        debug_assert!(!self.unsorted, "ring must be sorted");
+        if self.ring.is_empty() {
+            return None;
+        }
+        let idx = match self.ring.binary_search_by_key(&hash, |(p, _)| *p) {
+            Ok(idx) | Err(idx) => idx,
+        };
+        Some(&self.ring[idx % self.ring.len()].1)
+    }
+
+    /// Selects the first ready endpoint at or after `hash` on the ring.
+    ///
+    /// This is only used for requests that cannot wait for the endpoint that
+    /// owns `hash`.
+    fn select_hashed(&self, hash: u64) -> Option<D::Key> {
+        debug_assert!(!self.unsorted, "ring must be sorted");
+        let start = match self.ring.binary_search_by_key(&hash, |(p, _)| *p) {
+            Ok(idx) | Err(idx) => idx,
+        };
+        let len = self.ring.len();
+        (0..len)
+            .map(|i| &self.ring[(start + i) % len].1)
+            .find(|key| self.endpoints.get(*key).map(|ep| ep.ready).unwrap_or(false))
+            .cloned()
+    }
+
+    /// Selects the less-loaded of two random ready endpoints, avoiding those
+    /// to which other `attempts` of the request were dispatched.
+    fn select_p2c(&mut self, attempts: Option<&Attempts>) -> Option<D::Key>
+    where
+        D::Service: Load + HasEffectiveWeight,
+        <D::Service as Load>::Metric: PartialOrd,
+    {
+        let (keys, ready): (Vec<_>, Vec<_>) = self
+            .endpoints
+            .iter()
+            .filter(|(_, ep)| ep.ready)
+            .map(|(key, ep)| (key, (endpoint_id(key), &ep.service)))
+            .unzip();
+        p2c::select(&mut self.rng, &ready, attempts).map(|i| keys[i].clone())
+    }
+}
+
+impl<D, H, A> tower::Service<http::Request<A>> for Ring<D, H>
+where
+    A: Send + 'static,
+    D: Discover,
+    D::Key: Clone + Hash,
+    D::Error: Into<Error>,
+    D::Service: tower::Service<http::Request<A>> + HasWeight + HasEffectiveWeight + Load,
+    <D::Service as tower::Service<http::Request<A>>>::Error: Into<Error>,
+    <D::Service as tower::Service<http::Request<A>>>::Future: Send + 'static,
+    <D::Service as Load>::Metric: PartialOrd,
+    H: HashRequest<http::Request<A>>,
+{
+    type Response = <D::Service as tower::Service<http::Request<A>>>::Response;
+    type Error = Error;
+    type Future = ResponseFuture<<D::Service as tower::Service<http::Request<A>>>::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.poll_discover()?;
+
+        let mut failed = Vec::new();
+        for (key, ep) in self.endpoints.iter_mut() {
+            loop {
+                if !ep.ready {
+                    match ep.service.poll_ready() {
+                        Ok(Async::Ready(())) => ep.ready = true,
+                        Ok(Async::NotReady) => break,
+                        Err(e) => {
+                            let error: Error = e.into();
+                            debug!(%error, "dropping failed endpoint");
+                            failed.push(key.clone());
+                            break;
+                        }
+                    }
+                }
+
+                // Dispatches the requests that wait for the endpoint as it
+                // becomes ready.
+                match ep.waiting.pop() {
+                    Some((_, dispatch)) => {
+                        trace!("dispatching waiting request");
+                        ep.ready = false;
+                        dispatch(&mut ep.service, ());
+                    }
+                    None => break,
+                }
+            }
+
+            while let Some(request) = ep.waiting.pop_expired() {
+                debug!("request waited too long for its endpoint");
+                self.rerouted.push_back(request);
+            }
+        }
+        for key in failed.iter() {
+            self.remove(key);
+        }
+
+        // Requests that stopped waiting are dispatched to the next ready
+        // endpoint on the ring.
+        while let Some(hash) = self.rerouted.front().map(|(hash, _)| *hash) {
+            let key = match self.select_hashed(hash).or_else(|| self.select_p2c(None)) {
+                Some(key) => key,
+                None => break,
+            };
+            let (_, dispatch) = self.rerouted.pop_front().expect("rerouted request");
+            trace!("dispatching rerouted request");
+            let ep = self.endpoints.get_mut(&key).expect("selected endpoint");
+            ep.ready = false;
+            dispatch(&mut ep.service, ());
+        }
+
+        if self.endpoints.values().any(|ep| ep.ready) {
+            return Ok(Async::Ready(()));
+        }
+
+        trace!(endpoints = self.endpoints.len(), "no ready endpoints");
+        Ok(Async::NotReady)
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let attempts = Attempts::get(&req);
+        let hash = self.hash.hash_request(&req);
+
+        if let Some(hash) = hash {
+            if let Some(owner) = self.owner(hash).cloned() {
+                let ep = self.endpoints.get_mut(&owner).expect("owner is discovered");
+                if !ep.ready && !ep.waiting.is_full() {
+                    trace!(waiting = ep.waiting.len() + 1, "waiting for endpoint");
+                    let (dispatch, rx) = queue::dispatch(req);
+                    ep.waiting.push((hash, dispatch));
+                    return ResponseFuture::Waiting(rx);
+                }
+                if !ep.ready {
+                    debug!("too many requests waiting for endpoint");
+                }
+            }
+        }
+
+        let key = match hash {
+            Some(hash) => self.select_hashed(hash),
+            None => None,
+        }
+        .or_else(|| self.select_p2c(attempts.as_ref()))
+        .expect("called before ready");
+        if let Some(attempts) = attempts {
+            attempts.dispatched(endpoint_id(&key));
+        }
+
+        let ep = self.endpoints.get_mut(&key).expect("selected endpoint");
+        ep.ready = false;
+        ResponseFuture::Called(ep.service.call(req))
+    }
+}
+
+/// Hashes a value with FNV-1a.
+///
+/// Unlike `DefaultHasher`, whose algorithm may change between Rust releases,
+/// FNV-1a is fully specified, so that points and request hashes are
+/// consistent across proxies, as long as the values' `Hash` implementations
+/// are.
+pub fn hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = FnvHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// === impl ResponseFuture ===
+
+impl<F> Future for ResponseFuture<F>
+where
+    F: Future,
+    F::Error: Into<Error>,
+{
+    type Item = F::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<F::Item, Error> {
+        loop {
+            *self = match self {
+                ResponseFuture::Called(ref mut f) => return f.poll().map_err(Into::into),
+                ResponseFuture::Waiting(ref mut rx) => {
+                    let (f, ()) = try_ready!(rx.poll().map_err(|_| Dropped::new()));
+                    ResponseFuture::Called(f)
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::weight::Weighted;
+    use futures::future;
+    use rand::SeedableRng;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+    use std::time::Instant;
+    use tokio_timer::Delay;
+
+    struct Empty;
+
+    impl Discover for Empty {
+        type Key = u16;
+        type Service = Weighted<()>;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Change<u16, Weighted<()>>, Error> {
+            Ok(Async::NotReady)
+        }
+    }
+
+    fn ring() -> Ring<Empty, ()> {
+        Ring::new(Empty, (), SmallRng::seed_from_u64(0))
+    }
+
+    fn set_ready(ring: &mut Ring<Empty, ()>) {
+        for ep in ring.endpoints.values_mut() {
+            ep.ready = true;
+        }
+    }
+
+    #[test]
+    fn points_proportional_to_weight() {
+        let mut ring = ring();
+        ring.insert(1, Weighted::new((), DEFAULT_WEIGHT));
+        ring.insert(2, Weighted::new((), DEFAULT_WEIGHT * 2));
+        ring.insert(3, Weighted::new((), 0));
+        ring.sort();
+        assert_eq!(ring.ring.len(), 300);
+        assert!(ring.ring.windows(2).all(|w| w[0].0 <= w[1].0));
+
+        ring.remove(&2);
+        assert_eq!(ring.ring.len(), 100);
+        assert!(ring.ring.windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+
+    #[test]
+    fn bounds_points_per_endpoint() {
+        let mut ring = ring();
+        ring.insert(1, Weighted::new((), std::u32::MAX));
+        ring.sort();
+        assert_eq!(ring.ring.len() as u64, MAX_POINTS_PER_ENDPOINT);
+    }
+
+    #[test]
+    fn hashes_move_minimally() {
+        let mut ring = ring();
+        for key in 1..=4 {
+            ring.insert(key, Weighted::new((), DEFAULT_WEIGHT));
+        }
+        ring.sort();
+        set_ready(&mut ring);
+        let before = (0..1000u64)
+            .map(|i| ring.select_hashed(hash(&i)).unwrap())
+            .collect::<Vec<_>>();
+
+        ring.insert(5, Weighted::new((), DEFAULT_WEIGHT));
+        ring.sort();
+        set_ready(&mut ring);
+        for (i, key) in before.iter().enumerate() {
+            let after = ring.select_hashed(hash(&(i as u64))).unwrap();
+            // Requests either stay on their endpoint or move to the new one.
+            assert!(after == *key || after == 5);
+        }
+    }
+
+    #[test]
+    fn skips_unready_endpoints() {
+        let mut ring = ring();
+        ring.insert(1, Weighted::new((), DEFAULT_WEIGHT));
+        ring.insert(2, Weighted::new((), DEFAULT_WEIGHT));
+        ring.sort();
+        assert_eq!(ring.select_hashed(0), None);
+
+        ring.endpoints.get_mut(&2).unwrap().ready = true;
+        for i in 0..100u64 {
+            assert_eq!(ring.select_hashed(hash(&i)), Some(2));
+        }
+    }
+
+    /// Discovers endpoints, each of which is ready while its flag is set and
+    /// responds with its key in the `x-endpoint` header.
+    struct Endpoints(Vec<(u16, Arc<AtomicBool>)>);
+
+    struct Svc {
+        key: u16,
+        ready: Arc<AtomicBool>,
+    }
+
+    /// Hashes requests by the `x-hash` header's value.
+    struct ByHeader;
+
+    impl Discover for Endpoints {
+        type Key = u16;
+        type Service = Svc;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Change<u16, Svc>, Error> {
+            match self.0.pop() {
+                Some((key, ready)) => Ok(Async::Ready(Change::Insert(key, Svc { key, ready }))),
+                None => Ok(Async::NotReady),
+            }
+        }
+    }
+
+    impl tower::Service<http::Request<()>> for Svc {
+        type Response = http::Response<()>;
+        type Error = Error;
+        type Future = future::FutureResult<http::Response<()>, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            if self.ready.load(Ordering::SeqCst) {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            let rsp = http::Response::builder()
+                .header("x-endpoint", self.key.to_string().as_str())
+                .body(())
+                .unwrap();
+            future::ok(rsp)
+        }
+    }
+
+    impl HasWeight for Svc {
+        fn weight(&self) -> u32 {
+            DEFAULT_WEIGHT
+        }
+    }
+
+    impl HasEffectiveWeight for Svc {
+        fn effective_weight(&self) -> f64 {
+            1.0
+        }
+    }
+
+    impl Load for Svc {
+        type Metric = usize;
+
+        fn load(&self) -> usize {
+            0
+        }
+    }
+
+    impl HashRequest<http::Request<()>> for ByHeader {
+        fn hash_request(&self, req: &http::Request<()>) -> Option<u64> {
+            req.headers().get("x-hash")?.to_str().ok()?.parse().ok()
+        }
+    }
+
+    fn endpoint(rsp: ResponseFuture<future::FutureResult<http::Response<()>, Error>>) -> String {
+        let rsp = match rsp {
+            ResponseFuture::Called(f) => f.wait().unwrap(),
+            ResponseFuture::Waiting(_) => panic!("request must be dispatched"),
+        };
+        rsp.headers()["x-endpoint"].to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn waits_for_unready_owner() {
+        let ready1 = Arc::new(AtomicBool::new(true));
+        let ready2 = Arc::new(AtomicBool::new(true));
+        let endpoints = Endpoints(vec![(1, ready1.clone()), (2, ready2)]);
+        let mut ring = Ring::new(endpoints, ByHeader, SmallRng::seed_from_u64(0));
+
+        tokio::runtime::current_thread::run(future::lazy(move || {
+            use tower::Service;
+
+            assert!(ring.poll_ready().unwrap().is_ready());
+            let hash = (0..)
+                .map(|i: u64| i * (std::u64::MAX / 1000))
+                .find(|h| ring.owner(*h) == Some(&1))
+                .expect("endpoint 1 must own a point");
+            let req = move || {
+                http::Request::builder()
+                    .header("x-hash", hash.to_string().as_str())
+                    .body(())
+                    .unwrap()
+            };
+
+            // The owner is not ready, so the request waits for it rather than
+            // being dispatched to the other endpoint.
+            ready1.store(false, Ordering::SeqCst);
+            ring.endpoints.get_mut(&1).unwrap().ready = false;
+            assert!(ring.poll_ready().unwrap().is_ready());
+            let mut rsp = ring.call(req());
+            assert!(rsp.poll().unwrap().is_not_ready());
+
+            ready1.store(true, Ordering::SeqCst);
+            assert!(ring.poll_ready().unwrap().is_ready());
+            match rsp.poll().unwrap() {
+                Async::Ready(rsp) => assert_eq!(rsp.headers()["x-endpoint"], "1"),
+                Async::NotReady => panic!("waiting request must be dispatched"),
+            }
+
+            // Once the request has waited for too long, it is dispatched to
+            // the next ready endpoint.
+            ready1.store(false, Ordering::SeqCst);
+            assert!(ring.poll_ready().unwrap().is_ready());
+            let mut rsp = ring.call(req());
+            assert!(rsp.poll().unwrap().is_not_ready());
+
+            Delay::new(Instant::now() + queue::MAX_WAIT)
+                .map_err(|_| ())
+                .map(move |()| {
+                    assert!(ring.poll_ready().unwrap().is_ready());
+                    assert!(ring.rerouted.is_empty());
+                    match rsp.poll().unwrap() {
+                        Async::Ready(rsp) => assert_eq!(rsp.headers()["x-endpoint"], "2"),
+                        Async::NotReady => panic!("expired request must be rerouted"),
+                    }
+
+                    // Requests are not rerouted while the owner is ready.
+                    ready1.store(true, Ordering::SeqCst);
+                    assert!(ring.poll_ready().unwrap().is_ready());
+                    assert_eq!(endpoint(ring.call(req())), "1");
+                })
+        }));
+    }
+}
@@ -0,0 +1,72 @@
+//! Ramps up the weights of newly discovered endpoints.
+//!
+//! Freshly started endpoints often respond slowly until their caches are warm,
+//! so an endpoint's effective weight grows from `min_weight_ratio` of its
+//! weight to its full weight over the `window` after it is discovered.
+
+use std::time::Duration;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SlowStart {
+    /// The duration over which a new endpoint's weight ramps up.
+    pub window: Duration,
+
+    /// Controls the shape of the ramp: the weight ratio after a fraction `t`
+    /// of the window has elapsed is `t^(1 / aggression)`. An aggression of
+    /// 1.0 ramps up linearly; greater values ramp up more quickly at the start
+    /// of the window.
+    pub aggression: f64,
+
+    /// The smallest ratio, on `(0, 1]`, of an endpoint's weight that is used
+    /// during the window.
+    pub min_weight_ratio: f64,
+}
+
+impl SlowStart {
+    /// Returns the ratio of an endpoint's weight to be used after it has been
+    /// in the balancer for `elapsed`.
+    pub fn ratio(&self, elapsed: Duration) -> f64 {
+        if elapsed >= self.window {
+            return 1.0;
+        }
+
+        let t = elapsed.as_secs_f64() / self.window.as_secs_f64();
+        let ratio = if self.aggression > 0.0 {
+            t.powf(1.0 / self.aggression)
+        } else {
+            t
+        };
+        ratio.max(self.min_weight_ratio).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slow_start(aggression: f64) -> SlowStart {
+        SlowStart {
+            window: Duration::from_secs(10),
+            aggression,
+            min_weight_ratio: 0.1,
+        }
+    }
+
+    #[test]
+    fn ramps_linearly() {
+        let ss = slow_start(1.0);
+        assert_eq!(ss.ratio(Duration::from_secs(0)), 0.1);
+        assert!((ss.ratio(Duration::from_secs(5)) - 0.5).abs() < 1e-9);
+        assert_eq!(ss.ratio(Duration::from_secs(10)), 1.0);
+        assert_eq!(ss.ratio(Duration::from_secs(60)), 1.0);
+    }
+
+    #[test]
+    fn aggression_shapes_ramp() {
+        let linear = slow_start(1.0).ratio(Duration::from_secs(2));
+        let aggressive = slow_start(2.0).ratio(Duration::from_secs(2));
+        let gentle = slow_start(0.5).ratio(Duration::from_secs(2));
+        assert!(gentle < linear && linear < aggressive);
+        assert!((aggressive - 0.2f64.sqrt()).abs() < 1e-9);
+    }
+}
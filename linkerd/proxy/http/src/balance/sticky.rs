@@ -0,0 +1,549 @@
+//! A session-affinity balancer.
+//!
+//! Responses set a cookie that identifies the endpoint that served them.
+//! Requests that carry the cookie are dispatched to the same endpoint while it
+//! remains discovered. If the endpoint is not ready, requests wait for it (see
+//! `queue`). Requests without the cookie, whose endpoint is no longer
+//! discovered, or that wait too long (or cannot wait because too many requests
+//! are waiting) are dispatched to the less-loaded of two randomly-selected
+//! endpoints, and the response sets the cookie for the newly-selected
+//! endpoint.
+
+use super::p2c::{self, Attempts};
+use super::queue::{self, Dispatch, Dropped, Waiting};
+use super::ring;
+use super::weight::HasEffectiveWeight;
+use crate::Error;
+use futures::{sync::oneshot, try_ready, Async, Future, Poll};
+use http::header::{self, HeaderValue};
+use indexmap::IndexMap;
+use rand::rngs::SmallRng;
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::sync::Arc;
+use tower_discover::{Change, Discover};
+use tower_load::Load;
+use tracing::{debug, trace};
+
+/// Describes the cookie that pins requests to a target's endpoints, if it
+/// does.
+pub trait HasStickyCookie {
+    fn sticky_cookie(&self) -> Option<StickyCookie>;
+}
+
+/// The name of the cookie that identifies an endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StickyCookie(Arc<str>);
+
+pub struct Sticky<D: Discover> {
+    discover: D,
+    cookie: StickyCookie,
+    /// Endpoints, by the identifier stored in the cookie.
+    endpoints: IndexMap<u64, Endpoint<D::Service>>,
+    /// Requests that stopped waiting for their endpoints, either because the
+    /// endpoints are no longer discovered or because they waited too long.
+    unpinned: VecDeque<Dispatch<D::Service, Option<HeaderValue>>>,
+    rng: SmallRng,
+}
+
+struct Endpoint<S> {
+    service: S,
+    ready: bool,
+    /// Requests pinned to this endpoint that wait for it to become ready. Each
+    /// is dispatched with the cookie that its response should set, if any.
+    waiting: Waiting<Dispatch<S, Option<HeaderValue>>>,
+}
+
+pub struct ResponseFuture<F> {
+    inner: Inner<F>,
+    set_cookie: Option<HeaderValue>,
+}
+
+enum Inner<F> {
+    Called(F),
+    Waiting(oneshot::Receiver<(F, Option<HeaderValue>)>),
+}
+
+// === impl StickyCookie ===
+
+impl StickyCookie {
+    pub fn new(name: impl Into<Arc<str>>) -> Self {
+        StickyCookie(name.into())
+    }
+
+    /// Returns the endpoint identifier in the request's cookie, if it is set.
+    fn endpoint<B>(&self, req: &http::Request<B>) -> Option<u64> {
+        let value = req
+            .headers()
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .find_map(|cookie| {
+                let mut kv = cookie.trim().splitn(2, '=');
+                if kv.next()? == &*self.0 {
+                    kv.next()
+                } else {
+                    None
+                }
+            })?;
+        u64::from_str_radix(value, 16).ok()
+    }
+
+    fn set_cookie(&self, id: u64) -> Option<HeaderValue> {
+        HeaderValue::from_str(&format!("{}={:016x}; Path=/; HttpOnly", self.0, id)).ok()
+    }
+}
+
+// === impl Sticky ===
+
+impl<D> Sticky<D>
+where
+    D: Discover,
+    D::Key: Hash,
+{
+    pub fn new(discover: D, cookie: StickyCookie, rng: SmallRng) -> Self {
+        Self {
+            discover,
+            cookie,
+            endpoints: IndexMap::default(),
+            unpinned: VecDeque::new(),
+            rng,
+        }
+    }
+
+    fn poll_discover(&mut self) -> Result<(), Error>
+    where
+        D::Error: Into<Error>,
+    {
+        while let Async::Ready(change) = self.discover.poll().map_err(Into::into)? {
+            match change {
+                Change::Insert(key, service) => {
+                    trace!(endpoints = self.endpoints.len() + 1, "inserting");
+                    let endpoint = Endpoint {
+                        service,
+                        ready: false,
+                        waiting: Waiting::default(),
+                    };
+                    // Requests waiting for a replaced endpoint wait for its
+                    // replacement.
+                    let id = id(&key);
+                    if let Some(old) = self.endpoints.insert(id, endpoint) {
+                        let ep = self.endpoints.get_mut(&id).expect("inserted endpoint");
+                        ep.waiting = old.waiting;
+                    }
+                }
+                Change::Remove(key) => self.remove(id(&key)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes an endpoint, so that the requests waiting for it are
+    /// dispatched to other endpoints.
+    fn remove(&mut self, id: u64) {
+        if let Some(mut ep) = self.endpoints.swap_remove(&id) {
+            trace!(
+                endpoints = self.endpoints.len(),
+                waiting = ep.waiting.len(),
+                "removing"
+            );
+            self.unpinned.extend(ep.waiting.drain());
+        }
+    }
+
+    /// Selects the less-loaded of two random ready endpoints, avoiding those
+    /// to which other `attempts` of the request were dispatched.
+    fn select_p2c(&mut self, attempts: Option<&Attempts>) -> Option<u64>
+    where
+        D::Service: Load + HasEffectiveWeight,
+        <D::Service as Load>::Metric: PartialOrd,
+    {
+        let ready = self
+            .endpoints
+            .iter()
+            .filter(|(_, ep)| ep.ready)
+            .map(|(id, ep)| (*id, &ep.service))
+            .collect::<Vec<_>>();
+        p2c::select(&mut self.rng, &ready, attempts).map(|i| ready[i].0)
+    }
+}
+
+impl<D, A, B> tower::Service<http::Request<A>> for Sticky<D>
+where
+    A: Send + 'static,
+    D: Discover,
+    D::Key: Hash,
+    D::Error: Into<Error>,
+    D::Service:
+        tower::Service<http::Request<A>, Response = http::Response<B>> + Load + HasEffectiveWeight,
+    <D::Service as tower::Service<http::Request<A>>>::Error: Into<Error>,
+    <D::Service as tower::Service<http::Request<A>>>::Future: Send + 'static,
+    <D::Service as Load>::Metric: PartialOrd,
+{
+    type Response = http::Response<B>;
+    type Error = Error;
+    type Future = ResponseFuture<<D::Service as tower::Service<http::Request<A>>>::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.poll_discover()?;
+
+        let mut failed = Vec::new();
+        for (id, ep) in self.endpoints.iter_mut() {
+            loop {
+                if !ep.ready {
+                    match ep.service.poll_ready() {
+                        Ok(Async::Ready(())) => ep.ready = true,
+                        Ok(Async::NotReady) => break,
+                        Err(e) => {
+                            let error: Error = e.into();
+                            debug!(%error, "dropping failed endpoint");
+                            failed.push(*id);
+                            break;
+                        }
+                    }
+                }
+
+                // Dispatches the requests that wait for the endpoint as it
+                // becomes ready.
+                match ep.waiting.pop() {
+                    Some(dispatch) => {
+                        trace!(id, "dispatching waiting request");
+                        ep.ready = false;
+                        dispatch(&mut ep.service, None);
+                    }
+                    None => break,
+                }
+            }
+
+            while let Some(dispatch) = ep.waiting.pop_expired() {
+                debug!(id, "request waited too long for its endpoint");
+                self.unpinned.push_back(dispatch);
+            }
+        }
+        for id in failed.into_iter() {
+            self.remove(id);
+        }
+
+        // Requests that stopped waiting for their endpoints are dispatched to
+        // other endpoints.
+        while !self.unpinned.is_empty() {
+            let id = match self.select_p2c(None) {
+                Some(id) => id,
+                None => break,
+            };
+            let dispatch = self.unpinned.pop_front().expect("unpinned request");
+            trace!(id, "dispatching unpinned request");
+            let set_cookie = self.cookie.set_cookie(id);
+            let ep = self.endpoints.get_mut(&id).expect("selected endpoint");
+            ep.ready = false;
+            dispatch(&mut ep.service, set_cookie);
+        }
+
+        if self.endpoints.values().any(|ep| ep.ready) {
+            return Ok(Async::Ready(()));
+        }
+
+        trace!(endpoints = self.endpoints.len(), "no ready endpoints");
+        Ok(Async::NotReady)
+    }
+
+    fn call(&mut self, req: http::Request<A>) -> Self::Future {
+        let pinned = self
+            .cookie
+            .endpoint(&req)
+            .and_then(|id| self.endpoints.get_mut(&id).map(|ep| (id, ep)));
+        if let Some((id, ep)) = pinned {
+            if ep.ready {
+                ep.ready = false;
+                return ResponseFuture {
+                    inner: Inner::Called(ep.service.call(req)),
+                    set_cookie: None,
+                };
+            }
+
+            // The endpoint is still discovered, so the request waits for it
+            // to become ready, unless too many requests are already waiting.
+            if !ep.waiting.is_full() {
+                trace!(id, waiting = ep.waiting.len() + 1, "waiting for endpoint");
+                let (dispatch, rx) = queue::dispatch(req);
+                ep.waiting.push(dispatch);
+                return ResponseFuture {
+                    inner: Inner::Waiting(rx),
+                    set_cookie: None,
+                };
+            }
+            debug!(id, "too many requests waiting for endpoint");
+        }
+
+        // Pinned requests are dispatched to their endpoint, even if they are
+        // hedged, so other attempts are only avoided when selecting a new
+        // endpoint.
+        let attempts = Attempts::get(&req);
+        let id = self
+            .select_p2c(attempts.as_ref())
+            .expect("called before ready");
+        if let Some(attempts) = attempts {
+            attempts.dispatched(id);
+        }
+        trace!(id, "setting sticky cookie");
+        let set_cookie = self.cookie.set_cookie(id);
+        let ep = self.endpoints.get_mut(&id).expect("selected endpoint");
+        ep.ready = false;
+        ResponseFuture {
+            inner: Inner::Called(ep.service.call(req)),
+            set_cookie,
+        }
+    }
+}
+
+/// Identifies an endpoint in cookies.
+///
+/// The hash is unkeyed, so an endpoint's identifier does not depend on the
+/// balancer that discovered it.
+fn id<K: Hash>(key: &K) -> u64 {
+    ring::hash(key)
+}
+
+// === impl ResponseFuture ===
+
+impl<F, B> Future for ResponseFuture<F>
+where
+    F: Future<Item = http::Response<B>>,
+    F::Error: Into<Error>,
+{
+    type Item = http::Response<B>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.inner = match self.inner {
+                Inner::Called(ref mut f) => {
+                    let mut rsp = try_ready!(f.poll().map_err(Into::into));
+                    if let Some(value) = self.set_cookie.take() {
+                        rsp.headers_mut().append(header::SET_COOKIE, value);
+                    }
+                    return Ok(Async::Ready(rsp));
+                }
+                Inner::Waiting(ref mut rx) => {
+                    let (f, set_cookie) = try_ready!(rx.poll().map_err(|_| Dropped::new()));
+                    self.set_cookie = set_cookie;
+                    Inner::Called(f)
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use rand::SeedableRng;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+    use std::time::Instant;
+    use tokio_timer::Delay;
+
+    /// Discovers the changes pushed onto its queue.
+    #[derive(Clone, Default)]
+    struct Changes(Arc<Mutex<VecDeque<Change<u16, Svc>>>>);
+
+    /// Responds with its key in the `x-endpoint` header while it is ready.
+    struct Svc {
+        key: u16,
+        ready: Arc<AtomicBool>,
+    }
+
+    impl Changes {
+        fn push(&self, change: Change<u16, Svc>) {
+            self.0.lock().unwrap().push_back(change);
+        }
+    }
+
+    impl Discover for Changes {
+        type Key = u16;
+        type Service = Svc;
+        type Error = Error;
+
+        fn poll(&mut self) -> Poll<Change<u16, Svc>, Error> {
+            match self.0.lock().unwrap().pop_front() {
+                Some(change) => Ok(Async::Ready(change)),
+                None => Ok(Async::NotReady),
+            }
+        }
+    }
+
+    impl Svc {
+        fn new(key: u16) -> (Self, Arc<AtomicBool>) {
+            let ready = Arc::new(AtomicBool::new(true));
+            let svc = Svc {
+                key,
+                ready: ready.clone(),
+            };
+            (svc, ready)
+        }
+    }
+
+    impl tower::Service<http::Request<()>> for Svc {
+        type Response = http::Response<()>;
+        type Error = Error;
+        type Future = future::FutureResult<http::Response<()>, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Error> {
+            if self.ready.load(Ordering::SeqCst) {
+                Ok(Async::Ready(()))
+            } else {
+                Ok(Async::NotReady)
+            }
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            let rsp = http::Response::builder()
+                .header("x-endpoint", HeaderValue::from(self.key))
+                .body(())
+                .unwrap();
+            future::ok(rsp)
+        }
+    }
+
+    impl Load for Svc {
+        type Metric = usize;
+
+        fn load(&self) -> usize {
+            0
+        }
+    }
+
+    impl HasEffectiveWeight for Svc {
+        fn effective_weight(&self) -> f64 {
+            1.0
+        }
+    }
+
+    /// Returns the key of the endpoint that served the response.
+    fn endpoint(rsp: &http::Response<()>) -> &str {
+        rsp.headers()["x-endpoint"].to_str().unwrap()
+    }
+
+    fn req(cookie: &str) -> http::Request<()> {
+        http::Request::builder()
+            .header(header::COOKIE, cookie)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_endpoint_from_cookie() {
+        let cookie = StickyCookie::new("l5d-sticky");
+        let set = cookie.set_cookie(0xabc).unwrap();
+        assert_eq!(set, "l5d-sticky=0000000000000abc; Path=/; HttpOnly");
+
+        let value = set.to_str().unwrap().split(';').next().unwrap();
+        assert_eq!(cookie.endpoint(&req(value)), Some(0xabc));
+        assert_eq!(
+            cookie.endpoint(&req(&format!("session=foo; {}", value))),
+            Some(0xabc)
+        );
+        assert_eq!(cookie.endpoint(&req("session=foo")), None);
+        assert_eq!(cookie.endpoint(&req("l5d-sticky=nothex")), None);
+    }
+
+    #[test]
+    fn waits_for_pinned_endpoint() {
+        let cookie = StickyCookie::new("l5d-sticky");
+        let pinned = cookie.set_cookie(id(&1u16)).unwrap();
+        let pinned = pinned
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        let changes = Changes::default();
+        let (svc1, ready1) = Svc::new(1);
+        let (svc2, _) = Svc::new(2);
+        changes.push(Change::Insert(1, svc1));
+        changes.push(Change::Insert(2, svc2));
+        let mut sticky = Sticky::new(changes.clone(), cookie, SmallRng::seed_from_u64(0));
+
+        tokio::runtime::current_thread::run(future::lazy(move || {
+            use tower::Service;
+
+            // The pinned endpoint is discovered but not ready, so the request
+            // waits for it rather than being dispatched to another endpoint.
+            ready1.store(false, Ordering::SeqCst);
+            assert!(sticky.poll_ready().unwrap().is_ready());
+            let mut rsp = sticky.call(req(&pinned));
+            assert!(rsp.poll().unwrap().is_not_ready());
+
+            ready1.store(true, Ordering::SeqCst);
+            assert!(sticky.poll_ready().unwrap().is_ready());
+            let rsp = match rsp.poll().unwrap() {
+                Async::Ready(rsp) => rsp,
+                Async::NotReady => panic!("waiting request must be dispatched"),
+            };
+            assert_eq!(endpoint(&rsp), "1");
+            assert!(rsp.headers().get(header::SET_COOKIE).is_none());
+
+            // When the pinned endpoint is removed, the waiting request is
+            // dispatched to another endpoint, which sets a new cookie.
+            ready1.store(false, Ordering::SeqCst);
+            assert!(sticky.poll_ready().unwrap().is_ready());
+            let mut rsp = sticky.call(req(&pinned));
+            assert!(rsp.poll().unwrap().is_not_ready());
+
+            changes.push(Change::Remove(1));
+            sticky.poll_ready().unwrap();
+            assert!(sticky.unpinned.is_empty());
+            let rsp = match rsp.poll().unwrap() {
+                Async::Ready(rsp) => rsp,
+                Async::NotReady => panic!("unpinned request must be dispatched"),
+            };
+            assert_eq!(endpoint(&rsp), "2");
+            assert!(rsp.headers().get(header::SET_COOKIE).is_some());
+
+            // Requests that cannot wait, because too many requests are
+            // waiting, are dispatched to another endpoint immediately.
+            let (svc1, ready1) = Svc::new(1);
+            ready1.store(false, Ordering::SeqCst);
+            changes.push(Change::Insert(1, svc1));
+            assert!(sticky.poll_ready().unwrap().is_ready());
+            let mut waiting = (0..queue::MAX_WAITING)
+                .map(|_| sticky.call(req(&pinned)))
+                .collect::<Vec<_>>();
+            assert!(sticky.poll_ready().unwrap().is_ready());
+            let rsp = match sticky.call(req(&pinned)).poll().unwrap() {
+                Async::Ready(rsp) => rsp,
+                Async::NotReady => panic!("request must not wait"),
+            };
+            assert_eq!(endpoint(&rsp), "2");
+            assert!(rsp.headers().get(header::SET_COOKIE).is_some());
+
+            // Once requests have waited for too long, they are dispatched to
+            // another endpoint, which sets a new cookie.
+            for rsp in waiting.iter_mut() {
+                assert!(rsp.poll().unwrap().is_not_ready());
+            }
+            Delay::new(Instant::now() + queue::MAX_WAIT)
+                .map_err(|_| ())
+                .map(move |()| {
+                    // The other endpoint accepts one request each time it
+                    // becomes ready.
+                    for mut rsp in waiting.into_iter() {
+                        sticky.poll_ready().unwrap();
+                        let rsp = match rsp.poll().unwrap() {
+                            Async::Ready(rsp) => rsp,
+                            Async::NotReady => panic!("expired request must be dispatched"),
+                        };
+                        assert_eq!(endpoint(&rsp), "2");
+                        assert!(rsp.headers().get(header::SET_COOKIE).is_some());
+                    }
+                    assert!(sticky.unpinned.is_empty());
+                })
+        }));
+    }
+}
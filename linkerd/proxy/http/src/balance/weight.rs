@@ -0,0 +1,137 @@
+//! Annotates endpoint services with their relative weights.
+
+use futures::{try_ready, Async, Future, Poll};
+
+/// The weight of an endpoint that has no explicit weight, corresponding to 1.0.
+pub const DEFAULT_WEIGHT: u32 = 10_000;
+
+/// Describes an endpoint's weight relative to the other endpoints in a
+/// balancer.
+///
+/// Weights are expressed in ten-thousandths, so that `DEFAULT_WEIGHT`
+/// corresponds to 1.0.
+pub trait HasWeight {
+    fn weight(&self) -> u32;
+}
+
+/// Describes the weight with which a balancer samples an endpoint, as a ratio
+/// of `DEFAULT_WEIGHT`.
+///
+/// This may be less than the endpoint's `HasWeight` weight, e.g. while the
+/// endpoint is ramped up by slow-start. Zero-weighted endpoints have an
+/// effective weight of 0.
+pub trait HasEffectiveWeight {
+    fn effective_weight(&self) -> f64;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Layer(());
+
+/// Wraps the services built for `HasWeight` targets with their weights.
+#[derive(Clone, Debug)]
+pub struct MakeWeighted<M> {
+    inner: M,
+}
+
+pub struct MakeFuture<F> {
+    weight: u32,
+    inner: F,
+}
+
+/// An endpoint service annotated with its weight.
+#[derive(Clone, Debug)]
+pub struct Weighted<S> {
+    weight: u32,
+    inner: S,
+}
+
+// === impl Layer ===
+
+pub fn layer() -> Layer {
+    Layer::default()
+}
+
+impl<M> tower::layer::Layer<M> for Layer {
+    type Service = MakeWeighted<M>;
+
+    fn layer(&self, inner: M) -> Self::Service {
+        MakeWeighted { inner }
+    }
+}
+
+// === impl MakeWeighted ===
+
+impl<T, M> tower::Service<T> for MakeWeighted<M>
+where
+    T: HasWeight,
+    M: tower::Service<T>,
+{
+    type Response = Weighted<M::Response>;
+    type Error = M::Error;
+    type Future = MakeFuture<M::Future>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        MakeFuture {
+            weight: target.weight(),
+            inner: self.inner.call(target),
+        }
+    }
+}
+
+// === impl MakeFuture ===
+
+impl<F: Future> Future for MakeFuture<F> {
+    type Item = Weighted<F::Item>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let inner = try_ready!(self.inner.poll());
+        Ok(Async::Ready(Weighted {
+            weight: self.weight,
+            inner,
+        }))
+    }
+}
+
+// === impl Weighted ===
+
+impl<S> Weighted<S> {
+    pub fn new(inner: S, weight: u32) -> Self {
+        Self { weight, inner }
+    }
+}
+
+impl<S> HasWeight for Weighted<S> {
+    fn weight(&self) -> u32 {
+        self.weight
+    }
+}
+
+impl<S> HasEffectiveWeight for Weighted<S> {
+    fn effective_weight(&self) -> f64 {
+        f64::from(self.weight) / f64::from(DEFAULT_WEIGHT)
+    }
+}
+
+impl<S, Req> tower::Service<Req> for Weighted<S>
+where
+    S: tower::Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    #[inline]
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    #[inline]
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.inner.call(req)
+    }
+}
@@ -18,6 +18,7 @@ pub mod insert;
 pub mod normalize_uri;
 pub mod orig_proto;
 pub mod override_authority;
+pub mod replay;
 pub mod settings;
 pub mod strip_header;
 pub mod timeout;
@@ -0,0 +1,421 @@
+use bytes::{Buf, Bytes};
+use futures::{task, Async, Poll};
+use hyper::body::Payload;
+use linkerd2_error::Error;
+use std::sync::{Arc, Mutex};
+
+/// Wraps an HTTP body so that it may be replayed, i.e. when a request is
+/// retried.
+///
+/// Data read from the inner body is buffered, up to `max_bytes`. Clones of a
+/// body share this buffer: each clone first replays the buffered data and
+/// then continues reading from the inner body.
+///
+/// Once more than `max_bytes` have been read, the buffer is discarded and
+/// the body can no longer be replayed. Clones that have not yet read the
+/// discarded data fail with a `Capped` error.
+///
+/// A follower (see `ReplayBody::follower`) never reads from the inner body
+/// while another clone may, so that it cannot cause that clone to fail; it only
+/// replays the data that other clones have read.
+///
+/// Clones may be polled from different tasks. The inner body only notifies the
+/// task that polled it most recently, so every clone that is waiting on the
+/// inner body is notified when another clone reads from it (or is dropped).
+/// The inner body is polled while the shared buffer is locked, so clones that
+/// are polled concurrently wait for one another.
+pub struct ReplayBody<B> {
+    shared: Arc<Mutex<Shared<B>>>,
+    /// The number of chunks this body has yielded.
+    position: usize,
+    is_follower: bool,
+}
+
+/// Indicates that a body exceeded its buffer and cannot be replayed.
+#[derive(Debug)]
+pub struct Capped(());
+
+struct Shared<B> {
+    body: B,
+    buf: Vec<Bytes>,
+    buffered_bytes: usize,
+    max_bytes: usize,
+    /// The number of chunks read from the inner body.
+    read: usize,
+    /// The number of clones that are not followers.
+    leaders: usize,
+    is_capped: bool,
+    /// Whether the inner body's data has been read to its end.
+    is_eos: bool,
+    trailers: Option<http::HeaderMap>,
+    /// Tasks of clones waiting on the inner body.
+    waiting: Vec<task::Task>,
+}
+
+// === impl ReplayBody ===
+
+impl<B> ReplayBody<B> {
+    pub fn new(body: B, max_bytes: usize) -> Self {
+        Self {
+            shared: Arc::new(Mutex::new(Shared {
+                body,
+                buf: Vec::new(),
+                buffered_bytes: 0,
+                max_bytes,
+                read: 0,
+                leaders: 1,
+                is_capped: false,
+                is_eos: false,
+                trailers: None,
+                waiting: Vec::new(),
+            })),
+            position: 0,
+            is_follower: false,
+        }
+    }
+
+    /// Returns a clone that replays the data read by other clones, but does
+    /// not read from the inner body until all other clones that are not
+    /// followers have been dropped.
+    ///
+    /// A follower fails with a `Capped` error if it falls more than
+    /// `max_bytes` behind.
+    pub fn follower(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            position: 0,
+            is_follower: true,
+        }
+    }
+
+    /// Returns true if the body has exceeded its buffer, so that clones can
+    /// no longer replay it.
+    pub fn is_capped(&self) -> bool {
+        self.shared
+            .lock()
+            .expect("replay body lock poisoned")
+            .is_capped
+    }
+}
+
+impl<B> Clone for ReplayBody<B> {
+    fn clone(&self) -> Self {
+        if !self.is_follower {
+            self.shared
+                .lock()
+                .expect("replay body lock poisoned")
+                .leaders += 1;
+        }
+        Self {
+            shared: self.shared.clone(),
+            position: 0,
+            is_follower: self.is_follower,
+        }
+    }
+}
+
+impl<B> Payload for ReplayBody<B>
+where
+    B: Payload,
+    B::Error: Into<Error>,
+{
+    type Data = hyper::Chunk;
+    type Error = Error;
+
+    fn is_end_stream(&self) -> bool {
+        let shared = self.shared.lock().expect("replay body lock poisoned");
+        self.position == shared.read && shared.body.is_end_stream()
+    }
+
+    fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+        let mut shared = self.shared.lock().expect("replay body lock poisoned");
+
+        if self.position < shared.read {
+            if shared.is_capped {
+                return Err(Capped(()).into());
+            }
+            let chunk = shared.buf[self.position].clone();
+            self.position += 1;
+            return Ok(Async::Ready(Some(chunk.into())));
+        }
+
+        if shared.is_eos {
+            return Ok(Async::Ready(None));
+        }
+
+        // A follower waits for another clone to read from the inner body.
+        if self.is_follower && shared.leaders > 0 {
+            shared.park();
+            return Ok(Async::NotReady);
+        }
+
+        let data = match shared.body.poll_data() {
+            Ok(Async::NotReady) => {
+                shared.park();
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready(data)) => data,
+            Err(e) => {
+                shared.notify();
+                return Err(e.into());
+            }
+        };
+        shared.notify();
+        let bytes = match data {
+            Some(data) => data.collect::<Bytes>(),
+            None => {
+                shared.is_eos = true;
+                return Ok(Async::Ready(None));
+            }
+        };
+        shared.read += 1;
+        self.position += 1;
+
+        if !shared.is_capped {
+            shared.buffered_bytes += bytes.len();
+            if shared.buffered_bytes > shared.max_bytes {
+                shared.is_capped = true;
+                shared.buf = Vec::new();
+            } else {
+                shared.buf.push(bytes.clone());
+            }
+        }
+
+        Ok(Async::Ready(Some(bytes.into())))
+    }
+
+    fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+        let mut shared = self.shared.lock().expect("replay body lock poisoned");
+
+        if let Some(ref trailers) = shared.trailers {
+            return Ok(Async::Ready(Some(trailers.clone())));
+        }
+
+        // Trailers are cached once read, so a follower may read them once the
+        // inner body's data has ended without interfering with other clones.
+        if self.is_follower && shared.leaders > 0 && !shared.is_eos {
+            shared.park();
+            return Ok(Async::NotReady);
+        }
+
+        let trailers = match shared.body.poll_trailers() {
+            Ok(Async::NotReady) => {
+                shared.park();
+                return Ok(Async::NotReady);
+            }
+            Ok(Async::Ready(trailers)) => trailers,
+            Err(e) => {
+                shared.notify();
+                return Err(e.into());
+            }
+        };
+        shared.notify();
+        shared.trailers = trailers.clone();
+        Ok(Async::Ready(trailers))
+    }
+}
+
+impl<B> Drop for ReplayBody<B> {
+    fn drop(&mut self) {
+        // This body may have been the only one that the inner body would
+        // notify, so another waiting clone must take its place.
+        if let Ok(mut shared) = self.shared.lock() {
+            if !self.is_follower {
+                shared.leaders -= 1;
+            }
+            shared.notify();
+        }
+    }
+}
+
+impl<B> From<ReplayBody<B>> for crate::boxed::Payload
+where
+    B: Payload,
+    B::Error: Into<Error>,
+{
+    fn from(body: ReplayBody<B>) -> Self {
+        crate::boxed::Payload::new(body)
+    }
+}
+
+impl<B> std::fmt::Debug for ReplayBody<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReplayBody")
+            .field("position", &self.position)
+            .field("is_follower", &self.is_follower)
+            .finish()
+    }
+}
+
+// === impl Shared ===
+
+impl<B> Shared<B> {
+    /// Records the current task as waiting on the inner body.
+    fn park(&mut self) {
+        if !self.waiting.iter().any(|t| t.will_notify_current()) {
+            self.waiting.push(task::current());
+        }
+    }
+
+    fn notify(&mut self) {
+        for task in self.waiting.drain(..) {
+            task.notify();
+        }
+    }
+}
+
+// === impl Capped ===
+
+impl std::fmt::Display for Capped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "body exceeded its replay buffer")
+    }
+}
+
+impl std::error::Error for Capped {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_to_end<B: Payload<Data = hyper::Chunk>>(body: &mut B) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            match body.poll_data() {
+                Ok(Async::Ready(Some(chunk))) => out.extend_from_slice(&chunk),
+                Ok(Async::Ready(None)) => return out,
+                Ok(Async::NotReady) => panic!("body must be ready"),
+                Err(_) => panic!("body must not fail"),
+            }
+        }
+    }
+
+    #[test]
+    fn replays_buffered_body() {
+        let mut initial = ReplayBody::new(hyper::Body::from("hello world"), 64);
+        let mut replay = initial.clone();
+
+        assert_eq!(read_to_end(&mut initial), b"hello world");
+        assert!(!initial.is_capped());
+        assert_eq!(read_to_end(&mut replay), b"hello world");
+    }
+
+    #[test]
+    fn caps_large_body() {
+        let mut initial = ReplayBody::new(hyper::Body::from("hello world"), 5);
+        let mut replay = initial.clone();
+
+        assert_eq!(read_to_end(&mut initial), b"hello world");
+        assert!(initial.is_capped());
+        assert!(replay.poll_data().is_err());
+    }
+
+    #[test]
+    fn follower_replays_without_reading() {
+        use futures::{future, Future};
+
+        let mut leader = ReplayBody::new(hyper::Body::from("hello world"), 64);
+        let mut follower = leader.follower();
+
+        // The follower does not read from the inner body while the leader
+        // may, so it is not ready until the leader reads.
+        let poll = future::lazy(|| Ok::<_, ()>(follower.poll_data().unwrap()))
+            .wait()
+            .unwrap();
+        assert!(poll.is_not_ready());
+
+        assert_eq!(read_to_end(&mut leader), b"hello world");
+        assert_eq!(read_to_end(&mut follower), b"hello world");
+    }
+
+    #[test]
+    fn follower_reads_once_leaders_are_dropped() {
+        let leader = ReplayBody::new(hyper::Body::from("hello world"), 64);
+        let mut follower = leader.follower();
+        drop(leader);
+
+        assert_eq!(read_to_end(&mut follower), b"hello world");
+    }
+
+    #[test]
+    fn notifies_each_waiting_clone() {
+        use futures::{executor, future};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct Pending {
+            chunk: Option<hyper::Chunk>,
+            task: Option<task::Task>,
+        }
+
+        /// Like most bodies, only notifies the task that last polled it.
+        struct PendingBody(Arc<Mutex<Pending>>);
+
+        impl Payload for PendingBody {
+            type Data = hyper::Chunk;
+            type Error = Error;
+
+            fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+                let mut pending = self.0.lock().unwrap();
+                match pending.chunk.take() {
+                    Some(chunk) => Ok(Async::Ready(Some(chunk))),
+                    None => {
+                        pending.task = Some(task::current());
+                        Ok(Async::NotReady)
+                    }
+                }
+            }
+
+            fn poll_trailers(&mut self) -> Poll<Option<http::HeaderMap>, Self::Error> {
+                Ok(Async::Ready(None))
+            }
+        }
+
+        #[derive(Default)]
+        struct Notified(AtomicUsize);
+
+        impl executor::Notify for Notified {
+            fn notify(&self, _: usize) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let mut a = ReplayBody::new(PendingBody(pending.clone()), 64);
+        let mut b = a.clone();
+        let mut task_a = executor::spawn(future::poll_fn(move || a.poll_data()));
+        let mut task_b = executor::spawn(future::poll_fn(move || b.poll_data()));
+        let notified_a = Arc::new(Notified::default());
+        let notified_b = Arc::new(Notified::default());
+
+        assert!(task_a
+            .poll_future_notify(&notified_a, 0)
+            .unwrap()
+            .is_not_ready());
+        assert!(task_b
+            .poll_future_notify(&notified_b, 0)
+            .unwrap()
+            .is_not_ready());
+
+        // The inner body only notifies `b`...
+        let task = {
+            let mut pending = pending.lock().unwrap();
+            pending.chunk = Some("hello".into());
+            pending.task.take().unwrap()
+        };
+        task.notify();
+        assert_eq!(notified_a.0.load(Ordering::SeqCst), 0);
+        assert_eq!(notified_b.0.load(Ordering::SeqCst), 1);
+
+        // ...which notifies `a` once it has read the chunk.
+        match task_b.poll_future_notify(&notified_b, 0).unwrap() {
+            Async::Ready(Some(chunk)) => assert_eq!(chunk.as_ref(), b"hello"),
+            _ => panic!("body must be ready"),
+        }
+        assert_eq!(notified_a.0.load(Ordering::SeqCst), 1);
+        match task_a.poll_future_notify(&notified_a, 0).unwrap() {
+            Async::Ready(Some(chunk)) => assert_eq!(chunk.as_ref(), b"hello"),
+            _ => panic!("body must be ready"),
+        }
+    }
+}
@@ -93,6 +93,21 @@ impl Match {
             Match::Http(ref http) => http.matches(req, inspect),
         }
     }
+
+    /// Matches a forwarded TCP connection from `src` to `dst`.
+    ///
+    /// Connections have no destination labels, routes, or HTTP metadata, so
+    /// matches on these never match a connection.
+    pub fn matches_tcp(&self, src: net::SocketAddr, dst: net::SocketAddr) -> bool {
+        match self {
+            Match::Any(ref ms) => ms.iter().any(|m| m.matches_tcp(src, dst)),
+            Match::All(ref ms) => ms.iter().all(|m| m.matches_tcp(src, dst)),
+            Match::Not(ref not) => !not.matches_tcp(src, dst),
+            Match::Source(ref m) => m.matches(src),
+            Match::Destination(ref m) => m.matches(dst),
+            Match::DestinationLabel(_) | Match::RouteLabel(_) | Match::Http(_) => false,
+        }
+    }
 }
 
 impl Match {
@@ -396,6 +411,16 @@ mod tests {
             m.matches(addr) == matches
         }
 
+        fn tcp_connection_matches(
+            m: TcpMatch,
+            src: net::SocketAddr,
+            dst: net::SocketAddr
+        ) -> bool {
+            Match::Source(m.clone()).matches_tcp(src, dst) == m.matches(src) &&
+                Match::Destination(m.clone()).matches_tcp(src, dst) == m.matches(dst) &&
+                !Match::Http(HttpMatch::Method(::http::Method::GET)).matches_tcp(src, dst)
+        }
+
         fn labels_from_proto(label: observe_request::r#match::Label) -> bool {
             let err: Option<InvalidMatch> =
                 if label.key.is_empty() || label.value.is_empty() {
@@ -1,4 +1,7 @@
 mod match_;
 mod server;
 
-pub use self::server::{Server, Tap};
+pub use self::server::{
+    BodyEvent, BodyKind, Event, Events, EventsFuture, GrpcMessage, ResponseStream, Server, Tap,
+    TcpEvent, TcpEventKind,
+};
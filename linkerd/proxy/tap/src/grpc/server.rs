@@ -1,38 +1,149 @@
 use super::match_::Match;
-use crate::{iface, Inspect};
-use bytes::Buf;
+use crate::{iface, BodyCapture, Direction, Inspect};
+use bytes::{Buf, Bytes, BytesMut};
 use futures::sync::mpsc;
-use futures::{future, Async, Future, Poll, Stream};
+use futures::{try_ready, Async, Future, Poll, Stream};
 use hyper::body::Payload;
 use linkerd2_conditional::Conditional;
 use linkerd2_proxy_api::{http_types, pb_duration, tap as api};
 use linkerd2_proxy_http::HasH2Reason;
+use linkerd2_proxy_transport::tls;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::iter;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Weak};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio_timer::clock;
 use tower_grpc::{self as grpc, Response};
 use tracing::{debug, trace, warn};
 
+/// The gRPC request metadata that sets the number of bytes to capture from each
+/// tapped request and response body.
+///
+/// The tap API cannot describe body capture, so taps opt into it with this
+/// metadata rather than with an `ObserveRequest` field.
+const BODY_BYTES_METADATA: &str = "l5d-tap-body-bytes";
+
 #[derive(Clone, Debug)]
 pub struct Server<T> {
     subscribe: T,
     base_id: Arc<AtomicUsize>,
+    body_capture: BodyCapture,
+}
+
+/// Resolves to a stream of tap events once the tap has been registered.
+#[derive(Debug)]
+pub struct EventsFuture<F>(Observe<F>);
+
+#[derive(Debug)]
+enum Observe<F> {
+    Failed(Option<grpc::Status>),
+    Subscribe {
+        subscribe: F,
+        events_rx: Option<mpsc::Receiver<Event>>,
+        shared: Option<Arc<Shared>>,
+    },
 }
 
 #[derive(Debug)]
-pub struct ResponseFuture<F> {
-    subscribe: F,
-    events_rx: Option<mpsc::Receiver<api::TapEvent>>,
+pub struct ResponseFuture<F>(EventsFuture<F>);
+
+/// Streams all of a tap's events, including those that cannot be described
+/// by the tap API.
+#[derive(Debug)]
+pub struct Events {
+    events_rx: mpsc::Receiver<Event>,
     shared: Option<Arc<Shared>>,
 }
 
+/// Streams the tap events that can be described by the tap API.
+///
+/// Captured bodies are held until their stream's `ResponseEnd` event and are
+/// then appended to its trailers as pseudo-header fields:
+///
+/// * `:l5d-request-body`, `:l5d-response-body` -- the captured bytes.
+/// * `:l5d-request-body-bytes`, `:l5d-response-body-bytes` -- the total size
+///   of the body, including bytes that were not captured.
+/// * `:l5d-request-grpc-message`, `:l5d-response-grpc-message` -- each gRPC
+///   message in the captured bytes, in order, without its length prefix. The
+///   last message may be truncated, and compressed messages are not
+///   decompressed.
+///
+/// A request body that is still streaming when its response ends is not
+/// described.
 #[derive(Debug)]
 pub struct ResponseStream {
-    events_rx: mpsc::Receiver<api::TapEvent>,
-    shared: Option<Arc<Shared>>,
+    events: Events,
+    /// Pseudo-header fields describing captured bodies, by stream.
+    bodies: HashMap<u64, Vec<http_types::headers::Header>>,
+}
+
+/// An event emitted by a tap.
+#[derive(Debug)]
+pub enum Event {
+    Api(api::TapEvent),
+    /// Body events are served by the tap API only as pseudo-header fields in
+    /// the stream's `ResponseEnd` trailers (see `ResponseStream`).
+    Body(BodyEvent),
+    /// TCP events cannot be described by the tap API, so they are only served
+    /// by the JSON tap.
+    Tcp(TcpEvent),
+}
+
+/// Describes the first bytes of a request or response body.
+#[derive(Debug)]
+pub struct BodyEvent {
+    /// Describes the tapped stream, without an `event`.
+    pub base_event: api::TapEvent,
+    pub id: api::tap_event::http::StreamId,
+    pub kind: BodyKind,
+    /// Whether the body is a stream of gRPC length-prefixed messages.
+    pub grpc: bool,
+    /// The captured prefix of the body.
+    pub bytes: Bytes,
+    /// The total size of the body, including bytes that were not captured.
+    pub body_bytes: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BodyKind {
+    Request,
+    Response,
+}
+
+/// A gRPC length-prefixed message read from a captured body.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GrpcMessage<'a> {
+    pub compressed: bool,
+    /// The length of the whole message.
+    pub length: usize,
+    /// The captured bytes of the message, which may be truncated.
+    pub data: &'a [u8],
+}
+
+/// Describes a forwarded TCP connection.
+#[derive(Debug)]
+pub struct TcpEvent {
+    /// Describes the connection, without an `event`.
+    pub base_event: api::TapEvent,
+    pub id: api::tap_event::http::StreamId,
+    pub kind: TcpEventKind,
+}
+
+#[derive(Debug)]
+pub enum TcpEventKind {
+    Open,
+    Bytes {
+        from_source: u64,
+        from_destination: u64,
+    },
+    Close {
+        since_open: Duration,
+        from_source: u64,
+        from_destination: u64,
+        errno: Option<i32>,
+    },
 }
 
 #[derive(Debug)]
@@ -42,13 +153,15 @@ struct Shared {
     limit: usize,
     match_: Match,
     extract: ExtractKind,
-    events_tx: mpsc::Sender<api::TapEvent>,
+    /// Should forwarded TCP connections be tapped?
+    tcp: bool,
+    events_tx: mpsc::Sender<Event>,
 }
 
 #[derive(Clone, Debug)]
 struct TapTx {
     id: api::tap_event::http::StreamId,
-    tx: mpsc::Sender<api::TapEvent>,
+    tx: mpsc::Sender<Event>,
 }
 
 #[derive(Clone, Debug)]
@@ -62,6 +175,15 @@ pub struct TapResponse {
     request_init_at: Instant,
     /// Should headers be extracted?
     extract_headers: bool,
+    /// Should the response body be captured?
+    capture_body: Option<CaptureBodies>,
+    tap: TapTx,
+}
+
+#[derive(Debug)]
+pub struct TapTcp {
+    base_event: api::TapEvent,
+    opened_at: Instant,
     tap: TapTx,
 }
 
@@ -69,6 +191,7 @@ pub struct TapResponse {
 pub struct TapRequestPayload {
     base_event: api::TapEvent,
     tap: TapTx,
+    body: Option<Capture>,
 }
 
 #[derive(Debug)]
@@ -82,6 +205,7 @@ pub struct TapResponsePayload {
     extract_headers: bool,
     // Response-headers may include grpc-status when there is no response body.
     grpc_status: Option<u32>,
+    body: Option<Capture>,
 }
 
 /// Indicates what tap data should be extracted from traffic.
@@ -89,44 +213,86 @@ pub struct TapResponsePayload {
 /// This is constructed from the protobuf `Extract` message, and represents the
 /// same information about the tap, but has a simpler structure as it does not
 /// need to represent nullability the way the protobuf message does.
+///
+/// The protobuf message cannot describe body capture, so `bodies` is only set
+/// for taps served by the JSON tap.
 #[derive(Debug)]
 enum ExtractKind {
-    Http { headers: bool },
+    Http {
+        headers: bool,
+        bodies: Option<CaptureBodies>,
+    },
+}
+
+/// Configures body capture for a tap.
+#[derive(Clone, Debug)]
+struct CaptureBodies {
+    /// The maximum number of bytes captured from each body.
+    max_bytes: usize,
+    /// The number of bytes that may still be captured by the tap, across all
+    /// tapped bodies.
+    budget: Arc<AtomicUsize>,
+}
+
+/// Captures the first bytes of a body, subject to the tap's byte budget.
+#[derive(Debug)]
+struct Capture {
+    config: CaptureBodies,
+    grpc: bool,
+    bytes: BytesMut,
+    body_bytes: usize,
 }
 
 // === impl Server ===
 
 impl<T: iface::Subscribe<Tap>> Server<T> {
-    pub(in crate) fn new(subscribe: T) -> Self {
+    pub(in crate) fn new(subscribe: T, body_capture: BodyCapture) -> Self {
         let base_id = Arc::new(0.into());
-        Self { base_id, subscribe }
+        Self {
+            base_id,
+            subscribe,
+            body_capture,
+        }
     }
 
     fn invalid_arg(message: String) -> grpc::Status {
         grpc::Status::new(grpc::Code::InvalidArgument, message)
     }
-}
-
-impl<T> api::server::Tap for Server<T>
-where
-    T: iface::Subscribe<Tap> + Clone,
-{
-    type ObserveStream = ResponseStream;
-    type ObserveFuture = future::Either<
-        future::FutureResult<Response<Self::ObserveStream>, grpc::Status>,
-        ResponseFuture<T::Future>,
-    >;
-
-    fn observe(&mut self, req: grpc::Request<api::ObserveRequest>) -> Self::ObserveFuture {
-        let req = req.into_inner();
 
+    /// Starts a tap.
+    ///
+    /// If `body_bytes` is set, up to that many bytes are captured from each
+    /// tapped request and response body, so long as the proxy permits body
+    /// capture. If `tcp` is set, forwarded TCP connections are tapped as well
+    /// as requests.
+    pub(in crate) fn observe_events(
+        &mut self,
+        req: api::ObserveRequest,
+        body_bytes: Option<usize>,
+        tcp: bool,
+    ) -> EventsFuture<T::Future> {
         let limit = req.limit as usize;
         if limit == 0 {
             let err = Self::invalid_arg("limit must be positive".into());
-            return future::Either::A(future::err(err));
+            return EventsFuture(Observe::Failed(Some(err)));
         };
         trace!("tap: limit={}", limit);
 
+        let bodies = match (body_bytes, self.body_capture) {
+            (None, _) | (Some(0), _) => None,
+            (Some(_), BodyCapture::Forbidden) => {
+                let err = grpc::Status::new(
+                    grpc::Code::PermissionDenied,
+                    "body capture is disabled on this proxy",
+                );
+                return EventsFuture(Observe::Failed(Some(err)));
+            }
+            (Some(max_bytes), BodyCapture::Permitted) => Some(CaptureBodies {
+                max_bytes: max_bytes.min(super::super::MAX_BODY_BYTES_PER_TAP),
+                budget: Arc::new(AtomicUsize::new(super::super::MAX_BODY_BYTES_PER_TAP)),
+            }),
+        };
+
         // Read the match logic into a type we can use to evaluate against
         // requests. This match will be shared (weakly) by all registered
         // services to match requests. The response stream strongly holds the
@@ -137,11 +303,11 @@ where
             Err(e) => {
                 warn!("invalid tap request: {} ", e);
                 let err = Self::invalid_arg(e.to_string());
-                return future::Either::A(future::err(err));
+                return EventsFuture(Observe::Failed(Some(err)));
             }
         };
 
-        let extract = req
+        let extract = match req
             .extract
             .and_then(|ex| ExtractKind::try_from(ex).ok())
             // If there's no extract field, the request may have been sent
@@ -149,7 +315,10 @@ where
             // the case, rather than failing the tap, just do the only
             // behavior that older control planes know about --- extract
             // HTTP data without headers.
-            .unwrap_or_default();
+            .unwrap_or_default()
+        {
+            ExtractKind::Http { headers, .. } => ExtractKind::Http { headers, bodies },
+        };
 
         // Wrapping is okay. This is realy just to disambiguate events within a
         // single tap session (i.e. that may consist of several tap requests).
@@ -171,6 +340,7 @@ where
             limit,
             match_,
             extract,
+            tcp,
             events_tx,
         });
 
@@ -182,7 +352,7 @@ where
         // Reads up to `limit` requests from from `taps_rx` and satisfies them
         // with a cpoy of `events_tx`.
 
-        future::Either::B(ResponseFuture {
+        EventsFuture(Observe::Subscribe {
             subscribe,
             shared: Some(shared),
             events_rx: Some(events_rx),
@@ -190,28 +360,79 @@ where
     }
 }
 
-impl<F: Future<Item = ()>> Future for ResponseFuture<F> {
-    type Item = Response<ResponseStream>;
+impl<T> api::server::Tap for Server<T>
+where
+    T: iface::Subscribe<Tap> + Clone,
+{
+    type ObserveStream = ResponseStream;
+    type ObserveFuture = ResponseFuture<T::Future>;
+
+    fn observe(&mut self, req: grpc::Request<api::ObserveRequest>) -> Self::ObserveFuture {
+        let body_bytes = match req.metadata().get(BODY_BYTES_METADATA) {
+            None => None,
+            Some(v) => match v.to_str().ok().and_then(|v| v.parse::<usize>().ok()) {
+                Some(bytes) => Some(bytes),
+                None => {
+                    let err = Self::invalid_arg(format!("invalid {}", BODY_BYTES_METADATA));
+                    return ResponseFuture(EventsFuture(Observe::Failed(Some(err))));
+                }
+            },
+        };
+
+        // The tap API cannot describe TCP connections.
+        ResponseFuture(self.observe_events(req.into_inner(), body_bytes, false))
+    }
+}
+
+// === impl EventsFuture ===
+
+impl<F: Future<Item = ()>> Future for EventsFuture<F> {
+    type Item = Events;
     type Error = grpc::Status;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        // Ensure that tap registers successfully.
-        match self.subscribe.poll() {
-            Ok(Async::NotReady) => return Ok(Async::NotReady),
-            Ok(Async::Ready(())) => {}
-            Err(_) => {
-                let status =
-                    grpc::Status::new(grpc::Code::ResourceExhausted, "Too many active taps");
-                return Err(status);
+        match self.0 {
+            Observe::Failed(ref mut status) => Err(status.take().expect("polled after ready")),
+            Observe::Subscribe {
+                ref mut subscribe,
+                ref mut events_rx,
+                ref mut shared,
+            } => {
+                // Ensure that tap registers successfully.
+                match subscribe.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => {}
+                    Err(_) => {
+                        let status = grpc::Status::new(
+                            grpc::Code::ResourceExhausted,
+                            "Too many active taps",
+                        );
+                        return Err(status);
+                    }
+                }
+
+                Ok(Async::Ready(Events {
+                    shared: shared.take(),
+                    events_rx: events_rx.take().expect("events_rx must be set"),
+                }))
             }
         }
+    }
+}
 
-        let rsp = ResponseStream {
-            shared: self.shared.take(),
-            events_rx: self.events_rx.take().expect("events_rx must be set"),
-        };
+// === impl ResponseFuture ===
+
+impl<F: Future<Item = ()>> Future for ResponseFuture<F> {
+    type Item = Response<ResponseStream>;
+    type Error = grpc::Status;
 
-        Ok(Response::new(rsp).into())
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let events = try_ready!(self.0.poll());
+        Ok(Response::new(ResponseStream {
+            events,
+            bodies: HashMap::new(),
+        })
+        .into())
     }
 }
 
@@ -221,6 +442,66 @@ impl Stream for ResponseStream {
     type Item = api::TapEvent;
     type Error = grpc::Status;
 
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match try_ready!(self.events.poll()) {
+                Some(Event::Api(mut ev)) => {
+                    self.end_bodies(&mut ev);
+                    return Ok(Async::Ready(Some(ev)));
+                }
+                // Bodies are held until their stream ends, or until the tap
+                // ends if their stream already has. Either way, they are
+                // bounded by the tap's body capture budget.
+                Some(Event::Body(body)) => self
+                    .bodies
+                    .entry(body.id.stream)
+                    .or_insert_with(Vec::new)
+                    .extend(body_to_pb(&body)),
+                // TCP events are never emitted for taps started via the tap
+                // API, but they cannot be described by it in any case.
+                Some(Event::Tcp(_)) => continue,
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+
+impl ResponseStream {
+    /// Appends a stream's captured bodies to the trailers of its `ResponseEnd`.
+    fn end_bodies(&mut self, ev: &mut api::TapEvent) {
+        if let Some(api::tap_event::Event::Http(api::tap_event::Http {
+            event: Some(api::tap_event::http::Event::ResponseEnd(end)),
+        })) = &mut ev.event
+        {
+            let bodies = end
+                .id
+                .as_ref()
+                .and_then(|id| self.bodies.remove(&id.stream));
+            if let Some(bodies) = bodies {
+                end.trailers
+                    .get_or_insert_with(http_types::Headers::default)
+                    .headers
+                    .extend(bodies);
+            }
+        }
+    }
+}
+
+// === impl BodyEvent ===
+
+impl BodyEvent {
+    /// Reads the gRPC length-prefixed messages in the captured bytes.
+    pub fn grpc_messages(&self) -> Vec<GrpcMessage<'_>> {
+        grpc_messages(&self.bytes)
+    }
+}
+
+// === impl Events ===
+
+impl Stream for Events {
+    type Item = Event;
+    type Error = grpc::Status;
+
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
         // Drop the Shared handle once at our limit so that services do not do
         // any more matching against this tap.
@@ -247,6 +528,20 @@ impl Shared {
     fn is_under_limit(&self) -> bool {
         self.count.load(Ordering::Relaxed) < self.limit
     }
+
+    /// Allocates an ID for a tapped stream or connection, if the tap is not
+    /// yet at its limit.
+    fn next_id(&self) -> Option<api::tap_event::http::StreamId> {
+        let next_id = self.count.fetch_add(1, Ordering::Relaxed);
+        if next_id < self.limit {
+            Some(api::tap_event::http::StreamId {
+                base: self.base_id,
+                stream: next_id as u64,
+            })
+        } else {
+            None
+        }
+    }
 }
 
 // === impl Tap ===
@@ -255,6 +550,7 @@ impl iface::Tap for Tap {
     type TapRequestPayload = TapRequestPayload;
     type TapResponse = TapResponse;
     type TapResponsePayload = TapResponsePayload;
+    type TapTcp = TapTcp;
 
     fn can_tap_more(&self) -> bool {
         self.shared
@@ -282,19 +578,10 @@ impl iface::Tap for Tap {
         // HTTP-specific.
         let ExtractKind::Http {
             headers: extract_headers,
+            bodies: ref capture_body,
         } = shared.extract;
 
-        let id = {
-            let next_id = shared.count.fetch_add(1, Ordering::Relaxed);
-            if next_id < shared.limit {
-                api::tap_event::http::StreamId {
-                    base: shared.base_id,
-                    stream: next_id as u64,
-                }
-            } else {
-                return None;
-            }
-        };
+        let id = shared.next_id()?;
         let mut events_tx = shared.events_tx.clone();
 
         let request_init_at = clock::now();
@@ -359,22 +646,84 @@ impl iface::Tap for Tap {
         };
 
         // If try_send fails, just return `None`...
-        events_tx.try_send(event).ok()?;
+        events_tx.try_send(Event::Api(event)).ok()?;
 
         let tap = TapTx { id, tx: events_tx };
 
         let req = TapRequestPayload {
             tap: tap.clone(),
             base_event: base_event.clone(),
+            body: capture_body
+                .as_ref()
+                .map(|c| Capture::new(c.clone(), req.headers())),
         };
         let rsp = TapResponse {
             tap,
             base_event,
             request_init_at,
             extract_headers,
+            capture_body: capture_body.clone(),
         };
         Some((req, rsp))
     }
+
+    fn tap_tcp(&mut self, meta: &tls::accept::Meta, direction: Direction) -> Option<TapTcp> {
+        let shared = self.shared.upgrade()?;
+        let (src, dst) = (meta.addrs.peer(), meta.addrs.target_addr());
+        if !shared.tcp || !shared.match_.matches_tcp(src, dst) {
+            return None;
+        }
+
+        let id = shared.next_id()?;
+        let mut events_tx = shared.events_tx.clone();
+
+        let base_event = tcp_base_event(meta, direction);
+        let event = TcpEvent {
+            base_event: base_event.clone(),
+            id: id.clone(),
+            kind: TcpEventKind::Open,
+        };
+        // If try_send fails, just return `None`...
+        events_tx.try_send(Event::Tcp(event)).ok()?;
+
+        Some(TapTcp {
+            base_event,
+            opened_at: clock::now(),
+            tap: TapTx { id, tx: events_tx },
+        })
+    }
+}
+
+// === impl TapTcp ===
+
+impl iface::TapTcp for TapTcp {
+    fn bytes(&mut self, from_source: u64, from_destination: u64) {
+        self.send(TcpEventKind::Bytes {
+            from_source,
+            from_destination,
+        });
+    }
+
+    fn close(mut self, from_source: u64, from_destination: u64, errno: Option<i32>) {
+        let since_open = clock::now() - self.opened_at;
+        self.send(TcpEventKind::Close {
+            since_open,
+            from_source,
+            from_destination,
+            errno,
+        });
+    }
+}
+
+impl TapTcp {
+    fn send(&mut self, kind: TcpEventKind) {
+        let event = TcpEvent {
+            base_event: self.base_event.clone(),
+            id: self.tap.id.clone(),
+            kind,
+        };
+        let _ = self.tap.tx.try_send(Event::Tcp(event));
+    }
 }
 
 // === impl TapResponse ===
@@ -413,7 +762,7 @@ impl iface::TapResponse for TapResponse {
             })),
             ..self.base_event.clone()
         };
-        let _ = self.tap.tx.try_send(event);
+        let _ = self.tap.tx.try_send(Event::Api(event));
 
         TapResponsePayload {
             base_event: self.base_event,
@@ -427,6 +776,7 @@ impl iface::TapResponse for TapResponse {
                 .get("grpc-status")
                 .and_then(|v| v.to_str().ok())
                 .and_then(|s| s.parse::<u32>().ok()),
+            body: self.capture_body.map(|c| Capture::new(c, rsp.headers())),
         }
     }
 
@@ -450,18 +800,34 @@ impl iface::TapResponse for TapResponse {
             })),
             ..self.base_event
         };
-        let _ = self.tap.tx.try_send(event);
+        let _ = self.tap.tx.try_send(Event::Api(event));
     }
 }
 
 // === impl TapRequestPayload ===
 
 impl iface::TapPayload for TapRequestPayload {
-    fn data<B: Buf>(&mut self, _: &B) {}
+    fn data<B: Buf>(&mut self, data: &B) {
+        if let Some(ref mut body) = self.body {
+            body.data(data);
+        }
+    }
 
-    fn eos(self, _: Option<&http::HeaderMap>) {}
+    fn eos(self, _: Option<&http::HeaderMap>) {
+        self.send();
+    }
+
+    fn fail<E: HasH2Reason>(self, _: &E) {
+        self.send();
+    }
+}
 
-    fn fail<E: HasH2Reason>(self, _: &E) {}
+impl TapRequestPayload {
+    fn send(mut self) {
+        if let Some(body) = self.body.take() {
+            body.send(BodyKind::Request, self.base_event, &mut self.tap);
+        }
+    }
 }
 
 // === impl TapResponsePayload ===
@@ -469,6 +835,9 @@ impl iface::TapPayload for TapRequestPayload {
 impl iface::TapPayload for TapResponsePayload {
     fn data<B: Buf>(&mut self, data: &B) {
         self.response_bytes += data.remaining();
+        if let Some(ref mut body) = self.body {
+            body.data(data);
+        }
     }
 
     fn eos(self, trls: Option<&http::HeaderMap>) {
@@ -493,6 +862,12 @@ impl iface::TapPayload for TapResponsePayload {
 
 impl TapResponsePayload {
     fn send(mut self, end: Option<api::eos::End>, trls: Option<&http::HeaderMap>) {
+        // The response body's capture is emitted before the response ends, so
+        // that the response's end is the last event for its stream.
+        if let Some(body) = self.body.take() {
+            body.send(BodyKind::Response, self.base_event.clone(), &mut self.tap);
+        }
+
         let response_end_at = clock::now();
         let trailers = if self.extract_headers {
             trls.map(|trls| headers_to_pb(iter::empty(), trls))
@@ -514,7 +889,79 @@ impl TapResponsePayload {
             })),
             ..self.base_event
         };
-        let _ = self.tap.tx.try_send(event);
+        let _ = self.tap.tx.try_send(Event::Api(event));
+    }
+}
+
+// === impl Capture ===
+
+impl Capture {
+    fn new(config: CaptureBodies, headers: &http::HeaderMap) -> Self {
+        let grpc = headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|ct| ct.starts_with("application/grpc"))
+            .unwrap_or(false);
+        Self {
+            config,
+            grpc,
+            bytes: BytesMut::new(),
+            body_bytes: 0,
+        }
+    }
+
+    fn data<B: Buf>(&mut self, data: &B) {
+        self.body_bytes += data.remaining();
+
+        // Only the buffer's first chunk is visible without consuming it. Data
+        // frames are contiguous in practice; otherwise, the capture ends early.
+        let chunk = data.bytes();
+        let want = chunk
+            .len()
+            .min(self.config.max_bytes.saturating_sub(self.bytes.len()));
+        let n = self.config.reserve(want);
+        self.bytes.extend_from_slice(&chunk[..n]);
+    }
+
+    /// Emits the captured bytes, if the body had any.
+    fn send(self, kind: BodyKind, base_event: api::TapEvent, tap: &mut TapTx) {
+        if self.body_bytes == 0 {
+            return;
+        }
+        let event = BodyEvent {
+            base_event,
+            id: tap.id.clone(),
+            kind,
+            grpc: self.grpc,
+            bytes: self.bytes.freeze(),
+            body_bytes: self.body_bytes,
+        };
+        let _ = tap.tx.try_send(Event::Body(event));
+    }
+}
+
+// === impl CaptureBodies ===
+
+impl CaptureBodies {
+    /// Takes up to `want` bytes from the tap's budget, returning the number of
+    /// bytes that may be captured.
+    fn reserve(&self, want: usize) -> usize {
+        let mut remaining = self.budget.load(Ordering::Acquire);
+        loop {
+            let n = want.min(remaining);
+            if n == 0 {
+                return 0;
+            }
+            match self.budget.compare_exchange_weak(
+                remaining,
+                remaining - n,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return n,
+                Err(actual) => remaining = actual,
+            }
+        }
     }
 }
 
@@ -529,7 +976,10 @@ impl TryFrom<api::observe_request::Extract> for ExtractKind {
                     Some(api::observe_request::extract::http::Extract::Headers(_)) => true,
                     _ => false,
                 };
-                Ok(ExtractKind::Http { headers })
+                Ok(ExtractKind::Http {
+                    headers,
+                    bodies: None,
+                })
             }
             _ => Err(()),
         }
@@ -538,7 +988,10 @@ impl TryFrom<api::observe_request::Extract> for ExtractKind {
 
 impl Default for ExtractKind {
     fn default() -> Self {
-        ExtractKind::Http { headers: false }
+        ExtractKind::Http {
+            headers: false,
+            bodies: None,
+        }
     }
 }
 
@@ -594,6 +1047,79 @@ fn base_event<B, I: Inspect>(req: &http::Request<B>, inspect: &I) -> api::TapEve
     }
 }
 
+fn tcp_base_event(meta: &tls::accept::Meta, direction: Direction) -> api::TapEvent {
+    api::TapEvent {
+        proxy_direction: match direction {
+            Direction::Inbound => api::tap_event::ProxyDirection::Inbound.into(),
+            Direction::Outbound => api::tap_event::ProxyDirection::Outbound.into(),
+        },
+        source: Some((&meta.addrs.peer()).into()),
+        source_meta: {
+            let mut m = api::tap_event::EndpointMeta::default();
+            match meta.peer_identity {
+                Conditional::None(reason) => {
+                    m.labels.insert("tls".to_owned(), reason.to_string());
+                }
+                Conditional::Some(ref id) => {
+                    m.labels.insert("tls".to_owned(), "true".to_owned());
+                    m.labels
+                        .insert("client_id".to_owned(), id.as_ref().to_owned());
+                }
+            }
+            Some(m)
+        },
+        destination: Some((&meta.addrs.target_addr()).into()),
+        destination_meta: None,
+        route_meta: None,
+        event: None,
+    }
+}
+
+/// Describes a captured body as pseudo-header fields.
+fn body_to_pb(body: &BodyEvent) -> Vec<http_types::headers::Header> {
+    let kind = match body.kind {
+        BodyKind::Request => "request",
+        BodyKind::Response => "response",
+    };
+    let header = |name: &str, value: &[u8]| http_types::headers::Header {
+        name: format!(":l5d-{}-{}", kind, name),
+        value: value.into(),
+    };
+
+    let mut headers = vec![
+        header("body", &body.bytes),
+        header("body-bytes", body.body_bytes.to_string().as_bytes()),
+    ];
+    if body.grpc {
+        headers.extend(
+            body.grpc_messages()
+                .into_iter()
+                .map(|m| header("grpc-message", m.data)),
+        );
+    }
+    headers
+}
+
+/// Reads the gRPC length-prefixed messages in a captured body.
+///
+/// As only a prefix of the body is captured, the last message may be
+/// truncated: each message's `length` describes the whole message, while its
+/// `data` holds only the bytes that were captured.
+fn grpc_messages(mut buf: &[u8]) -> Vec<GrpcMessage<'_>> {
+    let mut messages = Vec::new();
+    while buf.len() >= 5 {
+        let length = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+        let end = buf.len().min(5 + length);
+        messages.push(GrpcMessage {
+            compressed: buf[0] == 1,
+            length,
+            data: &buf[5..end],
+        });
+        buf = &buf[end..];
+    }
+    messages
+}
+
 fn headers_to_pb(
     pseudos: impl IntoIterator<Item = http_types::headers::Header>,
     headers: &http::HeaderMap,
@@ -612,3 +1138,126 @@ fn headers_to_pb(
             .collect(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn captures_bodies_within_budget() {
+        let config = CaptureBodies {
+            max_bytes: 4,
+            budget: Arc::new(AtomicUsize::new(6)),
+        };
+
+        let mut first = Capture::new(config.clone(), &http::HeaderMap::new());
+        first.data(&Cursor::new(&b"hello"[..]));
+        assert_eq!(&first.bytes[..], b"hell");
+        assert_eq!(first.body_bytes, 5);
+
+        // Only two bytes remain in the tap's budget.
+        let mut second = Capture::new(config.clone(), &http::HeaderMap::new());
+        second.data(&Cursor::new(&b"world"[..]));
+        second.data(&Cursor::new(&b"!"[..]));
+        assert_eq!(&second.bytes[..], b"wo");
+        assert_eq!(second.body_bytes, 6);
+        assert_eq!(config.budget.load(Ordering::Acquire), 0);
+    }
+
+    #[test]
+    fn detects_grpc_bodies() {
+        let config = CaptureBodies {
+            max_bytes: 4,
+            budget: Arc::new(AtomicUsize::new(4)),
+        };
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            "application/grpc+proto".parse().unwrap(),
+        );
+        assert!(Capture::new(config.clone(), &headers).grpc);
+        assert!(!Capture::new(config, &http::HeaderMap::new()).grpc);
+    }
+
+    #[test]
+    fn decodes_grpc_messages() {
+        let mut body = vec![0, 0, 0, 0, 2, b'h', b'i'];
+        body.extend_from_slice(&[1, 0, 0, 0, 8, b'a', b'b', b'c']);
+        assert_eq!(
+            grpc_messages(&body),
+            vec![
+                GrpcMessage {
+                    compressed: false,
+                    length: 2,
+                    data: b"hi",
+                },
+                GrpcMessage {
+                    compressed: true,
+                    length: 8,
+                    data: b"abc",
+                },
+            ]
+        );
+
+        // An incomplete prefix is not a message.
+        assert!(grpc_messages(&[0, 0, 0]).is_empty());
+    }
+
+    #[test]
+    fn ends_bodies_in_trailers() {
+        let (_, events_rx) = mpsc::channel(1);
+        let mut stream = ResponseStream {
+            events: Events {
+                events_rx,
+                shared: None,
+            },
+            bodies: HashMap::new(),
+        };
+        let id = api::tap_event::http::StreamId { base: 1, stream: 2 };
+        let body = BodyEvent {
+            base_event: api::TapEvent::default(),
+            id: id.clone(),
+            kind: BodyKind::Request,
+            grpc: true,
+            bytes: Bytes::from(&[0, 0, 0, 0, 2, b'h', b'i'][..]),
+            body_bytes: 9,
+        };
+        stream.bodies.insert(id.stream, body_to_pb(&body));
+
+        let end = api::tap_event::http::ResponseEnd {
+            id: Some(id),
+            ..Default::default()
+        };
+        let mut ev = api::TapEvent {
+            event: Some(api::tap_event::Event::Http(api::tap_event::Http {
+                event: Some(api::tap_event::http::Event::ResponseEnd(end)),
+            })),
+            ..Default::default()
+        };
+        stream.end_bodies(&mut ev);
+        assert!(stream.bodies.is_empty());
+
+        let trailers = match ev.event {
+            Some(api::tap_event::Event::Http(api::tap_event::Http {
+                event: Some(api::tap_event::http::Event::ResponseEnd(end)),
+            })) => end.trailers.expect("trailers must be set").headers,
+            _ => panic!("unexpected event"),
+        };
+        let trailers = trailers
+            .into_iter()
+            .map(|h| (h.name, h.value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            trailers,
+            vec![
+                (
+                    ":l5d-request-body".to_owned(),
+                    vec![0, 0, 0, 0, 2, b'h', b'i']
+                ),
+                (":l5d-request-body-bytes".to_owned(), b"9".to_vec()),
+                (":l5d-request-grpc-message".to_owned(), b"hi".to_vec()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,430 @@
+//! Serves tap events as newline-delimited JSON.
+//!
+//! Taps are described by a query string rather than an `ObserveRequest`, so
+//! that they may be issued with a plain HTTP client (e.g. `curl`):
+//!
+//! * `limit` -- the maximum number of requests to tap (default: 100).
+//! * `path` -- matches requests with the given path prefix.
+//! * `method` -- matches requests with the given method.
+//! * `authority` -- matches requests with the given authority.
+//! * `src`, `dst` -- match requests from or to the given network (CIDR).
+//! * `body` -- captures up to the given number of bytes from each tapped
+//!   request and response body.
+//!
+//! Filters may be repeated, and a request must match all of them to be tapped.
+//!
+//! Forwarded TCP connections are tapped as well as HTTP requests, and are
+//! matched only by the `src` and `dst` filters. Each tapped connection emits a
+//! `tcpOpen` event, periodic `tcpBytes` events, and a `tcpClose` event. Bytes
+//! are counted in each direction: `sourceBytes` were sent by the source and
+//! `destinationBytes` by the destination.
+//!
+//! Body capture is opt-in, may be forbidden by the proxy's configuration, and
+//! is limited to a fixed number of bytes per tap. Captured bytes are emitted as
+//! `requestBody` and `responseBody` events, encoded as base64. gRPC bodies are
+//! also decoded into their length-prefixed messages, which may be truncated.
+
+use crate::grpc::{
+    BodyEvent, BodyKind, Event, Events, EventsFuture, GrpcMessage, Server, Tap, TcpEvent,
+    TcpEventKind,
+};
+use crate::iface;
+use futures::{Async, Future, Poll, Stream};
+use http::{header, StatusCode};
+use hyper::{Body, Chunk};
+use linkerd2_error::Never;
+use linkerd2_proxy_api::{http_types, net as api_net, tap as api};
+use serde_json::{json, Value};
+use std::net;
+use tower_grpc as grpc;
+use tracing::warn;
+
+/// The number of requests tapped when no `limit` is specified.
+const DEFAULT_LIMIT: u32 = 100;
+
+#[derive(Clone, Debug)]
+pub struct Json<T> {
+    server: Server<T>,
+}
+
+pub enum ResponseFuture<F> {
+    Invalid(Option<String>),
+    Observe(EventsFuture<F>),
+}
+
+/// Encodes tap events as lines of JSON.
+///
+/// Errors end the stream.
+pub struct Lines(Events);
+
+// === impl Json ===
+
+impl<T> Json<T>
+where
+    T: iface::Subscribe<Tap> + Clone,
+{
+    pub fn new(server: Server<T>) -> Self {
+        Self { server }
+    }
+
+    /// Starts a tap described by a query string.
+    pub fn observe(&mut self, query: Option<&str>) -> ResponseFuture<T::Future> {
+        match observe_request(query.unwrap_or_default()) {
+            Ok((req, body_bytes)) => {
+                ResponseFuture::Observe(self.server.observe_events(req, body_bytes, true))
+            }
+            Err(e) => ResponseFuture::Invalid(Some(e)),
+        }
+    }
+}
+
+/// Reads a tap request, and the number of bytes to capture from each body, from
+/// a query string.
+fn observe_request(query: &str) -> Result<(api::ObserveRequest, Option<usize>), String> {
+    use api::observe_request::r#match::{self, http, tcp, Match as Pb};
+
+    let mut limit = DEFAULT_LIMIT;
+    let mut body_bytes = None;
+    let mut matches = Vec::new();
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        let m = match key.as_ref() {
+            "limit" => {
+                limit = value
+                    .parse()
+                    .map_err(|_| format!("invalid limit: {}", value))?;
+                continue;
+            }
+            "body" => {
+                let bytes = value
+                    .parse()
+                    .map_err(|_| format!("invalid body size: {}", value))?;
+                body_bytes = Some(bytes);
+                continue;
+            }
+            "path" => Pb::Http(r#match::Http {
+                r#match: Some(http::Match::Path(http::StringMatch {
+                    r#match: Some(http::string_match::Match::Prefix(value.into_owned())),
+                })),
+            }),
+            "method" => {
+                let method = ::http::Method::from_bytes(value.as_bytes())
+                    .map_err(|_| format!("invalid method: {}", value))?;
+                Pb::Http(r#match::Http {
+                    r#match: Some(http::Match::Method(http_types::HttpMethod::from(&method))),
+                })
+            }
+            "authority" => Pb::Http(r#match::Http {
+                r#match: Some(http::Match::Authority(http::StringMatch {
+                    r#match: Some(http::string_match::Match::Exact(value.into_owned())),
+                })),
+            }),
+            "src" | "dst" => {
+                let net = value
+                    .parse::<ipnet::IpNet>()
+                    .map_err(|_| format!("invalid network: {}", value))?;
+                let tcp = r#match::Tcp {
+                    r#match: Some(tcp::Match::Netmask(tcp::Netmask {
+                        ip: Some(ip_address(net.addr())),
+                        mask: net.prefix_len().into(),
+                    })),
+                };
+                if key == "src" {
+                    Pb::Source(tcp)
+                } else {
+                    Pb::Destination(tcp)
+                }
+            }
+            _ => return Err(format!("unknown filter: {}", key)),
+        };
+        matches.push(api::observe_request::Match { r#match: Some(m) });
+    }
+
+    let req = api::ObserveRequest {
+        limit,
+        r#match: Some(api::observe_request::Match {
+            r#match: Some(Pb::All(r#match::Seq { matches })),
+        }),
+        extract: None,
+    };
+    Ok((req, body_bytes))
+}
+
+fn ip_address(ip: net::IpAddr) -> api_net::IpAddress {
+    let ip = match ip {
+        net::IpAddr::V4(ip) => api_net::ip_address::Ip::Ipv4(ip.into()),
+        net::IpAddr::V6(ip) => {
+            let ip = u128::from(ip);
+            api_net::ip_address::Ip::Ipv6(api_net::IPv6 {
+                first: (ip >> 64) as u64,
+                last: ip as u64,
+            })
+        }
+    };
+    api_net::IpAddress { ip: Some(ip) }
+}
+
+// === impl ResponseFuture ===
+
+impl<F: Future<Item = ()>> Future for ResponseFuture<F> {
+    type Item = http::Response<Body>;
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let rsp = match self {
+            ResponseFuture::Invalid(msg) => {
+                let msg = msg.take().expect("polled after ready");
+                rsp(StatusCode::BAD_REQUEST, msg)
+            }
+            ResponseFuture::Observe(f) => match f.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(events)) => http::Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/x-ndjson")
+                    .body(Body::wrap_stream(Lines(events)))
+                    .expect("builder with known status code must not fail"),
+                Err(status) => {
+                    warn!(message = "tap failed", code = ?status.code());
+                    let code = match status.code() {
+                        grpc::Code::InvalidArgument => StatusCode::BAD_REQUEST,
+                        grpc::Code::PermissionDenied => StatusCode::FORBIDDEN,
+                        grpc::Code::ResourceExhausted => StatusCode::SERVICE_UNAVAILABLE,
+                        _ => StatusCode::INTERNAL_SERVER_ERROR,
+                    };
+                    rsp(code, status.message().to_owned())
+                }
+            },
+        };
+        Ok(Async::Ready(rsp))
+    }
+}
+
+fn rsp(status: StatusCode, msg: String) -> http::Response<Body> {
+    http::Response::builder()
+        .status(status)
+        .body(format!("{}\n", msg).into())
+        .expect("builder with known status code must not fail")
+}
+
+// === impl Lines ===
+
+impl Stream for Lines {
+    type Item = Chunk;
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, Never> {
+        let json = match self.0.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(Some(Event::Api(ev)))) => event(ev),
+            Ok(Async::Ready(Some(Event::Body(body)))) => body_event(body),
+            Ok(Async::Ready(Some(Event::Tcp(tcp)))) => tcp_event(tcp),
+            Ok(Async::Ready(None)) | Err(_) => return Ok(Async::Ready(None)),
+        };
+        let line = format!("{}\n", json);
+        Ok(Async::Ready(Some(line.into())))
+    }
+}
+
+fn event(ev: api::TapEvent) -> Value {
+    use api::tap_event::http::Event;
+
+    let direction = match api::tap_event::ProxyDirection::from_i32(ev.proxy_direction) {
+        Some(api::tap_event::ProxyDirection::Inbound) => "inbound",
+        Some(api::tap_event::ProxyDirection::Outbound) => "outbound",
+        _ => "unknown",
+    };
+    let mut json = json!({
+        "source": ev.source.and_then(socket_addr).map(|a| a.to_string()),
+        "sourceMeta": ev.source_meta.map(|m| m.labels),
+        "destination": ev.destination.and_then(socket_addr).map(|a| a.to_string()),
+        "destinationMeta": ev.destination_meta.map(|m| m.labels),
+        "routeMeta": ev.route_meta.map(|m| m.labels),
+        "proxyDirection": direction,
+    });
+
+    let http = match ev.event {
+        Some(api::tap_event::Event::Http(api::tap_event::Http { event: Some(http) })) => http,
+        _ => return json,
+    };
+    let (key, value) = match http {
+        Event::RequestInit(init) => (
+            "requestInit",
+            json!({
+                "id": init.id.map(stream_id),
+                "method": init.method
+                    .and_then(|m| m.r#type)
+                    .and_then(|m| m.try_as_http().ok())
+                    .map(|m| m.as_str().to_owned()),
+                "scheme": init.scheme.and_then(scheme),
+                "authority": init.authority,
+                "path": init.path,
+            }),
+        ),
+        Event::ResponseInit(init) => (
+            "responseInit",
+            json!({
+                "id": init.id.map(stream_id),
+                "sinceRequestInitMs": init.since_request_init.map(millis),
+                "httpStatus": init.http_status,
+            }),
+        ),
+        Event::ResponseEnd(end) => {
+            let eos = end.eos.and_then(|eos| eos.end).map(|end| match end {
+                api::eos::End::GrpcStatusCode(code) => json!({ "grpcStatusCode": code }),
+                api::eos::End::ResetErrorCode(code) => json!({ "resetErrorCode": code }),
+            });
+            (
+                "responseEnd",
+                json!({
+                    "id": end.id.map(stream_id),
+                    "sinceRequestInitMs": end.since_request_init.map(millis),
+                    "sinceResponseInitMs": end.since_response_init.map(millis),
+                    "responseBytes": end.response_bytes,
+                    "eos": eos,
+                }),
+            )
+        }
+    };
+    json[key] = value;
+    json
+}
+
+fn body_event(body: BodyEvent) -> Value {
+    let key = match body.kind {
+        BodyKind::Request => "requestBody",
+        BodyKind::Response => "responseBody",
+    };
+    let mut value = json!({
+        "id": stream_id(body.id),
+        "bodyBytes": body.body_bytes,
+        "capturedBytes": body.bytes.len(),
+        "data": base64::encode(&body.bytes),
+    });
+    if body.grpc {
+        value["grpcMessages"] = grpc_messages(body.grpc_messages()).into();
+    }
+
+    // The base event has no `event`, so only its metadata is encoded.
+    let mut json = event(body.base_event);
+    json[key] = value;
+    json
+}
+
+fn tcp_event(tcp: TcpEvent) -> Value {
+    let id = stream_id(tcp.id);
+    let (key, value) = match tcp.kind {
+        TcpEventKind::Open => ("tcpOpen", json!({ "id": id })),
+        TcpEventKind::Bytes {
+            from_source,
+            from_destination,
+        } => (
+            "tcpBytes",
+            json!({
+                "id": id,
+                "sourceBytes": from_source,
+                "destinationBytes": from_destination,
+            }),
+        ),
+        TcpEventKind::Close {
+            since_open,
+            from_source,
+            from_destination,
+            errno,
+        } => (
+            "tcpClose",
+            json!({
+                "id": id,
+                "sinceOpenMs": since_open.as_secs_f64() * 1_000.0,
+                "sourceBytes": from_source,
+                "destinationBytes": from_destination,
+                "errno": errno,
+            }),
+        ),
+    };
+
+    // The base event has no `event`, so only its metadata is encoded.
+    let mut json = event(tcp.base_event);
+    json[key] = value;
+    json
+}
+
+fn grpc_messages(messages: Vec<GrpcMessage<'_>>) -> Vec<Value> {
+    messages
+        .into_iter()
+        .map(|m| {
+            json!({
+                "compressed": m.compressed,
+                "length": m.length,
+                "data": base64::encode(m.data),
+            })
+        })
+        .collect()
+}
+
+fn socket_addr(addr: api_net::TcpAddress) -> Option<net::SocketAddr> {
+    let ip = match addr.ip?.ip? {
+        api_net::ip_address::Ip::Ipv4(ip) => net::IpAddr::V4(ip.into()),
+        api_net::ip_address::Ip::Ipv6(ip) => net::IpAddr::V6((&ip).into()),
+    };
+    Some(net::SocketAddr::new(ip, addr.port as u16))
+}
+
+fn stream_id(id: api::tap_event::http::StreamId) -> Value {
+    json!({ "base": id.base, "stream": id.stream })
+}
+
+fn scheme(scheme: http_types::Scheme) -> Option<String> {
+    use http_types::scheme::{Registered, Type};
+
+    match scheme.r#type? {
+        Type::Registered(reg) if reg == Registered::Http.into() => Some("http".to_owned()),
+        Type::Registered(reg) if reg == Registered::Https.into() => Some("https".to_owned()),
+        Type::Registered(_) => None,
+        Type::Unregistered(s) => Some(s),
+    }
+}
+
+fn millis(d: prost_types::Duration) -> f64 {
+    d.seconds as f64 * 1_000.0 + f64::from(d.nanos) / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filters(query: &str) -> Vec<api::observe_request::Match> {
+        use api::observe_request::r#match::Match as Pb;
+
+        let (req, _) = observe_request(query).unwrap();
+        match req.r#match.and_then(|m| m.r#match) {
+            Some(Pb::All(seq)) => seq.matches,
+            m => panic!("unexpected match: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn empty_query_matches_all() {
+        let (req, body_bytes) = observe_request("").unwrap();
+        assert_eq!(req.limit, DEFAULT_LIMIT);
+        assert_eq!(body_bytes, None);
+        assert!(filters("").is_empty());
+    }
+
+    #[test]
+    fn parses_filters() {
+        let query = "limit=10&path=%2Fapi&method=POST&authority=web.svc:8080\
+                     &src=10.1.0.0/16&dst=fd00::/8&body=1024";
+        let (req, body_bytes) = observe_request(query).unwrap();
+        assert_eq!(req.limit, 10);
+        assert_eq!(body_bytes, Some(1024));
+        assert_eq!(filters(query).len(), 5);
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        assert!(observe_request("limit=lots").is_err());
+        assert!(observe_request("method=GE%20T").is_err());
+        assert!(observe_request("src=10.1.0.0").is_err());
+        assert!(observe_request("port=80").is_err());
+        assert!(observe_request("body=lots").is_err());
+    }
+}
@@ -7,20 +7,32 @@ use linkerd2_identity as identity;
 use linkerd2_proxy_transport::tls::ReasonForNoIdentity;
 use std::net;
 use std::sync::Arc;
+use std::time::Duration;
 
 mod accept;
 mod daemon;
 mod grpc;
+mod json;
 mod service;
+mod tcp;
 
 pub use self::accept::AcceptPermittedClients;
+pub use self::tcp::Direction;
 
 /// Instruments service stacks so that requests may be tapped.
 pub type Layer = service::Layer<daemon::Register<grpc::Tap>>;
 
+/// Instruments TCP forwarding services so that connections may be tapped.
+///
+/// Obtained from a `Layer` with `Layer::tcp`.
+pub type TcpLayer = tcp::Layer<daemon::Register<grpc::Tap>>;
+
 /// A gRPC tap server.
 pub type Server = grpc::Server<daemon::Subscribe<grpc::Tap>>;
 
+/// Serves taps described by query strings as newline-delimited JSON.
+pub type Json = json::Json<daemon::Subscribe<grpc::Tap>>;
+
 /// A Future that dispatches new tap requests to services and ensures that new
 /// services are notified of active tap requests.
 pub type Daemon = daemon::Daemon<grpc::Tap>;
@@ -35,11 +47,26 @@ const REGISTER_CHANNEL_CAPACITY: usize = 10_000;
 // The number of events that may be buffered for a given response.
 const PER_RESPONSE_EVENT_BUFFER_CAPACITY: usize = 400;
 
+// The maximum number of body bytes that may be captured by a single tap,
+// across all of the bodies it taps.
+const MAX_BODY_BYTES_PER_TAP: usize = 64 * 1024;
+
+// The interval at which the bytes transferred on tapped TCP connections are
+// recorded.
+const TCP_BYTES_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Determines whether taps may capture request and response bodies.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BodyCapture {
+    Permitted,
+    Forbidden,
+}
+
 /// Build the tap subsystem.
-pub fn new() -> (Layer, Server, Daemon) {
+pub fn new(body_capture: BodyCapture) -> (Layer, Server, Daemon) {
     let (daemon, register, subscribe) = daemon::new();
     let layer = Layer::new(register);
-    let server = Server::new(subscribe);
+    let server = Server::new(subscribe, body_capture);
     (layer, server, daemon)
 }
 
@@ -96,6 +123,7 @@ mod iface {
     use http;
     use hyper::body::Payload;
     use linkerd2_proxy_http::HasH2Reason;
+    use linkerd2_proxy_transport::tls;
 
     /// Registers a stack to receive taps.
     pub trait Register {
@@ -119,6 +147,7 @@ mod iface {
         type TapRequestPayload: TapPayload;
         type TapResponse: TapResponse<TapPayload = Self::TapResponsePayload>;
         type TapResponsePayload: TapPayload;
+        type TapTcp: TapTcp;
 
         /// Returns `true` as l
         fn can_tap_more(&self) -> bool;
@@ -132,6 +161,13 @@ mod iface {
             req: &http::Request<B>,
             inspect: &I,
         ) -> Option<(Self::TapRequestPayload, Self::TapResponse)>;
+
+        /// Initiate a tap on a forwarded TCP connection, if it matches.
+        fn tap_tcp(
+            &mut self,
+            meta: &tls::accept::Meta,
+            direction: super::Direction,
+        ) -> Option<Self::TapTcp>;
     }
 
     pub trait TapPayload {
@@ -152,6 +188,15 @@ mod iface {
         fn fail<E: HasH2Reason>(self, error: &E);
     }
 
+    pub trait TapTcp {
+        /// Record the number of bytes transferred so far, in each direction.
+        fn bytes(&mut self, from_source: u64, from_destination: u64);
+
+        /// Record the connection's close, with the error number of the
+        /// failure that closed it, if any.
+        fn close(self, from_source: u64, from_destination: u64, errno: Option<i32>);
+    }
+
     #[derive(Debug)]
     pub struct NoCapacity;
 
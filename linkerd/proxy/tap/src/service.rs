@@ -61,6 +61,12 @@ where
     pub(super) fn new(registry: R) -> Self {
         Self { registry }
     }
+
+    /// Returns a layer that records taps on the connections forwarded by a
+    /// TCP forwarding service.
+    pub fn tcp(&self, direction: super::Direction) -> super::tcp::Layer<R> {
+        super::tcp::Layer::new(self.registry.clone(), direction)
+    }
 }
 
 impl<R, M> tower::layer::Layer<M> for Layer<R>
@@ -0,0 +1,358 @@
+//! Taps TCP connections forwarded by the proxy.
+//!
+//! Connections that are not decoded as HTTP are forwarded as opaque byte
+//! streams. Each tapped connection emits an event when it is opened, the
+//! number of bytes transferred in each direction every
+//! `TCP_BYTES_INTERVAL`, and an event when it is closed.
+
+use super::iface::{Register, Tap, TapTcp};
+use bytes::Buf;
+use futures::{try_ready, Async, Future, Poll, Stream};
+use linkerd2_error::Error;
+use linkerd2_proxy_transport::tls;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_timer::{clock, Interval};
+
+/// Indicates whether tapped connections are accepted by the inbound or the
+/// outbound proxy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// A layer that wraps a TCP forwarding service to record taps.
+#[derive(Clone, Debug)]
+pub struct Layer<R> {
+    registry: R,
+    direction: Direction,
+}
+
+/// Records taps on the connections passed to a TCP forwarding service.
+///
+/// Connections are forwarded by clones of the service, so the taps it has
+/// been notified of are shared by all of its clones.
+pub struct Forward<R: Register, S> {
+    inner: S,
+    direction: Direction,
+    taps: Arc<Mutex<Taps<R>>>,
+}
+
+struct Taps<R: Register> {
+    tap_rx: R::Taps,
+    taps: Vec<R::Tap>,
+}
+
+pub struct ForwardFuture<F, T: TapTcp> {
+    inner: F,
+    taps: Vec<T>,
+    counts: Option<Arc<Counts>>,
+}
+
+/// Forwards a connection, recording its progress with its taps.
+pub struct Connection<F, T: TapTcp> {
+    inner: F,
+    taps: Vec<T>,
+    counts: Option<Arc<Counts>>,
+    interval: Option<Interval>,
+}
+
+/// Counts the bytes transferred on a tapped connection.
+#[derive(Debug, Default)]
+struct Counts {
+    from_source: AtomicU64,
+    from_destination: AtomicU64,
+}
+
+/// An accepted connection whose bytes are counted if it is tapped.
+#[derive(Debug)]
+pub struct Io<I> {
+    io: I,
+    counts: Option<Arc<Counts>>,
+}
+
+// === impl Layer ===
+
+impl<R> Layer<R> {
+    pub(super) fn new(registry: R, direction: Direction) -> Self {
+        Self {
+            registry,
+            direction,
+        }
+    }
+}
+
+impl<R, S> tower::layer::Layer<S> for Layer<R>
+where
+    R: Register + Clone,
+{
+    type Service = Forward<R, S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        let tap_rx = self.registry.clone().register();
+        Forward {
+            inner,
+            direction: self.direction,
+            taps: Arc::new(Mutex::new(Taps {
+                tap_rx,
+                taps: Vec::default(),
+            })),
+        }
+    }
+}
+
+// === impl Forward ===
+
+impl<R: Register, S: Clone> Clone for Forward<R, S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            direction: self.direction,
+            taps: self.taps.clone(),
+        }
+    }
+}
+
+impl<R, S, I> tower::Service<(tls::accept::Meta, I)> for Forward<R, S>
+where
+    R: Register,
+    S: tower::Service<(tls::accept::Meta, Io<I>)>,
+{
+    type Response = Connection<S::Response, <R::Tap as Tap>::TapTcp>;
+    type Error = S::Error;
+    type Future = ForwardFuture<S::Future, <R::Tap as Tap>::TapTcp>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready()
+    }
+
+    fn call(&mut self, (meta, io): (tls::accept::Meta, I)) -> Self::Future {
+        let taps = {
+            let mut taps = self.taps.lock().expect("Lock poisoned");
+            taps.update();
+
+            let direction = self.direction;
+            taps.taps
+                .iter_mut()
+                .filter_map(|t| t.tap_tcp(&meta, direction))
+                .collect::<Vec<_>>()
+        };
+
+        // Bytes are only counted on tapped connections.
+        let counts = if taps.is_empty() {
+            None
+        } else {
+            Some(Arc::new(Counts::default()))
+        };
+        let io = Io {
+            io,
+            counts: counts.clone(),
+        };
+
+        ForwardFuture {
+            inner: self.inner.call((meta, io)),
+            taps,
+            counts,
+        }
+    }
+}
+
+// === impl Taps ===
+
+impl<R: Register> Taps<R> {
+    fn update(&mut self) {
+        // Load new taps from the tap server.
+        while let Ok(Async::Ready(Some(t))) = self.tap_rx.poll() {
+            self.taps.push(t);
+        }
+        // Drop taps that have been canceled or completed.
+        self.taps.retain(|t| t.can_tap_more());
+    }
+}
+
+// === impl ForwardFuture ===
+
+impl<F: Future, T: TapTcp> Future for ForwardFuture<F, T> {
+    type Item = Connection<F::Item, T>;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // If the connection cannot be forwarded, its taps are closed when this
+        // future is dropped.
+        let inner = try_ready!(self.inner.poll());
+
+        let taps = self.taps.drain(..).collect::<Vec<_>>();
+        let interval = if taps.is_empty() {
+            None
+        } else {
+            let interval = super::TCP_BYTES_INTERVAL;
+            Some(Interval::new(clock::now() + interval, interval))
+        };
+        Ok(Async::Ready(Connection {
+            inner,
+            taps,
+            counts: self.counts.take(),
+            interval,
+        }))
+    }
+}
+
+impl<F, T: TapTcp> Drop for ForwardFuture<F, T> {
+    fn drop(&mut self) {
+        for tap in self.taps.drain(..) {
+            tap.close(0, 0, None);
+        }
+    }
+}
+
+// === impl Connection ===
+
+impl<F, T> Future for Connection<F, T>
+where
+    F: Future<Item = (), Error = Error>,
+    T: TapTcp,
+{
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        match self.inner.poll() {
+            Ok(Async::NotReady) => {
+                self.poll_interval();
+                Ok(Async::NotReady)
+            }
+            Ok(Async::Ready(())) => {
+                self.close(None);
+                Ok(Async::Ready(()))
+            }
+            Err(e) => {
+                let errno = e
+                    .downcast_ref::<io::Error>()
+                    .and_then(io::Error::raw_os_error);
+                self.close(errno);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<F, T: TapTcp> Connection<F, T> {
+    /// Records the bytes transferred each time the interval elapses.
+    fn poll_interval(&mut self) {
+        let interval = match self.interval.as_mut() {
+            Some(interval) => interval,
+            None => return,
+        };
+        // If the timer fails, byte counts are only recorded when the
+        // connection closes.
+        while let Ok(Async::Ready(Some(_))) = interval.poll() {
+            let (from_source, from_destination) = load(&self.counts);
+            for tap in &mut self.taps {
+                tap.bytes(from_source, from_destination);
+            }
+        }
+    }
+
+    fn close(&mut self, errno: Option<i32>) {
+        let (from_source, from_destination) = load(&self.counts);
+        for tap in self.taps.drain(..) {
+            tap.close(from_source, from_destination, errno);
+        }
+    }
+}
+
+impl<F, T: TapTcp> Drop for Connection<F, T> {
+    fn drop(&mut self) {
+        // The connection may be dropped before it completes, e.g. as the proxy
+        // shuts down.
+        self.close(None);
+    }
+}
+
+fn load(counts: &Option<Arc<Counts>>) -> (u64, u64) {
+    counts
+        .as_ref()
+        .map(|c| {
+            (
+                c.from_source.load(Ordering::Acquire),
+                c.from_destination.load(Ordering::Acquire),
+            )
+        })
+        .unwrap_or_default()
+}
+
+// === impl Io ===
+
+impl<I: io::Read> io::Read for Io<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.io.read(buf)?;
+        if let Some(ref counts) = self.counts {
+            counts.from_source.fetch_add(bytes as u64, Ordering::AcqRel);
+        }
+        Ok(bytes)
+    }
+}
+
+impl<I: io::Write> io::Write for Io<I> {
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes = self.io.write(buf)?;
+        self.record_write(bytes);
+        Ok(bytes)
+    }
+}
+
+impl<I: AsyncRead> AsyncRead for Io<I> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<I: AsyncWrite> AsyncWrite for Io<I> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        let bytes = try_ready!(self.io.write_buf(buf));
+        self.record_write(bytes);
+        Ok(Async::Ready(bytes))
+    }
+}
+
+impl<I> Io<I> {
+    fn record_write(&self, bytes: usize) {
+        if let Some(ref counts) = self.counts {
+            counts
+                .from_destination
+                .fetch_add(bytes as u64, Ordering::AcqRel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn counts_tapped_bytes() {
+        let counts = Arc::new(Counts::default());
+        let mut io = Io {
+            io: io::Cursor::new(b"hello".to_vec()),
+            counts: Some(counts.clone()),
+        };
+
+        let mut buf = [0u8; 3];
+        io.read_exact(&mut buf).unwrap();
+        io.write_all(b"hi").unwrap();
+        assert_eq!(load(&Some(counts)), (3, 2));
+        assert_eq!(load(&None), (0, 0));
+    }
+}
@@ -0,0 +1,161 @@
+use bytes::Buf;
+use futures::{future, Poll};
+use linkerd2_error::{Error, Never};
+use rand::{rngs::SmallRng, SeedableRng};
+use std::io;
+use tokio::io::{AsyncRead, AsyncWrite};
+pub use tower_balance::p2c::Balance;
+use tower_discover::Discover;
+pub use tower_load::{Instrument, Load, PendingRequestsDiscover};
+
+/// Configures a stack to balance connections over discovered endpoints.
+///
+/// Endpoints are selected by the number of connections they currently have
+/// open, so that long-lived connections are spread evenly over the endpoints.
+#[derive(Clone, Debug)]
+pub struct Layer {
+    rng: SmallRng,
+}
+
+/// Instruments connections to hold their load handle until they are dropped.
+#[derive(Clone, Debug, Default)]
+pub struct PendingUntilClose(());
+
+/// A connection that holds a load handle while it is open.
+#[derive(Debug)]
+pub struct PendingUntilCloseIo<H, I> {
+    io: I,
+    _handle: H,
+}
+
+/// Builds an endpoint service that establishes a new connection to the
+/// endpoint each time it is called.
+#[derive(Clone, Debug)]
+pub struct MakeConnect<C> {
+    connect: C,
+}
+
+#[derive(Clone, Debug)]
+pub struct Connect<C, T> {
+    connect: C,
+    target: T,
+}
+
+// === impl Layer ===
+
+pub fn layer() -> Layer {
+    Layer {
+        rng: SmallRng::from_entropy(),
+    }
+}
+
+impl<D, S> tower::layer::Layer<D> for Layer
+where
+    D: Discover<Service = S>,
+    S: tower::Service<()>,
+    S::Error: Into<Error>,
+    Balance<PendingRequestsDiscover<D, PendingUntilClose>, ()>: tower::Service<()>,
+{
+    type Service = Balance<PendingRequestsDiscover<D, PendingUntilClose>, ()>;
+
+    fn layer(&self, discover: D) -> Self::Service {
+        let instrument = PendingUntilClose::default();
+        let loaded = PendingRequestsDiscover::new(discover, instrument);
+        Balance::new(loaded, self.rng.clone())
+    }
+}
+
+// === impl PendingUntilClose ===
+
+impl<H, I> Instrument<H, I> for PendingUntilClose
+where
+    I: AsyncRead + AsyncWrite,
+{
+    type Output = PendingUntilCloseIo<H, I>;
+
+    fn instrument(&self, handle: H, io: I) -> Self::Output {
+        PendingUntilCloseIo {
+            io,
+            _handle: handle,
+        }
+    }
+}
+
+// === impl PendingUntilCloseIo ===
+
+impl<H, I: io::Read> io::Read for PendingUntilCloseIo<H, I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl<H, I: io::Write> io::Write for PendingUntilCloseIo<H, I> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<H, I: AsyncRead> AsyncRead for PendingUntilCloseIo<H, I> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl<H, I: AsyncWrite> AsyncWrite for PendingUntilCloseIo<H, I> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+
+    fn write_buf<B: Buf>(&mut self, buf: &mut B) -> Poll<usize, io::Error> {
+        self.io.write_buf(buf)
+    }
+}
+
+// === impl MakeConnect ===
+
+impl<C> MakeConnect<C> {
+    pub fn new(connect: C) -> Self {
+        Self { connect }
+    }
+}
+
+impl<C: Clone, T> tower::Service<T> for MakeConnect<C> {
+    type Response = Connect<C, T>;
+    type Error = Never;
+    type Future = future::FutureResult<Self::Response, Never>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        Ok(().into())
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        future::ok(Connect {
+            connect: self.connect.clone(),
+            target,
+        })
+    }
+}
+
+// === impl Connect ===
+
+impl<C, T> tower::Service<()> for Connect<C, T>
+where
+    C: tower::Service<T>,
+    T: Clone,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = C::Future;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.connect.poll_ready()
+    }
+
+    fn call(&mut self, (): ()) -> Self::Future {
+        self.connect.call(self.target.clone())
+    }
+}
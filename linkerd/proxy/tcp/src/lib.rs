@@ -1,5 +1,6 @@
 #![deny(warnings, rust_2018_idioms)]
 
+pub mod balance;
 pub mod forward;
 
 pub use self::forward::Forward;
@@ -0,0 +1,248 @@
+//! A layer that checks each request against an external rate limit service,
+//! implementing Envoy's `envoy.service.ratelimit.v3.RateLimitService` API,
+//! before the request is forwarded.
+//!
+//! Requests are described to the service by descriptors extracted from the
+//! request. If the service responds that the request is over its limit, the
+//! request fails with an `OverLimit` error. If the service cannot be reached
+//! in time, requests are handled according to the client's `FailureMode`.
+
+#![deny(warnings, rust_2018_idioms)]
+
+pub use envoy_proto as proto;
+use futures::{try_ready, Async, Future, Poll};
+use linkerd2_error::Error;
+pub use linkerd2_grpc_check::{Config, FailureMode, Unavailable};
+use linkerd2_grpc_check::{Method, ResponseFuture as CheckFuture};
+use std::sync::Arc;
+use std::{fmt, mem};
+use tracing::{debug, trace};
+
+pub type Descriptor = proto::envoy::extensions::common::ratelimit::v3::RateLimitDescriptor;
+pub type DescriptorEntry =
+    proto::envoy::extensions::common::ratelimit::v3::rate_limit_descriptor::Entry;
+
+use proto::envoy::service::ratelimit::v3::{
+    rate_limit_response::Code, RateLimitRequest, RateLimitResponse,
+};
+
+/// A rate limit service client.
+pub type Client = linkerd2_grpc_check::Client<RateLimitRequest, RateLimitResponse>;
+
+/// The rate limit service's `ShouldRateLimit` method.
+pub const SHOULD_RATE_LIMIT: Method<RateLimitRequest, RateLimitResponse> = Method::new(
+    "rate limit service",
+    "/envoy.service.ratelimit.v3.RateLimitService/ShouldRateLimit",
+);
+
+/// Builds the descriptors that describe a request to the rate limit service.
+pub trait ExtractDescriptors<Req> {
+    /// Returns the request's descriptors. Requests without descriptors are not
+    /// checked.
+    fn descriptors(&self, req: &Req) -> Vec<Descriptor>;
+}
+
+/// Wraps services so that requests are checked against the rate limit service.
+///
+/// If the layer is disabled, requests are forwarded without being checked.
+#[derive(Clone, Debug)]
+pub struct Layer<D> {
+    limit: Option<Limit<D>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Service<D, S> {
+    inner: S,
+    limit: Option<Limit<D>>,
+}
+
+pub struct ResponseFuture<S, Req>
+where
+    S: tower::Service<Req>,
+{
+    state: State<S, Req>,
+}
+
+/// Indicates that the rate limit service determined that a request is over
+/// its limit.
+#[derive(Debug)]
+pub struct OverLimit(());
+
+#[derive(Debug)]
+struct Limit<D> {
+    client: Client,
+    /// Namespaces the descriptors in the rate limit service's configuration.
+    domain: Arc<str>,
+    descriptors: Arc<D>,
+}
+
+enum State<S, Req>
+where
+    S: tower::Service<Req>,
+{
+    Check {
+        check: CheckFuture<RateLimitResponse>,
+        forward: Option<(S, Req)>,
+    },
+    Forward(S::Future),
+}
+
+// === impl Layer ===
+
+impl<D> Layer<D> {
+    /// Checks requests with the given client, describing them by
+    /// `descriptors` in the given `domain`.
+    pub fn new(client: Client, domain: String, descriptors: D) -> Self {
+        Self {
+            limit: Some(Limit {
+                client,
+                domain: domain.into(),
+                descriptors: Arc::new(descriptors),
+            }),
+        }
+    }
+
+    /// Forwards requests without checking them.
+    pub fn disabled() -> Self {
+        Self { limit: None }
+    }
+}
+
+impl<D, S> tower::layer::Layer<S> for Layer<D> {
+    type Service = Service<D, S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            limit: self.limit.clone(),
+        }
+    }
+}
+
+// === impl Service ===
+
+impl<D, S, B> tower::Service<http::Request<B>> for Service<D, S>
+where
+    D: ExtractDescriptors<http::Request<B>>,
+    S: tower::Service<http::Request<B>> + Clone,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = ResponseFuture<S, http::Request<B>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.inner.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let limit = match self.limit {
+            Some(ref limit) => limit,
+            None => return ResponseFuture::forward(self.inner.call(req)),
+        };
+
+        let descriptors = limit.descriptors.descriptors(&req);
+        if descriptors.is_empty() {
+            trace!("No descriptors; skipping rate limit check");
+            return ResponseFuture::forward(self.inner.call(req));
+        }
+
+        let check = limit.client.check(RateLimitRequest {
+            domain: limit.domain.to_string(),
+            descriptors,
+            hits_addend: 0,
+        });
+
+        // The ready service is moved into the response future so that the
+        // request may be dispatched once it has been checked.
+        let inner = self.inner.clone();
+        let inner = mem::replace(&mut self.inner, inner);
+
+        ResponseFuture {
+            state: State::Check {
+                check,
+                forward: Some((inner, req)),
+            },
+        }
+    }
+}
+
+// === impl Limit ===
+
+impl<D> Clone for Limit<D> {
+    fn clone(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+            domain: self.domain.clone(),
+            descriptors: self.descriptors.clone(),
+        }
+    }
+}
+
+// === impl ResponseFuture ===
+
+impl<S, Req> ResponseFuture<S, Req>
+where
+    S: tower::Service<Req>,
+{
+    fn forward(future: S::Future) -> Self {
+        Self {
+            state: State::Forward(future),
+        }
+    }
+}
+
+impl<S, B> Future for ResponseFuture<S, http::Request<B>>
+where
+    S: tower::Service<http::Request<B>>,
+    S::Error: Into<Error>,
+{
+    type Item = S::Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                State::Check {
+                    ref mut check,
+                    ref mut forward,
+                } => {
+                    let headers = match try_ready!(check.poll()) {
+                        Some(rsp) => {
+                            if rsp.overall_code == Code::OverLimit as i32 {
+                                debug!("Over limit");
+                                return Err(OverLimit(()).into());
+                            }
+                            rsp.request_headers_to_add
+                        }
+                        None => Vec::new(),
+                    };
+
+                    let (mut svc, mut req) = forward.take().expect("polled after complete");
+                    for header in headers.into_iter() {
+                        let name = http::header::HeaderName::from_bytes(header.key.as_bytes());
+                        let value = http::header::HeaderValue::from_str(&header.value);
+                        if let (Ok(name), Ok(value)) = (name, value) {
+                            req.headers_mut().insert(name, value);
+                        }
+                    }
+                    State::Forward(svc.call(req))
+                }
+                State::Forward(ref mut future) => {
+                    let rsp = try_ready!(future.poll().map_err(Into::into));
+                    return Ok(Async::Ready(rsp));
+                }
+            };
+        }
+    }
+}
+
+// === impl OverLimit ===
+
+impl fmt::Display for OverLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate limit exceeded")
+    }
+}
+
+impl std::error::Error for OverLimit {}
@@ -0,0 +1,143 @@
+use super::Error;
+use futures::{future, try_ready, Async, Future, Poll};
+use std::mem;
+
+/// Like `RequestFilter`, but the decision may be made asynchronously, e.g. by
+/// an external service.
+pub trait AsyncRequestFilter<T> {
+    type Error: Into<Error>;
+    type Future: Future<Item = T, Error = Self::Error>;
+
+    fn filter(&self, request: T) -> Self::Future;
+}
+
+#[derive(Clone, Debug)]
+pub struct AsyncLayer<I> {
+    filter: I,
+}
+
+/// Dispatches each request to the inner service once it has been accepted by
+/// the filter.
+///
+/// The inner service is cloned for each request so that the ready service may
+/// be held while the request is filtered.
+#[derive(Clone, Debug)]
+pub struct AsyncService<I, S> {
+    filter: I,
+    service: S,
+}
+
+pub struct AsyncResponseFuture<F, S, T>
+where
+    S: tower::Service<T>,
+{
+    state: State<F, S, T>,
+}
+
+enum State<F, S, T>
+where
+    S: tower::Service<T>,
+{
+    Filter(F, Option<S>),
+    Future(S::Future),
+}
+
+// === impl AsyncRequestFilter ===
+
+/// Accepts all requests when no filter is configured.
+impl<T, I: AsyncRequestFilter<T>> AsyncRequestFilter<T> for Option<I> {
+    type Error = I::Error;
+    type Future = future::Either<I::Future, future::FutureResult<T, I::Error>>;
+
+    fn filter(&self, request: T) -> Self::Future {
+        match self {
+            Some(ref filter) => future::Either::A(filter.filter(request)),
+            None => future::Either::B(future::ok(request)),
+        }
+    }
+}
+
+// === impl AsyncLayer ===
+
+impl<I> AsyncLayer<I> {
+    pub fn new(filter: I) -> Self {
+        Self { filter }
+    }
+}
+
+impl<I: Clone, S> tower::layer::Layer<S> for AsyncLayer<I> {
+    type Service = AsyncService<I, S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        AsyncService::new(self.filter.clone(), service)
+    }
+}
+
+// === impl AsyncService ===
+
+impl<I, S> AsyncService<I, S> {
+    pub fn new(filter: I, service: S) -> Self {
+        Self { filter, service }
+    }
+}
+
+impl<T, I, S> tower::Service<T> for AsyncService<I, S>
+where
+    I: AsyncRequestFilter<T>,
+    S: tower::Service<T> + Clone,
+    S::Error: Into<Error>,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = AsyncResponseFuture<I::Future, S, T>;
+
+    #[inline]
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        self.service.poll_ready().map_err(Into::into)
+    }
+
+    fn call(&mut self, request: T) -> Self::Future {
+        let service = self.service.clone();
+        let service = mem::replace(&mut self.service, service);
+        AsyncResponseFuture {
+            state: State::Filter(self.filter.filter(request), Some(service)),
+        }
+    }
+}
+
+// === impl AsyncResponseFuture ===
+
+impl<F, S, T> Future for AsyncResponseFuture<F, S, T>
+where
+    F: Future<Item = T>,
+    F::Error: Into<Error>,
+    S: tower::Service<T>,
+    S::Error: Into<Error>,
+{
+    type Item = S::Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                State::Filter(ref mut filter, ref mut service) => {
+                    let request = match filter.poll() {
+                        Ok(Async::Ready(request)) => request,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(e) => {
+                            tracing::trace!("rejected");
+                            return Err(e.into());
+                        }
+                    };
+                    tracing::trace!("accepted");
+                    let mut service = service.take().expect("polled after complete");
+                    State::Future(service.call(request))
+                }
+                State::Future(ref mut future) => {
+                    let rsp = try_ready!(future.poll().map_err(Into::into));
+                    return Ok(Async::Ready(rsp));
+                }
+            };
+        }
+    }
+}
@@ -1,10 +1,17 @@
 //! A `Service` middleware that applies arbitrary-user provided logic to each
 //! target before it is issued to an inner service.
+//!
+//! `RequestFilter`s decide synchronously; `AsyncRequestFilter`s may consult
+//! another service before deciding.
 
 #![deny(warnings, rust_2018_idioms)]
 
 use futures::{Future, Poll};
 
+mod async_filter;
+
+pub use self::async_filter::{AsyncLayer, AsyncRequestFilter, AsyncResponseFuture, AsyncService};
+
 pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
 pub trait RequestFilter<T> {
@@ -13,6 +20,11 @@ pub trait RequestFilter<T> {
     fn filter(&self, request: T) -> Result<T, Self::Error>;
 }
 
+#[derive(Clone, Debug)]
+pub struct Layer<I> {
+    filter: I,
+}
+
 #[derive(Clone, Debug)]
 pub struct Service<I, S> {
     filter: I,
@@ -25,6 +37,36 @@ pub enum ResponseFuture<F> {
     Rejected(Option<Error>),
 }
 
+// === impl RequestFilter ===
+
+/// Accepts all requests when no filter is configured.
+impl<T, I: RequestFilter<T>> RequestFilter<T> for Option<I> {
+    type Error = I::Error;
+
+    fn filter(&self, request: T) -> Result<T, Self::Error> {
+        match self {
+            Some(ref filter) => filter.filter(request),
+            None => Ok(request),
+        }
+    }
+}
+
+// === impl Layer ===
+
+impl<I> Layer<I> {
+    pub fn new(filter: I) -> Self {
+        Self { filter }
+    }
+}
+
+impl<I: Clone, S> tower::layer::Layer<S> for Layer<I> {
+    type Service = Service<I, S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        Service::new(self.filter.clone(), service)
+    }
+}
+
 // === impl Service ===
 
 impl<I, S> Service<I, S> {
@@ -3,6 +3,8 @@
 use futures::{Future, Poll};
 use linkerd2_error::Error;
 use linkerd2_stack::{NewService, Proxy, ProxyService};
+use linkerd2_timeout::Timeout;
+use std::time::Duration;
 pub use tower::retry::{budget::Budget, Policy};
 use tower::util::{Oneshot, ServiceExt};
 use tracing::trace;
@@ -14,6 +16,20 @@ pub trait NewPolicy<T> {
     fn new_policy(&self, target: &T) -> Option<Self::Policy>;
 }
 
+/// Prepares a request to be dispatched by a retry policy.
+///
+/// This allows a policy to modify a request (e.g. by wrapping its body) so
+/// that it may be cloned for retries. A policy is obtained for each request
+/// so that it may track per-request state.
+pub trait PrepareRequest<Req>: Sized {
+    fn prepare_request(&self, req: Req) -> (Self, Req);
+}
+
+/// Determines the timeout applied to each attempt of a retryable request.
+pub trait PerTryTimeout {
+    fn per_try_timeout(&self) -> Option<Duration>;
+}
+
 /// A layer that applies per-target retry polcies.
 ///
 /// Composes `NewService`s that produce a `Proxy`.
@@ -42,7 +58,7 @@ where
     S::Error: Into<Error>,
 {
     Disabled(P::Future),
-    Retry(Oneshot<tower::retry::Retry<R, ProxyService<P, S>>, Req>),
+    Retry(Oneshot<tower::retry::Retry<R, ProxyService<Timeout<P>, S>>, Req>),
 }
 
 // === impl NewRetryLayer ===
@@ -86,7 +102,7 @@ where
 
 impl<R, P, Req, S> Proxy<Req, S> for Retry<R, P>
 where
-    R: tower::retry::Policy<Req, P::Response, Error> + Clone,
+    R: tower::retry::Policy<Req, P::Response, Error> + PrepareRequest<Req> + PerTryTimeout + Clone,
     P: Proxy<Req, S> + Clone,
     S: tower::Service<P::Request> + Clone,
     S::Error: Into<Error>,
@@ -100,8 +116,14 @@ where
         trace!(retryable = %self.policy.is_some());
 
         if let Some(policy) = self.policy.as_ref() {
-            let inner = self.inner.clone().wrap_service(svc.clone());
-            let retry = tower::retry::Retry::new(policy.clone(), inner);
+            let (policy, req) = policy.prepare_request(req);
+            // Each attempt is bounded by the policy's per-try timeout, if any.
+            let inner = match policy.per_try_timeout() {
+                Some(timeout) => Timeout::new(self.inner.clone(), timeout),
+                None => Timeout::passthru(self.inner.clone()),
+            };
+            let inner = inner.wrap_service(svc.clone());
+            let retry = tower::retry::Retry::new(policy, inner);
             return ResponseFuture::Retry(retry.oneshot(req));
         }
 
@@ -1,4 +1,5 @@
 use crate::http as profiles;
+use crate::policy::{Policy, PolicyReceiver};
 use futures::{try_ready, Async, Future, Poll, Stream};
 use http;
 use linkerd2_addr::{Addr, NameAddr};
@@ -22,6 +23,7 @@ pub struct Client<S, R> {
     initial_timeout: Duration,
     context_token: String,
     suffixes: Vec<dns::Suffix>,
+    policies: PolicyReceiver,
 }
 
 pub type Receiver = watch::Receiver<profiles::Routes>;
@@ -66,6 +68,12 @@ where
     recover: R,
     state: State<S, R::Backoff>,
     request: api::GetDestination,
+    dst: NameAddr,
+    policies: PolicyReceiver,
+    policy: Option<Arc<Policy>>,
+    /// The last profile received from the destination service, with its retry
+    /// budget, before the local policy is applied.
+    received: Option<(profiles::Routes, Option<Arc<Budget>>)>,
 }
 
 enum State<S, B>
@@ -102,6 +110,7 @@ where
         initial_timeout: Duration,
         context_token: String,
         suffixes: impl IntoIterator<Item = dns::Suffix>,
+        policies: PolicyReceiver,
     ) -> Self {
         Self {
             service: api::client::Destination::new(service),
@@ -109,6 +118,7 @@ where
             initial_timeout,
             context_token,
             suffixes: suffixes.into_iter().collect(),
+            policies,
         }
     }
 }
@@ -164,11 +174,16 @@ where
 
         let timeout = Delay::new(Instant::now() + self.initial_timeout);
 
+        let policy = self.policies.get_ref().get(&dst);
         let inner = Inner {
             service,
             request,
             recover: self.recover.clone(),
             state: State::Disconnected { backoff: None },
+            dst,
+            policies: self.policies.clone(),
+            policy,
+            received: None,
         };
         ProfileFuture {
             inner: ProfileFutureInner::Pending(Some(inner), timeout),
@@ -203,7 +218,7 @@ where
                         }
 
                         info!("Using default service profile after timeout");
-                        profiles::Routes::default()
+                        inner.as_ref().expect("polled after ready").profile()
                     }
                     Ok(Async::Ready(profile)) => profile,
                 };
@@ -232,7 +247,7 @@ where
 {
     fn poll_rx(
         rx: &mut grpc::Streaming<api::DestinationProfile, S::ResponseBody>,
-    ) -> Poll<Option<profiles::Routes>, grpc::Status> {
+    ) -> Poll<Option<(profiles::Routes, Option<Arc<Budget>>)>, grpc::Status> {
         trace!("poll");
         let profile = try_ready!(rx.poll()).map(|proto| {
             debug!("profile received: {:?}", proto);
@@ -240,21 +255,58 @@ where
             let routes = proto
                 .routes
                 .into_iter()
-                .filter_map(move |orig| convert_route(orig, retry_budget.as_ref()))
+                .filter_map(|orig| convert_route(orig, retry_budget.as_ref()))
                 .collect();
             let dst_overrides = proto
                 .dst_overrides
                 .into_iter()
                 .filter_map(convert_dst_override)
                 .collect();
-            profiles::Routes {
+            // The destination API does not describe a destination's
+            // conditional overrides, load balancer, or health check, so these
+            // may only be configured by a local policy.
+            let profile = profiles::Routes {
                 routes,
+                dst_matches: Vec::new(),
                 dst_overrides,
-            }
+                load_balancer: profiles::LoadBalancer::default(),
+                health_check: None,
+            };
+            (profile, retry_budget)
         });
         Ok(profile.into())
     }
 
+    /// Returns the last profile received from the destination service (or a
+    /// default profile), with the local policy applied.
+    fn profile(&self) -> profiles::Routes {
+        let (profile, retry_budget) = match self.received {
+            Some((ref profile, ref retry_budget)) => (profile.clone(), retry_budget.as_ref()),
+            None => (profiles::Routes::default(), None),
+        };
+        match self.policy {
+            Some(ref policy) => policy.apply(profile, retry_budget),
+            None => profile,
+        }
+    }
+
+    /// Returns an updated profile if the destination's policy has changed
+    /// since the policies were last polled.
+    fn poll_policy(&mut self) -> Option<profiles::Routes> {
+        let policy = match self.policies.poll_ref() {
+            Ok(Async::Ready(Some(policies))) => policies.get(&self.dst),
+            // The policies have not been reloaded or can no longer be reloaded.
+            Ok(Async::NotReady) | Ok(Async::Ready(None)) | Err(_) => return None,
+        };
+        if Policy::is_same(&policy, &self.policy) {
+            return None;
+        }
+
+        debug!("policy changed");
+        self.policy = policy;
+        Some(self.profile())
+    }
+
     fn poll_profile(&mut self) -> Poll<profiles::Routes, Error> {
         let span = info_span!("poll_profile");
         let _enter = span.enter();
@@ -293,7 +345,10 @@ where
                     trace!("streaming");
                     let status = match Self::poll_rx(s) {
                         Ok(Async::NotReady) => return Ok(Async::NotReady),
-                        Ok(Async::Ready(Some(profile))) => return Ok(profile.into()),
+                        Ok(Async::Ready(Some(received))) => {
+                            self.received = Some(received);
+                            return Ok(self.profile().into());
+                        }
                         Ok(Async::Ready(None)) => grpc::Status::new(grpc::Code::Ok, ""),
                         Err(status) => status,
                     };
@@ -338,6 +393,16 @@ where
                 }
             }
 
+            // Profiles are republished when the destination's policy changes.
+            if let Some(profile) = self.inner.poll_policy() {
+                trace!(?profile, "publishing");
+                if self.tx.broadcast(profile).is_err() {
+                    trace!("failed to publish profile");
+                    return Ok(().into());
+                }
+                continue;
+            }
+
             let profile = match self.inner.poll_profile() {
                 Ok(Async::NotReady) => return Ok(Async::NotReady),
                 Ok(Async::Ready(profile)) => profile,
@@ -388,7 +453,10 @@ fn convert_dst_override(orig: api::WeightedDst) -> Option<profiles::WeightedAddr
         })
 }
 
-fn set_route_retry(route: &mut profiles::Route, retry_budget: Option<&Arc<Budget>>) {
+// The destination API does not describe retryable error categories, per-try
+// timeouts, backoffs, hedging, rate limits, faults, or mirrors, so these may
+// only be configured by a local policy.
+pub(crate) fn set_route_retry(route: &mut profiles::Route, retry_budget: Option<&Arc<Budget>>) {
     let budget = match retry_budget {
         Some(budget) => budget.clone(),
         None => {
@@ -411,6 +479,8 @@ fn set_route_timeout(route: &mut profiles::Route, timeout: Result<Duration, Dura
     }
 }
 
+// The destination API does not describe authority, header, or query-parameter
+// matches, so these may only be configured by a local policy.
 fn convert_req_match(orig: api::RequestMatch) -> Option<profiles::RequestMatch> {
     let m = match orig.r#match? {
         api::request_match::Match::All(ms) => {
@@ -426,17 +496,7 @@ fn convert_req_match(orig: api::RequestMatch) -> Option<profiles::RequestMatch>
             profiles::RequestMatch::Not(Box::new(m))
         }
         api::request_match::Match::Path(api::PathMatch { regex }) => {
-            let regex = regex.trim();
-            let re = match (regex.starts_with('^'), regex.ends_with('$')) {
-                (true, true) => Regex::new(regex).ok()?,
-                (hd_anchor, tl_anchor) => {
-                    let hd = if hd_anchor { "" } else { "^" };
-                    let tl = if tl_anchor { "" } else { "$" };
-                    let re = format!("{}{}{}", hd, regex, tl);
-                    Regex::new(&re).ok()?
-                }
-            };
-            profiles::RequestMatch::Path(re)
+            profiles::RequestMatch::Path(convert_regex(&regex)?)
         }
         api::request_match::Match::Method(mm) => {
             let m = mm.r#type.and_then(|m| m.try_as_http().ok())?;
@@ -447,11 +507,27 @@ fn convert_req_match(orig: api::RequestMatch) -> Option<profiles::RequestMatch>
     Some(m)
 }
 
+/// Parses a regular expression that must match its entire input.
+pub(crate) fn convert_regex(regex: &str) -> Option<Regex> {
+    let regex = regex.trim();
+    match (regex.starts_with('^'), regex.ends_with('$')) {
+        (true, true) => Regex::new(regex).ok(),
+        (hd_anchor, tl_anchor) => {
+            let hd = if hd_anchor { "" } else { "^" };
+            let tl = if tl_anchor { "" } else { "$" };
+            let re = format!("{}{}{}", hd, regex, tl);
+            Regex::new(&re).ok()
+        }
+    }
+}
+
 fn convert_rsp_class(orig: api::ResponseClass) -> Option<profiles::ResponseClass> {
     let c = orig.condition.and_then(convert_rsp_match)?;
     Some(profiles::ResponseClass::new(orig.is_failure, c))
 }
 
+// The destination API does not describe header, trailer, or gRPC status
+// matches, so these may only be configured by a local policy.
 fn convert_rsp_match(orig: api::ResponseMatch) -> Option<profiles::ResponseMatch> {
     let m = match orig.r#match? {
         api::response_match::Match::All(ms) => {
@@ -490,7 +566,7 @@ fn convert_rsp_match(orig: api::ResponseMatch) -> Option<profiles::ResponseMatch
     Some(m)
 }
 
-fn convert_retry_budget(orig: api::RetryBudget) -> Option<Arc<Budget>> {
+pub(crate) fn convert_retry_budget(orig: api::RetryBudget) -> Option<Arc<Budget>> {
     let min_retries = if orig.min_retries_per_second <= ::std::i32::MAX as u32 {
         orig.min_retries_per_second
     } else {
@@ -1,40 +1,63 @@
-use super::{OverrideDestination, WeightedAddr};
-use futures::{future, Async, Future, Poll};
+use super::{
+    HealthCheck, LoadBalancer, MatchedAddr, OverrideDestination, OverrideHealthCheck,
+    OverrideLoadBalancer, WeightedAddr,
+};
+use futures::{try_ready, Async, Future, Poll};
 use linkerd2_addr::NameAddr;
 use linkerd2_error::Error;
 use rand::distributions::{Distribution, WeightedIndex};
 use rand::rngs::SmallRng;
+use std::sync::Arc;
 use tokio::sync::watch;
 pub use tokio::sync::watch::error::SendError;
+use tower::util::{Oneshot, ServiceExt};
 use tracing::{debug, trace};
 
 pub fn default<M>(make: M, rng: SmallRng) -> (Service<M>, Update) {
     let routes = Routes::Forward(None);
-    let (tx, rx) = watch::channel(routes.clone());
+    let load_balancer = LoadBalancer::default();
+    let (tx, rx) = watch::channel((Vec::new(), routes.clone(), load_balancer.clone(), None));
     let concrete = Service {
         make,
+        matches: Arc::new(Vec::new()),
         routes: routes.clone(),
+        load_balancer: load_balancer.clone(),
+        health_check: None,
         updates: rx.clone(),
         rng,
     };
-    let update = Update { tx, routes };
+    let update = Update {
+        tx,
+        matches: Vec::new(),
+        routes,
+        load_balancer,
+        health_check: None,
+    };
     (concrete, update)
 }
 
 #[derive(Clone, Debug)]
 pub struct Service<M> {
     make: M,
+    matches: Arc<Vec<MatchedAddr>>,
     routes: Routes,
-    updates: watch::Receiver<Routes>,
+    load_balancer: LoadBalancer,
+    health_check: Option<HealthCheck>,
+    updates: watch::Receiver<Settings>,
     rng: SmallRng,
 }
 
 #[derive(Debug)]
 pub struct Update {
+    matches: Vec<MatchedAddr>,
     routes: Routes,
-    tx: watch::Sender<Routes>,
+    load_balancer: LoadBalancer,
+    health_check: Option<HealthCheck>,
+    tx: watch::Sender<Settings>,
 }
 
+type Settings = (Vec<MatchedAddr>, Routes, LoadBalancer, Option<HealthCheck>);
+
 #[derive(Clone)]
 enum Routes {
     Forward(Option<NameAddr>),
@@ -64,23 +87,72 @@ impl std::fmt::Debug for Routes {
     }
 }
 
-impl<T, S> tower::Service<T> for Service<S>
+/// The service for a target.
+///
+/// If the profile has conditional overrides, they are evaluated as each request
+/// is dispatched, and a service is obtained for the request's concrete
+/// destination. Otherwise, the target's service is used directly.
+pub enum Concrete<M, T>
+where
+    M: tower::Service<T>,
+{
+    Target(M::Response),
+    Matched(Matched<M, T>),
+}
+
+/// Evaluates conditional overrides against each request.
+#[derive(Clone, Debug)]
+pub struct Matched<M, T> {
+    make: M,
+    target: T,
+    matches: Arc<Vec<MatchedAddr>>,
+}
+
+pub enum ResponseFuture<M, T>
+where
+    M: tower::Service<T>,
+{
+    Make(M::Future),
+    Matched(Option<Matched<M, T>>),
+}
+
+pub enum CallFuture<M, T, Req>
 where
-    T: OverrideDestination,
-    S: tower::Service<T>,
-    S::Error: Into<Error>,
+    M: tower::Service<T>,
+    M::Response: tower::Service<Req>,
 {
-    type Response = S::Response;
+    Target(<M::Response as tower::Service<Req>>::Future),
+    Make(Oneshot<M, T>, Option<Req>),
+    Call(Oneshot<M::Response, Req>),
+}
+
+impl<T, M> tower::Service<T> for Service<M>
+where
+    T: OverrideDestination + OverrideLoadBalancer + OverrideHealthCheck,
+    M: tower::Service<T> + Clone,
+    M::Error: Into<Error>,
+{
+    type Response = Concrete<M, T>;
     type Error = Error;
-    type Future = future::MapErr<S::Future, fn(S::Error) -> Self::Error>;
+    type Future = ResponseFuture<M, T>;
 
     fn poll_ready(&mut self) -> Poll<(), Self::Error> {
         loop {
             match self.updates.poll_ref().map_err(Error::from)? {
                 Async::NotReady | Async::Ready(None) => break,
-                Async::Ready(Some(routes)) => {
-                    debug!(?routes, "updated");
-                    self.routes = (*routes).clone();
+                Async::Ready(Some(update)) => {
+                    let (ref matches, ref routes, ref load_balancer, ref health_check) = *update;
+                    debug!(
+                        matches = matches.len(),
+                        ?routes,
+                        ?load_balancer,
+                        ?health_check,
+                        "updated"
+                    );
+                    self.matches = Arc::new(matches.clone());
+                    self.routes = routes.clone();
+                    self.load_balancer = load_balancer.clone();
+                    self.health_check = health_check.clone();
                 }
             }
         }
@@ -89,6 +161,9 @@ where
     }
 
     fn call(&mut self, mut target: T) -> Self::Future {
+        *target.load_balancer_mut() = self.load_balancer.clone();
+        *target.health_check_mut() = self.health_check.clone();
+
         match self.routes {
             Routes::Forward(None) => {}
             Routes::Forward(Some(ref addr)) => {
@@ -105,11 +180,115 @@ where
             }
         }
 
-        self.make.call(target).map_err(Into::into)
+        // Conditional overrides take precedence over the weighted split, so
+        // they are evaluated against each request before it is dispatched.
+        if !self.matches.is_empty() {
+            return ResponseFuture::Matched(Some(Matched {
+                make: self.make.clone(),
+                target,
+                matches: self.matches.clone(),
+            }));
+        }
+
+        ResponseFuture::Make(self.make.call(target))
+    }
+}
+
+impl<M, T> Future for ResponseFuture<M, T>
+where
+    M: tower::Service<T>,
+    M::Error: Into<Error>,
+{
+    type Item = Concrete<M, T>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self {
+            ResponseFuture::Make(ref mut make) => {
+                let svc = try_ready!(make.poll().map_err(Into::into));
+                Ok(Async::Ready(Concrete::Target(svc)))
+            }
+            ResponseFuture::Matched(ref mut matched) => {
+                let matched = matched.take().expect("polled after ready");
+                Ok(Async::Ready(Concrete::Matched(matched)))
+            }
+        }
+    }
+}
+
+impl<M, T, B> tower::Service<http::Request<B>> for Concrete<M, T>
+where
+    T: OverrideDestination + Clone,
+    M: tower::Service<T> + Clone,
+    M::Error: Into<Error>,
+    M::Response: tower::Service<http::Request<B>>,
+    <M::Response as tower::Service<http::Request<B>>>::Error: Into<Error>,
+{
+    type Response = <M::Response as tower::Service<http::Request<B>>>::Response;
+    type Error = Error;
+    type Future = CallFuture<M, T, http::Request<B>>;
+
+    /// When conditional overrides are evaluated, readiness is determined as
+    /// each request is dispatched, since a service is obtained for each
+    /// request's concrete destination.
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        match self {
+            Concrete::Target(ref mut svc) => svc.poll_ready().map_err(Into::into),
+            Concrete::Matched(_) => Ok(Async::Ready(())),
+        }
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let matched = match self {
+            Concrete::Target(ref mut svc) => return CallFuture::Target(svc.call(req)),
+            Concrete::Matched(ref matched) => matched,
+        };
+
+        let mut target = matched.target.clone();
+        if let Some(m) = matched.matches.iter().find(|m| m.condition.is_match(&req)) {
+            debug!(addr = %m.addr, "Using matched override");
+            *target.dst_mut() = m.addr.clone().into();
+        }
+        CallFuture::Make(matched.make.clone().oneshot(target), Some(req))
+    }
+}
+
+impl<M, T, Req> Future for CallFuture<M, T, Req>
+where
+    M: tower::Service<T>,
+    M::Error: Into<Error>,
+    M::Response: tower::Service<Req>,
+    <M::Response as tower::Service<Req>>::Error: Into<Error>,
+{
+    type Item = <M::Response as tower::Service<Req>>::Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            *self = match self {
+                CallFuture::Target(ref mut call) => return call.poll().map_err(Into::into),
+                CallFuture::Make(ref mut make, ref mut req) => {
+                    let svc = try_ready!(make.poll().map_err(Into::into));
+                    let req = req.take().expect("polled after ready");
+                    CallFuture::Call(svc.oneshot(req))
+                }
+                CallFuture::Call(ref mut call) => return call.poll().map_err(Into::into),
+            };
+        }
     }
 }
 
 impl Update {
+    pub fn set_matches(&mut self, matches: Vec<MatchedAddr>) -> Result<(), error::LostService> {
+        if self.matches.is_empty() && matches.is_empty() {
+            trace!("no matched overrides");
+            return Ok(());
+        }
+
+        self.matches = matches;
+        self.broadcast()
+    }
+
     pub fn set_forward(&mut self) -> Result<(), error::LostService> {
         if let Routes::Forward(None) = self.routes {
             trace!("default forward already set");
@@ -119,9 +298,7 @@ impl Update {
         trace!("building default forward");
         self.routes = Routes::Forward(None);
 
-        self.tx
-            .broadcast(self.routes.clone())
-            .map_err(|_| error::LostService(()))
+        self.broadcast()
     }
 
     pub fn set_split(&mut self, mut addrs: Vec<WeightedAddr>) -> Result<(), error::LostService> {
@@ -160,9 +337,44 @@ impl Update {
             }
         };
 
-        self.routes = routes.clone();
+        self.routes = routes;
+        self.broadcast()
+    }
+
+    pub fn set_load_balancer(
+        &mut self,
+        load_balancer: LoadBalancer,
+    ) -> Result<(), error::LostService> {
+        if self.load_balancer == load_balancer {
+            trace!(?load_balancer, "load balancer already set");
+            return Ok(());
+        }
+
+        self.load_balancer = load_balancer;
+        self.broadcast()
+    }
+
+    pub fn set_health_check(
+        &mut self,
+        health_check: Option<HealthCheck>,
+    ) -> Result<(), error::LostService> {
+        if self.health_check == health_check {
+            trace!(?health_check, "health check already set");
+            return Ok(());
+        }
+
+        self.health_check = health_check;
+        self.broadcast()
+    }
+
+    fn broadcast(&mut self) -> Result<(), error::LostService> {
         self.tx
-            .broadcast(routes)
+            .broadcast((
+                self.matches.clone(),
+                self.routes.clone(),
+                self.load_balancer.clone(),
+                self.health_check.clone(),
+            ))
             .map_err(|_| error::LostService(()))
     }
 }
@@ -179,3 +391,176 @@ pub mod error {
 
     impl std::error::Error for LostService {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::{RequestMatch, ValueMatch};
+    use super::*;
+    use futures::future;
+    use linkerd2_addr::Addr;
+    use rand::SeedableRng;
+
+    #[derive(Clone)]
+    struct Target {
+        dst: Addr,
+        load_balancer: LoadBalancer,
+        health_check: Option<HealthCheck>,
+    }
+
+    /// Builds a `Dst` service for each target.
+    #[derive(Clone)]
+    struct MakeDst;
+
+    /// Responds to each request with the target's concrete destination.
+    struct Dst(Addr);
+
+    impl OverrideDestination for Target {
+        fn dst_mut(&mut self) -> &mut Addr {
+            &mut self.dst
+        }
+    }
+
+    impl OverrideLoadBalancer for Target {
+        fn load_balancer_mut(&mut self) -> &mut LoadBalancer {
+            &mut self.load_balancer
+        }
+    }
+
+    impl OverrideHealthCheck for Target {
+        fn health_check_mut(&mut self) -> &mut Option<HealthCheck> {
+            &mut self.health_check
+        }
+    }
+
+    impl tower::Service<Target> for MakeDst {
+        type Response = Dst;
+        type Error = Error;
+        type Future = future::FutureResult<Dst, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, target: Target) -> Self::Future {
+            future::ok(Dst(target.dst))
+        }
+    }
+
+    impl tower::Service<http::Request<()>> for Dst {
+        type Response = Addr;
+        type Error = Error;
+        type Future = future::FutureResult<Addr, Error>;
+
+        fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+            Ok(Async::Ready(()))
+        }
+
+        fn call(&mut self, _: http::Request<()>) -> Self::Future {
+            future::ok(self.0.clone())
+        }
+    }
+
+    fn addr(s: &str) -> NameAddr {
+        NameAddr::from_str(s).unwrap()
+    }
+
+    fn target() -> Target {
+        Target {
+            dst: addr("svc.ns.svc.cluster.local:80").into(),
+            load_balancer: LoadBalancer::default(),
+            health_check: None,
+        }
+    }
+
+    fn request(canary: bool) -> http::Request<()> {
+        let mut req = http::Request::builder();
+        if canary {
+            req.header("x-canary", "true");
+        }
+        req.body(()).unwrap()
+    }
+
+    #[test]
+    fn matches_before_split() {
+        let (mut svc, mut update) = default(MakeDst, SmallRng::seed_from_u64(0));
+        update
+            .set_matches(vec![MatchedAddr {
+                condition: RequestMatch::Header {
+                    name: http::header::HeaderName::from_static("x-canary"),
+                    value: ValueMatch::Exact("true".into()),
+                },
+                addr: addr("svc-v2.ns.svc.cluster.local:80"),
+            }])
+            .unwrap();
+        update
+            .set_split(vec![
+                WeightedAddr {
+                    addr: addr("svc-v1.ns.svc.cluster.local:80"),
+                    weight: 1,
+                },
+                WeightedAddr {
+                    addr: addr("svc-v2.ns.svc.cluster.local:80"),
+                    weight: 0,
+                },
+            ])
+            .unwrap();
+
+        future::lazy(move || {
+            assert!(tower::Service::poll_ready(&mut svc).unwrap().is_ready());
+            for _ in 0..10 {
+                let mut concrete = tower::Service::call(&mut svc, target()).wait().unwrap();
+                let canary = tower::Service::call(&mut concrete, request(true))
+                    .wait()
+                    .unwrap();
+                assert_eq!(canary, Addr::from(addr("svc-v2.ns.svc.cluster.local:80")));
+                let split = tower::Service::call(&mut concrete, request(false))
+                    .wait()
+                    .unwrap();
+                assert_eq!(split, Addr::from(addr("svc-v1.ns.svc.cluster.local:80")));
+            }
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+
+    #[test]
+    fn evaluates_requests_only_with_matches() {
+        let (mut svc, mut update) = default(MakeDst, SmallRng::seed_from_u64(0));
+
+        future::lazy(move || {
+            // Without conditional overrides, requests are dispatched directly
+            // to the target's service.
+            assert!(tower::Service::poll_ready(&mut svc).unwrap().is_ready());
+            match tower::Service::call(&mut svc, target()).wait().unwrap() {
+                Concrete::Target(_) => {}
+                Concrete::Matched(_) => panic!("requests must not be evaluated"),
+            }
+
+            update
+                .set_matches(vec![MatchedAddr {
+                    condition: RequestMatch::Header {
+                        name: http::header::HeaderName::from_static("x-canary"),
+                        value: ValueMatch::Exact("true".into()),
+                    },
+                    addr: addr("svc-v2.ns.svc.cluster.local:80"),
+                }])
+                .unwrap();
+            assert!(tower::Service::poll_ready(&mut svc).unwrap().is_ready());
+            match tower::Service::call(&mut svc, target()).wait().unwrap() {
+                Concrete::Matched(_) => {}
+                Concrete::Target(_) => panic!("requests must be evaluated"),
+            }
+
+            update.set_matches(Vec::new()).unwrap();
+            assert!(tower::Service::poll_ready(&mut svc).unwrap().is_ready());
+            match tower::Service::call(&mut svc, target()).wait().unwrap() {
+                Concrete::Target(_) => {}
+                Concrete::Matched(_) => panic!("requests must not be evaluated"),
+            }
+            Ok::<(), ()>(())
+        })
+        .wait()
+        .unwrap();
+    }
+}
@@ -3,6 +3,7 @@ use http;
 use indexmap::IndexMap;
 use linkerd2_addr::{Addr, NameAddr};
 use linkerd2_error::Error;
+pub use linkerd2_exp_backoff::ExponentialBackoff;
 use regex::Regex;
 use std::fmt;
 use std::hash::{Hash, Hasher};
@@ -25,10 +26,76 @@ pub struct WeightedAddr {
     pub weight: u32,
 }
 
+/// Routes requests that match a condition to a destination, e.g. so that
+/// requests with an `x-canary: true` header are sent to a new version of a
+/// service.
+#[derive(Clone, Debug)]
+pub struct MatchedAddr {
+    pub condition: RequestMatch,
+    pub addr: NameAddr,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Routes {
     pub routes: Vec<(RequestMatch, Route)>,
+    /// Evaluated in order before `dst_overrides`. The first matching entry's
+    /// destination is used regardless of the weighted split.
+    pub dst_matches: Vec<MatchedAddr>,
     pub dst_overrides: Vec<WeightedAddr>,
+    pub load_balancer: LoadBalancer,
+    pub health_check: Option<HealthCheck>,
+}
+
+/// Determines how requests are distributed over a destination's endpoints.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LoadBalancer {
+    /// Requests are dispatched to the least-loaded of two randomly-selected
+    /// endpoints.
+    PeakEwma,
+    /// Requests with the same hash key are dispatched to the same endpoint, so
+    /// long as it is available.
+    ConsistentHash(HashKey),
+    /// Responses set the named cookie to identify the endpoint that served
+    /// them, and requests that carry the cookie are dispatched to that
+    /// endpoint, so long as it is available. Requests without the cookie are
+    /// dispatched as with `PeakEwma`.
+    StickyCookie(String),
+}
+
+/// The part of a request that is hashed to select an endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    Header(http::header::HeaderName),
+    Cookie(String),
+    /// The original client's IP address, i.e. the first `x-forwarded-for`
+    /// entry. Requests without it are not hashed.
+    SourceIp,
+}
+
+/// Configures active health checking of a destination's endpoints.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HealthCheck {
+    pub probe: HealthProbe,
+    /// The interval between probes of each endpoint.
+    pub interval: Duration,
+    /// The time after which a probe is considered to have failed.
+    pub timeout: Duration,
+    /// The number of consecutive failed probes after which a healthy endpoint
+    /// is considered unhealthy.
+    pub unhealthy_threshold: u32,
+    /// The number of consecutive successful probes after which an unhealthy
+    /// endpoint is considered healthy.
+    pub healthy_threshold: u32,
+}
+
+/// The request issued to probe an endpoint's health.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HealthProbe {
+    /// A `GET` request to the given path, which succeeds with a 2XX status.
+    Http { path: String },
+    /// A `grpc.health.v1.Health/Check` request for the given service, which
+    /// succeeds if the service is `SERVING`.
+    Grpc { service: String },
 }
 
 pub type Receiver = watch::Receiver<Routes>;
@@ -78,6 +145,16 @@ pub trait OverrideDestination {
     fn dst_mut(&mut self) -> &mut Addr;
 }
 
+/// Implemented by target types that can have their load balancer changed.
+pub trait OverrideLoadBalancer {
+    fn load_balancer_mut(&mut self) -> &mut LoadBalancer;
+}
+
+/// Implemented by target types that can have their health check changed.
+pub trait OverrideHealthCheck {
+    fn health_check_mut(&mut self) -> &mut Option<HealthCheck>;
+}
+
 /// Implemented by target types that may have a `NameAddr` destination that
 /// can be discovered via `GetRoutes`.
 pub trait HasDestination {
@@ -89,7 +166,11 @@ pub struct Route {
     labels: Labels,
     response_classes: ResponseClasses,
     retries: Option<Retries>,
+    hedge: Option<Hedge>,
     timeout: Option<Duration>,
+    rate_limit: Option<RateLimit>,
+    fault: Option<Fault>,
+    mirror: Option<Mirror>,
 }
 
 #[derive(Clone, Debug)]
@@ -99,6 +180,28 @@ pub enum RequestMatch {
     Not(Box<RequestMatch>),
     Path(Regex),
     Method(http::Method),
+    /// Matches the request's authority, as determined by its URI or,
+    /// otherwise, its `host` header.
+    Authority(Regex),
+    Header {
+        name: http::header::HeaderName,
+        value: ValueMatch,
+    },
+    /// Matches a query parameter. Parameters are compared without
+    /// percent-decoding.
+    Query {
+        name: String,
+        value: ValueMatch,
+    },
+}
+
+/// Matches a header or query-parameter value.
+#[derive(Clone, Debug)]
+pub enum ValueMatch {
+    /// Matches if the value is present, regardless of its contents.
+    Present,
+    Exact(String),
+    Regex(Regex),
 }
 
 #[derive(Clone, Debug)]
@@ -119,11 +222,138 @@ pub enum ResponseMatch {
         min: http::StatusCode,
         max: http::StatusCode,
     },
+    Header {
+        name: http::header::HeaderName,
+        value: ValueMatch,
+    },
+    /// Matches a trailer. This can only be evaluated once the response
+    /// stream has completed.
+    Trailer {
+        name: http::header::HeaderName,
+        value: ValueMatch,
+    },
+    /// Matches the response's `grpc-status`, whether it is sent as a header
+    /// or as a trailer.
+    GrpcStatus(u32),
 }
 
 #[derive(Clone, Debug)]
 pub struct Retries {
     budget: Arc<Budget>,
+    errors: RetryErrors,
+    per_try_timeout: Option<Duration>,
+    backoff: Option<ExponentialBackoff>,
+}
+
+/// The categories of errors on which a request may be retried.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RetryErrors {
+    /// Retry when a connection to the endpoint is refused while the request is
+    /// dispatched.
+    ///
+    /// Only HTTP/1 connections are established as requests are dispatched.
+    /// When an HTTP/2 connection cannot be established, the endpoint is
+    /// unavailable, so the balancer dispatches requests to other endpoints.
+    pub connect_refused: bool,
+    /// Retry when the connection is reset, or the stream is refused with
+    /// `REFUSED_STREAM`, before response headers are received.
+    pub reset: bool,
+    /// Retry when the stream is reset with one of these HTTP/2 error codes
+    /// before response headers are received.
+    pub reset_codes: ResetCodes,
+    /// Retry when an attempt exceeds its per-try timeout.
+    pub timeout: bool,
+}
+
+/// A set of HTTP/2 error codes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ResetCodes(u16);
+
+/// Configures requests on a route to be hedged, i.e. so that a second copy of
+/// a slow request is issued.
+///
+/// Hedged requests are withdrawn from the route's retry budget, so hedging is
+/// only enabled on routes that are retryable. A hedged request is not
+/// guaranteed to be sent to a different endpoint than the original request.
+#[derive(Copy, Clone, Debug)]
+pub struct Hedge {
+    percentile: f64,
+}
+
+/// Limits the rate of requests on a route.
+///
+/// When both limits are set, a request must be admitted by each of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RateLimit {
+    /// Limits all requests on the route.
+    pub total: Option<TokenBucket>,
+    /// Limits the requests of each client identity on the route.
+    /// Unauthenticated clients share a single bucket.
+    pub per_client: Option<TokenBucket>,
+}
+
+/// Admits a request for each token in the bucket. The bucket holds at most
+/// `max_tokens` and is refilled with `tokens_per_fill` every `fill_interval`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TokenBucket {
+    pub max_tokens: u32,
+    pub tokens_per_fill: u32,
+    pub fill_interval: Duration,
+}
+
+/// Injects faults into a portion of a route's requests, i.e. for chaos
+/// testing.
+///
+/// When both faults are set, a request may be delayed and then aborted.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Fault {
+    pub delay: Option<FaultDelay>,
+    pub abort: Option<FaultAbort>,
+}
+
+/// Delays a percentage (on `[0, 100]`) of requests before they are
+/// dispatched.
+#[derive(Copy, Clone, Debug)]
+pub struct FaultDelay {
+    pub percentage: f64,
+    pub duration: DelayDuration,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DelayDuration {
+    Fixed(Duration),
+    /// Delays each request by a duration selected uniformly from
+    /// `[min, max]`.
+    Random {
+        min: Duration,
+        max: Duration,
+    },
+}
+
+/// Fails a percentage (on `[0, 100]`) of requests with the given status,
+/// without a response from the destination.
+#[derive(Copy, Clone, Debug)]
+pub struct FaultAbort {
+    pub percentage: f64,
+    pub status: AbortStatus,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AbortStatus {
+    Http(http::StatusCode),
+    /// A `grpc-status` code.
+    Grpc(u32),
+}
+
+/// Mirrors a percentage (on `[0, 100]`) of a route's requests to another
+/// destination, e.g. to exercise a new version of a service.
+///
+/// Like a `dst_overrides` entry, the destination is used as the concrete
+/// target of the mirrored requests. Their responses are discarded.
+#[derive(Clone, Debug)]
+pub struct Mirror {
+    pub addr: NameAddr,
+    pub percentage: f64,
 }
 
 #[derive(Clone, Default)]
@@ -146,7 +376,11 @@ impl Route {
             labels,
             response_classes: ResponseClasses(response_classes.into()),
             retries: None,
+            hedge: None,
             timeout: None,
+            rate_limit: None,
+            fault: None,
+            mirror: None,
         }
     }
 
@@ -162,17 +396,61 @@ impl Route {
         self.retries.as_ref()
     }
 
+    pub fn retries_mut(&mut self) -> Option<&mut Retries> {
+        self.retries.as_mut()
+    }
+
+    pub fn hedge(&self) -> Option<&Hedge> {
+        self.hedge.as_ref()
+    }
+
     pub fn timeout(&self) -> Option<Duration> {
         self.timeout
     }
 
+    pub fn rate_limit(&self) -> Option<&RateLimit> {
+        self.rate_limit.as_ref()
+    }
+
+    pub fn fault(&self) -> Option<&Fault> {
+        self.fault.as_ref()
+    }
+
+    pub fn mirror(&self) -> Option<&Mirror> {
+        self.mirror.as_ref()
+    }
+
     pub fn set_retries(&mut self, budget: Arc<Budget>) {
-        self.retries = Some(Retries { budget });
+        self.retries = Some(Retries::new(budget));
+    }
+
+    pub fn set_hedge(&mut self, hedge: Hedge) {
+        self.hedge = Some(hedge);
     }
 
     pub fn set_timeout(&mut self, timeout: Duration) {
         self.timeout = Some(timeout);
     }
+
+    pub fn set_rate_limit(&mut self, rate_limit: RateLimit) {
+        self.rate_limit = Some(rate_limit);
+    }
+
+    pub fn set_fault(&mut self, fault: Fault) {
+        self.fault = Some(fault);
+    }
+
+    pub fn set_mirror(&mut self, mirror: Mirror) {
+        self.mirror = Some(mirror);
+    }
+}
+
+// === impl LoadBalancer ===
+
+impl Default for LoadBalancer {
+    fn default() -> Self {
+        LoadBalancer::PeakEwma
+    }
 }
 
 // === impl RequestMatch ===
@@ -182,11 +460,64 @@ impl RequestMatch {
         match self {
             RequestMatch::Method(ref method) => req.method() == *method,
             RequestMatch::Path(ref re) => re.is_match(req.uri().path()),
+            RequestMatch::Authority(ref re) => Self::authority(req)
+                .map(|a| re.is_match(a))
+                .unwrap_or(false),
+            RequestMatch::Header {
+                ref name,
+                ref value,
+            } => value.is_header_match(req.headers(), name),
+            RequestMatch::Query {
+                ref name,
+                ref value,
+            } => req
+                .uri()
+                .query()
+                .into_iter()
+                .flat_map(|q| q.split('&'))
+                .filter_map(|param| {
+                    let mut kv = param.splitn(2, '=');
+                    let k = kv.next()?;
+                    if k == name {
+                        Some(kv.next().unwrap_or(""))
+                    } else {
+                        None
+                    }
+                })
+                .any(|v| value.is_match(v)),
             RequestMatch::Not(ref m) => !m.is_match(req),
             RequestMatch::All(ref ms) => ms.iter().all(|m| m.is_match(req)),
             RequestMatch::Any(ref ms) => ms.iter().any(|m| m.is_match(req)),
         }
     }
+
+    fn authority<B>(req: &http::Request<B>) -> Option<&str> {
+        req.uri().authority_part().map(|a| a.as_str()).or_else(|| {
+            req.headers()
+                .get(http::header::HOST)
+                .and_then(|h| h.to_str().ok())
+        })
+    }
+}
+
+// === impl ValueMatch ===
+
+impl ValueMatch {
+    fn is_match(&self, value: &str) -> bool {
+        match self {
+            ValueMatch::Present => true,
+            ValueMatch::Exact(ref v) => v == value,
+            ValueMatch::Regex(ref re) => re.is_match(value),
+        }
+    }
+
+    fn is_header_match(&self, headers: &http::HeaderMap, name: &http::header::HeaderName) -> bool {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .any(|v| self.is_match(v))
+    }
 }
 
 // === impl ResponseClass ===
@@ -200,8 +531,27 @@ impl ResponseClass {
         self.is_failure
     }
 
-    pub fn is_match<B>(&self, req: &http::Response<B>) -> bool {
-        self.match_.is_match(req)
+    /// Returns true if the class matches the response's headers.
+    ///
+    /// Matches that depend on trailers never match.
+    pub fn is_match<B>(&self, rsp: &http::Response<B>) -> bool {
+        self.match_.is_match(rsp.status(), rsp.headers(), None)
+    }
+
+    /// Returns true if the class matches a completed response.
+    pub fn is_match_eos(
+        &self,
+        status: http::StatusCode,
+        headers: &http::HeaderMap,
+        trailers: Option<&http::HeaderMap>,
+    ) -> bool {
+        self.match_.is_match(status, headers, trailers)
+    }
+
+    /// Returns true if the class can only be matched once the response's
+    /// trailers are available.
+    pub fn requires_eos(&self) -> bool {
+        self.match_.requires_eos()
     }
 }
 
@@ -238,29 +588,139 @@ impl fmt::Debug for ResponseClasses {
 // === impl ResponseMatch ===
 
 impl ResponseMatch {
-    fn is_match<B>(&self, req: &http::Response<B>) -> bool {
+    fn is_match(
+        &self,
+        status: http::StatusCode,
+        headers: &http::HeaderMap,
+        trailers: Option<&http::HeaderMap>,
+    ) -> bool {
+        match self {
+            ResponseMatch::Status { ref min, ref max } => *min <= status && status <= *max,
+            ResponseMatch::Header {
+                ref name,
+                ref value,
+            } => value.is_header_match(headers, name),
+            ResponseMatch::Trailer {
+                ref name,
+                ref value,
+            } => trailers
+                .map(|t| value.is_header_match(t, name))
+                .unwrap_or(false),
+            ResponseMatch::GrpcStatus(code) => Self::grpc_status(headers)
+                .or_else(|| trailers.and_then(Self::grpc_status))
+                .map(|c| c == *code)
+                .unwrap_or(false),
+            ResponseMatch::Not(ref m) => !m.is_match(status, headers, trailers),
+            ResponseMatch::All(ref ms) => ms.iter().all(|m| m.is_match(status, headers, trailers)),
+            ResponseMatch::Any(ref ms) => ms.iter().any(|m| m.is_match(status, headers, trailers)),
+        }
+    }
+
+    fn requires_eos(&self) -> bool {
         match self {
-            ResponseMatch::Status { ref min, ref max } => {
-                *min <= req.status() && req.status() <= *max
+            ResponseMatch::Trailer { .. } | ResponseMatch::GrpcStatus(_) => true,
+            ResponseMatch::Status { .. } | ResponseMatch::Header { .. } => false,
+            ResponseMatch::Not(ref m) => m.requires_eos(),
+            ResponseMatch::All(ref ms) | ResponseMatch::Any(ref ms) => {
+                ms.iter().any(ResponseMatch::requires_eos)
+            }
+        }
+    }
+
+    fn grpc_status(headers: &http::HeaderMap) -> Option<u32> {
+        headers
+            .get("grpc-status")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|s| s.parse::<u32>().ok())
+    }
+}
+
+// === impl ResetCodes ===
+
+impl ResetCodes {
+    /// The names of the error codes defined by HTTP/2, indexed by code.
+    const NAMES: [&'static str; 14] = [
+        "NO_ERROR",
+        "PROTOCOL_ERROR",
+        "INTERNAL_ERROR",
+        "FLOW_CONTROL_ERROR",
+        "SETTINGS_TIMEOUT",
+        "STREAM_CLOSED",
+        "FRAME_SIZE_ERROR",
+        "REFUSED_STREAM",
+        "CANCEL",
+        "COMPRESSION_ERROR",
+        "CONNECT_ERROR",
+        "ENHANCE_YOUR_CALM",
+        "INADEQUATE_SECURITY",
+        "HTTP_1_1_REQUIRED",
+    ];
+
+    /// Adds the error code with the given name, returning false if the name
+    /// is unknown.
+    pub fn insert_name(&mut self, name: &str) -> bool {
+        match Self::NAMES.iter().position(|n| *n == name) {
+            Some(code) => {
+                self.0 |= 1 << code;
+                true
             }
-            ResponseMatch::Not(ref m) => !m.is_match(req),
-            ResponseMatch::All(ref ms) => ms.iter().all(|m| m.is_match(req)),
-            ResponseMatch::Any(ref ms) => ms.iter().any(|m| m.is_match(req)),
+            None => false,
         }
     }
+
+    pub fn contains(&self, code: u32) -> bool {
+        code < Self::NAMES.len() as u32 && self.0 & (1 << code) != 0
+    }
 }
 
 // === impl Retries ===
 
 impl Retries {
+    pub fn new(budget: Arc<Budget>) -> Self {
+        Self {
+            budget,
+            errors: RetryErrors::default(),
+            per_try_timeout: None,
+            backoff: None,
+        }
+    }
+
     pub fn budget(&self) -> &Arc<Budget> {
         &self.budget
     }
+
+    pub fn errors(&self) -> RetryErrors {
+        self.errors
+    }
+
+    pub fn per_try_timeout(&self) -> Option<Duration> {
+        self.per_try_timeout
+    }
+
+    pub fn backoff(&self) -> Option<&ExponentialBackoff> {
+        self.backoff.as_ref()
+    }
+
+    pub fn set_errors(&mut self, errors: RetryErrors) {
+        self.errors = errors;
+    }
+
+    pub fn set_per_try_timeout(&mut self, timeout: Duration) {
+        self.per_try_timeout = Some(timeout);
+    }
+
+    pub fn set_backoff(&mut self, backoff: ExponentialBackoff) {
+        self.backoff = Some(backoff);
+    }
 }
 
+// The backoff is not compared, as `ExponentialBackoff` is neither `Eq` nor
+// `Hash`; retries from distinct profile updates never share a budget.
 impl PartialEq for Retries {
     fn eq(&self, other: &Self) -> bool {
         Arc::ptr_eq(&self.budget, &other.budget)
+            && self.errors == other.errors
+            && self.per_try_timeout == other.per_try_timeout
     }
 }
 
@@ -269,6 +729,88 @@ impl Eq for Retries {}
 impl Hash for Retries {
     fn hash<H: Hasher>(&self, state: &mut H) {
         state.write_usize(Arc::as_ref(&self.budget) as *const _ as usize);
+        self.errors.hash(state);
+        self.per_try_timeout.hash(state);
+    }
+}
+
+// === impl Hedge ===
+
+impl Hedge {
+    /// Hedges requests that have not received a response within the given
+    /// percentile (on `(0, 100]`) of the route's response latencies.
+    pub fn new(percentile: f64) -> Self {
+        debug_assert!(percentile > 0.0 && percentile <= 100.0);
+        Self { percentile }
+    }
+
+    pub fn percentile(&self) -> f64 {
+        self.percentile
+    }
+}
+
+impl PartialEq for Hedge {
+    fn eq(&self, other: &Self) -> bool {
+        self.percentile.to_bits() == other.percentile.to_bits()
+    }
+}
+
+impl Eq for Hedge {}
+
+impl Hash for Hedge {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.percentile.to_bits().hash(state);
+    }
+}
+
+// === impl FaultDelay ===
+
+impl PartialEq for FaultDelay {
+    fn eq(&self, other: &Self) -> bool {
+        self.percentage.to_bits() == other.percentage.to_bits() && self.duration == other.duration
+    }
+}
+
+impl Eq for FaultDelay {}
+
+impl Hash for FaultDelay {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.percentage.to_bits().hash(state);
+        self.duration.hash(state);
+    }
+}
+
+// === impl FaultAbort ===
+
+impl PartialEq for FaultAbort {
+    fn eq(&self, other: &Self) -> bool {
+        self.percentage.to_bits() == other.percentage.to_bits() && self.status == other.status
+    }
+}
+
+impl Eq for FaultAbort {}
+
+impl Hash for FaultAbort {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.percentage.to_bits().hash(state);
+        self.status.hash(state);
+    }
+}
+
+// === impl Mirror ===
+
+impl PartialEq for Mirror {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr == other.addr && self.percentage.to_bits() == other.percentage.to_bits()
+    }
+}
+
+impl Eq for Mirror {}
+
+impl Hash for Mirror {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr.hash(state);
+        self.percentage.to_bits().hash(state);
     }
 }
 
@@ -293,3 +835,73 @@ impl fmt::Debug for Labels {
         self.0.fmt(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn req(uri: &str) -> http::Request<()> {
+        http::Request::builder()
+            .uri(uri)
+            .header("x-api-version", "v2")
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn header_match() {
+        let name = http::header::HeaderName::from_static("x-api-version");
+        let present = RequestMatch::Header {
+            name: name.clone(),
+            value: ValueMatch::Present,
+        };
+        let exact = RequestMatch::Header {
+            name: name.clone(),
+            value: ValueMatch::Exact("v1".into()),
+        };
+        let regex = RequestMatch::Header {
+            name,
+            value: ValueMatch::Regex(Regex::new("^v[23]$").unwrap()),
+        };
+
+        let r = req("http://foo.ns.svc.cluster.local/");
+        assert!(present.is_match(&r));
+        assert!(!exact.is_match(&r));
+        assert!(regex.is_match(&r));
+        assert!(RequestMatch::Not(Box::new(exact)).is_match(&r));
+    }
+
+    #[test]
+    fn authority_match() {
+        let m = RequestMatch::Authority(Regex::new(r"^foo\.ns\.").unwrap());
+        assert!(m.is_match(&req("http://foo.ns.svc.cluster.local/")));
+        assert!(!m.is_match(&req("http://bar.ns.svc.cluster.local/")));
+
+        let host = http::Request::builder()
+            .uri("/")
+            .header(http::header::HOST, "foo.ns.svc.cluster.local")
+            .body(())
+            .unwrap();
+        assert!(m.is_match(&host));
+    }
+
+    #[test]
+    fn query_match() {
+        let version = RequestMatch::Query {
+            name: "version".into(),
+            value: ValueMatch::Exact("2".into()),
+        };
+        let debug = RequestMatch::Query {
+            name: "debug".into(),
+            value: ValueMatch::Present,
+        };
+        let both = RequestMatch::All(vec![version.clone(), debug.clone()]);
+
+        assert!(version.is_match(&req("/users?debug&version=2")));
+        assert!(debug.is_match(&req("/users?debug&version=2")));
+        assert!(both.is_match(&req("/users?debug&version=2")));
+        assert!(!version.is_match(&req("/users?version=20")));
+        assert!(!both.is_match(&req("/users?version=2")));
+        assert!(RequestMatch::Any(vec![version, debug]).is_match(&req("/users?version=2")));
+    }
+}
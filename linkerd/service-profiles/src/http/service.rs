@@ -5,14 +5,19 @@
 //! target and it is used to get route profiles from ` GetRoutes` implementation.
 //!
 //! Each route uses a shared underlying concrete dst router.  The concrete dst
-//! router picks a concrete dst (NameAddr) from the profile's `dst_overrides` if
-//! they exist, or uses the router's target's addr if no `dst_overrides` exist.
+//! router picks a concrete dst (NameAddr) from the first of the profile's
+//! `dst_matches` that matches the request, or from its `dst_overrides` if they
+//! exist, or uses the router's target's addr if no `dst_overrides` exist.
 //! The concrete dst router uses the concrete dst as the target for the
-//! underlying stack.
+//! underlying stack, setting the profile's `load_balancer` and `health_check` on
+//! it.
 
 use super::concrete;
 use super::requests::Requests;
-use super::{GetRoutes, OverrideDestination, Route, Routes, WithRoute};
+use super::{
+    GetRoutes, OverrideDestination, OverrideHealthCheck, OverrideLoadBalancer, Route, Routes,
+    WithRoute,
+};
 use futures::{try_ready, Async, Future, Poll, Stream};
 use linkerd2_error::Error;
 use linkerd2_stack::{NewService, ProxyService};
@@ -262,6 +267,12 @@ where
         }
 
         if let Some(profile) = profile {
+            debug!(matches = profile.dst_matches.len(), "updating matches");
+            self.concrete
+                .update
+                .set_matches(profile.dst_matches)
+                .expect("both sides of the concrete updater must be held");
+
             if profile.dst_overrides.is_empty() {
                 self.concrete
                     .update
@@ -276,6 +287,15 @@ where
                     .expect("both sides of the concrete updater must be held");
             }
 
+            self.concrete
+                .update
+                .set_load_balancer(profile.load_balancer)
+                .expect("both sides of the concrete updater must be held");
+            self.concrete
+                .update
+                .set_health_check(profile.health_check)
+                .expect("both sides of the concrete updater must be held");
+
             debug!(routes = profile.routes.len(), "updating routes");
             self.requests.set_routes(profile.routes);
         }
@@ -361,11 +381,11 @@ impl<T, M: tower::Service<T>> tower::Service<T> for Forward<M> {
 
 impl<T, M> tower::Service<T> for Override<M>
 where
-    T: OverrideDestination,
-    M: tower::Service<T>,
+    T: OverrideDestination + OverrideLoadBalancer + OverrideHealthCheck,
+    M: tower::Service<T> + Clone,
     M::Error: Into<Error>,
 {
-    type Response = M::Response;
+    type Response = <concrete::Service<M> as tower::Service<T>>::Response;
     type Error = Error;
     type Future = <concrete::Service<M> as tower::Service<T>>::Future;
 
@@ -2,6 +2,8 @@
 
 mod client;
 mod http;
+mod policy;
 
 pub use self::client::*;
 pub use self::http::*;
+pub use self::policy::{InvalidPolicy, Policies, PolicyDaemon, PolicyReceiver};
@@ -0,0 +1,1321 @@
+//! Loads profile policies, which supplement the profiles served by the
+//! destination service with configuration that its API cannot describe.
+//!
+//! Policies are described by a JSON object that maps each destination's
+//! authority (e.g. `web.ns.svc.cluster.local:8080`) to an object with the
+//! following optional fields:
+//!
+//! * `routes` -- a list of routes, which are evaluated in order before the
+//!   destination service's routes. Each route has a request match
+//!   `condition` and, optionally, `labels` (an object of metrics labels),
+//!   `responseClasses` (a list of objects with a response match `condition`
+//!   and `isFailure`), `isRetryable`, `retries`, `hedge`, `rateLimit`,
+//!   `fault`, `mirror`, and `timeoutMs`.
+//! * `retries` -- configures the retries of a retryable route, with
+//!   `errors` (a list of the errors that may be retried: `connect-refused`,
+//!   `reset`, or `timeout`), `resetCodes` (a list of the names of additional
+//!   HTTP/2 error codes, e.g. `INTERNAL_ERROR`, with which a stream may be
+//!   reset to be retried; `reset` only retries `REFUSED_STREAM`),
+//!   `perTryTimeoutMs`, and `backoff` (an object with `minMs`, `maxMs`, and
+//!   `jitter`).
+//! * `hedge` -- configures a retryable route to hedge requests that have not
+//!   received a response within the `percentile` (on `(0, 100]`) of the
+//!   route's response latencies. Hedged requests are withdrawn from the retry
+//!   budget.
+//! * `rateLimit` -- limits the rate of a route's inbound requests with
+//!   `total` and/or `perClient` token buckets, each an object with
+//!   `maxTokens`, `tokensPerFill`, and `fillIntervalMs`.
+//! * `fault` -- injects faults into a route's outbound requests, with a
+//!   `delay` (an object with a `percentage` and either `fixedMs` or `minMs`
+//!   and `maxMs`) and/or an `abort` (an object with a `percentage` and either
+//!   `httpStatus` or `grpcStatus`). Percentages are on `[0, 100]`.
+//! * `mirror` -- copies a `percentage` (on `[0, 100]`) of a route's requests
+//!   without bodies to another destination's `authority` (e.g.
+//!   `web-canary.ns.svc.cluster.local:8080`). Mirrored responses are
+//!   discarded.
+//! * `dstMatches` -- a list of conditional overrides, each an object with a
+//!   request match `condition` and the `authority` (e.g.
+//!   `web-v2.ns.svc.cluster.local:8080`) of the destination to which matching
+//!   requests are sent. They are evaluated in order before the destination
+//!   service's weighted overrides.
+//! * `loadBalancer` -- an object with exactly one of the following fields:
+//!   * `consistentHash` -- hashes requests onto the destination's endpoints
+//!     by an object with exactly one of the following fields: `header` or
+//!     `cookie` (an object with a `name`), or `sourceIp` (an empty object),
+//!     which hashes the original client's address from the first
+//!     `x-forwarded-for` entry.
+//!   * `stickyCookie` -- pins clients to endpoints with the cookie `name`,
+//!     which responses set to identify the endpoint that served them.
+//! * `healthCheck` -- actively probes each of the destination's endpoints,
+//!   with a `probe` (an object with exactly one of `http`, an object with a
+//!   `path`, or `grpc`, an object with a `service`), and, optionally,
+//!   `intervalMs` (10000 by default), `timeoutMs` (1000 by default),
+//!   `unhealthyThreshold` (3 by default), and `healthyThreshold` (2 by
+//!   default).
+//! * `retryBudget` -- an object with `minRetriesPerSecond`, `retryRatio`, and
+//!   `ttlMs`. Retryable routes use the destination service's budget if this is
+//!   not set.
+//!
+//! A request match is an object with exactly one of the following fields:
+//!
+//! * `all`, `any` -- a list of request matches.
+//! * `not` -- a request match.
+//! * `path`, `authority` -- a regular expression that must match the entire
+//!   path or authority.
+//! * `method` -- an HTTP method.
+//! * `header`, `query` -- an object with a `name` and exactly one of
+//!   `present: true`, `exact` (a string), or `regex`.
+//!
+//! A response match is an object with exactly one of the following fields:
+//!
+//! * `all`, `any` -- a list of response matches.
+//! * `not` -- a response match.
+//! * `status` -- an object with the `min` and `max` status codes.
+//! * `header`, `trailer` -- an object with a `name` and exactly one of
+//!   `present: true`, `exact` (a string), or `regex`.
+//! * `grpcStatus` -- a `grpc-status` code, sent as a header or a trailer.
+//!
+//! Retryable routes are classified as soon as response headers are received,
+//! so their response classes may not use `trailer` or `grpcStatus` matches.
+//!
+//! Policies are a stopgap until the destination service's API describes these
+//! fields: they are configured per proxy rather than served by the control
+//! plane. The policy file is reloaded when its modification time changes, and
+//! profiles are updated as their destinations' policies change. If a reloaded
+//! file cannot be read or parsed, the previously loaded policies are retained.
+
+use crate::client::{convert_regex, convert_retry_budget, set_route_retry};
+use crate::http as profiles;
+use futures::{try_ready, Async, Future, Poll, Stream};
+use linkerd2_addr::NameAddr;
+use linkerd2_error::Error;
+use linkerd2_exp_backoff::ExponentialBackoff;
+use linkerd2_proxy_api::destination as api;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::watch;
+use tokio::timer::Interval;
+use tower::retry::budget::Budget;
+use tracing::{debug, info, warn};
+
+/// Profile policies, keyed by destination.
+#[derive(Clone, Debug, Default)]
+pub struct Policies(Arc<HashMap<(String, u16), Arc<Policy>>>);
+
+/// Receives profile policies as they are reloaded.
+pub type PolicyReceiver = watch::Receiver<Policies>;
+
+/// Reloads a profile policy file when it changes.
+///
+/// The file's modification time is checked at a fixed interval.
+pub struct PolicyDaemon {
+    path: PathBuf,
+    interval: Interval,
+    modified: SystemTime,
+    tx: watch::Sender<Policies>,
+}
+
+#[derive(Debug)]
+pub struct InvalidPolicy(String);
+
+#[derive(Debug, Default)]
+pub(crate) struct Policy {
+    /// The policy's JSON description, used to detect changes on reload.
+    json: Value,
+    routes: Vec<LocalRoute>,
+    retry_budget: Option<Arc<Budget>>,
+    dst_matches: Vec<profiles::MatchedAddr>,
+    load_balancer: Option<profiles::LoadBalancer>,
+    health_check: Option<profiles::HealthCheck>,
+}
+
+#[derive(Debug)]
+struct LocalRoute {
+    condition: profiles::RequestMatch,
+    route: profiles::Route,
+    /// Set if the route is retryable.
+    retries: Option<Retries>,
+}
+
+#[derive(Debug, Default)]
+struct Retries {
+    errors: profiles::RetryErrors,
+    per_try_timeout: Option<Duration>,
+    backoff: Option<ExponentialBackoff>,
+}
+
+// === impl Policies ===
+
+impl Policies {
+    /// Parses a JSON profile policy document.
+    pub fn from_json(json: &[u8]) -> Result<Self, InvalidPolicy> {
+        let doc: Value = serde_json::from_slice(json).map_err(|e| InvalidPolicy(e.to_string()))?;
+        let dsts = doc
+            .as_object()
+            .ok_or_else(|| InvalidPolicy("policies must be an object".into()))?;
+
+        let mut policies = HashMap::with_capacity(dsts.len());
+        for (authority, policy) in dsts {
+            let addr = NameAddr::from_str(authority)
+                .map_err(|_| InvalidPolicy(format!("invalid authority: {}", authority)))?;
+            let policy = Policy::from_json(policy)
+                .map_err(|InvalidPolicy(e)| InvalidPolicy(format!("{}: {}", authority, e)))?;
+            policies.insert(key(&addr), Arc::new(policy));
+        }
+        Ok(Policies(Arc::new(policies)))
+    }
+
+    /// Loads a profile policy file and returns a receiver of its policies,
+    /// with a daemon that reloads them when the file changes.
+    pub fn watch(
+        path: PathBuf,
+        interval: Duration,
+    ) -> Result<(PolicyReceiver, PolicyDaemon), Error> {
+        let (modified, policies) = Self::load(&path)?;
+        info!(path = %path.display(), dsts = policies.0.len(), "Loaded profile policies");
+        let (tx, rx) = watch::channel(policies);
+        let daemon = PolicyDaemon {
+            path,
+            interval: Interval::new_interval(interval),
+            modified,
+            tx,
+        };
+        Ok((rx, daemon))
+    }
+
+    /// Returns a receiver of policies that are never reloaded.
+    pub fn into_receiver(self) -> PolicyReceiver {
+        let (_, rx) = watch::channel(self);
+        rx
+    }
+
+    fn load(path: &Path) -> Result<(SystemTime, Self), Error> {
+        let read = |path: &Path| {
+            let modified = fs::metadata(path)?.modified()?;
+            fs::read(path).map(|json| (modified, json))
+        };
+        let (modified, json) =
+            read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        let policies = Self::from_json(&json)?;
+        Ok((modified, policies))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn get(&self, dst: &NameAddr) -> Option<Arc<Policy>> {
+        self.0.get(&key(dst)).cloned()
+    }
+}
+
+/// Names are compared case-insensitively and without a trailing dot.
+fn key(addr: &NameAddr) -> (String, u16) {
+    let name = addr.name().without_trailing_dot().to_ascii_lowercase();
+    (name, addr.port())
+}
+
+// === impl Policy ===
+
+impl Policy {
+    fn from_json(policy: &Value) -> Result<Self, InvalidPolicy> {
+        let routes = array(policy, "routes")?
+            .iter()
+            .map(LocalRoute::from_json)
+            .collect::<Result<Vec<_>, _>>()?;
+        let retry_budget = match policy.get("retryBudget") {
+            None => None,
+            Some(budget) => Some(retry_budget(budget)?),
+        };
+        let dst_matches = array(policy, "dstMatches")?
+            .iter()
+            .map(|m| {
+                let condition = req_match(required(m, "condition")?)?;
+                let addr = authority(m)?;
+                Ok(profiles::MatchedAddr { condition, addr })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let load_balancer = match policy.get("loadBalancer") {
+            None => None,
+            Some(lb) => Some(load_balancer(lb)?),
+        };
+        let health_check = match policy.get("healthCheck") {
+            None => None,
+            Some(hc) => Some(health_check(hc)?),
+        };
+        Ok(Self {
+            json: policy.clone(),
+            routes,
+            retry_budget,
+            dst_matches,
+            load_balancer,
+            health_check,
+        })
+    }
+
+    /// Indicates whether the policies are described identically.
+    pub(crate) fn is_same(a: &Option<Arc<Self>>, b: &Option<Arc<Self>>) -> bool {
+        a.as_ref().map(|p| &p.json) == b.as_ref().map(|p| &p.json)
+    }
+
+    /// Applies the policy to a profile obtained from the destination service.
+    pub(crate) fn apply(
+        &self,
+        mut profile: profiles::Routes,
+        retry_budget: Option<&Arc<Budget>>,
+    ) -> profiles::Routes {
+        let retry_budget = self.retry_budget.as_ref().or(retry_budget);
+        let routes = self
+            .routes
+            .iter()
+            .map(|r| r.to_route(retry_budget))
+            .chain(profile.routes.drain(..))
+            .collect();
+        profile.routes = routes;
+        if !self.dst_matches.is_empty() {
+            profile.dst_matches = self.dst_matches.clone();
+        }
+        if let Some(ref lb) = self.load_balancer {
+            profile.load_balancer = lb.clone();
+        }
+        if let Some(ref hc) = self.health_check {
+            profile.health_check = Some(hc.clone());
+        }
+        profile
+    }
+}
+
+// === impl PolicyDaemon ===
+
+impl Future for PolicyDaemon {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            match try_ready!(self.interval.poll().map_err(|e| warn!(%e, "Timer failed"))) {
+                Some(_) => {}
+                None => return Ok(Async::Ready(())),
+            }
+
+            let modified = match fs::metadata(&self.path).and_then(|m| m.modified()) {
+                Ok(modified) if modified != self.modified => modified,
+                _ => continue,
+            };
+            // Failures are not retried until the file changes again.
+            self.modified = modified;
+
+            match Policies::load(&self.path) {
+                Ok((_, policies)) => {
+                    info!(path = %self.path.display(), dsts = policies.0.len(), "Reloaded profile policies");
+                    if self.tx.broadcast(policies).is_err() {
+                        debug!("Profile policies no longer used");
+                        return Ok(Async::Ready(()));
+                    }
+                }
+                Err(error) => {
+                    warn!(path = %self.path.display(), %error, "Failed to reload profile policies");
+                }
+            }
+        }
+    }
+}
+
+// === impl LocalRoute ===
+
+impl LocalRoute {
+    fn from_json(json: &Value) -> Result<Self, InvalidPolicy> {
+        let condition = req_match(required(json, "condition")?)?;
+
+        let labels = match json.get("labels") {
+            None => Vec::new(),
+            Some(Value::Object(labels)) => labels
+                .iter()
+                .map(|(k, v)| match v.as_str() {
+                    Some(v) => Ok((k.clone(), v.to_owned())),
+                    None => Err(InvalidPolicy(format!("label `{}` must be a string", k))),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(InvalidPolicy("`labels` must be an object".into())),
+        };
+
+        let rsp_classes = array(json, "responseClasses")?
+            .iter()
+            .map(|class| {
+                let condition = rsp_match(required(class, "condition")?)?;
+                let is_failure = boolean(class, "isFailure")?;
+                Ok(profiles::ResponseClass::new(is_failure, condition))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut route = profiles::Route::new(labels.into_iter(), rsp_classes);
+        if let Some(timeout) = millis(json.get("timeoutMs"))? {
+            route.set_timeout(timeout);
+        }
+
+        let retries = match (boolean(json, "isRetryable")?, json.get("retries")) {
+            (false, None) => None,
+            (true, None) => Some(Retries::default()),
+            (true, Some(retries)) => Some(Retries::from_json(retries)?),
+            (false, Some(_)) => {
+                return Err(InvalidPolicy(
+                    "`retries` may only be set on retryable routes".into(),
+                ))
+            }
+        };
+
+        // Retries are decided as soon as a response's headers are received,
+        // so a retryable route may not classify responses by their trailers.
+        if retries.is_some() && route.response_classes().iter().any(|c| c.requires_eos()) {
+            return Err(InvalidPolicy(
+                "retryable routes may not classify responses by `trailer` or `grpcStatus`".into(),
+            ));
+        }
+
+        if let Some(hedge) = json.get("hedge") {
+            if retries.is_none() {
+                return Err(InvalidPolicy(
+                    "`hedge` may only be set on retryable routes".into(),
+                ));
+            }
+            let percentile = number(hedge, "percentile")?
+                .as_f64()
+                .filter(|p| *p > 0.0 && *p <= 100.0)
+                .ok_or_else(|| InvalidPolicy("`percentile` must be on (0, 100]".into()))?;
+            route.set_hedge(profiles::Hedge::new(percentile));
+        }
+
+        if let Some(limit) = json.get("rateLimit") {
+            route.set_rate_limit(rate_limit(limit)?);
+        }
+
+        if let Some(f) = json.get("fault") {
+            route.set_fault(fault(f)?);
+        }
+
+        if let Some(m) = json.get("mirror") {
+            route.set_mirror(mirror(m)?);
+        }
+
+        Ok(Self {
+            condition,
+            route,
+            retries,
+        })
+    }
+
+    fn to_route(
+        &self,
+        retry_budget: Option<&Arc<Budget>>,
+    ) -> (profiles::RequestMatch, profiles::Route) {
+        let mut route = self.route.clone();
+        if let Some(ref retries) = self.retries {
+            set_route_retry(&mut route, retry_budget);
+            if let Some(r) = route.retries_mut() {
+                r.set_errors(retries.errors);
+                if let Some(timeout) = retries.per_try_timeout {
+                    r.set_per_try_timeout(timeout);
+                }
+                if let Some(ref backoff) = retries.backoff {
+                    r.set_backoff(backoff.clone());
+                }
+            }
+        }
+        (self.condition.clone(), route)
+    }
+}
+
+// === impl Retries ===
+
+impl Retries {
+    fn from_json(retries: &Value) -> Result<Self, InvalidPolicy> {
+        let mut errors = profiles::RetryErrors::default();
+        for error in array(retries, "errors")? {
+            match error.as_str() {
+                Some("connect-refused") => errors.connect_refused = true,
+                Some("reset") => errors.reset = true,
+                Some("timeout") => errors.timeout = true,
+                _ => return Err(InvalidPolicy(format!("unknown retry error: {}", error))),
+            }
+        }
+        for code in array(retries, "resetCodes")? {
+            let known = code.as_str().map(|c| errors.reset_codes.insert_name(c));
+            if known != Some(true) {
+                return Err(InvalidPolicy(format!("unknown error code: {}", code)));
+            }
+        }
+
+        let backoff = match retries.get("backoff") {
+            None => None,
+            Some(backoff) => {
+                let min = millis(backoff.get("minMs"))?.unwrap_or_default();
+                let max = millis(backoff.get("maxMs"))?.unwrap_or_default();
+                let jitter = match backoff.get("jitter") {
+                    None => 0.0,
+                    Some(j) => j
+                        .as_f64()
+                        .ok_or_else(|| InvalidPolicy(format!("invalid jitter: {}", j)))?,
+                };
+                let backoff = ExponentialBackoff::new(min, max, jitter)
+                    .map_err(|e| InvalidPolicy(format!("invalid backoff: {:?}", e)))?;
+                Some(backoff)
+            }
+        };
+
+        Ok(Self {
+            errors,
+            per_try_timeout: millis(retries.get("perTryTimeoutMs"))?,
+            backoff,
+        })
+    }
+}
+
+fn req_match(m: &Value) -> Result<profiles::RequestMatch, InvalidPolicy> {
+    let m = match single(m, "request match")? {
+        ("all", ms) => profiles::RequestMatch::All(list(ms, req_match)?),
+        ("any", ms) => profiles::RequestMatch::Any(list(ms, req_match)?),
+        ("not", m) => profiles::RequestMatch::Not(Box::new(req_match(m)?)),
+        ("path", re) => profiles::RequestMatch::Path(regex(re)?),
+        ("authority", re) => profiles::RequestMatch::Authority(regex(re)?),
+        ("method", method) => {
+            let method = method
+                .as_str()
+                .and_then(|m| http::Method::from_bytes(m.as_bytes()).ok())
+                .ok_or_else(|| InvalidPolicy(format!("invalid method: {}", method)))?;
+            profiles::RequestMatch::Method(method)
+        }
+        ("header", m) => profiles::RequestMatch::Header {
+            name: header_name(m)?,
+            value: value_match(m)?,
+        },
+        ("query", m) => profiles::RequestMatch::Query {
+            name: string(m, "name")?,
+            value: value_match(m)?,
+        },
+        (kind, _) => return Err(InvalidPolicy(format!("unknown request match: {}", kind))),
+    };
+    Ok(m)
+}
+
+fn rsp_match(m: &Value) -> Result<profiles::ResponseMatch, InvalidPolicy> {
+    let m = match single(m, "response match")? {
+        ("all", ms) => profiles::ResponseMatch::All(list(ms, rsp_match)?),
+        ("any", ms) => profiles::ResponseMatch::Any(list(ms, rsp_match)?),
+        ("not", m) => profiles::ResponseMatch::Not(Box::new(rsp_match(m)?)),
+        ("status", range) => profiles::ResponseMatch::Status {
+            min: status(range, "min")?,
+            max: status(range, "max")?,
+        },
+        ("header", m) => profiles::ResponseMatch::Header {
+            name: header_name(m)?,
+            value: value_match(m)?,
+        },
+        ("trailer", m) => profiles::ResponseMatch::Trailer {
+            name: header_name(m)?,
+            value: value_match(m)?,
+        },
+        ("grpcStatus", code) => profiles::ResponseMatch::GrpcStatus(grpc_status(code)?),
+        (kind, _) => return Err(InvalidPolicy(format!("unknown response match: {}", kind))),
+    };
+    Ok(m)
+}
+
+fn rate_limit(limit: &Value) -> Result<profiles::RateLimit, InvalidPolicy> {
+    let bucket = |name: &str| -> Result<Option<profiles::TokenBucket>, InvalidPolicy> {
+        let bucket = match limit.get(name) {
+            None => return Ok(None),
+            Some(b) => b,
+        };
+        let count = |name: &str| -> Result<u32, InvalidPolicy> {
+            number(bucket, name)?
+                .as_u64()
+                .and_then(|n| u32::try_from(n).ok())
+                .filter(|n| *n > 0)
+                .ok_or_else(|| InvalidPolicy(format!("invalid `{}`", name)))
+        };
+        Ok(Some(profiles::TokenBucket {
+            max_tokens: count("maxTokens")?,
+            tokens_per_fill: count("tokensPerFill")?,
+            fill_interval: millis(bucket.get("fillIntervalMs"))?
+                .ok_or_else(|| InvalidPolicy("missing `fillIntervalMs`".into()))?,
+        }))
+    };
+    let limit = profiles::RateLimit {
+        total: bucket("total")?,
+        per_client: bucket("perClient")?,
+    };
+    if limit.total.is_none() && limit.per_client.is_none() {
+        return Err(InvalidPolicy(
+            "`rateLimit` must have `total` or `perClient`".into(),
+        ));
+    }
+    Ok(limit)
+}
+
+fn fault(fault: &Value) -> Result<profiles::Fault, InvalidPolicy> {
+    let delay = match fault.get("delay") {
+        None => None,
+        Some(delay) => {
+            let duration = match (
+                millis(delay.get("fixedMs"))?,
+                millis(delay.get("minMs"))?,
+                millis(delay.get("maxMs"))?,
+            ) {
+                (Some(fixed), None, None) => profiles::DelayDuration::Fixed(fixed),
+                (None, Some(min), Some(max)) if min <= max => {
+                    profiles::DelayDuration::Random { min, max }
+                }
+                _ => {
+                    return Err(InvalidPolicy(
+                        "`delay` must have either `fixedMs` or `minMs` and `maxMs`".into(),
+                    ))
+                }
+            };
+            Some(profiles::FaultDelay {
+                percentage: percentage(delay)?,
+                duration,
+            })
+        }
+    };
+
+    let abort = match fault.get("abort") {
+        None => None,
+        Some(abort) => {
+            let status = match (abort.get("httpStatus"), abort.get("grpcStatus")) {
+                (Some(_), None) => profiles::AbortStatus::Http(status(abort, "httpStatus")?),
+                (None, Some(code)) => profiles::AbortStatus::Grpc(grpc_status(code)?),
+                _ => {
+                    return Err(InvalidPolicy(
+                        "`abort` must have either `httpStatus` or `grpcStatus`".into(),
+                    ))
+                }
+            };
+            Some(profiles::FaultAbort {
+                percentage: percentage(abort)?,
+                status,
+            })
+        }
+    };
+
+    if delay.is_none() && abort.is_none() {
+        return Err(InvalidPolicy("`fault` must have `delay` or `abort`".into()));
+    }
+    Ok(profiles::Fault { delay, abort })
+}
+
+fn mirror(mirror: &Value) -> Result<profiles::Mirror, InvalidPolicy> {
+    Ok(profiles::Mirror {
+        addr: authority(mirror)?,
+        percentage: percentage(mirror)?,
+    })
+}
+
+fn authority(v: &Value) -> Result<NameAddr, InvalidPolicy> {
+    let authority = string(v, "authority")?;
+    NameAddr::from_str(&authority)
+        .map_err(|_| InvalidPolicy(format!("invalid authority: {}", authority)))
+}
+
+fn percentage(v: &Value) -> Result<f64, InvalidPolicy> {
+    number(v, "percentage")?
+        .as_f64()
+        .filter(|p| *p >= 0.0 && *p <= 100.0)
+        .ok_or_else(|| InvalidPolicy("`percentage` must be on [0, 100]".into()))
+}
+
+fn load_balancer(lb: &Value) -> Result<profiles::LoadBalancer, InvalidPolicy> {
+    match single(lb, "load balancer")? {
+        ("consistentHash", key) => {
+            let key = match single(key, "hash key")? {
+                ("header", h) => profiles::HashKey::Header(header_name(h)?),
+                ("cookie", c) => profiles::HashKey::Cookie(string(c, "name")?),
+                ("sourceIp", _) => profiles::HashKey::SourceIp,
+                (kind, _) => return Err(InvalidPolicy(format!("unknown hash key: {}", kind))),
+            };
+            Ok(profiles::LoadBalancer::ConsistentHash(key))
+        }
+        ("stickyCookie", c) => {
+            let name = string(c, "name")?;
+            let is_token = |c: char| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c);
+            if name.is_empty() || !name.chars().all(is_token) {
+                return Err(InvalidPolicy(format!("invalid cookie name: {}", name)));
+            }
+            Ok(profiles::LoadBalancer::StickyCookie(name))
+        }
+        (kind, _) => Err(InvalidPolicy(format!("unknown load balancer: {}", kind))),
+    }
+}
+
+fn health_check(hc: &Value) -> Result<profiles::HealthCheck, InvalidPolicy> {
+    let probe = match single(required(hc, "probe")?, "probe")? {
+        ("http", p) => profiles::HealthProbe::Http {
+            path: string(p, "path")?,
+        },
+        ("grpc", p) => profiles::HealthProbe::Grpc {
+            service: string(p, "service")?,
+        },
+        (kind, _) => return Err(InvalidPolicy(format!("unknown probe: {}", kind))),
+    };
+    let threshold = |name: &str, default: u32| match hc.get(name) {
+        None => Ok(default),
+        Some(t) => t
+            .as_u64()
+            .and_then(|t| u32::try_from(t).ok())
+            .filter(|t| *t > 0)
+            .ok_or_else(|| InvalidPolicy(format!("invalid `{}`: {}", name, t))),
+    };
+    Ok(profiles::HealthCheck {
+        probe,
+        interval: millis(hc.get("intervalMs"))?.unwrap_or(Duration::from_secs(10)),
+        timeout: millis(hc.get("timeoutMs"))?.unwrap_or(Duration::from_secs(1)),
+        unhealthy_threshold: threshold("unhealthyThreshold", 3)?,
+        healthy_threshold: threshold("healthyThreshold", 2)?,
+    })
+}
+
+fn value_match(m: &Value) -> Result<profiles::ValueMatch, InvalidPolicy> {
+    match (m.get("present"), m.get("exact"), m.get("regex")) {
+        (Some(Value::Bool(true)), None, None) => Ok(profiles::ValueMatch::Present),
+        (None, Some(Value::String(v)), None) => Ok(profiles::ValueMatch::Exact(v.clone())),
+        (None, None, Some(re)) => Ok(profiles::ValueMatch::Regex(regex(re)?)),
+        _ => Err(InvalidPolicy(
+            "value matches must have one of `present: true`, `exact`, or `regex`".into(),
+        )),
+    }
+}
+
+fn retry_budget(budget: &Value) -> Result<Arc<Budget>, InvalidPolicy> {
+    let min_retries_per_second = number(budget, "minRetriesPerSecond")?
+        .as_u64()
+        .filter(|n| *n <= u64::from(std::u32::MAX))
+        .ok_or_else(|| InvalidPolicy("invalid `minRetriesPerSecond`".into()))?;
+    let retry_ratio = number(budget, "retryRatio")?
+        .as_f64()
+        .ok_or_else(|| InvalidPolicy("invalid `retryRatio`".into()))?;
+    let ttl = millis(budget.get("ttlMs"))?;
+    let proto = api::RetryBudget {
+        min_retries_per_second: min_retries_per_second as u32,
+        retry_ratio: retry_ratio as f32,
+        ttl: ttl.map(Into::into),
+    };
+    convert_retry_budget(proto).ok_or_else(|| InvalidPolicy("invalid `retryBudget`".into()))
+}
+
+/// Returns the name and value of an object's only field.
+fn single<'v>(v: &'v Value, what: &str) -> Result<(&'v str, &'v Value), InvalidPolicy> {
+    match v.as_object() {
+        Some(obj) if obj.len() == 1 => {
+            let (k, v) = obj.iter().next().expect("object must have a field");
+            Ok((k.as_str(), v))
+        }
+        _ => Err(InvalidPolicy(format!(
+            "{} must have exactly one field",
+            what
+        ))),
+    }
+}
+
+fn list<T>(
+    v: &Value,
+    parse: impl Fn(&Value) -> Result<T, InvalidPolicy>,
+) -> Result<Vec<T>, InvalidPolicy> {
+    v.as_array()
+        .ok_or_else(|| InvalidPolicy(format!("expected a list: {}", v)))?
+        .iter()
+        .map(parse)
+        .collect()
+}
+
+fn required<'v>(v: &'v Value, name: &str) -> Result<&'v Value, InvalidPolicy> {
+    v.get(name)
+        .ok_or_else(|| InvalidPolicy(format!("missing `{}`", name)))
+}
+
+fn array<'v>(v: &'v Value, name: &str) -> Result<&'v [Value], InvalidPolicy> {
+    match v.get(name) {
+        None => Ok(&[][..]),
+        Some(Value::Array(vs)) => Ok(vs.as_slice()),
+        Some(_) => Err(InvalidPolicy(format!("`{}` must be a list", name))),
+    }
+}
+
+fn string(v: &Value, name: &str) -> Result<String, InvalidPolicy> {
+    required(v, name)?
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| InvalidPolicy(format!("`{}` must be a string", name)))
+}
+
+fn boolean(v: &Value, name: &str) -> Result<bool, InvalidPolicy> {
+    match v.get(name) {
+        None => Ok(false),
+        Some(Value::Bool(b)) => Ok(*b),
+        Some(_) => Err(InvalidPolicy(format!("`{}` must be a boolean", name))),
+    }
+}
+
+fn number<'v>(v: &'v Value, name: &str) -> Result<&'v serde_json::Number, InvalidPolicy> {
+    match required(v, name)? {
+        Value::Number(n) => Ok(n),
+        _ => Err(InvalidPolicy(format!("`{}` must be a number", name))),
+    }
+}
+
+fn millis(v: Option<&Value>) -> Result<Option<Duration>, InvalidPolicy> {
+    match v {
+        None => Ok(None),
+        Some(ms) => ms
+            .as_u64()
+            .map(|ms| Some(Duration::from_millis(ms)))
+            .ok_or_else(|| InvalidPolicy(format!("invalid duration: {}", ms))),
+    }
+}
+
+fn regex(re: &Value) -> Result<regex::Regex, InvalidPolicy> {
+    re.as_str()
+        .and_then(convert_regex)
+        .ok_or_else(|| InvalidPolicy(format!("invalid regex: {}", re)))
+}
+
+fn header_name(m: &Value) -> Result<http::header::HeaderName, InvalidPolicy> {
+    let name = string(m, "name")?;
+    http::header::HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| InvalidPolicy(format!("invalid header name: {}", name)))
+}
+
+fn status(range: &Value, name: &str) -> Result<http::StatusCode, InvalidPolicy> {
+    number(range, name)?
+        .as_u64()
+        .and_then(|s| u16::try_from(s).ok())
+        .and_then(|s| http::StatusCode::from_u16(s).ok())
+        .ok_or_else(|| InvalidPolicy(format!("invalid `{}` status", name)))
+}
+
+fn grpc_status(code: &Value) -> Result<u32, InvalidPolicy> {
+    code.as_u64()
+        .and_then(|c| u32::try_from(c).ok())
+        .ok_or_else(|| InvalidPolicy(format!("invalid gRPC status: {}", code)))
+}
+
+// === impl InvalidPolicy ===
+
+impl fmt::Display for InvalidPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid profile policy: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPolicy {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn policies(doc: Value) -> Result<Policies, InvalidPolicy> {
+        Policies::from_json(doc.to_string().as_bytes())
+    }
+
+    fn dst(s: &str) -> NameAddr {
+        NameAddr::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn parses_request_matches() {
+        let policies = policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "routes": [{
+                    "condition": { "all": [
+                        { "method": "GET" },
+                        { "authority": "web\\.ns\\..*" },
+                        { "header": { "name": "x-api-version", "regex": "v[23]" } },
+                        { "not": { "query": { "name": "debug", "present": true } } },
+                    ]},
+                    "labels": { "route": "users" },
+                    "responseClasses": [
+                        { "condition": { "status": { "min": 500, "max": 599 } }, "isFailure": true },
+                    ],
+                    "timeoutMs": 1000,
+                }],
+            },
+        }))
+        .unwrap();
+
+        assert!(policies.get(&dst("web.ns.svc.cluster.local:80")).is_none());
+        let policy = policies
+            .get(&dst("WEB.ns.svc.cluster.local:8080"))
+            .expect("policy must be found");
+
+        let profile = policy.apply(profiles::Routes::default(), None);
+        assert_eq!(profile.routes.len(), 1);
+        let (ref condition, ref route) = profile.routes[0];
+        match condition {
+            profiles::RequestMatch::All(ms) => assert_eq!(ms.len(), 4),
+            m => panic!("unexpected match: {:?}", m),
+        }
+        assert_eq!(
+            route.labels().get("route").map(String::as_str),
+            Some("users")
+        );
+        assert_eq!(route.response_classes().len(), 1);
+        assert_eq!(route.timeout(), Some(Duration::from_secs(1)));
+        assert!(route.retries().is_none());
+    }
+
+    #[test]
+    fn prepends_routes_with_budget() {
+        let policies = policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "routes": [{ "condition": { "path": "/users/.*" }, "isRetryable": true }],
+            },
+        }))
+        .unwrap();
+        let policy = policies.get(&dst("web.ns.svc.cluster.local:8080")).unwrap();
+
+        let budget = Arc::new(Budget::new(Duration::from_secs(10), 10, 0.2));
+        let mut profile = profiles::Routes::default();
+        profile.routes.push((
+            profiles::RequestMatch::Method(http::Method::GET),
+            profiles::Route::default(),
+        ));
+        let profile = policy.apply(profile, Some(&budget));
+        assert_eq!(profile.routes.len(), 2);
+        match profile.routes[0].0 {
+            profiles::RequestMatch::Path(ref re) => assert_eq!(re.as_str(), "^/users/.*$"),
+            ref m => panic!("unexpected match: {:?}", m),
+        }
+        let retries = profile.routes[0]
+            .1
+            .retries()
+            .expect("route must be retryable");
+        assert!(Arc::ptr_eq(retries.budget(), &budget));
+    }
+
+    #[test]
+    fn parses_response_matches() {
+        let policies = policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "routes": [{
+                    "condition": { "path": "/.*" },
+                    "responseClasses": [
+                        { "condition": { "grpcStatus": 5 }, "isFailure": true },
+                        { "condition": { "trailer": { "name": "x-error", "present": true } }, "isFailure": true },
+                        { "condition": { "header": { "name": "x-retry", "exact": "true" } }, "isFailure": true },
+                    ],
+                }],
+            },
+        }))
+        .unwrap();
+        let policy = policies.get(&dst("web.ns.svc.cluster.local:8080")).unwrap();
+        let profile = policy.apply(profiles::Routes::default(), None);
+        let classes = profile.routes[0].1.response_classes();
+        assert_eq!(classes.len(), 3);
+        assert!(classes[0].requires_eos());
+        assert!(classes[1].requires_eos());
+        assert!(!classes[2].requires_eos());
+
+        let rsp = http::Response::builder()
+            .header("grpc-status", "5")
+            .body(())
+            .unwrap();
+        // Trailers-only responses carry their `grpc-status` in headers.
+        assert!(classes[0].is_match(&rsp));
+        assert!(classes[0].is_match_eos(rsp.status(), rsp.headers(), None));
+
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("x-error", "1".parse().unwrap());
+        assert!(classes[1].is_match_eos(
+            http::StatusCode::OK,
+            &Default::default(),
+            Some(&trailers)
+        ));
+    }
+
+    #[test]
+    fn parses_retries() {
+        let policies = policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "retryBudget": { "minRetriesPerSecond": 10, "retryRatio": 0.2, "ttlMs": 10000 },
+                "routes": [{
+                    "condition": { "path": "/.*" },
+                    "isRetryable": true,
+                    "retries": {
+                        "errors": ["connect-refused", "timeout"],
+                        "resetCodes": ["INTERNAL_ERROR"],
+                        "perTryTimeoutMs": 250,
+                        "backoff": { "minMs": 10, "maxMs": 100, "jitter": 0.5 },
+                    },
+                }],
+            },
+        }))
+        .unwrap();
+        let policy = policies.get(&dst("web.ns.svc.cluster.local:8080")).unwrap();
+
+        // The policy's budget is used in favor of the destination service's.
+        let budget = Arc::new(Budget::new(Duration::from_secs(10), 10, 0.2));
+        let profile = policy.apply(profiles::Routes::default(), Some(&budget));
+        let retries = profile.routes[0]
+            .1
+            .retries()
+            .expect("route must be retryable");
+        assert!(!Arc::ptr_eq(retries.budget(), &budget));
+        let errors = retries.errors();
+        assert!(errors.connect_refused);
+        assert!(!errors.reset);
+        assert!(errors.timeout);
+        assert!(errors.reset_codes.contains(2));
+        assert!(!errors.reset_codes.contains(7));
+        assert!(!errors.reset_codes.contains(u32::max_value()));
+        assert_eq!(retries.per_try_timeout(), Some(Duration::from_millis(250)));
+        let backoff = retries.backoff().expect("backoff must be set");
+        assert_eq!(backoff.min, Duration::from_millis(10));
+        assert_eq!(backoff.max, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn parses_hedges() {
+        let policies = policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "routes": [{
+                    "condition": { "method": "GET" },
+                    "isRetryable": true,
+                    "hedge": { "percentile": 95 },
+                }],
+            },
+        }))
+        .unwrap();
+        let policy = policies.get(&dst("web.ns.svc.cluster.local:8080")).unwrap();
+        let profile = policy.apply(profiles::Routes::default(), None);
+        let hedge = profile.routes[0].1.hedge().expect("route must be hedged");
+        assert_eq!(hedge.percentile(), 95.0);
+    }
+
+    #[test]
+    fn parses_load_balancers() {
+        let policies = policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "loadBalancer": { "consistentHash": { "header": { "name": "x-user" } } },
+            },
+            "web.ns.svc.cluster.local:8081": {
+                "loadBalancer": { "consistentHash": { "sourceIp": {} } },
+            },
+            "web.ns.svc.cluster.local:8082": {
+                "loadBalancer": { "stickyCookie": { "name": "l5d-sticky" } },
+            },
+        }))
+        .unwrap();
+        let lb = |name: &str| {
+            let policy = policies.get(&dst(name)).unwrap();
+            policy
+                .apply(profiles::Routes::default(), None)
+                .load_balancer
+        };
+        assert_eq!(
+            lb("web.ns.svc.cluster.local:8080"),
+            profiles::LoadBalancer::ConsistentHash(profiles::HashKey::Header(
+                http::header::HeaderName::from_static("x-user")
+            ))
+        );
+        assert_eq!(
+            lb("web.ns.svc.cluster.local:8081"),
+            profiles::LoadBalancer::ConsistentHash(profiles::HashKey::SourceIp)
+        );
+        assert_eq!(
+            lb("web.ns.svc.cluster.local:8082"),
+            profiles::LoadBalancer::StickyCookie("l5d-sticky".into())
+        );
+    }
+
+    #[test]
+    fn parses_health_checks() {
+        let policies = policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "healthCheck": {
+                    "probe": { "http": { "path": "/ready" } },
+                    "intervalMs": 5000,
+                    "unhealthyThreshold": 1,
+                },
+            },
+        }))
+        .unwrap();
+        let policy = policies.get(&dst("web.ns.svc.cluster.local:8080")).unwrap();
+        let profile = policy.apply(profiles::Routes::default(), None);
+        assert_eq!(
+            profile.health_check,
+            Some(profiles::HealthCheck {
+                probe: profiles::HealthProbe::Http {
+                    path: "/ready".into()
+                },
+                interval: Duration::from_secs(5),
+                timeout: Duration::from_secs(1),
+                unhealthy_threshold: 1,
+                healthy_threshold: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn parses_rate_limits() {
+        let policies = policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "routes": [{
+                    "condition": { "path": "/.*" },
+                    "rateLimit": {
+                        "perClient": { "maxTokens": 10, "tokensPerFill": 1, "fillIntervalMs": 100 },
+                    },
+                }],
+            },
+        }))
+        .unwrap();
+        let policy = policies.get(&dst("web.ns.svc.cluster.local:8080")).unwrap();
+        let profile = policy.apply(profiles::Routes::default(), None);
+        assert_eq!(
+            profile.routes[0].1.rate_limit(),
+            Some(&profiles::RateLimit {
+                total: None,
+                per_client: Some(profiles::TokenBucket {
+                    max_tokens: 10,
+                    tokens_per_fill: 1,
+                    fill_interval: Duration::from_millis(100),
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn parses_faults() {
+        let policies = policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "routes": [{
+                    "condition": { "path": "/.*" },
+                    "fault": {
+                        "delay": { "percentage": 10, "minMs": 10, "maxMs": 100 },
+                        "abort": { "percentage": 2.5, "grpcStatus": 14 },
+                    },
+                }],
+            },
+        }))
+        .unwrap();
+        let policy = policies.get(&dst("web.ns.svc.cluster.local:8080")).unwrap();
+        let profile = policy.apply(profiles::Routes::default(), None);
+        assert_eq!(
+            profile.routes[0].1.fault(),
+            Some(&profiles::Fault {
+                delay: Some(profiles::FaultDelay {
+                    percentage: 10.0,
+                    duration: profiles::DelayDuration::Random {
+                        min: Duration::from_millis(10),
+                        max: Duration::from_millis(100),
+                    },
+                }),
+                abort: Some(profiles::FaultAbort {
+                    percentage: 2.5,
+                    status: profiles::AbortStatus::Grpc(14),
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn parses_dst_matches() {
+        let policies = policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "dstMatches": [{
+                    "condition": { "header": { "name": "x-canary", "exact": "true" } },
+                    "authority": "web-v2.ns.svc.cluster.local:8080",
+                }],
+            },
+        }))
+        .unwrap();
+        let policy = policies.get(&dst("web.ns.svc.cluster.local:8080")).unwrap();
+        let profile = policy.apply(profiles::Routes::default(), None);
+        assert_eq!(profile.dst_matches.len(), 1);
+        let matched = &profile.dst_matches[0];
+        assert_eq!(matched.addr, dst("web-v2.ns.svc.cluster.local:8080"));
+        match matched.condition {
+            profiles::RequestMatch::Header { ref name, .. } => assert_eq!(name, "x-canary"),
+            ref condition => panic!("unexpected condition: {:?}", condition),
+        }
+    }
+
+    #[test]
+    fn parses_mirrors() {
+        let policies = policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "routes": [{
+                    "condition": { "method": "GET" },
+                    "mirror": {
+                        "authority": "web-canary.ns.svc.cluster.local:8080",
+                        "percentage": 5,
+                    },
+                }],
+            },
+        }))
+        .unwrap();
+        let policy = policies.get(&dst("web.ns.svc.cluster.local:8080")).unwrap();
+        let profile = policy.apply(profiles::Routes::default(), None);
+        assert_eq!(
+            profile.routes[0].1.mirror(),
+            Some(&profiles::Mirror {
+                addr: dst("web-canary.ns.svc.cluster.local:8080"),
+                percentage: 5.0,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_policies() {
+        let route = |condition: Value| {
+            policies(json!({
+                "web.ns.svc.cluster.local:8080": { "routes": [{ "condition": condition }] },
+            }))
+        };
+        // Returns a policy with a single route that has the given fields.
+        let route_with = |fields: Value| {
+            let mut route = json!({ "condition": { "path": "/" } });
+            let fields = fields.as_object().cloned().unwrap_or_default();
+            route.as_object_mut().unwrap().extend(fields);
+            policies(json!({ "web.ns.svc.cluster.local:8080": { "routes": [route] } }))
+        };
+        assert!(route(json!({ "path": "(" })).is_err());
+        assert!(route(json!({ "method": "GE T" })).is_err());
+        assert!(route(json!({ "cookie": "session" })).is_err());
+        assert!(route(json!({ "path": "/", "method": "GET" })).is_err());
+        assert!(route(json!({ "header": { "name": "x-foo" } })).is_err());
+        assert!(policies(json!({
+            "web.ns.svc.cluster.local:8080": { "routes": [{
+                "condition": { "path": "/" },
+                "responseClasses": [{ "condition": { "grpcStatus": -1 } }],
+            }] },
+        }))
+        .is_err());
+        assert!(route_with(json!({
+            "isRetryable": true,
+            "responseClasses": [{ "condition": { "grpcStatus": 14 }, "isFailure": true }],
+        }))
+        .is_err());
+        assert!(route_with(json!({
+            "isRetryable": true,
+            "responseClasses": [{
+                "condition": { "not": { "trailer": { "name": "x-ok", "present": true } } },
+                "isFailure": true,
+            }],
+        }))
+        .is_err());
+        assert!(policies(json!({
+            "web.ns.svc.cluster.local:8080": { "routes": [{
+                "condition": { "path": "/" },
+                "retries": { "errors": ["timeout"] },
+            }] },
+        }))
+        .is_err());
+        assert!(route_with(json!({
+            "isRetryable": true,
+            "retries": { "resetCodes": ["NOT_AN_ERROR"] },
+        }))
+        .is_err());
+        assert!(policies(json!({
+            "web.ns.svc.cluster.local:8080": { "routes": [{
+                "condition": { "path": "/" },
+                "isRetryable": true,
+                "retries": { "backoff": { "minMs": 100, "maxMs": 10 } },
+            }] },
+        }))
+        .is_err());
+        assert!(policies(json!({
+            "web.ns.svc.cluster.local:8080": { "routes": [{
+                "condition": { "path": "/" },
+                "hedge": { "percentile": 95 },
+            }] },
+        }))
+        .is_err());
+        assert!(policies(json!({
+            "web.ns.svc.cluster.local:8080": { "routes": [{
+                "condition": { "path": "/" },
+                "isRetryable": true,
+                "hedge": { "percentile": 0 },
+            }] },
+        }))
+        .is_err());
+        assert!(policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "loadBalancer": { "consistentHash": { "path": {} } },
+            },
+        }))
+        .is_err());
+        assert!(policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "healthCheck": {
+                    "probe": { "grpc": { "service": "web" } },
+                    "healthyThreshold": 0,
+                },
+            },
+        }))
+        .is_err());
+        assert!(route_with(json!({ "rateLimit": {} })).is_err());
+        assert!(route_with(json!({
+            "rateLimit": { "total": { "maxTokens": 0, "tokensPerFill": 1, "fillIntervalMs": 100 } },
+        }))
+        .is_err());
+        assert!(route_with(json!({
+            "fault": { "abort": { "percentage": 101, "httpStatus": 503 } },
+        }))
+        .is_err());
+        assert!(route_with(json!({
+            "fault": { "delay": { "percentage": 10, "fixedMs": 10, "maxMs": 100 } },
+        }))
+        .is_err());
+        assert!(route_with(json!({
+            "mirror": { "authority": "web-canary.ns.svc.cluster.local", "percentage": 5 },
+        }))
+        .is_err());
+        assert!(route_with(json!({
+            "mirror": { "authority": "web-canary.ns.svc.cluster.local:8080", "percentage": -1 },
+        }))
+        .is_err());
+        assert!(policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "dstMatches": [{ "condition": { "path": "/" }, "authority": "web-v2" }],
+            },
+        }))
+        .is_err());
+        assert!(policies(json!({
+            "web.ns.svc.cluster.local:8080": {
+                "loadBalancer": { "stickyCookie": { "name": "l5d sticky" } },
+            },
+        }))
+        .is_err());
+        assert!(policies(json!({ "web.ns.svc.cluster.local": {} })).is_err());
+        assert!(policies(json!([])).is_err());
+    }
+
+    #[test]
+    fn detects_changed_policies() {
+        let doc = |path: &str| {
+            json!({
+                "web.ns.svc.cluster.local:8080": {
+                    "routes": [{ "condition": { "path": path } }],
+                },
+                "api.ns.svc.cluster.local:8080": {},
+            })
+        };
+        let web = dst("web.ns.svc.cluster.local:8080");
+        let api = dst("api.ns.svc.cluster.local:8080");
+        let other = dst("other.ns.svc.cluster.local:8080");
+
+        let before = policies(doc("/users")).unwrap();
+        let reloaded = policies(doc("/users")).unwrap();
+        let changed = policies(doc("/accounts")).unwrap();
+        assert!(Policy::is_same(&before.get(&web), &reloaded.get(&web)));
+        assert!(!Policy::is_same(&before.get(&web), &changed.get(&web)));
+        assert!(Policy::is_same(&before.get(&api), &changed.get(&api)));
+        assert!(Policy::is_same(&before.get(&other), &changed.get(&other)));
+        assert!(!Policy::is_same(&before.get(&web), &before.get(&other)));
+    }
+}